@@ -1,4 +1,5 @@
 use clap::{Arg, ArgAction, Command};
+use ethereum_rust_evm::EvmEngine;
 use ethereum_rust_net::bootnode::BootNode;
 
 pub fn cli() -> Command {
@@ -19,6 +20,20 @@ pub fn cli() -> Command {
                 .value_name("PORT")
                 .action(ArgAction::Set),
         )
+        .arg(
+            Arg::new("ws.addr")
+                .long("ws.addr")
+                .default_value("localhost")
+                .value_name("ADDRESS")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("ws.port")
+                .long("ws.port")
+                .default_value("8546")
+                .value_name("PORT")
+                .action(ArgAction::Set),
+        )
         .arg(
             Arg::new("authrpc.addr")
                 .long("authrpc.addr")
@@ -40,6 +55,30 @@ pub fn cli() -> Command {
                 .value_name("JWTSECRET_PATH")
                 .action(ArgAction::Set),
         )
+        .arg(
+            Arg::new("rpc.maxbatchsize")
+                .long("rpc.maxbatchsize")
+                .default_value("1000")
+                .value_name("REQUESTS")
+                .value_parser(clap::value_parser!(usize))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("logs.maxblockrange")
+                .long("logs.maxblockrange")
+                .default_value("10000")
+                .value_name("BLOCKS")
+                .value_parser(clap::value_parser!(u64))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("logs.maxresults")
+                .long("logs.maxresults")
+                .default_value("10000")
+                .value_name("LOGS")
+                .value_parser(clap::value_parser!(usize))
+                .action(ArgAction::Set),
+        )
         .arg(
             Arg::new("p2p.addr")
                 .long("p2p.addr")
@@ -68,6 +107,14 @@ pub fn cli() -> Command {
                 .value_name("PORT")
                 .action(ArgAction::Set),
         )
+        .arg(
+            Arg::new("maxpeers")
+                .long("maxpeers")
+                .default_value("50")
+                .value_name("PEERS")
+                .value_parser(clap::value_parser!(usize))
+                .action(ArgAction::Set),
+        )
         .arg(
             Arg::new("network")
                 .long("network")
@@ -91,6 +138,20 @@ pub fn cli() -> Command {
                 .value_name("DATABASE_DIRECTORY")
                 .action(ArgAction::Set),
         )
+        .arg(
+            Arg::new("archive")
+                .long("archive")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("evm")
+                .long("evm")
+                .default_value("revm")
+                .value_name("EVM_ENGINE")
+                .value_parser(clap::value_parser!(EvmEngine))
+                .action(ArgAction::Set),
+        )
         .arg(
             Arg::new("import")
                 .long("import")
@@ -1,10 +1,14 @@
 use bytes::Bytes;
 use ethereum_rust_chain::add_block;
 use ethereum_rust_core::types::{Block, Genesis};
+use ethereum_rust_evm::EvmEngine;
 use ethereum_rust_net::bootnode::BootNode;
 use ethereum_rust_net::node_id_from_signing_key;
+use ethereum_rust_net::peer_manager::{PeerLimits, PeerManager};
+use ethereum_rust_net::sync::SyncManager;
 use ethereum_rust_net::types::Node;
-use ethereum_rust_storage::{EngineType, Store};
+use ethereum_rust_rpc::{LogsLimits, RpcLimits};
+use ethereum_rust_storage::{EngineType, StateHistory, Store};
 use k256::{ecdsa::SigningKey, elliptic_curve::rand_core::OsRng};
 use std::{
     fs::File,
@@ -33,6 +37,12 @@ async fn main() {
     let http_port = matches
         .get_one::<String>("http.port")
         .expect("http.port is required");
+    let ws_addr = matches
+        .get_one::<String>("ws.addr")
+        .expect("ws.addr is required");
+    let ws_port = matches
+        .get_one::<String>("ws.port")
+        .expect("ws.port is required");
     let authrpc_addr = matches
         .get_one::<String>("authrpc.addr")
         .expect("authrpc.addr is required");
@@ -42,6 +52,19 @@ async fn main() {
     let authrpc_jwtsecret = matches
         .get_one::<String>("authrpc.jwtsecret")
         .expect("authrpc.jwtsecret is required");
+    let rpc_limits = RpcLimits {
+        max_batch_size: *matches
+            .get_one::<usize>("rpc.maxbatchsize")
+            .expect("rpc.maxbatchsize is required"),
+        logs: LogsLimits {
+            max_block_range: *matches
+                .get_one::<u64>("logs.maxblockrange")
+                .expect("logs.maxblockrange is required"),
+            max_results: *matches
+                .get_one::<usize>("logs.maxresults")
+                .expect("logs.maxresults is required"),
+        },
+    };
 
     let tcp_addr = matches
         .get_one::<String>("p2p.addr")
@@ -56,6 +79,18 @@ async fn main() {
         .get_one::<String>("discovery.port")
         .expect("discovery.port is required");
 
+    let peer_limits = PeerLimits {
+        max_peers: *matches
+            .get_one::<usize>("maxpeers")
+            .expect("maxpeers is required"),
+        ..Default::default()
+    };
+
+    let evm_engine = *matches
+        .get_one::<EvmEngine>("evm")
+        .expect("evm is required");
+    ethereum_rust_evm::select_engine(evm_engine).expect("Failed to select EVM engine");
+
     let genesis_file_path = matches
         .get_one::<String>("network")
         .expect("network is required");
@@ -72,6 +107,8 @@ async fn main() {
 
     let http_socket_addr =
         parse_socket_addr(http_addr, http_port).expect("Failed to parse http address and port");
+    let ws_socket_addr =
+        parse_socket_addr(ws_addr, ws_port).expect("Failed to parse ws address and port");
     let authrpc_socket_addr = parse_socket_addr(authrpc_addr, authrpc_port)
         .expect("Failed to parse authrpc address and port");
 
@@ -85,6 +122,9 @@ async fn main() {
         _ => Store::new("storage.db", EngineType::InMemory),
     }
     .expect("Failed to create Store");
+    if matches.get_flag("archive") {
+        store.set_state_history(StateHistory::Archive);
+    }
 
     let genesis = read_genesis_file(genesis_file_path);
     store
@@ -110,16 +150,27 @@ async fn main() {
         tcp_port: tcp_socket_addr.port(),
         node_id: local_node_id,
     };
+    let peer_manager = PeerManager::new(local_p2p_node, peer_limits);
+    let syncer = SyncManager::new(peer_manager.clone(), store.clone());
 
     let rpc_api = ethereum_rust_rpc::start_api(
         http_socket_addr,
+        ws_socket_addr,
         authrpc_socket_addr,
-        store,
+        store.clone(),
         jwt_secret,
-        local_p2p_node,
+        peer_manager.clone(),
+        syncer,
+        rpc_limits,
+    );
+    let networking = ethereum_rust_net::start_network(
+        udp_socket_addr,
+        tcp_socket_addr,
+        bootnodes,
+        signer,
+        peer_manager,
+        store,
     );
-    let networking =
-        ethereum_rust_net::start_network(udp_socket_addr, tcp_socket_addr, bootnodes, signer);
 
     try_join!(tokio::spawn(rpc_api), tokio::spawn(networking)).unwrap();
 }
@@ -1,67 +1,328 @@
 pub mod constants;
 pub mod error;
-use constants::{GAS_PER_BLOB, MAX_BLOB_GAS_PER_BLOCK, MAX_BLOB_NUMBER_PER_BLOCK};
+pub mod mempool;
+pub mod payload;
+use constants::{
+    GAS_PER_BLOB, MAX_BLOB_GAS_PER_BLOCK, MAX_BLOB_NUMBER_PER_BLOCK, STATE_PRUNING_INTERVAL,
+};
 use error::{ChainError, InvalidBlockError};
 use ethereum_rust_core::types::{
     validate_block_header, validate_cancun_header_fields, validate_no_cancun_header_fields, Block,
-    BlockHeader, BlockNumber, EIP4844Transaction, Receipt, Transaction,
+    BlockHash, BlockHeader, BlockNumber, EIP4844Transaction, Receipt, Transaction,
 };
 use ethereum_rust_core::H256;
 
-use ethereum_rust_evm::{
-    apply_state_transitions, evm_state, execute_block, spec_id, EvmState, SpecId,
-};
+use ethereum_rust_evm::{selected_backend, spec_id, SpecId, VmBackend};
 use ethereum_rust_storage::error::StoreError;
-use ethereum_rust_storage::Store;
+use ethereum_rust_storage::{ChainEvent, StateDiff, Store, WriteBatch};
+use std::{
+    ops::Deref,
+    sync::{Arc, MutexGuard},
+};
 
-//TODO: Implement a struct Chain or BlockChain to encapsulate
-//functionality and canonical chain state and config
+/// Block tree built on top of the store: the canonical chain plus the side branches forked off it.
+/// Side blocks are kept by hash along with their receipts, and every executed block keeps the state
+/// changes it produced, so the state can be moved between any two blocks of the tree
+#[derive(Debug, Clone)]
+pub struct Blockchain {
+    storage: Store,
+    /// Backend blocks are executed with
+    vm: Arc<dyn VmBackend>,
+}
 
-/// Adds a new block as head of the chain.
-/// Performs pre and post execution validation, and updates the database.
-/// Right now we can only handle blocks that extend the canonical chain.
-/// This is:
-/// - The parent_hash field on the block header is the hash of the head of the canonical chain
-/// - The block's number is the latest block number of the canonical chain+1
-pub fn add_block(block: &Block, storage: &Store) -> Result<(), ChainError> {
-    //TODO: Eventually we should be able to handle blocks
-    //which are not directly extending the canonical chain
-    extends_canonical_chain(block, storage)?;
-    // Validate if it can be the new head and find the parent
-    let parent_header = find_parent_header(&block.header, storage)?;
-    let mut state = evm_state(storage.clone());
+impl Blockchain {
+    /// Builds the block tree on top of the store, executing blocks with the backend selected for the process
+    pub fn new(storage: Store) -> Self {
+        Self::with_backend(storage, selected_backend())
+    }
 
-    // Validate the block pre-execution
-    validate_block(block, &parent_header, &state)?;
+    pub fn with_backend(storage: Store, vm: Arc<dyn VmBackend>) -> Self {
+        Self { storage, vm }
+    }
 
-    let receipts = execute_block(block, &mut state)?;
+    /// Waits until no other writer, such as the engine API or the syncer, is changing the block tree, and keeps the
+    /// others waiting until the returned writer is dropped.
+    /// Changes are serialized so that blocks are always executed on top of a state that is still stored
+    pub fn write_lock(&self) -> ChainWriter<'_> {
+        ChainWriter {
+            blockchain: self,
+            _lock: self.storage.lock_chain_writes(),
+        }
+    }
 
-    validate_gas_used(&receipts, &block.header)?;
+    /// Adds a block to the block tree. See [ChainWriter::add_block]
+    pub fn add_block(&self, block: &Block) -> Result<(), ChainError> {
+        self.write_lock().add_block(block)
+    }
 
-    apply_state_transitions(&mut state)?;
+    /// Makes the given block the head of the canonical chain. See [ChainWriter::set_head]
+    pub fn set_head(&self, head_hash: BlockHash) -> Result<(), ChainError> {
+        self.write_lock().set_head(head_hash)
+    }
 
-    // Check state root matches the one in block header after execution
-    validate_state_root(&block.header, storage)?;
+    /// Returns the header of a block in the block tree, be it canonical or not
+    pub fn get_block_header_by_hash(
+        &self,
+        block_hash: BlockHash,
+    ) -> Result<Option<BlockHeader>, ChainError> {
+        if let Some(block_number) = self.storage.get_block_number(block_hash)? {
+            return Ok(self.storage.get_block_header(block_number)?);
+        }
+        Ok(self
+            .storage
+            .get_side_block(block_hash)?
+            .map(|(block, _)| block.header))
+    }
 
-    store_block(storage, block.clone())?;
-    store_receipts(storage, receipts, block.header.number)?;
+    /// Returns true if the block is part of the canonical chain
+    pub fn is_canonical(&self, block_hash: BlockHash) -> Result<bool, ChainError> {
+        Ok(self.storage.get_block_number(block_hash)?.is_some())
+    }
 
-    Ok(())
+    /// Returns true if `ancestor` is either `descendant` or one of its ancestors
+    pub fn is_ancestor(
+        &self,
+        ancestor: &BlockHeader,
+        descendant: &BlockHeader,
+    ) -> Result<bool, ChainError> {
+        let mut header = descendant.clone();
+        while header.number > ancestor.number {
+            header = self.get_known_header(header.parent_hash)?;
+        }
+        Ok(header == *ancestor)
+    }
+
+    /// Executes the block on top of its parent's state, returning a batch with the resulting changes applied.
+    /// The changes are only applied to the current state if the block extends the canonical head, otherwise the
+    /// batch just holds the block's world state.
+    /// The batch is only returned if the resulting state matches the block's state root
+    fn execute_and_validate(
+        &self,
+        block: &Block,
+        parent_header: &BlockHeader,
+        extends_head: bool,
+    ) -> Result<(Vec<Receipt>, StateDiff, WriteBatch), ChainError> {
+        // Validate the block pre-execution
+        validate_block(block, parent_header, &self.storage)?;
+
+        let (receipts, state_diff) =
+            self.vm
+                .execute_block(block, self.storage.clone(), parent_header.state_root)?;
+
+        validate_gas_used(&receipts, &block.header)?;
+
+        let mut batch = self.storage.write_batch();
+        let state_root = if extends_head {
+            batch.apply_state_diff(&state_diff);
+            batch.state_root()?
+        } else {
+            batch.apply_state_diff_at(parent_header.state_root, &state_diff)?
+        };
+
+        // Check state root matches the one in block header after execution
+        validate_state_root(&block.header, state_root)?;
+        Ok((receipts, state_diff, batch))
+    }
+
+    /// Returns the path between two blocks of the block tree as:
+    /// - The headers from `from` down to their common ancestor (excluded), in descending order
+    /// - The headers from their common ancestor (excluded) up to `to`, in ascending order
+    fn tree_route(
+        &self,
+        from: BlockHash,
+        to: BlockHash,
+    ) -> Result<(Vec<BlockHeader>, Vec<BlockHeader>), ChainError> {
+        let mut from_header = self.get_known_header(from)?;
+        let mut to_header = self.get_known_header(to)?;
+        let mut unwind = Vec::new();
+        let mut apply = Vec::new();
+
+        while from_header.number > to_header.number {
+            let parent_header = self.get_known_header(from_header.parent_hash)?;
+            unwind.push(std::mem::replace(&mut from_header, parent_header));
+        }
+        while to_header.number > from_header.number {
+            let parent_header = self.get_known_header(to_header.parent_hash)?;
+            apply.push(std::mem::replace(&mut to_header, parent_header));
+        }
+        while from_header != to_header {
+            let from_parent = self.get_known_header(from_header.parent_hash)?;
+            let to_parent = self.get_known_header(to_header.parent_hash)?;
+            unwind.push(std::mem::replace(&mut from_header, from_parent));
+            apply.push(std::mem::replace(&mut to_header, to_parent));
+        }
+        apply.reverse();
+        Ok((unwind, apply))
+    }
+
+    /// Adds to the batch the writes that remove a block from the canonical chain and store it as a side block
+    fn unwind_canonical_block(
+        &self,
+        batch: &mut WriteBatch,
+        header: BlockHeader,
+    ) -> Result<(Block, Vec<Receipt>), ChainError> {
+        let block_number = header.number;
+        let block_hash = header.compute_block_hash();
+        let body = self
+            .storage
+            .get_block_body(block_number)?
+            .ok_or(ChainError::UnknownBlock(block_hash))?;
+        let mut receipts = Vec::new();
+        for index in 0..body.transactions.len() {
+            if let Some(receipt) = self.storage.get_receipt(block_number, index as u64)? {
+                receipts.push(receipt);
+            }
+        }
+        let block = Block { header, body };
+        batch.remove_block(&block);
+        batch.add_side_block(block_hash, block.clone(), receipts.clone());
+        Ok((block, receipts))
+    }
+
+    fn get_known_header(&self, block_hash: BlockHash) -> Result<BlockHeader, ChainError> {
+        self.get_block_header_by_hash(block_hash)?
+            .ok_or(ChainError::UnknownBlock(block_hash))
+    }
+
+    fn get_state_diff(&self, block_hash: BlockHash) -> Result<StateDiff, ChainError> {
+        self.storage
+            .get_state_diff(block_hash)?
+            .ok_or(ChainError::MissingStateDiff(block_hash))
+    }
 }
 
-pub fn extends_canonical_chain(block: &Block, storage: &Store) -> Result<(), ChainError> {
-    let latest_block_number = storage
-        .get_latest_block_number()?
-        .ok_or(ChainError::StoreError(StoreError::Custom(
-            "Could not find latest valid hash".to_string(),
-        )))?;
+/// Exclusive access to the block tree, so a sequence of checks and changes is made without any other writer
+/// changing the chain in between
+pub struct ChainWriter<'a> {
+    blockchain: &'a Blockchain,
+    _lock: MutexGuard<'a, ()>,
+}
 
-    if block.header.number != latest_block_number.saturating_add(1) {
-        Err(ChainError::NonCanonicalBlock)
-    } else {
+impl Deref for ChainWriter<'_> {
+    type Target = Blockchain;
+
+    fn deref(&self) -> &Blockchain {
+        self.blockchain
+    }
+}
+
+impl ChainWriter<'_> {
+    /// Adds a block to the block tree, executing it on top of its parent's state.
+    /// Performs pre and post execution validation, and updates the database.
+    /// Blocks extending the canonical head become the new head, while blocks built on top of any other
+    /// known block are stored as side blocks until a reorg makes their branch canonical
+    pub fn add_block(&self, block: &Block) -> Result<(), ChainError> {
+        let block_hash = block.header.compute_block_hash();
+        if self.get_block_header_by_hash(block_hash)?.is_some() {
+            // Block was already added
+            return Ok(());
+        }
+        let parent_hash = block.header.parent_hash;
+        if let Some(latest_valid_hash) = self.storage.get_latest_valid_ancestor(parent_hash)? {
+            // Descendants of invalid blocks are also invalid
+            self.storage
+                .add_invalid_block(block_hash, latest_valid_hash)?;
+            return Err(ChainError::InvalidAncestor(latest_valid_hash));
+        }
+        let parent_header = self
+            .get_block_header_by_hash(parent_hash)?
+            .ok_or(ChainError::ParentNotFound)?;
+        let extends_head = parent_hash == latest_valid_hash(&self.storage)?;
+
+        let result = self.execute_and_validate(block, &parent_header, extends_head);
+        let (receipts, state_diff, mut batch) = match result {
+            // Errors that don't prove the block invalid, such as failed reads, leave it free to be retried
+            Err(error) if error.is_invalid_block() => {
+                // Keep track of the invalid block so its descendants can be rejected
+                self.storage.add_invalid_block(block_hash, parent_hash)?;
+                return Err(error);
+            }
+            result => result?,
+        };
+        batch.add_state_diff(block_hash, state_diff);
+        if extends_head {
+            // The block's state changes, data and receipts are committed along with the new head
+            batch.add_block(block.clone());
+            batch.add_receipts(block.header.number, receipts.clone());
+            batch.commit()?;
+            self.storage
+                .notify_chain_event(ChainEvent::new_head(block.clone(), receipts));
+            mempool::update_on_new_head(&self.storage)?;
+            if block.header.number % STATE_PRUNING_INTERVAL == 0 {
+                self.storage.prune_state_in_background();
+            }
+        } else {
+            // Only the trie nodes of the block's state are committed, the current state is left as is
+            batch.add_side_block(block_hash, block.clone(), receipts);
+            batch.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Makes the given block the head of the canonical chain, reorganizing the chain if needed.
+    /// Canonical blocks that are no longer part of the chain are kept as side blocks
+    pub fn set_head(&self, head_hash: BlockHash) -> Result<(), ChainError> {
+        let current_head_hash = latest_valid_hash(&self.storage)?;
+        if head_hash == current_head_hash {
+            return Ok(());
+        }
+        let (unwind, apply) = self.tree_route(current_head_hash, head_hash)?;
+        // Finalized blocks can't be removed from the canonical chain
+        if let (Some(lowest), Some(finalized)) =
+            (unwind.last(), self.storage.get_finalized_block_number()?)
+        {
+            if lowest.number <= finalized {
+                return Err(ChainError::FinalizedBlockUnwind(lowest.number));
+            }
+        }
+
+        // The whole reorg is committed through a single batch, so the store never holds a half-applied branch
+        let mut batch = self.storage.write_batch();
+        let mut events = Vec::new();
+        let mut unwound_blocks = Vec::new();
+
+        // Remove the blocks of the old branch from the canonical chain
+        for header in unwind.iter() {
+            let state_diff = self.get_state_diff(header.compute_block_hash())?;
+            batch.revert_state_diff(&state_diff);
+            let (block, receipts) = self.unwind_canonical_block(&mut batch, header.clone())?;
+            batch.update_latest_block_number(header.number.saturating_sub(1));
+            unwound_blocks.push(block.body.transactions.clone());
+            events.push(ChainEvent::removed_block(block, receipts));
+        }
+
+        // Add the blocks of the new branch to the canonical chain
+        for header in apply.iter() {
+            let block_hash = header.compute_block_hash();
+            let (block, receipts) = self
+                .storage
+                .get_side_block(block_hash)?
+                .ok_or(ChainError::UnknownBlock(block_hash))?;
+            let state_diff = self.get_state_diff(block_hash)?;
+            batch.apply_state_diff(&state_diff);
+            batch.add_block(block.clone());
+            batch.add_receipts(header.number, receipts.clone());
+            batch.remove_side_block(block_hash);
+            events.push(ChainEvent::new_head(block, receipts));
+        }
+        batch.commit()?;
+
+        for event in events {
+            self.storage.notify_chain_event(event);
+        }
+        mempool::update_on_new_head(&self.storage)?;
+        // Transactions of the old branch that are not part of the new one go back to the mempool
+        mempool::readd_transactions(unwound_blocks.into_iter().rev().flatten(), &self.storage);
         Ok(())
     }
 }
+
+/// Adds a new block to the block tree.
+/// See [Blockchain::add_block]
+pub fn add_block(block: &Block, storage: &Store) -> Result<(), ChainError> {
+    Blockchain::new(storage.clone()).add_block(block)
+}
+
 /// Stores block and header in the database
 pub fn store_block(storage: &Store, block: Block) -> Result<(), ChainError> {
     storage.add_block(block)?;
@@ -79,10 +340,10 @@ pub fn store_receipts(
     Ok(())
 }
 
-/// Performs post-execution checks
-pub fn validate_state_root(block_header: &BlockHeader, storage: &Store) -> Result<(), ChainError> {
+/// Performs post-execution checks, comparing the state root left by the block against the one in its header
+pub fn validate_state_root(block_header: &BlockHeader, state_root: H256) -> Result<(), ChainError> {
     // Compare state root
-    if storage.world_state_root() == block_header.state_root {
+    if state_root == block_header.state_root {
         Ok(())
     } else {
         Err(ChainError::InvalidBlock(
@@ -131,9 +392,9 @@ pub fn find_parent_header(
 pub fn validate_block(
     block: &Block,
     parent_header: &BlockHeader,
-    state: &EvmState,
+    storage: &Store,
 ) -> Result<(), ChainError> {
-    let spec = spec_id(state.database(), block.header.timestamp).unwrap();
+    let spec = spec_id(storage, block.header.timestamp)?;
 
     // Verify initial header validity against parent
     let mut valid_header = validate_block_header(&block.header, parent_header);
@@ -200,4 +461,238 @@ fn get_total_blob_gas(tx: &EIP4844Transaction) -> u64 {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use ethereum_rust_core::{
+        types::{
+            compute_transactions_root, EIP1559Transaction, Genesis, TxKind, Withdrawal, GWEI_TO_WEI,
+        },
+        Address, Bytes, U256,
+    };
+    use ethereum_rust_evm::EvmError;
+    use ethereum_rust_storage::EngineType;
+
+    use super::*;
+    use crate::payload::{build_payload, create_payload, BuildPayloadArgs};
+
+    fn test_store() -> Store {
+        const GENESIS_KURTOSIS: &str = include_str!("../../test_data/genesis-kurtosis.json");
+        let genesis: Genesis =
+            serde_json::from_str(GENESIS_KURTOSIS).expect("deserialize genesis-kurtosis.json");
+        let mut store = Store::new("", EngineType::InMemory).unwrap();
+        store.add_initial_state(genesis).unwrap();
+        store
+    }
+
+    /// Builds an empty block on top of the given parent, paying a 1 gwei withdrawal to the recipient
+    fn new_block(store: &Store, parent: &BlockHeader, recipient: Address) -> Block {
+        let args = BuildPayloadArgs {
+            parent: parent.compute_block_hash(),
+            timestamp: parent.timestamp + 12,
+            fee_recipient: Address::zero(),
+            random: H256::zero(),
+            withdrawals: vec![Withdrawal {
+                index: 0,
+                validator_index: 0,
+                address: recipient,
+                amount: 1,
+            }],
+            beacon_root: Some(H256::zero()),
+            version: 3,
+        };
+        let mut block = create_payload(&args, store).unwrap();
+        build_payload(&mut block, store, Vec::new()).unwrap();
+        block
+    }
+
+    fn balance(store: &Store, address: Address) -> U256 {
+        store
+            .get_account_info(address)
+            .unwrap()
+            .map(|info| info.balance)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn side_block_leaves_current_state_untouched() {
+        let store = test_store();
+        let blockchain = Blockchain::new(store.clone());
+        let genesis = store.get_block_header(0).unwrap().unwrap();
+        let (canonical_recipient, side_recipient) = (Address::random(), Address::random());
+
+        let canonical = new_block(&store, &genesis, canonical_recipient);
+        blockchain.add_block(&canonical).unwrap();
+        let side = new_block(&store, &genesis, side_recipient);
+        blockchain.add_block(&side).unwrap();
+
+        assert_eq!(
+            latest_valid_hash(&store).unwrap(),
+            canonical.header.compute_block_hash()
+        );
+        assert_eq!(
+            store.world_state_root().unwrap(),
+            canonical.header.state_root
+        );
+        assert_eq!(
+            balance(&store, canonical_recipient),
+            U256::from(GWEI_TO_WEI)
+        );
+        assert_eq!(balance(&store, side_recipient), U256::zero());
+        assert!(!blockchain
+            .is_canonical(side.header.compute_block_hash())
+            .unwrap());
+        // The side block's state can still be read through its root
+        let side_balance = store
+            .get_account_info_by_state_root(side.header.state_root, side_recipient)
+            .unwrap()
+            .map(|info| info.balance);
+        assert_eq!(side_balance, Some(U256::from(GWEI_TO_WEI)));
+    }
+
+    #[test]
+    fn reorg_to_side_branch_and_back() {
+        let store = test_store();
+        let blockchain = Blockchain::new(store.clone());
+        let genesis = store.get_block_header(0).unwrap().unwrap();
+        let (canonical_recipient, side_recipient) = (Address::random(), Address::random());
+
+        let canonical = new_block(&store, &genesis, canonical_recipient);
+        blockchain.add_block(&canonical).unwrap();
+        let side_1 = new_block(&store, &genesis, side_recipient);
+        blockchain.add_block(&side_1).unwrap();
+        // Executed on top of the state of a side block
+        let side_2 = new_block(&store, &side_1.header, side_recipient);
+        blockchain.add_block(&side_2).unwrap();
+
+        blockchain
+            .set_head(side_2.header.compute_block_hash())
+            .unwrap();
+        assert_eq!(store.get_latest_block_number().unwrap(), Some(2));
+        assert_eq!(store.world_state_root().unwrap(), side_2.header.state_root);
+        assert_eq!(balance(&store, side_recipient), U256::from(2 * GWEI_TO_WEI));
+        assert_eq!(balance(&store, canonical_recipient), U256::zero());
+        assert!(!blockchain
+            .is_canonical(canonical.header.compute_block_hash())
+            .unwrap());
+
+        blockchain
+            .set_head(canonical.header.compute_block_hash())
+            .unwrap();
+        assert_eq!(store.get_latest_block_number().unwrap(), Some(1));
+        assert_eq!(
+            store.world_state_root().unwrap(),
+            canonical.header.state_root
+        );
+        assert_eq!(
+            balance(&store, canonical_recipient),
+            U256::from(GWEI_TO_WEI)
+        );
+        assert_eq!(balance(&store, side_recipient), U256::zero());
+        assert_eq!(store.get_block_header(1).unwrap(), Some(canonical.header));
+        assert_eq!(store.get_block_header(2).unwrap(), None);
+        assert!(blockchain
+            .get_block_header_by_hash(side_2.header.compute_block_hash())
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn set_head_keeps_finalized_blocks() {
+        let store = test_store();
+        let blockchain = Blockchain::new(store.clone());
+        let genesis = store.get_block_header(0).unwrap().unwrap();
+        let canonical = new_block(&store, &genesis, Address::random());
+        blockchain.add_block(&canonical).unwrap();
+        let side = new_block(&store, &genesis, Address::random());
+        blockchain.add_block(&side).unwrap();
+        store.update_finalized_block_number(1).unwrap();
+
+        assert!(matches!(
+            blockchain.set_head(side.header.compute_block_hash()),
+            Err(ChainError::FinalizedBlockUnwind(1))
+        ));
+        assert_eq!(
+            latest_valid_hash(&store).unwrap(),
+            canonical.header.compute_block_hash()
+        );
+    }
+
+    #[test]
+    fn side_branch_can_be_extended_after_pruning() {
+        let store = test_store();
+        let blockchain = Blockchain::new(store.clone());
+        let genesis = store.get_block_header(0).unwrap().unwrap();
+        let canonical = new_block(&store, &genesis, Address::random());
+        blockchain.add_block(&canonical).unwrap();
+        let side = new_block(&store, &genesis, Address::random());
+        blockchain.add_block(&side).unwrap();
+
+        store.prune_state().unwrap();
+
+        // The side block's state is still there to execute its child on, which makes the side branch canonical
+        let side_child = new_block(&store, &side.header, Address::random());
+        blockchain.add_block(&side_child).unwrap();
+        blockchain
+            .set_head(side_child.header.compute_block_hash())
+            .unwrap();
+        assert_eq!(
+            store.world_state_root().unwrap(),
+            side_child.header.state_root
+        );
+    }
+
+    /// Transfer signed with `r` set to the x coordinate of the curve's generator and `s` set to 1, which is a valid
+    /// signature for some sender that has no funds on the test chain
+    fn unfunded_transfer() -> Transaction {
+        let generator_x = U256::from_str_radix(
+            "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            16,
+        )
+        .unwrap();
+        Transaction::EIP1559Transaction(EIP1559Transaction {
+            chain_id: 3151908,
+            nonce: 0,
+            max_priority_fee_per_gas: GWEI_TO_WEI,
+            max_fee_per_gas: 10 * GWEI_TO_WEI,
+            gas_limit: 21_000,
+            to: TxKind::Call(Address::random()),
+            value: U256::one(),
+            data: Bytes::new(),
+            access_list: Vec::new(),
+            signature_y_parity: false,
+            signature_r: generator_x,
+            signature_s: U256::one(),
+        })
+    }
+
+    #[test]
+    fn only_invalid_blocks_are_blacklisted() {
+        let store = test_store();
+        let blockchain = Blockchain::new(store.clone());
+        let genesis = store.get_block_header(0).unwrap().unwrap();
+        let block = new_block(&store, &genesis, Address::random());
+        let block_hash = block.header.compute_block_hash();
+        blockchain.add_block(&block).unwrap();
+
+        // The child's transaction can't be paid for, so the child is invalid and so will be its descendants
+        let mut child = new_block(&store, &block.header, Address::random());
+        child.body.transactions.push(unfunded_transfer());
+        child.header.transactions_root = compute_transactions_root(&child.body.transactions);
+        let result = blockchain.add_block(&child);
+        assert!(matches!(
+            result,
+            Err(ChainError::EvmError(EvmError::Transaction(_)))
+        ));
+        assert_eq!(
+            store
+                .get_latest_valid_ancestor(child.header.compute_block_hash())
+                .unwrap(),
+            Some(block_hash)
+        );
+
+        // A failed read doesn't make the block invalid, so it can be retried
+        let failed_read =
+            ChainError::EvmError(EvmError::DB(StoreError::Custom("read failed".to_string())));
+        assert!(!failed_read.is_invalid_block());
+        assert!(!ChainError::ParentNotFound.is_invalid_block());
+    }
+}
@@ -1,3 +1,57 @@
+// === Payload building constants ===
+
+/// Gas limit the block builder will move towards when building new payloads
+pub const DEFAULT_BUILDER_GAS_CEIL: u64 = 30_000_000;
+
+// === State pruning constants ===
+
+/// Amount of canonical blocks between each pruning of the world state nodes that are no longer needed.
+/// Pruning is skipped when the node keeps the whole state history (archive mode)
+pub const STATE_PRUNING_INTERVAL: u64 = 1024;
+
+// === Transaction pool constants ===
+
+/// Maximum amount of transactions held by the mempool
+pub const MEMPOOL_MAX_SIZE: usize = 4096;
+
+/// Maximum amount of transactions a single sender can have in the mempool
+pub const MEMPOOL_MAX_TXS_PER_SENDER: usize = 64;
+
+/// Maximum amount of blobs held by the blob pool (~128MB of blob data)
+pub const BLOB_POOL_MAX_BLOBS: usize = 1024;
+
+/// Minimum fee increase (in percentage) required to replace a transaction in the mempool
+pub const PRICE_BUMP_PERCENTAGE: u64 = 10;
+
+/// Maximum size of a transaction's data, in bytes
+pub const TX_MAX_DATA_SIZE: usize = 128 * 1024;
+
+// === Intrinsic gas constants ===
+
+/// Base gas cost of every transaction
+pub const TX_GAS_COST: u64 = 21_000;
+
+/// Base gas cost of every contract creation transaction
+pub const TX_CREATE_GAS_COST: u64 = 53_000;
+
+/// Gas cost of each zero byte in the transaction's data
+pub const TX_DATA_ZERO_GAS_COST: u64 = 4;
+
+/// Gas cost of each non-zero byte in the transaction's data
+pub const TX_DATA_NON_ZERO_GAS_COST: u64 = 16;
+
+/// Gas cost of each address in the transaction's access list
+pub const TX_ACCESS_LIST_ADDRESS_GAS_COST: u64 = 2_400;
+
+/// Gas cost of each storage key in the transaction's access list
+pub const TX_ACCESS_LIST_STORAGE_KEY_GAS_COST: u64 = 1_900;
+
+/// Gas cost of each 32-byte word of a contract creation's init code (EIP-3860)
+pub const TX_INIT_CODE_WORD_GAS_COST: u64 = 2;
+
+/// Maximum size of a contract creation's init code, in bytes (EIP-3860)
+pub const MAX_INITCODE_SIZE: usize = 2 * 24_576;
+
 // === EIP-4844 constants ===
 
 /// Gas consumption of a single data blob (== blob byte size).
@@ -1,5 +1,6 @@
 use thiserror::Error;
 
+use ethereum_rust_core::types::{BlockHash, BlockNumber};
 use ethereum_rust_evm::EvmError;
 use ethereum_rust_storage::error::StoreError;
 
@@ -9,16 +10,32 @@ pub enum ChainError {
     InvalidBlock(#[from] InvalidBlockError),
     #[error("Parent block not found")]
     ParentNotFound,
-    //TODO: If a block with block_number greater than latest plus one is received
-    //maybe we are missing data and should wait for syncing
-    #[error("Block number is greater than the latest plus one")]
-    NonCanonicalBlock,
+    #[error("Block {0:#x} not found in the block tree")]
+    UnknownBlock(BlockHash),
+    #[error("Block has an invalid ancestor, latest valid ancestor: {0:#x}")]
+    InvalidAncestor(BlockHash),
+    #[error("Missing state changes for block {0:#x}, cannot move the state past it")]
+    MissingStateDiff(BlockHash),
+    #[error("Cannot remove block {0} from the canonical chain, it is already finalized")]
+    FinalizedBlockUnwind(BlockNumber),
     #[error("DB error: {0}")]
     StoreError(#[from] StoreError),
     #[error("EVM error: {0}")]
     EvmError(#[from] EvmError),
 }
 
+impl ChainError {
+    /// Returns true if the error proves that the block is invalid.
+    /// Other errors, such as the ones reading from the database, may not happen again if the block is retried
+    pub fn is_invalid_block(&self) -> bool {
+        matches!(
+            self,
+            ChainError::InvalidBlock(_)
+                | ChainError::EvmError(EvmError::Transaction(_) | EvmError::Header(_))
+        )
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum InvalidBlockError {
     #[error("World State Root does not match the one in the header after executing")]
@@ -34,3 +51,45 @@ pub enum InvalidBlockError {
     #[error("Blob gas used doesn't match value in header")]
     BlobGasUsedMismatch,
 }
+
+#[derive(Debug, Error)]
+pub enum MempoolError {
+    #[error("invalid sender")]
+    InvalidSignature,
+    #[error("invalid chain id for signer: have {0}, want {1}")]
+    InvalidChainId(u64, u64),
+    #[error("only replay-protected (EIP-155) transactions allowed")]
+    UnprotectedTransaction,
+    #[error("transaction type not supported")]
+    TxTypeNotSupported,
+    #[error("blob transactions must be sent along with their blobs, commitments and proofs")]
+    MissingBlobsBundle,
+    #[error("invalid blobs bundle")]
+    InvalidBlobsBundle,
+    #[error("too many blobs")]
+    TooManyBlobs,
+    #[error("oversized data")]
+    OversizedData,
+    #[error("max initcode size exceeded")]
+    InitCodeSizeExceeded,
+    #[error("nonce too low: next nonce {0}, tx nonce {1}")]
+    NonceTooLow(u64, u64),
+    #[error("intrinsic gas too low")]
+    IntrinsicGasTooLow,
+    #[error("exceeds block gas limit")]
+    GasLimitExceeded,
+    #[error("max priority fee per gas higher than max fee per gas")]
+    TipAboveFeeCap,
+    #[error("max fee per gas less than block base fee")]
+    FeeCapTooLow,
+    #[error("insufficient funds for gas * price + value")]
+    InsufficientFunds,
+    #[error("replacement transaction underpriced")]
+    ReplacementUnderpriced,
+    #[error("already known")]
+    AlreadyKnown,
+    #[error("txpool is full")]
+    PoolFull,
+    #[error("DB error: {0}")]
+    StoreError(#[from] StoreError),
+}
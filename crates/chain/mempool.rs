@@ -0,0 +1,565 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+};
+
+use ethereum_rust_core::{
+    types::{BlobsBundle, BlockHeader, ChainConfig, EIP4844Transaction, Transaction, TxKind},
+    Address, H256, U256,
+};
+use ethereum_rust_evm::verify_blobs_bundle;
+use ethereum_rust_storage::{error::StoreError, ChainEvent, Mempool, MempoolTransaction, Store};
+use lazy_static::lazy_static;
+
+use crate::{
+    constants::{
+        BLOB_POOL_MAX_BLOBS, GAS_PER_BLOB, MAX_BLOB_NUMBER_PER_BLOCK, MAX_INITCODE_SIZE,
+        MEMPOOL_MAX_SIZE, MEMPOOL_MAX_TXS_PER_SENDER, PRICE_BUMP_PERCENTAGE,
+        TX_ACCESS_LIST_ADDRESS_GAS_COST, TX_ACCESS_LIST_STORAGE_KEY_GAS_COST, TX_CREATE_GAS_COST,
+        TX_DATA_NON_ZERO_GAS_COST, TX_DATA_ZERO_GAS_COST, TX_GAS_COST, TX_INIT_CODE_WORD_GAS_COST,
+        TX_MAX_DATA_SIZE,
+    },
+    error::MempoolError,
+};
+
+lazy_static! {
+    /// Half of the order of the secp256k1 curve, signatures with a higher `s` value are not valid (EIP-2)
+    static ref SECP256K1_N_HALF: U256 = U256::from_str_radix(
+        "7fffffffffffffffffffffffffffffff5d576e7357a4501ddfe92f46681b20a0",
+        16
+    )
+    .unwrap();
+}
+
+/// Validates a transaction against the current state and adds it to the mempool.
+/// Returns the hash of the added transaction
+pub fn add_transaction(transaction: Transaction, storage: &Store) -> Result<H256, MempoolError> {
+    // Blob transactions can't be included in a block without their sidecar
+    if matches!(transaction, Transaction::EIP4844Transaction(_)) {
+        return Err(MempoolError::MissingBlobsBundle);
+    }
+    let hash = transaction.compute_hash();
+    let (sender, account_nonce) = validate_new_transaction(hash, &transaction, storage)?;
+    storage.add_transaction_to_pool(
+        MempoolTransaction {
+            hash,
+            sender,
+            transaction,
+        },
+        account_nonce,
+        check_pool_space,
+    )?;
+    storage.notify_chain_event(ChainEvent::NewPendingTransaction(hash));
+    Ok(hash)
+}
+
+/// Validates a blob transaction and its sidecar, verifying the sidecar's KZG proofs, and adds them to the mempool.
+/// Sidecars are kept in a separate pool, with its own size limit.
+/// Returns the hash of the added transaction
+pub fn add_blob_transaction(
+    transaction: EIP4844Transaction,
+    blobs_bundle: BlobsBundle,
+    storage: &Store,
+) -> Result<H256, MempoolError> {
+    if !blobs_bundle.validate_for_transaction(&transaction) {
+        return Err(MempoolError::InvalidBlobsBundle);
+    }
+    let transaction = Transaction::EIP4844Transaction(transaction);
+    let hash = transaction.compute_hash();
+    let (sender, account_nonce) = validate_new_transaction(hash, &transaction, storage)?;
+    if !verify_blobs_bundle(&blobs_bundle).map_err(|_| MempoolError::InvalidBlobsBundle)? {
+        return Err(MempoolError::InvalidBlobsBundle);
+    }
+    let blob_count = blobs_bundle.blobs.len();
+    storage.add_blob_transaction_to_pool(
+        MempoolTransaction {
+            hash,
+            sender,
+            transaction,
+        },
+        blobs_bundle,
+        account_nonce,
+        |mempool, transaction| {
+            check_pool_space(mempool, transaction)?;
+            // The blobs of the transaction being replaced, if any, leave the pool along with it
+            let replaced_blobs = mempool
+                .get_by_nonce(sender, transaction.transaction.nonce())
+                .map(|replaced| replaced.transaction.blob_versioned_hashes().len())
+                .unwrap_or_default();
+            if mempool.blob_count().saturating_sub(replaced_blobs) + blob_count
+                > BLOB_POOL_MAX_BLOBS
+            {
+                return Err(MempoolError::PoolFull);
+            }
+            Ok(())
+        },
+    )?;
+    storage.notify_chain_event(ChainEvent::NewPendingTransaction(hash));
+    Ok(hash)
+}
+
+/// Validates a transaction that is not in the mempool yet against the current state.
+/// Returns the transaction's sender and its current account nonce
+fn validate_new_transaction(
+    hash: H256,
+    transaction: &Transaction,
+    storage: &Store,
+) -> Result<(Address, u64), MempoolError> {
+    // Skip validating transactions we already have
+    if storage.get_transaction_from_pool(hash).is_some() {
+        return Err(MempoolError::AlreadyKnown);
+    }
+    validate_transaction(transaction, storage)
+}
+
+/// Checks that a new transaction fits in the mempool, run with the mempool locked right before adding it.
+/// A transaction from the same sender and with the same nonce as one already in the mempool replaces it
+/// as long as it pays enough extra fees
+fn check_pool_space(
+    mempool: &Mempool,
+    transaction: &MempoolTransaction,
+) -> Result<(), MempoolError> {
+    if mempool.get(transaction.hash).is_some() {
+        return Err(MempoolError::AlreadyKnown);
+    }
+    let sender = transaction.sender;
+    match mempool.get_by_nonce(sender, transaction.transaction.nonce()) {
+        Some(replaced) => {
+            if !is_valid_replacement(&replaced.transaction, &transaction.transaction) {
+                return Err(MempoolError::ReplacementUnderpriced);
+            }
+        }
+        None => {
+            if mempool.len() >= MEMPOOL_MAX_SIZE
+                || mempool.sender_len(sender) >= MEMPOOL_MAX_TXS_PER_SENDER
+            {
+                return Err(MempoolError::PoolFull);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds the transactions of blocks removed from the canonical chain back to the mempool.
+/// Blob transactions are skipped, as their sidecars are no longer available, and so are the transactions
+/// that are no longer valid, such as the ones already included in the new canonical chain
+pub fn readd_transactions(transactions: impl IntoIterator<Item = Transaction>, storage: &Store) {
+    for transaction in transactions {
+        if !matches!(transaction, Transaction::EIP4844Transaction(_)) {
+            let _ = add_transaction(transaction, storage);
+        }
+    }
+}
+
+/// Returns the pending transactions of the mempool that can pay the given base fee, sorted so that
+/// the ones paying the highest tips come first while keeping each sender's transactions in nonce order
+pub fn transactions_for_payload(storage: &Store, base_fee: Option<u64>) -> Vec<Transaction> {
+    let mut senders: Vec<VecDeque<MempoolTransaction>> = storage
+        .pending_pool_transactions()
+        .into_iter()
+        .map(VecDeque::from)
+        .collect();
+    // Max-heap with the tip of each sender's next transaction
+    let mut heads = BinaryHeap::new();
+    for (index, transactions) in senders.iter().enumerate() {
+        if let Some(tip) = next_tip(transactions, base_fee) {
+            heads.push((tip, Reverse(index)));
+        }
+    }
+    let mut selected = Vec::new();
+    while let Some((_, Reverse(index))) = heads.pop() {
+        if let Some(next) = senders[index].pop_front() {
+            selected.push(next.transaction);
+        }
+        if let Some(tip) = next_tip(&senders[index], base_fee) {
+            heads.push((tip, Reverse(index)));
+        }
+    }
+    selected
+}
+
+/// Removes the transactions included in the canonical chain from the mempool, and updates
+/// the pending and queued transactions of each sender to their new account nonce
+pub fn update_on_new_head(storage: &Store) -> Result<(), StoreError> {
+    for sender in storage.pool_senders() {
+        let account_nonce = account_nonce(sender, storage)?;
+        storage.update_pool_sender(sender, account_nonce);
+    }
+    Ok(())
+}
+
+/// Validates a transaction against the latest block and state.
+/// Returns the transaction's sender and its current account nonce
+fn validate_transaction(
+    transaction: &Transaction,
+    storage: &Store,
+) -> Result<(Address, u64), MempoolError> {
+    let chain_config = storage.get_chain_config()?;
+    let header = latest_block_header(storage)?;
+
+    let blob_count = transaction.blob_versioned_hashes().len() as u64;
+    if matches!(transaction, Transaction::EIP4844Transaction(_)) {
+        if !chain_config.is_cancun_activated(header.timestamp) {
+            return Err(MempoolError::TxTypeNotSupported);
+        }
+        if blob_count > MAX_BLOB_NUMBER_PER_BLOCK {
+            return Err(MempoolError::TooManyBlobs);
+        }
+    }
+    if transaction.data().len() > TX_MAX_DATA_SIZE {
+        return Err(MempoolError::OversizedData);
+    }
+    let is_shanghai = chain_config.is_shanghai_activated(header.timestamp);
+    if is_shanghai
+        && transaction.to() == TxKind::Create
+        && transaction.data().len() > MAX_INITCODE_SIZE
+    {
+        return Err(MempoolError::InitCodeSizeExceeded);
+    }
+    if transaction.gas_limit() > header.gas_limit {
+        return Err(MempoolError::GasLimitExceeded);
+    }
+    if transaction
+        .max_priority_fee()
+        .is_some_and(|tip| tip > transaction.gas_price())
+    {
+        return Err(MempoolError::TipAboveFeeCap);
+    }
+    if transaction.gas_price() < header.base_fee_per_gas.unwrap_or_default() {
+        return Err(MempoolError::FeeCapTooLow);
+    }
+    validate_chain_id(transaction, &chain_config)?;
+    if signature_s(transaction) > *SECP256K1_N_HALF {
+        return Err(MempoolError::InvalidSignature);
+    }
+    let sender = transaction
+        .try_sender()
+        .ok_or(MempoolError::InvalidSignature)?;
+    if transaction.gas_limit() < intrinsic_gas(transaction, is_shanghai) {
+        return Err(MempoolError::IntrinsicGasTooLow);
+    }
+
+    let account_info = storage.get_account_info(sender)?.unwrap_or_default();
+    if transaction.nonce() < account_info.nonce {
+        return Err(MempoolError::NonceTooLow(
+            account_info.nonce,
+            transaction.nonce(),
+        ));
+    }
+    // The sender must be able to pay for the transaction on its own, regardless of its other transactions in the mempool
+    let max_blob_cost = U256::from(blob_count * GAS_PER_BLOB)
+        .saturating_mul(transaction.max_fee_per_blob_gas().unwrap_or_default());
+    let max_cost = U256::from(transaction.gas_limit())
+        .saturating_mul(U256::from(transaction.gas_price()))
+        .saturating_add(transaction.value())
+        .saturating_add(max_blob_cost);
+    if account_info.balance < max_cost {
+        return Err(MempoolError::InsufficientFunds);
+    }
+    Ok((sender, account_info.nonce))
+}
+
+fn validate_chain_id(
+    transaction: &Transaction,
+    chain_config: &ChainConfig,
+) -> Result<(), MempoolError> {
+    match transaction.chain_id() {
+        // Legacy transactions without replay protection would be valid on every chain
+        None => Err(MempoolError::UnprotectedTransaction),
+        Some(chain_id) if chain_id == chain_config.chain_id => Ok(()),
+        Some(chain_id) => Err(MempoolError::InvalidChainId(
+            chain_id,
+            chain_config.chain_id,
+        )),
+    }
+}
+
+/// Calculates the gas charged to a transaction before its execution starts
+fn intrinsic_gas(transaction: &Transaction, is_shanghai: bool) -> u64 {
+    let is_create = transaction.to() == TxKind::Create;
+    let mut gas = if is_create {
+        TX_CREATE_GAS_COST
+    } else {
+        TX_GAS_COST
+    };
+    let data = transaction.data();
+    let zero_bytes = data.iter().filter(|byte| **byte == 0).count() as u64;
+    let non_zero_bytes = data.len() as u64 - zero_bytes;
+    gas += zero_bytes * TX_DATA_ZERO_GAS_COST + non_zero_bytes * TX_DATA_NON_ZERO_GAS_COST;
+    if is_create && is_shanghai {
+        gas += (data.len() as u64).div_ceil(32) * TX_INIT_CODE_WORD_GAS_COST;
+    }
+    for (_, storage_keys) in transaction.access_list() {
+        gas += TX_ACCESS_LIST_ADDRESS_GAS_COST
+            + storage_keys.len() as u64 * TX_ACCESS_LIST_STORAGE_KEY_GAS_COST;
+    }
+    gas
+}
+
+/// A replacement must raise both the fee cap and the tip of the replaced transaction by at least `PRICE_BUMP_PERCENTAGE`
+fn is_valid_replacement(replaced: &Transaction, replacement: &Transaction) -> bool {
+    let bumped = |value: u64| value.saturating_mul(100 + PRICE_BUMP_PERCENTAGE) / 100;
+    let tip = |transaction: &Transaction| {
+        transaction
+            .max_priority_fee()
+            .unwrap_or(transaction.gas_price())
+    };
+    replacement.gas_price() >= bumped(replaced.gas_price())
+        && tip(replacement) >= bumped(tip(replaced))
+}
+
+fn next_tip(transactions: &VecDeque<MempoolTransaction>, base_fee: Option<u64>) -> Option<u64> {
+    transactions
+        .front()
+        .and_then(|next| next.transaction.effective_gas_tip(base_fee))
+}
+
+fn signature_s(transaction: &Transaction) -> U256 {
+    match transaction {
+        Transaction::LegacyTransaction(tx) => tx.s,
+        Transaction::EIP2930Transaction(tx) => tx.signature_s,
+        Transaction::EIP1559Transaction(tx) => tx.signature_s,
+        Transaction::EIP4844Transaction(tx) => tx.signature_s,
+    }
+}
+
+fn account_nonce(address: Address, storage: &Store) -> Result<u64, StoreError> {
+    Ok(storage
+        .get_account_info(address)?
+        .map(|info| info.nonce)
+        .unwrap_or_default())
+}
+
+fn latest_block_header(storage: &Store) -> Result<BlockHeader, StoreError> {
+    storage
+        .get_latest_block_number()?
+        .and_then(|number| storage.get_block_header(number).transpose())
+        .transpose()?
+        .ok_or_else(|| StoreError::Custom("Could not find latest block header".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_rust_core::{
+        types::{AccountInfo, EIP1559Transaction, Genesis, LegacyTransaction},
+        Bytes,
+    };
+    use ethereum_rust_storage::EngineType;
+
+    use super::*;
+
+    const CHAIN_ID: u64 = 3151908;
+    const GWEI: u64 = 1_000_000_000;
+
+    fn test_store() -> Store {
+        const GENESIS_KURTOSIS: &str = include_str!("../../test_data/genesis-kurtosis.json");
+        let genesis: Genesis =
+            serde_json::from_str(GENESIS_KURTOSIS).expect("deserialize genesis-kurtosis.json");
+        let mut store = Store::new("", EngineType::InMemory).unwrap();
+        store.add_initial_state(genesis).unwrap();
+        store
+    }
+
+    /// Signs the transaction with `r` set to the x coordinate of the curve's generator and `s` set to 1,
+    /// which is a valid signature for some sender that depends on the transaction's contents
+    fn signed(mut transaction: Transaction) -> Transaction {
+        let generator_x = U256::from_str_radix(
+            "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            16,
+        )
+        .unwrap();
+        match &mut transaction {
+            Transaction::LegacyTransaction(tx) => (tx.r, tx.s) = (generator_x, U256::one()),
+            Transaction::EIP1559Transaction(tx) => {
+                (tx.signature_r, tx.signature_s) = (generator_x, U256::one())
+            }
+            _ => unreachable!(),
+        }
+        transaction
+    }
+
+    /// A transfer valid on the test chain, once its sender is funded
+    fn transfer(nonce: u64) -> Transaction {
+        let mut transaction = eip1559_tx(10 * GWEI, GWEI);
+        if let Transaction::EIP1559Transaction(tx) = &mut transaction {
+            tx.chain_id = CHAIN_ID;
+            tx.nonce = nonce;
+        }
+        signed(transaction)
+    }
+
+    fn fund_sender(store: &Store, transaction: &Transaction, nonce: u64, balance: U256) {
+        let sender = transaction.try_sender().unwrap();
+        let account_info = AccountInfo {
+            nonce,
+            balance,
+            ..Default::default()
+        };
+        store.add_account_info(sender, account_info).unwrap();
+    }
+
+    fn eip1559_tx(max_fee_per_gas: u64, max_priority_fee_per_gas: u64) -> Transaction {
+        Transaction::EIP1559Transaction(EIP1559Transaction {
+            chain_id: 1,
+            nonce: 0,
+            max_priority_fee_per_gas,
+            max_fee_per_gas,
+            gas_limit: TX_GAS_COST,
+            to: TxKind::Call(Address::random()),
+            value: U256::zero(),
+            data: Bytes::new(),
+            access_list: Vec::new(),
+            signature_y_parity: false,
+            signature_r: U256::zero(),
+            signature_s: U256::zero(),
+        })
+    }
+
+    #[test]
+    fn intrinsic_gas_of_call_with_data_and_access_list() {
+        let mut transaction = eip1559_tx(10, 1);
+        if let Transaction::EIP1559Transaction(tx) = &mut transaction {
+            tx.data = Bytes::from(vec![0, 1, 2]);
+            tx.access_list = vec![(Address::random(), vec![H256::zero(), H256::zero()])];
+        }
+        let expected = TX_GAS_COST
+            + TX_DATA_ZERO_GAS_COST
+            + 2 * TX_DATA_NON_ZERO_GAS_COST
+            + TX_ACCESS_LIST_ADDRESS_GAS_COST
+            + 2 * TX_ACCESS_LIST_STORAGE_KEY_GAS_COST;
+        assert_eq!(intrinsic_gas(&transaction, true), expected);
+    }
+
+    #[test]
+    fn replacement_requires_price_bump() {
+        let replaced = eip1559_tx(100, 10);
+        assert!(!is_valid_replacement(&replaced, &eip1559_tx(109, 11)));
+        assert!(!is_valid_replacement(&replaced, &eip1559_tx(110, 10)));
+        assert!(is_valid_replacement(&replaced, &eip1559_tx(110, 11)));
+    }
+
+    #[test]
+    fn rejects_transactions_invalid_for_the_chain() {
+        let store = test_store();
+        let with = |modify: fn(&mut EIP1559Transaction)| {
+            let mut transaction = transfer(0);
+            if let Transaction::EIP1559Transaction(tx) = &mut transaction {
+                modify(tx);
+            }
+            signed(transaction)
+        };
+
+        let wrong_chain = with(|tx| tx.chain_id = 1);
+        assert!(matches!(
+            add_transaction(wrong_chain, &store),
+            Err(MempoolError::InvalidChainId(1, CHAIN_ID))
+        ));
+        let unprotected = signed(Transaction::LegacyTransaction(LegacyTransaction {
+            nonce: 0,
+            gas_price: 10 * GWEI,
+            gas: TX_GAS_COST,
+            to: TxKind::Call(Address::random()),
+            value: U256::zero(),
+            data: Bytes::new(),
+            v: U256::from(27),
+            r: U256::zero(),
+            s: U256::zero(),
+        }));
+        assert!(matches!(
+            add_transaction(unprotected, &store),
+            Err(MempoolError::UnprotectedTransaction)
+        ));
+        let below_base_fee = with(|tx| (tx.max_fee_per_gas, tx.max_priority_fee_per_gas) = (1, 1));
+        assert!(matches!(
+            add_transaction(below_base_fee, &store),
+            Err(MempoolError::FeeCapTooLow)
+        ));
+        let tip_above_cap = with(|tx| tx.max_priority_fee_per_gas = 20 * GWEI);
+        assert!(matches!(
+            add_transaction(tip_above_cap, &store),
+            Err(MempoolError::TipAboveFeeCap)
+        ));
+        let above_gas_limit = with(|tx| tx.gas_limit = u64::MAX);
+        assert!(matches!(
+            add_transaction(above_gas_limit, &store),
+            Err(MempoolError::GasLimitExceeded)
+        ));
+        let oversized = with(|tx| tx.data = Bytes::from(vec![1; TX_MAX_DATA_SIZE + 1]));
+        assert!(matches!(
+            add_transaction(oversized, &store),
+            Err(MempoolError::OversizedData)
+        ));
+        let mut high_s = transfer(0);
+        if let Transaction::EIP1559Transaction(tx) = &mut high_s {
+            tx.signature_s = *SECP256K1_N_HALF + U256::one();
+        }
+        assert!(matches!(
+            add_transaction(high_s, &store),
+            Err(MempoolError::InvalidSignature)
+        ));
+        assert_eq!(store.pool_len(), 0);
+    }
+
+    #[test]
+    fn rejects_transactions_invalid_for_the_sender() {
+        let store = test_store();
+        let stale = transfer(1);
+        fund_sender(&store, &stale, 2, U256::MAX);
+        assert!(matches!(
+            add_transaction(stale, &store),
+            Err(MempoolError::NonceTooLow(2, 1))
+        ));
+
+        let unaffordable = transfer(0);
+        fund_sender(&store, &unaffordable, 0, U256::from(TX_GAS_COST * GWEI));
+        assert!(matches!(
+            add_transaction(unaffordable, &store),
+            Err(MempoolError::InsufficientFunds)
+        ));
+
+        let valid = transfer(3);
+        fund_sender(&store, &valid, 3, U256::MAX);
+        assert!(add_transaction(valid.clone(), &store).is_ok());
+        assert!(matches!(
+            add_transaction(valid, &store),
+            Err(MempoolError::AlreadyKnown)
+        ));
+        assert_eq!(store.pool_len(), 1);
+    }
+
+    #[test]
+    fn readds_only_valid_transactions() {
+        let store = test_store();
+        let valid = transfer(0);
+        fund_sender(&store, &valid, 0, U256::MAX);
+        let included = transfer(1);
+        fund_sender(&store, &included, 2, U256::MAX);
+        let blob = Transaction::EIP4844Transaction(EIP4844Transaction {
+            chain_id: CHAIN_ID,
+            nonce: 0,
+            max_priority_fee_per_gas: GWEI,
+            max_fee_per_gas: 10 * GWEI,
+            gas: TX_GAS_COST,
+            to: Address::random(),
+            value: U256::zero(),
+            data: Bytes::new(),
+            access_list: Vec::new(),
+            max_fee_per_blob_gas: U256::from(GWEI),
+            blob_versioned_hashes: vec![H256::zero()],
+            signature_y_parity: false,
+            signature_r: U256::zero(),
+            signature_s: U256::zero(),
+        });
+
+        readd_transactions([valid.clone(), included.clone(), blob.clone()], &store);
+        assert!(store
+            .get_transaction_from_pool(valid.compute_hash())
+            .is_some());
+        assert!(store
+            .get_transaction_from_pool(included.compute_hash())
+            .is_none());
+        assert!(store
+            .get_transaction_from_pool(blob.compute_hash())
+            .is_none());
+    }
+}
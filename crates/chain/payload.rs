@@ -0,0 +1,242 @@
+use std::cmp::min;
+
+use ethereum_rust_core::{
+    rlp::encode::RLPEncode,
+    types::{
+        calc_excess_blob_gas, calculate_base_fee_per_gas, compute_receipts_root,
+        compute_transactions_root, compute_withdrawals_root, Block, BlockBody, BlockHash,
+        BlockHeader, Receipt, Transaction, Withdrawal, DEFAULT_OMMERS_HASH,
+        GAS_LIMIT_ADJUSTMENT_FACTOR, GAS_LIMIT_MINIMUM, INITIAL_BASE_FEE,
+    },
+    Address, Bloom, Bytes, H256, U256,
+};
+use ethereum_rust_evm::{
+    beacon_root_contract_call, evm_state_at, execute_tx, get_state_transitions,
+    process_withdrawals, spec_id, EvmError, SpecId,
+};
+use ethereum_rust_storage::Store;
+use sha3::{Digest, Keccak256};
+
+use crate::{
+    constants::{DEFAULT_BUILDER_GAS_CEIL, GAS_PER_BLOB, MAX_BLOB_GAS_PER_BLOCK},
+    error::ChainError,
+    Blockchain,
+};
+
+/// Arguments received from the consensus client to build a new payload
+pub struct BuildPayloadArgs {
+    pub parent: BlockHash,
+    pub timestamp: u64,
+    pub fee_recipient: Address,
+    pub random: H256,
+    pub withdrawals: Vec<Withdrawal>,
+    pub beacon_root: Option<H256>,
+    pub version: u8,
+}
+
+impl BuildPayloadArgs {
+    /// Computes an 8-byte identifier by hashing the components of the payload arguments.
+    /// The first byte of the identifier is the version of the engine api used to request the payload
+    pub fn id(&self) -> u64 {
+        let mut hasher = Keccak256::new();
+        hasher.update(self.parent);
+        hasher.update(self.timestamp.to_be_bytes());
+        hasher.update(self.random);
+        hasher.update(self.fee_recipient);
+        hasher.update(self.withdrawals.encode_to_vec());
+        if let Some(beacon_root) = self.beacon_root {
+            hasher.update(beacon_root);
+        }
+        let mut id: [u8; 8] = hasher.finalize()[..8].try_into().unwrap();
+        id[0] = self.version;
+        u64::from_be_bytes(id)
+    }
+}
+
+/// Creates a new payload (aka an empty block) on top of the parent block referenced by the arguments.
+/// The payload's transactions and the header fields that depend on them are filled in when building the payload
+pub fn create_payload(args: &BuildPayloadArgs, storage: &Store) -> Result<Block, ChainError> {
+    let parent_block = Blockchain::new(storage.clone())
+        .get_block_header_by_hash(args.parent)?
+        .ok_or(ChainError::ParentNotFound)?;
+    let chain_config = storage.get_chain_config()?;
+    let gas_limit = calc_gas_limit(parent_block.gas_limit, DEFAULT_BUILDER_GAS_CEIL);
+    let is_cancun = chain_config.is_cancun_activated(args.timestamp);
+
+    let body = BlockBody {
+        transactions: Vec::new(),
+        ommers: Vec::new(),
+        withdrawals: Some(args.withdrawals.clone()),
+    };
+
+    let header = BlockHeader {
+        parent_hash: args.parent,
+        ommers_hash: *DEFAULT_OMMERS_HASH,
+        coinbase: args.fee_recipient,
+        state_root: parent_block.state_root,
+        transactions_root: compute_transactions_root(&body.transactions),
+        receipts_root: compute_receipts_root(&[]),
+        logs_bloom: Bloom::default(),
+        difficulty: U256::zero(),
+        number: parent_block.number.saturating_add(1),
+        gas_limit,
+        gas_used: 0,
+        timestamp: args.timestamp,
+        extra_data: Bytes::new(),
+        prev_randao: args.random,
+        nonce: 0,
+        base_fee_per_gas: calculate_base_fee_per_gas(
+            gas_limit,
+            parent_block.gas_limit,
+            parent_block.gas_used,
+            parent_block.base_fee_per_gas.unwrap_or(INITIAL_BASE_FEE),
+        ),
+        withdrawals_root: chain_config
+            .is_shanghai_activated(args.timestamp)
+            .then_some(compute_withdrawals_root(&args.withdrawals)),
+        blob_gas_used: is_cancun.then_some(0),
+        excess_blob_gas: is_cancun.then_some(calc_excess_blob_gas(&parent_block)),
+        parent_beacon_block_root: args.beacon_root,
+    };
+
+    Ok(Block { header, body })
+}
+
+/// Fills the payload with the given transactions, executing them on top of the parent block's state, and completes
+/// the header fields that depend on the execution (state, transactions and receipts roots, logs bloom and gas used).
+/// Transactions that don't fit in the block or are invalid at this point are left out.
+/// The parent's state is read through its state root, so nothing is written to the store while building.
+/// Returns the value of the payload for its fee recipient
+pub fn build_payload(
+    payload: &mut Block,
+    storage: &Store,
+    transactions: Vec<Transaction>,
+) -> Result<U256, ChainError> {
+    let parent_header = Blockchain::new(storage.clone())
+        .get_block_header_by_hash(payload.header.parent_hash)?
+        .ok_or(ChainError::ParentNotFound)?;
+    let mut state = evm_state_at(storage.clone(), parent_header.state_root);
+    let spec_id = spec_id(storage, payload.header.timestamp)?;
+    //eip 4788: execute beacon_root_contract_call before block transactions
+    if payload.header.parent_beacon_block_root.is_some() && spec_id == SpecId::CANCUN {
+        beacon_root_contract_call(&mut state, &payload.header, spec_id)?;
+    }
+
+    let mut receipts: Vec<Receipt> = Vec::new();
+    let mut included_transactions = Vec::new();
+    let mut gas_used = 0_u64;
+    let mut blob_gas_used = 0_u64;
+    let mut block_value = U256::zero();
+    for transaction in transactions {
+        if transaction.gas_limit() > payload.header.gas_limit - gas_used {
+            continue;
+        }
+        let blob_gas = GAS_PER_BLOB * transaction.blob_versioned_hashes().len() as u64;
+        if blob_gas > 0
+            && (spec_id != SpecId::CANCUN || blob_gas_used + blob_gas > MAX_BLOB_GAS_PER_BLOCK)
+        {
+            continue;
+        }
+        let Some(gas_tip) = transaction.effective_gas_tip(payload.header.base_fee_per_gas) else {
+            continue;
+        };
+        let result = match execute_tx(&transaction, &payload.header, &mut state, spec_id) {
+            Ok(result) => result,
+            // The transaction is not valid on top of the current state, it is not included
+            Err(EvmError::Transaction(_)) => continue,
+            Err(error) => return Err(error.into()),
+        };
+        gas_used += result.gas_used();
+        blob_gas_used += blob_gas;
+        block_value += U256::from(result.gas_used()) * U256::from(gas_tip);
+        receipts.push(Receipt::new(
+            transaction.tx_type(),
+            result.is_success(),
+            gas_used,
+            result.logs(),
+        ));
+        included_transactions.push(transaction);
+    }
+
+    if let Some(withdrawals) = &payload.body.withdrawals {
+        process_withdrawals(&mut state, withdrawals)?;
+    }
+
+    // Compute the resulting state root by applying the state changes to a batch that is never committed,
+    // as the payload is not part of the block tree
+    let state_diff = get_state_transitions(&mut state)?;
+    let state_root = storage
+        .write_batch()
+        .apply_state_diff_at(parent_header.state_root, &state_diff)?;
+
+    let mut logs_bloom = Bloom::zero();
+    for receipt in receipts.iter() {
+        logs_bloom.accrue_bloom(&receipt.bloom);
+    }
+    payload.header.state_root = state_root;
+    payload.header.transactions_root = compute_transactions_root(&included_transactions);
+    payload.header.receipts_root = compute_receipts_root(&receipts);
+    payload.header.logs_bloom = logs_bloom;
+    payload.header.gas_used = gas_used;
+    if payload.header.blob_gas_used.is_some() {
+        payload.header.blob_gas_used = Some(blob_gas_used);
+    }
+    payload.body.transactions = included_transactions;
+    Ok(block_value)
+}
+
+/// Calculates the gas limit of a new block, moving the parent's gas limit towards the desired
+/// gas limit as much as the protocol allows
+fn calc_gas_limit(parent_gas_limit: u64, desired_limit: u64) -> u64 {
+    // Stay just under the maximum delta allowed by the protocol
+    let delta = (parent_gas_limit / GAS_LIMIT_ADJUSTMENT_FACTOR).saturating_sub(1);
+    let mut limit = parent_gas_limit;
+    let desired_limit = desired_limit.max(GAS_LIMIT_MINIMUM);
+    if limit < desired_limit {
+        limit = min(parent_gas_limit + delta, desired_limit);
+    } else if limit > desired_limit {
+        limit = parent_gas_limit.saturating_sub(delta).max(desired_limit);
+    }
+    limit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gas_limit_moves_towards_desired_limit() {
+        let parent_gas_limit = 30_000_000;
+        let delta = parent_gas_limit / GAS_LIMIT_ADJUSTMENT_FACTOR - 1;
+        assert_eq!(
+            calc_gas_limit(parent_gas_limit, 40_000_000),
+            parent_gas_limit + delta
+        );
+        assert_eq!(
+            calc_gas_limit(parent_gas_limit, 20_000_000),
+            parent_gas_limit - delta
+        );
+        assert_eq!(
+            calc_gas_limit(parent_gas_limit, parent_gas_limit + 10),
+            parent_gas_limit + 10
+        );
+        assert_eq!(
+            calc_gas_limit(parent_gas_limit, parent_gas_limit),
+            parent_gas_limit
+        );
+    }
+
+    #[test]
+    fn payload_id_includes_version() {
+        let args = BuildPayloadArgs {
+            parent: H256::random(),
+            timestamp: 1,
+            fee_recipient: Address::random(),
+            random: H256::random(),
+            withdrawals: Vec::new(),
+            beacon_root: Some(H256::random()),
+            version: 3,
+        };
+        assert_eq!(args.id() >> 56, 3);
+    }
+}
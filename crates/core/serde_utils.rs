@@ -1,4 +1,4 @@
-use serde::{de::Error, Deserialize, Deserializer, Serializer};
+use serde::{de::Error, ser::SerializeSeq, Deserialize, Deserializer, Serializer};
 
 pub mod u256 {
     use super::*;
@@ -113,6 +113,32 @@ pub mod u64 {
         }
     }
 
+    pub mod hex_str_padding_opt {
+        use serde::Serialize;
+
+        use super::*;
+
+        pub fn serialize<S>(value: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            Option::<String>::serialize(&value.map(|v| format!("{:#018x}", v)), serializer)
+        }
+
+        pub fn deserialize<'de, D>(d: D) -> Result<Option<u64>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let value = Option::<String>::deserialize(d)?;
+            match value {
+                Some(s) if !s.is_empty() => u64::from_str_radix(s.trim_start_matches("0x"), 16)
+                    .map_err(|_| D::Error::custom("Failed to deserialize u64 value"))
+                    .map(Some),
+                _ => Ok(None),
+            }
+        }
+    }
+
     pub mod hex_str_opt {
         use serde::Serialize;
 
@@ -230,6 +256,43 @@ pub mod bytes {
             }
             Ok(output)
         }
+
+        pub fn serialize<S>(value: &Vec<Bytes>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            let mut seq_serializer = serializer.serialize_seq(Some(value.len()))?;
+            for encoded in value {
+                seq_serializer.serialize_element(&format!("0x{:x}", encoded))?;
+            }
+            seq_serializer.end()
+        }
+    }
+
+    pub mod opt {
+        use super::*;
+
+        pub fn deserialize<'de, D>(d: D) -> Result<Option<Bytes>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let Some(value) = Option::<String>::deserialize(d)? else {
+                return Ok(None);
+            };
+            let bytes = hex::decode(value.trim_start_matches("0x"))
+                .map_err(|e| D::Error::custom(e.to_string()))?;
+            Ok(Some(Bytes::from(bytes)))
+        }
+
+        pub fn serialize<S>(value: &Option<Bytes>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match value {
+                Some(bytes) => serializer.serialize_str(&format!("0x{:x}", bytes)),
+                None => serializer.serialize_none(),
+            }
+        }
     }
 }
 
@@ -0,0 +1,148 @@
+use ethereum_types::H256;
+use sha2::{Digest, Sha256};
+
+use crate::rlp::{
+    decode::RLPDecode,
+    encode::RLPEncode,
+    error::RLPDecodeError,
+    structs::{Decoder, Encoder},
+};
+
+use super::{EIP4844Transaction, TxType};
+
+/// Size of a blob in bytes: 4096 field elements of 32 bytes each
+pub const BYTES_PER_BLOB: usize = 4096 * 32;
+
+/// Version byte of the versioned hashes computed from KZG commitments, as defined in [EIP-4844](https://eips.ethereum.org/EIPS/eip-4844)
+pub const VERSIONED_HASH_VERSION_KZG: u8 = 0x01;
+
+pub type Blob = [u8; BYTES_PER_BLOB];
+pub type Commitment = [u8; 48];
+pub type Proof = [u8; 48];
+
+/// Blob sidecar of an EIP-4844 transaction: the blobs along with their KZG commitments and proofs.
+/// It is not part of the transaction included in a block, but it is needed to propagate and validate it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlobsBundle {
+    pub blobs: Vec<Blob>,
+    pub commitments: Vec<Commitment>,
+    pub proofs: Vec<Proof>,
+}
+
+impl BlobsBundle {
+    /// Computes the versioned hashes of the bundle's commitments
+    pub fn generate_versioned_hashes(&self) -> Vec<H256> {
+        self.commitments
+            .iter()
+            .map(kzg_commitment_to_versioned_hash)
+            .collect()
+    }
+
+    /// Checks that the bundle contains a blob, commitment and proof for each of the transaction's versioned hashes,
+    /// and that the versioned hashes match the bundle's commitments.
+    /// This doesn't verify the KZG proofs
+    pub fn validate_for_transaction(&self, tx: &EIP4844Transaction) -> bool {
+        let blob_count = tx.blob_versioned_hashes.len();
+        blob_count > 0
+            && self.blobs.len() == blob_count
+            && self.commitments.len() == blob_count
+            && self.proofs.len() == blob_count
+            && self.generate_versioned_hashes() == tx.blob_versioned_hashes
+    }
+}
+
+/// Computes the versioned hash of a KZG commitment: its sha256 hash with the first byte replaced by the hash version
+pub fn kzg_commitment_to_versioned_hash(commitment: &Commitment) -> H256 {
+    let mut versioned_hash: [u8; 32] = Sha256::digest(commitment).into();
+    versioned_hash[0] = VERSIONED_HASH_VERSION_KZG;
+    H256(versioned_hash)
+}
+
+impl RLPEncode for BlobsBundle {
+    fn encode(&self, buf: &mut dyn bytes::BufMut) {
+        Encoder::new(buf)
+            .encode_field(&self.blobs)
+            .encode_field(&self.commitments)
+            .encode_field(&self.proofs)
+            .finish();
+    }
+}
+
+impl RLPDecode for BlobsBundle {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        let decoder = Decoder::new(rlp)?;
+        let (blobs, decoder) = decoder.decode_field("blobs")?;
+        let (commitments, decoder) = decoder.decode_field("commitments")?;
+        let (proofs, decoder) = decoder.decode_field("proofs")?;
+        let bundle = BlobsBundle {
+            blobs,
+            commitments,
+            proofs,
+        };
+        Ok((bundle, decoder.finish()?))
+    }
+}
+
+/// EIP-4844 transaction along with its blob sidecar, as it is sent to the network (aka its network representation)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WrappedEIP4844Transaction {
+    pub tx: EIP4844Transaction,
+    pub blobs_bundle: BlobsBundle,
+}
+
+impl WrappedEIP4844Transaction {
+    /// Encodes the transaction as `0x03 || rlp([tx_payload_body, blobs, commitments, proofs])`
+    pub fn encode_canonical_to_vec(&self) -> Vec<u8> {
+        let mut buf = vec![TxType::EIP4844 as u8];
+        Encoder::new(&mut buf)
+            .encode_field(&self.tx)
+            .encode_field(&self.blobs_bundle.blobs)
+            .encode_field(&self.blobs_bundle.commitments)
+            .encode_field(&self.blobs_bundle.proofs)
+            .finish();
+        buf
+    }
+
+    /// Decodes a transaction encoded as `0x03 || rlp([tx_payload_body, blobs, commitments, proofs])`
+    pub fn decode_canonical(bytes: &[u8]) -> Result<Self, RLPDecodeError> {
+        match bytes.split_first() {
+            Some((tx_type, rlp)) if *tx_type == TxType::EIP4844 as u8 => {
+                let decoder = Decoder::new(rlp)?;
+                let (tx, decoder) = decoder.decode_field("tx")?;
+                let (blobs, decoder) = decoder.decode_field("blobs")?;
+                let (commitments, decoder) = decoder.decode_field("commitments")?;
+                let (proofs, decoder) = decoder.decode_field("proofs")?;
+                let remaining = decoder.finish()?;
+                if !remaining.is_empty() {
+                    return Err(RLPDecodeError::InvalidLength);
+                }
+                Ok(WrappedEIP4844Transaction {
+                    tx,
+                    blobs_bundle: BlobsBundle {
+                        blobs,
+                        commitments,
+                        proofs,
+                    },
+                })
+            }
+            _ => Err(RLPDecodeError::Custom(
+                "Invalid transaction type for a blob transaction with sidecar".to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versioned_hash_has_kzg_version() {
+        let versioned_hash = kzg_commitment_to_versioned_hash(&[0xaa; 48]);
+        assert_eq!(versioned_hash.0[0], VERSIONED_HASH_VERSION_KZG);
+        assert_eq!(
+            versioned_hash.0[1..],
+            Sha256::digest([0xaa; 48]).as_slice()[1..]
+        );
+    }
+}
@@ -348,7 +348,7 @@ fn fake_exponential(factor: u64, numerator: u64, denominator: u64) -> u64 {
 
 // Calculates the base fee for the current block based on its gas_limit and parent's gas and fee
 // Returns None if the block gas limit is not valid in relation to its parent's gas limit
-fn calculate_base_fee_per_gas(
+pub fn calculate_base_fee_per_gas(
     block_gas_limit: u64,
     parent_gas_limit: u64,
     parent_gas_used: u64,
@@ -429,7 +429,8 @@ pub fn validate_no_cancun_header_fields(header: &BlockHeader) -> bool {
     header.excess_blob_gas.is_none() && header.blob_gas_used.is_none()
 }
 
-fn calc_excess_blob_gas(parent_header: &BlockHeader) -> u64 {
+/// Calculates the excess blob gas for the current block based on its parent's blob gas values
+pub fn calc_excess_blob_gas(parent_header: &BlockHeader) -> u64 {
     let parent_excess_blob_gas = parent_header.excess_blob_gas.unwrap_or_default();
     let parent_blob_gas_used = parent_header.blob_gas_used.unwrap_or_default();
     let parent_blob_gas = parent_excess_blob_gas + parent_blob_gas_used;
@@ -441,6 +442,15 @@ fn calc_excess_blob_gas(parent_header: &BlockHeader) -> u64 {
     }
 }
 
+/// Calculates the base fee per blob gas of the block following the given one
+pub fn calculate_next_base_fee_per_blob_gas(header: &BlockHeader) -> u64 {
+    fake_exponential(
+        MIN_BASE_FEE_PER_BLOB_GAS,
+        calc_excess_blob_gas(header),
+        BLOB_BASE_FEE_UPDATE_FRACTION,
+    )
+}
+
 #[cfg(test)]
 mod test {
 
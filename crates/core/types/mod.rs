@@ -1,13 +1,17 @@
 mod account;
+mod blobs_bundle;
 mod block;
 mod constants;
 mod genesis;
+mod overrides;
 mod receipt;
 mod transaction;
 
 pub use account::*;
+pub use blobs_bundle::*;
 pub use block::*;
 pub use constants::*;
 pub use genesis::*;
+pub use overrides::*;
 pub use receipt::*;
 pub use transaction::*;
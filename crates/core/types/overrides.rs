@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use ethereum_types::{Address, H256, U256};
+use serde::Deserialize;
+
+use super::BlockHeader;
+
+/// Values an account is made to have while simulating a call, instead of the ones held by the state
+#[derive(Deserialize, Debug, Default, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountOverride {
+    #[serde(default)]
+    pub balance: Option<U256>,
+    #[serde(default, with = "crate::serde_utils::u64::hex_str_opt")]
+    pub nonce: Option<u64>,
+    #[serde(default, with = "crate::serde_utils::bytes::opt")]
+    pub code: Option<Bytes>,
+    /// Replaces the whole storage of the account
+    #[serde(default)]
+    pub state: Option<HashMap<H256, H256>>,
+    /// Replaces the given storage slots, leaving the rest of them untouched
+    #[serde(default)]
+    pub state_diff: Option<HashMap<H256, H256>>,
+}
+
+/// Accounts overridden while simulating a call
+pub type StateOverride = HashMap<Address, AccountOverride>;
+
+/// Fields of the block a call is simulated in that are replaced by the given values
+#[derive(Deserialize, Debug, Default, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockOverrides {
+    #[serde(default, with = "crate::serde_utils::u64::hex_str_opt")]
+    pub number: Option<u64>,
+    #[serde(default, with = "crate::serde_utils::u64::hex_str_opt")]
+    pub time: Option<u64>,
+    #[serde(default, with = "crate::serde_utils::u64::hex_str_opt")]
+    pub gas_limit: Option<u64>,
+    #[serde(default, alias = "feeRecipient")]
+    pub coinbase: Option<Address>,
+    #[serde(
+        default,
+        alias = "baseFeePerGas",
+        with = "crate::serde_utils::u64::hex_str_opt"
+    )]
+    pub base_fee: Option<u64>,
+}
+
+impl AccountOverride {
+    /// An account's storage can either be replaced as a whole or slot by slot, but not both
+    pub fn is_valid(&self) -> bool {
+        self.state.is_none() || self.state_diff.is_none()
+    }
+}
+
+impl BlockOverrides {
+    /// Returns the header with the overridden fields replaced
+    pub fn apply(&self, header: &BlockHeader) -> BlockHeader {
+        let mut header = header.clone();
+        if let Some(number) = self.number {
+            header.number = number;
+        }
+        if let Some(time) = self.time {
+            header.timestamp = time;
+        }
+        if let Some(gas_limit) = self.gas_limit {
+            header.gas_limit = gas_limit;
+        }
+        if let Some(coinbase) = self.coinbase {
+            header.coinbase = coinbase;
+        }
+        if let Some(base_fee) = self.base_fee {
+            header.base_fee_per_gas = Some(base_fee);
+        }
+        header
+    }
+}
@@ -391,7 +391,14 @@ impl RLPDecode for EIP4844Transaction {
 }
 
 impl Transaction {
+    /// Recovers the address that signed the transaction.
+    /// Panics if the signature is invalid, use [Transaction::try_sender] for transactions that weren't validated yet
     pub fn sender(&self) -> Address {
+        self.try_sender().expect("Invalid transaction signature")
+    }
+
+    /// Recovers the address that signed the transaction, or returns None if the signature is invalid
+    pub fn try_sender(&self) -> Option<Address> {
         match self {
             Transaction::LegacyTransaction(tx) => {
                 let signature_y_parity = match self.chain_id() {
@@ -531,6 +538,16 @@ impl Transaction {
         }
     }
 
+    /// Returns the tip per gas paid to the block's fee recipient when included in a block with the given base fee,
+    /// or None if the transaction's max fee can't cover the base fee
+    pub fn effective_gas_tip(&self, base_fee: Option<u64>) -> Option<u64> {
+        let available_tip = self.gas_price().checked_sub(base_fee.unwrap_or_default())?;
+        Some(
+            self.max_priority_fee()
+                .map_or(available_tip, |max_tip| max_tip.min(available_tip)),
+        )
+    }
+
     pub fn chain_id(&self) -> Option<u64> {
         match self {
             Transaction::LegacyTransaction(tx) => derive_legacy_chain_id(tx.v),
@@ -595,7 +612,7 @@ fn recover_address(
     signature_s: &U256,
     signature_y_parity: bool,
     message: &Bytes,
-) -> Address {
+) -> Option<Address> {
     // Create signature
     let mut signature_bytes = [0; 64];
     signature_r.to_big_endian(&mut signature_bytes[0..32]);
@@ -604,7 +621,7 @@ fn recover_address(
         &signature_bytes,
         RecoveryId::from_i32(signature_y_parity as i32).unwrap(), // cannot fail
     )
-    .unwrap();
+    .ok()?;
     // Hash message
     let msg_digest: [u8; 32] = Keccak256::new_with_prefix(message.as_ref())
         .finalize()
@@ -612,10 +629,10 @@ fn recover_address(
     // Recover public key
     let public = SECP256K1
         .recover_ecdsa(&Message::from_digest(msg_digest), &signature)
-        .unwrap();
+        .ok()?;
     // Hash public key to obtain address
     let hash = Keccak256::new_with_prefix(&public.serialize_uncompressed()[1..]).finalize();
-    Address::from_slice(&hash[12..])
+    Some(Address::from_slice(&hash[12..]))
 }
 
 fn derive_legacy_chain_id(v: U256) -> Option<u64> {
@@ -0,0 +1,561 @@
+use std::{
+    fmt::Debug,
+    str::FromStr,
+    sync::{Arc, OnceLock},
+};
+
+use ethereum_rust_core::{
+    types::{Block, BlockHeader, BlockNumber, GenericTransaction, Receipt, StateOverride},
+    Address, H256,
+};
+use ethereum_rust_storage::{StateDiff, Store};
+
+use crate::{
+    create_access_list, evm_state, evm_state_at, execute_block, execute_block_until,
+    get_accumulated_state_transitions, get_state_transitions, simulate_tx_and_commit,
+    simulate_tx_from_generic, trace_tx, trace_tx_from_generic, AccessList, EvmError, EvmState,
+    ExecutionResult, SpecId, Trace, Tracer,
+};
+
+/// Engine selected for the whole process, used by every component that doesn't pick one explicitly
+static SELECTED_ENGINE: OnceLock<EvmEngine> = OnceLock::new();
+
+/// EVM implementation the client executes blocks and transactions with.
+/// Every backend reads the state from the store without modifying it, returning the changes instead,
+/// so different backends can be run against the same blocks and have their results compared
+pub trait VmBackend: Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Executes the block on top of the state with the given root, which should be the one left by its parent,
+    /// returning the receipts of its transactions along with the state changes it makes
+    fn execute_block(
+        &self,
+        block: &Block,
+        store: Store,
+        state_root: H256,
+    ) -> Result<(Vec<Receipt>, StateDiff), EvmError>;
+
+    /// Executes a single transaction without committing its result.
+    /// The transaction runs on the state with the given root, or the current one if none is given, with the overrides applied
+    fn simulate_tx(
+        &self,
+        tx: &GenericTransaction,
+        header: &BlockHeader,
+        store: Store,
+        state_root: Option<H256>,
+        spec_id: SpecId,
+        state_override: &StateOverride,
+    ) -> Result<ExecutionResult, EvmError>;
+
+    /// Executes a single transaction without committing its result, returning the accounts and storage slots it accessed
+    fn create_access_list(
+        &self,
+        tx: &GenericTransaction,
+        header: &BlockHeader,
+        store: Store,
+        state_root: Option<H256>,
+        spec_id: SpecId,
+    ) -> Result<(ExecutionResult, AccessList), EvmError>;
+
+    /// Executes the transactions of the block preceding the one at the given index on top of the state with the given
+    /// root, or the current one if none is given, and then executes that one with the given tracer attached
+    fn trace_tx(
+        &self,
+        block: &Block,
+        tx_index: usize,
+        store: Store,
+        state_root: Option<H256>,
+        tracer: Tracer,
+    ) -> Result<(ExecutionResult, Trace), EvmError>;
+
+    /// Executes each of the block's transactions with the given tracer attached, on top of the state with the given root,
+    /// or the current one if none is given
+    fn trace_block(
+        &self,
+        block: &Block,
+        store: Store,
+        state_root: Option<H256>,
+        tracer: Tracer,
+    ) -> Result<Vec<(ExecutionResult, Trace)>, EvmError>;
+
+    /// Executes a single transaction with the given tracer attached without committing its result.
+    /// The transaction runs on the state with the given root, or the current one if none is given
+    fn trace_call(
+        &self,
+        tx: &GenericTransaction,
+        header: &BlockHeader,
+        store: Store,
+        state_root: Option<H256>,
+        spec_id: SpecId,
+        tracer: Tracer,
+    ) -> Result<(ExecutionResult, Trace), EvmError>;
+
+    /// Starts a simulation of blocks on top of the state with the given root, or the current one if none is given
+    fn simulation(
+        &self,
+        store: Store,
+        state_root: Option<H256>,
+    ) -> Result<Box<dyn BlockSimulation>, EvmError>;
+}
+
+/// State of a simulation of several blocks, in which each transaction is executed on top of the changes made by the
+/// previous ones. None of the changes are written to the store
+pub trait BlockSimulation {
+    /// Applies the given overrides on top of the changes made so far
+    fn apply_state_override(&mut self, overrides: &StateOverride) -> Result<(), EvmError>;
+
+    /// Returns the nonce of the account, including the changes made so far
+    fn account_nonce(&mut self, address: Address) -> Result<u64, EvmError>;
+
+    /// Executes a single transaction as part of the block with the given header and keeps its changes.
+    /// Unless validating, its nonce is not checked and no fees are charged if it doesn't set any prices
+    fn simulate_tx(
+        &mut self,
+        tx: &GenericTransaction,
+        header: &BlockHeader,
+        spec_id: SpecId,
+        validation: bool,
+        trace_transfers: bool,
+    ) -> Result<ExecutionResult, EvmError>;
+
+    /// Returns every change made since the simulation started
+    fn state_transitions(&mut self) -> Result<StateDiff, EvmError>;
+
+    /// Makes the BLOCKHASH opcode return the given hash for the simulated block with the given number
+    fn add_block_hash(&mut self, block_number: BlockNumber, block_hash: H256);
+}
+
+impl BlockSimulation for EvmState {
+    fn apply_state_override(&mut self, overrides: &StateOverride) -> Result<(), EvmError> {
+        EvmState::apply_state_override(self, overrides)
+    }
+
+    fn account_nonce(&mut self, address: Address) -> Result<u64, EvmError> {
+        EvmState::account_nonce(self, address)
+    }
+
+    fn simulate_tx(
+        &mut self,
+        tx: &GenericTransaction,
+        header: &BlockHeader,
+        spec_id: SpecId,
+        validation: bool,
+        trace_transfers: bool,
+    ) -> Result<ExecutionResult, EvmError> {
+        simulate_tx_and_commit(tx, header, self, spec_id, validation, trace_transfers)
+    }
+
+    fn state_transitions(&mut self) -> Result<StateDiff, EvmError> {
+        Ok(get_accumulated_state_transitions(self)?)
+    }
+
+    fn add_block_hash(&mut self, block_number: BlockNumber, block_hash: H256) {
+        EvmState::add_block_hash(self, block_number, block_hash)
+    }
+}
+
+/// Backends that can be selected when starting the client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvmEngine {
+    #[default]
+    Revm,
+    /// Executes blocks on revm twice, comparing the results, to check the differential backend itself until another
+    /// engine is available
+    #[cfg(feature = "differential")]
+    Differential,
+}
+
+impl EvmEngine {
+    pub fn backend(&self) -> Arc<dyn VmBackend> {
+        match self {
+            EvmEngine::Revm => Arc::new(RevmBackend),
+            #[cfg(feature = "differential")]
+            EvmEngine::Differential => Arc::new(DifferentialBackend {
+                reference: Arc::new(RevmBackend),
+                candidate: Arc::new(RevmBackend),
+            }),
+        }
+    }
+}
+
+impl FromStr for EvmEngine {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "revm" => Ok(EvmEngine::Revm),
+            #[cfg(feature = "differential")]
+            "differential" => Ok(EvmEngine::Differential),
+            other => Err(format!("unknown EVM engine: {other}")),
+        }
+    }
+}
+
+/// Selects the engine used by the whole process. Can only be done once, before executing any block
+pub fn select_engine(engine: EvmEngine) -> Result<(), EvmError> {
+    SELECTED_ENGINE
+        .set(engine)
+        .map_err(|_| EvmError::Custom("EVM engine was already selected".to_string()))
+}
+
+/// Returns the backend of the engine selected for the process, revm if none was selected
+pub fn selected_backend() -> Arc<dyn VmBackend> {
+    SELECTED_ENGINE.get().copied().unwrap_or_default().backend()
+}
+
+/// Backend running on top of revm
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RevmBackend;
+
+impl RevmBackend {
+    fn state(store: Store, state_root: Option<H256>) -> EvmState {
+        match state_root {
+            Some(state_root) => evm_state_at(store, state_root),
+            None => evm_state(store),
+        }
+    }
+}
+
+impl VmBackend for RevmBackend {
+    fn name(&self) -> &'static str {
+        "revm"
+    }
+
+    fn execute_block(
+        &self,
+        block: &Block,
+        store: Store,
+        state_root: H256,
+    ) -> Result<(Vec<Receipt>, StateDiff), EvmError> {
+        // The current state is read directly instead of going through the trie whenever possible
+        let state_root = (store.world_state_root()? != state_root).then_some(state_root);
+        let mut state = Self::state(store, state_root);
+        let receipts = execute_block(block, &mut state)?;
+        let state_diff = get_state_transitions(&mut state)?;
+        Ok((receipts, state_diff))
+    }
+
+    fn simulate_tx(
+        &self,
+        tx: &GenericTransaction,
+        header: &BlockHeader,
+        store: Store,
+        state_root: Option<H256>,
+        spec_id: SpecId,
+        state_override: &StateOverride,
+    ) -> Result<ExecutionResult, EvmError> {
+        let mut state = Self::state(store, state_root).with_overrides(state_override.clone());
+        simulate_tx_from_generic(tx, header, &mut state, spec_id)
+    }
+
+    fn create_access_list(
+        &self,
+        tx: &GenericTransaction,
+        header: &BlockHeader,
+        store: Store,
+        state_root: Option<H256>,
+        spec_id: SpecId,
+    ) -> Result<(ExecutionResult, AccessList), EvmError> {
+        create_access_list(tx, header, &mut Self::state(store, state_root), spec_id)
+    }
+
+    fn trace_tx(
+        &self,
+        block: &Block,
+        tx_index: usize,
+        store: Store,
+        state_root: Option<H256>,
+        tracer: Tracer,
+    ) -> Result<(ExecutionResult, Trace), EvmError> {
+        let transaction = block
+            .body
+            .transactions
+            .get(tx_index)
+            .ok_or_else(|| EvmError::Custom(format!("Block has no transaction {tx_index}")))?;
+        let mut state = Self::state(store, state_root);
+        let spec_id = execute_block_until(block, tx_index, &mut state)?;
+        trace_tx(transaction, &block.header, &mut state, spec_id, tracer)
+    }
+
+    fn trace_block(
+        &self,
+        block: &Block,
+        store: Store,
+        state_root: Option<H256>,
+        tracer: Tracer,
+    ) -> Result<Vec<(ExecutionResult, Trace)>, EvmError> {
+        let mut state = Self::state(store, state_root);
+        let spec_id = execute_block_until(block, 0, &mut state)?;
+        block
+            .body
+            .transactions
+            .iter()
+            .map(|transaction| trace_tx(transaction, &block.header, &mut state, spec_id, tracer))
+            .collect()
+    }
+
+    fn trace_call(
+        &self,
+        tx: &GenericTransaction,
+        header: &BlockHeader,
+        store: Store,
+        state_root: Option<H256>,
+        spec_id: SpecId,
+        tracer: Tracer,
+    ) -> Result<(ExecutionResult, Trace), EvmError> {
+        let mut state = Self::state(store, state_root);
+        trace_tx_from_generic(tx, header, &mut state, spec_id, tracer)
+    }
+
+    fn simulation(
+        &self,
+        store: Store,
+        state_root: Option<H256>,
+    ) -> Result<Box<dyn BlockSimulation>, EvmError> {
+        Ok(Box::new(Self::state(store, state_root)))
+    }
+}
+
+/// Backend executing blocks on two backends and failing if their results differ, for differential testing.
+/// Transactions are only simulated and traced on the reference backend
+#[derive(Debug)]
+pub struct DifferentialBackend {
+    pub reference: Arc<dyn VmBackend>,
+    pub candidate: Arc<dyn VmBackend>,
+}
+
+impl VmBackend for DifferentialBackend {
+    fn name(&self) -> &'static str {
+        "differential"
+    }
+
+    fn execute_block(
+        &self,
+        block: &Block,
+        store: Store,
+        state_root: H256,
+    ) -> Result<(Vec<Receipt>, StateDiff), EvmError> {
+        let expected = self
+            .reference
+            .execute_block(block, store.clone(), state_root)?;
+        let result = self.candidate.execute_block(block, store, state_root)?;
+        if result.0 != expected.0 {
+            return Err(EvmError::Custom(format!(
+                "{} and {} produced different receipts for block {}",
+                self.reference.name(),
+                self.candidate.name(),
+                block.header.number
+            )));
+        }
+        if result.1 != expected.1 {
+            return Err(EvmError::Custom(format!(
+                "{} and {} produced different state changes for block {}",
+                self.reference.name(),
+                self.candidate.name(),
+                block.header.number
+            )));
+        }
+        Ok(expected)
+    }
+
+    fn simulate_tx(
+        &self,
+        tx: &GenericTransaction,
+        header: &BlockHeader,
+        store: Store,
+        state_root: Option<H256>,
+        spec_id: SpecId,
+        state_override: &StateOverride,
+    ) -> Result<ExecutionResult, EvmError> {
+        self.reference
+            .simulate_tx(tx, header, store, state_root, spec_id, state_override)
+    }
+
+    fn create_access_list(
+        &self,
+        tx: &GenericTransaction,
+        header: &BlockHeader,
+        store: Store,
+        state_root: Option<H256>,
+        spec_id: SpecId,
+    ) -> Result<(ExecutionResult, AccessList), EvmError> {
+        self.reference
+            .create_access_list(tx, header, store, state_root, spec_id)
+    }
+
+    fn trace_tx(
+        &self,
+        block: &Block,
+        tx_index: usize,
+        store: Store,
+        state_root: Option<H256>,
+        tracer: Tracer,
+    ) -> Result<(ExecutionResult, Trace), EvmError> {
+        self.reference
+            .trace_tx(block, tx_index, store, state_root, tracer)
+    }
+
+    fn trace_block(
+        &self,
+        block: &Block,
+        store: Store,
+        state_root: Option<H256>,
+        tracer: Tracer,
+    ) -> Result<Vec<(ExecutionResult, Trace)>, EvmError> {
+        self.reference.trace_block(block, store, state_root, tracer)
+    }
+
+    fn trace_call(
+        &self,
+        tx: &GenericTransaction,
+        header: &BlockHeader,
+        store: Store,
+        state_root: Option<H256>,
+        spec_id: SpecId,
+        tracer: Tracer,
+    ) -> Result<(ExecutionResult, Trace), EvmError> {
+        self.reference
+            .trace_call(tx, header, store, state_root, spec_id, tracer)
+    }
+
+    fn simulation(
+        &self,
+        store: Store,
+        state_root: Option<H256>,
+    ) -> Result<Box<dyn BlockSimulation>, EvmError> {
+        self.reference.simulation(store, state_root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_rust_core::types::{BlockBody, TxType};
+    use ethereum_rust_storage::{EngineType, EMPTY_TRIE_HASH};
+
+    use super::*;
+
+    /// Backend executing every block as if it had a single transaction using the given amount of gas
+    #[derive(Debug)]
+    struct FixedGasBackend(u64);
+
+    fn unsupported() -> EvmError {
+        EvmError::Custom("Only block execution is supported".to_string())
+    }
+
+    impl VmBackend for FixedGasBackend {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        fn execute_block(
+            &self,
+            _block: &Block,
+            _store: Store,
+            _state_root: H256,
+        ) -> Result<(Vec<Receipt>, StateDiff), EvmError> {
+            let receipt = Receipt::new(TxType::EIP1559, true, self.0, vec![]);
+            Ok((vec![receipt], StateDiff::default()))
+        }
+
+        fn simulate_tx(
+            &self,
+            _tx: &GenericTransaction,
+            _header: &BlockHeader,
+            _store: Store,
+            _state_root: Option<H256>,
+            _spec_id: SpecId,
+            _state_override: &StateOverride,
+        ) -> Result<ExecutionResult, EvmError> {
+            Err(unsupported())
+        }
+
+        fn create_access_list(
+            &self,
+            _tx: &GenericTransaction,
+            _header: &BlockHeader,
+            _store: Store,
+            _state_root: Option<H256>,
+            _spec_id: SpecId,
+        ) -> Result<(ExecutionResult, AccessList), EvmError> {
+            Err(unsupported())
+        }
+
+        fn trace_tx(
+            &self,
+            _block: &Block,
+            _tx_index: usize,
+            _store: Store,
+            _state_root: Option<H256>,
+            _tracer: Tracer,
+        ) -> Result<(ExecutionResult, Trace), EvmError> {
+            Err(unsupported())
+        }
+
+        fn trace_block(
+            &self,
+            _block: &Block,
+            _store: Store,
+            _state_root: Option<H256>,
+            _tracer: Tracer,
+        ) -> Result<Vec<(ExecutionResult, Trace)>, EvmError> {
+            Err(unsupported())
+        }
+
+        fn trace_call(
+            &self,
+            _tx: &GenericTransaction,
+            _header: &BlockHeader,
+            _store: Store,
+            _state_root: Option<H256>,
+            _spec_id: SpecId,
+            _tracer: Tracer,
+        ) -> Result<(ExecutionResult, Trace), EvmError> {
+            Err(unsupported())
+        }
+
+        fn simulation(
+            &self,
+            _store: Store,
+            _state_root: Option<H256>,
+        ) -> Result<Box<dyn BlockSimulation>, EvmError> {
+            Err(unsupported())
+        }
+    }
+
+    #[test]
+    fn differential_backend_compares_receipts() {
+        let store = Store::new("temp.db", EngineType::InMemory).unwrap();
+        let block = Block {
+            header: BlockHeader::default(),
+            body: BlockBody::empty(),
+        };
+
+        let matching = DifferentialBackend {
+            reference: Arc::new(FixedGasBackend(21_000)),
+            candidate: Arc::new(FixedGasBackend(21_000)),
+        };
+        let (receipts, _) = matching
+            .execute_block(&block, store.clone(), *EMPTY_TRIE_HASH)
+            .unwrap();
+        assert_eq!(receipts[0].cumulative_gas_used, 21_000);
+
+        let diverging = DifferentialBackend {
+            reference: Arc::new(FixedGasBackend(21_000)),
+            candidate: Arc::new(FixedGasBackend(22_000)),
+        };
+        assert!(diverging
+            .execute_block(&block, store, *EMPTY_TRIE_HASH)
+            .is_err());
+    }
+
+    #[test]
+    fn parse_engine() {
+        assert_eq!(EvmEngine::from_str("revm"), Ok(EvmEngine::Revm));
+        assert!(EvmEngine::from_str("evmone").is_err());
+        #[cfg(feature = "differential")]
+        assert_eq!(
+            EvmEngine::from_str("differential"),
+            Ok(EvmEngine::Differential)
+        );
+    }
+}
@@ -0,0 +1,328 @@
+use bytes::Bytes;
+use ethereum_rust_core::{Address, H256, U256};
+use revm::{
+    interpreter::{
+        CallInputs, CallOutcome, CallScheme, CreateInputs, CreateOutcome, CreateScheme,
+        InstructionResult, Interpreter,
+    },
+    primitives::{Address as RevmAddress, Log as RevmLog, U256 as RevmU256},
+    Database, EvmContext, Inspector,
+};
+
+/// Selector of the `Error(string)` function, used to encode revert reasons
+const REVERT_REASON_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CallTracerConfig {
+    /// Only trace the top level call, leaving out its inner calls
+    pub only_top_call: bool,
+    /// Collect the logs emitted by each call
+    pub with_log: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallKind {
+    Call,
+    StaticCall,
+    DelegateCall,
+    CallCode,
+    Create,
+    Create2,
+    SelfDestruct,
+}
+
+/// Log emitted by a call, along with the amount of inner calls that were made before it
+#[derive(Debug, Clone)]
+pub struct CallLog {
+    pub address: Address,
+    pub topics: Vec<H256>,
+    pub data: Bytes,
+    pub position: u64,
+}
+
+/// Call made during the execution of a transaction, along with the calls it made
+#[derive(Debug, Clone)]
+pub struct CallFrame {
+    pub kind: CallKind,
+    pub from: Address,
+    pub to: Option<Address>,
+    /// Not set for calls that can't transfer value
+    pub value: Option<U256>,
+    pub gas: u64,
+    pub gas_used: u64,
+    pub input: Bytes,
+    pub output: Bytes,
+    pub error: Option<String>,
+    pub revert_reason: Option<String>,
+    pub logs: Vec<CallLog>,
+    pub calls: Vec<CallFrame>,
+}
+
+/// Inspector building the tree of calls made by a transaction, as geth's callTracer does
+#[derive(Debug, Default)]
+pub struct CallTracer {
+    config: CallTracerConfig,
+    /// Calls that have started but not yet finished, from outermost to innermost
+    open_frames: Vec<CallFrame>,
+    top_frame: Option<CallFrame>,
+}
+
+impl CallKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CallKind::Call => "CALL",
+            CallKind::StaticCall => "STATICCALL",
+            CallKind::DelegateCall => "DELEGATECALL",
+            CallKind::CallCode => "CALLCODE",
+            CallKind::Create => "CREATE",
+            CallKind::Create2 => "CREATE2",
+            CallKind::SelfDestruct => "SELFDESTRUCT",
+        }
+    }
+}
+
+impl CallTracer {
+    pub fn new(config: CallTracerConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the top level call, which is given the gas limit and gas used of the whole transaction
+    pub fn into_frame(self, gas_limit: u64, gas_used: u64) -> Option<CallFrame> {
+        self.top_frame.map(|mut frame| {
+            frame.gas = gas_limit;
+            frame.gas_used = gas_used;
+            frame
+        })
+    }
+
+    fn open_frame(&mut self, frame: CallFrame) {
+        self.open_frames.push(frame);
+    }
+
+    fn close_frame(
+        &mut self,
+        result: InstructionResult,
+        output: &[u8],
+        gas_used: u64,
+        to: Option<Address>,
+    ) {
+        let Some(mut frame) = self.open_frames.pop() else {
+            return;
+        };
+        frame.gas_used = gas_used;
+        frame.output = Bytes::copy_from_slice(output);
+        if to.is_some() {
+            frame.to = to;
+        }
+        frame.error = frame_error(result);
+        if result == InstructionResult::Revert {
+            frame.revert_reason = revert_reason(output);
+        }
+        // Logs of failed calls are reverted along with them
+        if frame.error.is_some() {
+            clear_logs(&mut frame);
+        }
+        match self.open_frames.last_mut() {
+            Some(parent) if !self.config.only_top_call => parent.calls.push(frame),
+            Some(_) => {}
+            None => self.top_frame = Some(frame),
+        }
+    }
+}
+
+impl<DB: Database> Inspector<DB> for CallTracer {
+    fn log(&mut self, _interp: &mut Interpreter, _context: &mut EvmContext<DB>, log: &RevmLog) {
+        if !self.config.with_log {
+            return;
+        }
+        let Some(frame) = self.open_frames.last_mut() else {
+            return;
+        };
+        frame.logs.push(CallLog {
+            address: address(log.address),
+            topics: log
+                .topics()
+                .iter()
+                .map(|topic| H256::from_slice(topic.as_slice()))
+                .collect(),
+            data: log.data.data.0.clone(),
+            position: frame.calls.len() as u64,
+        });
+    }
+
+    fn call(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        inputs: &mut CallInputs,
+    ) -> Option<CallOutcome> {
+        let kind = match inputs.scheme {
+            CallScheme::StaticCall => CallKind::StaticCall,
+            CallScheme::DelegateCall => CallKind::DelegateCall,
+            CallScheme::CallCode => CallKind::CallCode,
+            _ => CallKind::Call,
+        };
+        let value = match kind {
+            CallKind::StaticCall | CallKind::DelegateCall => None,
+            _ => Some(u256(inputs.call_value())),
+        };
+        self.open_frame(CallFrame {
+            kind,
+            from: address(inputs.caller),
+            to: Some(address(inputs.target_address)),
+            value,
+            gas: inputs.gas_limit,
+            gas_used: 0,
+            input: inputs.input.0.clone(),
+            output: Bytes::new(),
+            error: None,
+            revert_reason: None,
+            logs: vec![],
+            calls: vec![],
+        });
+        None
+    }
+
+    fn call_end(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        _inputs: &CallInputs,
+        outcome: CallOutcome,
+    ) -> CallOutcome {
+        self.close_frame(
+            outcome.result.result,
+            &outcome.result.output,
+            outcome.result.gas.spent(),
+            None,
+        );
+        outcome
+    }
+
+    fn create(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        inputs: &mut CreateInputs,
+    ) -> Option<CreateOutcome> {
+        let kind = match inputs.scheme {
+            CreateScheme::Create => CallKind::Create,
+            CreateScheme::Create2 { .. } => CallKind::Create2,
+        };
+        self.open_frame(CallFrame {
+            kind,
+            from: address(inputs.caller),
+            to: None,
+            value: Some(u256(inputs.value)),
+            gas: inputs.gas_limit,
+            gas_used: 0,
+            input: inputs.init_code.0.clone(),
+            output: Bytes::new(),
+            error: None,
+            revert_reason: None,
+            logs: vec![],
+            calls: vec![],
+        });
+        None
+    }
+
+    fn create_end(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        _inputs: &CreateInputs,
+        outcome: CreateOutcome,
+    ) -> CreateOutcome {
+        self.close_frame(
+            outcome.result.result,
+            &outcome.result.output,
+            outcome.result.gas.spent(),
+            outcome.address.map(address),
+        );
+        outcome
+    }
+
+    fn selfdestruct(&mut self, contract: RevmAddress, target: RevmAddress, value: RevmU256) {
+        if self.config.only_top_call {
+            return;
+        }
+        let Some(parent) = self.open_frames.last_mut() else {
+            return;
+        };
+        parent.calls.push(CallFrame {
+            kind: CallKind::SelfDestruct,
+            from: address(contract),
+            to: Some(address(target)),
+            value: Some(u256(value)),
+            gas: 0,
+            gas_used: 0,
+            input: Bytes::new(),
+            output: Bytes::new(),
+            error: None,
+            revert_reason: None,
+            logs: vec![],
+            calls: vec![],
+        });
+    }
+}
+
+/// Describes why a call failed the way geth does
+fn frame_error(result: InstructionResult) -> Option<String> {
+    match result {
+        result if result.is_ok() => None,
+        InstructionResult::Revert => Some("execution reverted".to_string()),
+        InstructionResult::OutOfGas
+        | InstructionResult::MemoryOOG
+        | InstructionResult::MemoryLimitOOG
+        | InstructionResult::PrecompileOOG
+        | InstructionResult::InvalidOperandOOG => Some("out of gas".to_string()),
+        other => Some(format!("{other:?}")),
+    }
+}
+
+/// Decodes the message of a revert caused by `revert(string)` or a failed `require`
+fn revert_reason(output: &[u8]) -> Option<String> {
+    let data = output.strip_prefix(&REVERT_REASON_SELECTOR)?;
+    // The string is encoded as its offset, its length and its contents
+    let offset = usize::try_from(RevmU256::try_from_be_slice(data.get(..32)?)?).ok()?;
+    let length_end = offset.checked_add(32)?;
+    let length =
+        usize::try_from(RevmU256::try_from_be_slice(data.get(offset..length_end)?)?).ok()?;
+    let reason = data.get(length_end..length_end.checked_add(length)?)?;
+    String::from_utf8(reason.to_vec()).ok()
+}
+
+fn clear_logs(frame: &mut CallFrame) {
+    frame.logs.clear();
+    frame.calls.iter_mut().for_each(clear_logs);
+}
+
+fn address(address: RevmAddress) -> Address {
+    Address::from_slice(address.0.as_slice())
+}
+
+fn u256(value: RevmU256) -> U256 {
+    U256(value.into_limbs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_revert_reason() {
+        // Output of `revert("Not enough Ether provided.")`
+        let output = hex::decode(
+            "08c379a0\
+             0000000000000000000000000000000000000000000000000000000000000020\
+             000000000000000000000000000000000000000000000000000000000000001a\
+             4e6f7420656e6f7567682045746865722070726f76696465642e000000000000",
+        )
+        .unwrap();
+        assert_eq!(
+            revert_reason(&output),
+            Some("Not enough Ether provided.".to_string())
+        );
+        assert_eq!(revert_reason(&output[..40]), None);
+        assert_eq!(revert_reason(&[0xde, 0xad, 0xbe, 0xef]), None);
+    }
+}
@@ -1,25 +1,79 @@
-use ethereum_rust_core::{Address as CoreAddress, H256 as CoreH256};
+use std::collections::HashMap;
+
+use ethereum_rust_core::{
+    types::{AccountInfo, AccountOverride, BlockNumber, StateOverride},
+    Address as CoreAddress, H256 as CoreH256, U256 as CoreU256,
+};
 use ethereum_rust_storage::{error::StoreError, Store};
-use revm::primitives::{
-    AccountInfo as RevmAccountInfo, Address as RevmAddress, Bytecode as RevmBytecode,
-    Bytes as RevmBytes, B256 as RevmB256, U256 as RevmU256,
+use revm::{
+    primitives::{
+        AccountInfo as RevmAccountInfo, Address as RevmAddress, Bytecode as RevmBytecode,
+        Bytes as RevmBytes, B256 as RevmB256, U256 as RevmU256,
+    },
+    Database,
 };
 
-pub struct StoreWrapper(pub Store);
+/// Exposes the state held by the store to the EVM.
+/// When a state root is given, the state is read from the world state with that root instead of the current one
+pub struct StoreWrapper {
+    pub store: Store,
+    pub state_root: Option<CoreH256>,
+    /// Hashes of blocks that are not part of the canonical chain, such as the ancestors of a block executed on top
+    /// of a side branch. They take precedence over the canonical blocks with the same number
+    pub block_hashes: HashMap<BlockNumber, CoreH256>,
+}
+
+impl StoreWrapper {
+    pub(crate) fn get_account_info(
+        &self,
+        address: CoreAddress,
+    ) -> Result<Option<AccountInfo>, StoreError> {
+        match self.state_root {
+            Some(state_root) => self
+                .store
+                .get_account_info_by_state_root(state_root, address),
+            None => self.store.get_account_info(address),
+        }
+    }
+
+    pub(crate) fn get_storage_at(
+        &self,
+        address: CoreAddress,
+        storage_key: CoreH256,
+    ) -> Result<Option<CoreU256>, StoreError> {
+        match self.state_root {
+            Some(state_root) => {
+                self.store
+                    .get_storage_by_state_root(state_root, address, storage_key)
+            }
+            None => self.store.get_storage_at(address, storage_key),
+        }
+    }
+
+    /// Returns every storage slot of the account
+    pub(crate) fn get_account_storage(
+        &self,
+        address: CoreAddress,
+    ) -> Result<Vec<(CoreH256, CoreU256)>, StoreError> {
+        match self.state_root {
+            Some(state_root) => self
+                .store
+                .get_account_storage_by_state_root(state_root, address),
+            None => Ok(self.store.account_storage_iter(address)?.collect()),
+        }
+    }
+}
 
-impl revm::Database for StoreWrapper {
+impl Database for StoreWrapper {
     type Error = StoreError;
 
     fn basic(&mut self, address: RevmAddress) -> Result<Option<RevmAccountInfo>, Self::Error> {
-        let acc_info = match self
-            .0
-            .get_account_info(CoreAddress::from(address.0.as_ref()))?
-        {
+        let acc_info = match self.get_account_info(CoreAddress::from(address.0.as_ref()))? {
             None => return Ok(None),
             Some(acc_info) => acc_info,
         };
         let code = self
-            .0
+            .store
             .get_account_code(acc_info.code_hash)?
             .map(|b| RevmBytecode::new_raw(RevmBytes(b)));
 
@@ -32,7 +86,7 @@ impl revm::Database for StoreWrapper {
     }
 
     fn code_by_hash(&mut self, code_hash: RevmB256) -> Result<RevmBytecode, Self::Error> {
-        self.0
+        self.store
             .get_account_code(CoreH256::from(code_hash.as_ref()))?
             .map(|b| RevmBytecode::new_raw(RevmBytes(b)))
             .ok_or_else(|| StoreError::Custom(format!("No code for hash {code_hash}")))
@@ -40,7 +94,6 @@ impl revm::Database for StoreWrapper {
 
     fn storage(&mut self, address: RevmAddress, index: RevmU256) -> Result<RevmU256, Self::Error> {
         Ok(self
-            .0
             .get_storage_at(
                 CoreAddress::from(address.0.as_ref()),
                 CoreH256::from(index.to_be_bytes()),
@@ -50,9 +103,89 @@ impl revm::Database for StoreWrapper {
     }
 
     fn block_hash(&mut self, number: RevmU256) -> Result<RevmB256, Self::Error> {
-        self.0
+        if let Some(block_hash) = self.block_hashes.get(&number.to()) {
+            return Ok(RevmB256::from_slice(&block_hash.0));
+        }
+        self.store
             .get_block_header(number.to())?
             .map(|header| RevmB256::from_slice(&header.compute_block_hash().0))
             .ok_or_else(|| StoreError::Custom(format!("Block {number} not found")))
     }
 }
+
+/// Overlays the state overrides given to a simulated call on top of the state held by the store,
+/// so the store itself is never modified
+pub struct OverlayDb {
+    pub inner: StoreWrapper,
+    pub overrides: StateOverride,
+}
+
+impl Database for OverlayDb {
+    type Error = StoreError;
+
+    fn basic(&mut self, address: RevmAddress) -> Result<Option<RevmAccountInfo>, Self::Error> {
+        let acc_info = self.inner.basic(address)?;
+        let Some(account_override) = self.overrides.get(&CoreAddress::from(address.0.as_ref()))
+        else {
+            return Ok(acc_info);
+        };
+        let mut acc_info = acc_info.unwrap_or_default();
+        override_account_info(&mut acc_info, account_override);
+        Ok(Some(acc_info))
+    }
+
+    fn code_by_hash(&mut self, code_hash: RevmB256) -> Result<RevmBytecode, Self::Error> {
+        self.inner.code_by_hash(code_hash)
+    }
+
+    fn storage(&mut self, address: RevmAddress, index: RevmU256) -> Result<RevmU256, Self::Error> {
+        if let Some((slots, replaces_storage)) = self
+            .overrides
+            .get(&CoreAddress::from(address.0.as_ref()))
+            .and_then(overridden_storage)
+        {
+            let key = CoreH256::from(index.to_be_bytes());
+            if let Some(value) = slots.get(&key) {
+                return Ok(RevmU256::from_be_bytes(value.0));
+            }
+            // A full storage override leaves every slot it doesn't mention empty
+            if replaces_storage {
+                return Ok(RevmU256::ZERO);
+            }
+        }
+        self.inner.storage(address, index)
+    }
+
+    fn block_hash(&mut self, number: RevmU256) -> Result<RevmB256, Self::Error> {
+        self.inner.block_hash(number)
+    }
+}
+
+/// Replaces the fields of the account info set by the override
+pub(crate) fn override_account_info(
+    info: &mut RevmAccountInfo,
+    account_override: &AccountOverride,
+) {
+    if let Some(balance) = account_override.balance {
+        info.balance = RevmU256::from_limbs(balance.0);
+    }
+    if let Some(nonce) = account_override.nonce {
+        info.nonce = nonce;
+    }
+    if let Some(code) = &account_override.code {
+        let code = RevmBytecode::new_raw(RevmBytes(code.clone()));
+        info.code_hash = code.hash_slow();
+        info.code = Some(code);
+    }
+}
+
+/// Returns the storage slots set by the override, along with whether they replace the account's whole storage
+pub(crate) fn overridden_storage(
+    account_override: &AccountOverride,
+) -> Option<(&HashMap<CoreH256, CoreH256>, bool)> {
+    match (&account_override.state, &account_override.state_diff) {
+        (Some(state), _) => Some((state, true)),
+        (None, Some(state_diff)) => Some((state_diff, false)),
+        (None, None) => None,
+    }
+}
@@ -1,27 +1,40 @@
+mod backend;
+mod call_tracer;
 mod db;
 mod errors;
 mod execution_result;
+mod kzg;
+mod prestate_tracer;
+mod struct_logger;
+mod transfer_logger;
 
-use std::cmp::min;
+use std::{cmp::min, collections::HashMap};
 
-use db::StoreWrapper;
+use call_tracer::CallTracer;
+use db::{overridden_storage, override_account_info, OverlayDb, StoreWrapper};
+use prestate_tracer::PrestateTracer;
+use struct_logger::StructLogger;
+use transfer_logger::TransferLogger;
 
 use ethereum_rust_core::{
     types::{
-        AccountInfo, Block, BlockHeader, ForkId, GenericTransaction, Receipt, Transaction, TxKind,
-        Withdrawal, GWEI_TO_WEI, INITIAL_BASE_FEE,
+        AccountInfo, Block, BlockHeader, BlockNumber, ForkId, GenericTransaction, Receipt,
+        StateOverride, Transaction, TxKind, Withdrawal, GWEI_TO_WEI, INITIAL_BASE_FEE,
     },
     Address, BigEndianHash, H256, U256,
 };
-use ethereum_rust_storage::{error::StoreError, Store};
+use ethereum_rust_storage::{error::StoreError, AccountDiff, StateDiff, StorageSlotDiff, Store};
 use lazy_static::lazy_static;
 use revm::{
-    db::states::bundle_state::BundleRetention,
+    db::states::bundle_state::{BundleRetention, BundleState},
     inspector_handle_register,
-    inspectors::TracerEip3155,
+    inspectors::NoOpInspector,
     precompile::{PrecompileSpecId, Precompiles},
-    primitives::{BlobExcessGasAndPrice, BlockEnv, TxEnv, B256, U256 as RevmU256},
-    Database, DatabaseCommit, Evm,
+    primitives::{
+        Account, BlobExcessGasAndPrice, BlockEnv, EvmStorageSlot, ResultAndState, TxEnv, B256,
+        U256 as RevmU256,
+    },
+    Database, DatabaseCommit, Evm, Inspector,
 };
 use revm_inspectors::access_list::AccessListInspector;
 // Rename imported types for clarity
@@ -30,20 +43,104 @@ use revm_primitives::{
     TxKind as RevmTxKind,
 };
 // Export needed types
+pub use backend::{
+    select_engine, selected_backend, BlockSimulation, DifferentialBackend, EvmEngine, RevmBackend,
+    VmBackend,
+};
+pub use call_tracer::{CallFrame, CallKind, CallLog, CallTracerConfig};
 pub use errors::EvmError;
 pub use execution_result::*;
+pub use kzg::verify_blobs_bundle;
+pub use prestate_tracer::{PrestateAccount, PrestateTrace, PrestateTracerConfig};
 pub use revm::primitives::{Address as RevmAddress, SpecId};
+pub use struct_logger::{StructLog, StructLoggerConfig};
 
-type AccessList = Vec<(Address, Vec<H256>)>;
+/// Accounts accessed by a transaction, along with the storage slots accessed on each of them
+pub type AccessList = Vec<(Address, Vec<H256>)>;
+
+/// Tracer attached to the EVM when tracing a transaction, along with its options
+#[derive(Debug, Clone, Copy)]
+pub enum Tracer {
+    StructLogger(StructLoggerConfig),
+    CallTracer(CallTracerConfig),
+    PrestateTracer(PrestateTracerConfig),
+}
+
+/// Output of each of the tracers
+#[derive(Debug, Clone)]
+pub enum Trace {
+    StructLogs(Vec<StructLog>),
+    Call(CallFrame),
+    Prestate(PrestateTrace),
+}
 
 /// State used when running the EVM
 // Encapsulates state behaviour to be agnostic to the evm implementation for crate users
-pub struct EvmState(revm::db::State<StoreWrapper>);
+pub struct EvmState(revm::db::State<OverlayDb>);
 
 impl EvmState {
     /// Get a reference to inner `Store` database
     pub fn database(&self) -> &Store {
-        &self.0.database.0
+        &self.0.database.inner.store
+    }
+
+    /// Makes the state read the overridden accounts from the given overrides instead of the store.
+    /// Should be called before running any transaction, as the state built so far is discarded
+    pub fn with_overrides(self, overrides: StateOverride) -> Self {
+        build_evm_state(OverlayDb {
+            inner: self.0.database.inner,
+            overrides,
+        })
+    }
+
+    /// Makes the BLOCKHASH opcode return the given hash for the block with the given number, such as the one of a
+    /// simulated block
+    pub fn add_block_hash(&mut self, block_number: BlockNumber, block_hash: H256) {
+        self.0
+            .database
+            .inner
+            .block_hashes
+            .insert(block_number, block_hash);
+    }
+
+    /// Returns the nonce of the account, including the changes made by the transactions executed so far
+    pub fn account_nonce(&mut self, address: Address) -> Result<u64, EvmError> {
+        let info = self.0.basic(RevmAddress(address.0.into()))?;
+        Ok(info.map(|info| info.nonce).unwrap_or_default())
+    }
+
+    /// Applies the given overrides on top of the state, including the changes made by the transactions executed so far.
+    /// Overrides are interpreted as [OverlayDb] does, but are committed to the state so they are part of its changes
+    pub fn apply_state_override(&mut self, overrides: &StateOverride) -> Result<(), EvmError> {
+        let mut changes = HashMap::new();
+        for (address, account_override) in overrides {
+            let address = RevmAddress(address.0.into());
+            let mut info = self.0.basic(address)?.unwrap_or_default();
+            override_account_info(&mut info, account_override);
+            let mut account = Account::from(info);
+            account.mark_touch();
+            if let Some((slots, replaces_storage)) = overridden_storage(account_override) {
+                // Replacing the whole storage is done by recreating the account, which wipes its previous storage
+                if replaces_storage {
+                    account.mark_created();
+                }
+                for (key, value) in slots {
+                    let key = RevmU256::from_be_bytes(key.0);
+                    let original = if replaces_storage {
+                        RevmU256::ZERO
+                    } else {
+                        self.0.storage(address, key)?
+                    };
+                    account.storage.insert(
+                        key,
+                        EvmStorageSlot::new_changed(original, RevmU256::from_be_bytes(value.0)),
+                    );
+                }
+            }
+            changes.insert(address, account);
+        }
+        self.0.commit(changes);
+        Ok(())
     }
 }
 
@@ -51,6 +148,7 @@ impl EvmState {
 pub fn execute_block(block: &Block, state: &mut EvmState) -> Result<Vec<Receipt>, EvmError> {
     let block_header = &block.header;
     let spec_id = spec_id(state.database(), block_header.timestamp)?;
+    add_branch_hashes(block_header, state)?;
     //eip 4788: execute beacon_root_contract_call before block transactions
     if block_header.parent_beacon_block_root.is_some() && spec_id == SpecId::CANCUN {
         beacon_root_contract_call(state, block_header, spec_id)?;
@@ -89,6 +187,159 @@ pub fn execute_tx(
     run_evm(tx_env, block_env, state, spec_id)
 }
 
+/// Executes the system calls and the transactions of a block that precede the one at the given index,
+/// leaving the state as it was right before executing it
+pub fn execute_block_until(
+    block: &Block,
+    tx_index: usize,
+    state: &mut EvmState,
+) -> Result<SpecId, EvmError> {
+    let block_header = &block.header;
+    let spec_id = spec_id(state.database(), block_header.timestamp)?;
+    add_branch_hashes(block_header, state)?;
+    if block_header.parent_beacon_block_root.is_some() && spec_id == SpecId::CANCUN {
+        beacon_root_contract_call(state, block_header, spec_id)?;
+    }
+    for transaction in block.body.transactions.iter().take(tx_index) {
+        execute_tx(transaction, block_header, state, spec_id)?;
+    }
+    Ok(spec_id)
+}
+
+/// Makes the BLOCKHASH opcode return the hashes of the branch the block is built on when its parent is a side block,
+/// by recording the hashes of its non-canonical ancestors
+fn add_branch_hashes(header: &BlockHeader, state: &mut EvmState) -> Result<(), StoreError> {
+    let store = state.database().clone();
+    let mut block_hash = header.parent_hash;
+    // Only the latest 256 blocks can be queried
+    for _ in 0..256 {
+        if store.get_block_number(block_hash)?.is_some() {
+            break;
+        }
+        let Some((block, _)) = store.get_side_block(block_hash)? else {
+            break;
+        };
+        state
+            .0
+            .database
+            .inner
+            .block_hashes
+            .insert(block.header.number, block_hash);
+        block_hash = block.header.parent_hash;
+    }
+    Ok(())
+}
+
+/// Executes a single tx with the given tracer attached, doesn't perform state transitions
+pub fn trace_tx(
+    tx: &Transaction,
+    header: &BlockHeader,
+    state: &mut EvmState,
+    spec_id: SpecId,
+    tracer: Tracer,
+) -> Result<(ExecutionResult, Trace), EvmError> {
+    let (tx_result, trace) =
+        run_tracer(tx_env(tx), block_env(header), state, spec_id, false, tracer)?;
+    state.0.commit(tx_result.state);
+    Ok((tx_result.result.into(), trace))
+}
+
+/// Executes a single GenericTransaction with the given tracer attached, doesn't commit the result
+pub fn trace_tx_from_generic(
+    tx: &GenericTransaction,
+    header: &BlockHeader,
+    state: &mut EvmState,
+    spec_id: SpecId,
+    tracer: Tracer,
+) -> Result<(ExecutionResult, Trace), EvmError> {
+    let tx_env = tx_env_from_generic(tx, header.base_fee_per_gas.unwrap_or(INITIAL_BASE_FEE));
+    let mut block_env = block_env(header);
+    adjust_disabled_base_fee(
+        &mut block_env,
+        tx_env.gas_price,
+        tx_env.max_fee_per_blob_gas,
+    );
+    let (tx_result, trace) = run_tracer(tx_env, block_env, state, spec_id, true, tracer)?;
+    Ok((tx_result.result.into(), trace))
+}
+
+/// Runs the transaction with the inspector of the given tracer attached, without committing the result
+fn run_tracer(
+    tx_env: TxEnv,
+    block_env: BlockEnv,
+    state: &mut EvmState,
+    spec_id: SpecId,
+    simulation: bool,
+    tracer: Tracer,
+) -> Result<(ResultAndState, Trace), EvmError> {
+    let gas_limit = tx_env.gas_limit;
+    match tracer {
+        Tracer::StructLogger(config) => {
+            let mut logger = StructLogger::new(config);
+            let tx_result = inspect_tx(tx_env, block_env, state, spec_id, simulation, &mut logger)?;
+            Ok((tx_result, Trace::StructLogs(logger.into_logs())))
+        }
+        Tracer::CallTracer(config) => {
+            let mut call_tracer = CallTracer::new(config);
+            let tx_result = inspect_tx(
+                tx_env,
+                block_env,
+                state,
+                spec_id,
+                simulation,
+                &mut call_tracer,
+            )?;
+            let frame = call_tracer
+                .into_frame(gas_limit, tx_result.result.gas_used())
+                .ok_or_else(|| EvmError::Custom("Transaction made no calls".to_string()))?;
+            Ok((tx_result, Trace::Call(frame)))
+        }
+        Tracer::PrestateTracer(config) => {
+            let mut prestate_tracer = PrestateTracer::new(config);
+            let tx_result = inspect_tx(
+                tx_env,
+                block_env,
+                state,
+                spec_id,
+                simulation,
+                &mut prestate_tracer,
+            )?;
+            let trace = prestate_tracer.into_trace(&tx_result.state);
+            Ok((tx_result, Trace::Prestate(trace)))
+        }
+    }
+}
+
+/// Runs the transaction with the given inspector attached, without committing the result.
+/// Simulated transactions are not checked against the base fee and the block's gas limit
+fn inspect_tx<I>(
+    tx_env: TxEnv,
+    block_env: BlockEnv,
+    state: &mut EvmState,
+    spec_id: SpecId,
+    simulation: bool,
+    inspector: &mut I,
+) -> Result<ResultAndState, EvmError>
+where
+    I: for<'db> Inspector<&'db mut revm::db::State<OverlayDb>>,
+{
+    let chain_config = state.database().get_chain_config()?;
+    let mut evm = Evm::builder()
+        .with_db(&mut state.0)
+        .with_block_env(block_env)
+        .with_tx_env(tx_env)
+        .with_spec_id(spec_id)
+        .modify_cfg_env(|env| {
+            env.disable_base_fee = simulation;
+            env.disable_block_gas_limit = simulation;
+            env.chain_id = chain_config.chain_id;
+        })
+        .with_external_context(inspector)
+        .append_handler_register(inspector_handle_register)
+        .build();
+    evm.transact().map_err(EvmError::from)
+}
+
 // Executes a single GenericTransaction, doesn't commit the result or perform state transitions
 pub fn simulate_tx_from_generic(
     tx: &GenericTransaction,
@@ -96,9 +347,63 @@ pub fn simulate_tx_from_generic(
     state: &mut EvmState,
     spec_id: SpecId,
 ) -> Result<ExecutionResult, EvmError> {
-    let block_env = block_env(header);
-    let tx_env = tx_env_from_generic(tx, header.base_fee_per_gas.unwrap_or(INITIAL_BASE_FEE));
-    run_without_commit(tx_env, block_env, state, spec_id)
+    let (tx_env, block_env) = simulation_env(tx, header, false);
+    let tx_result = inspect_tx(tx_env, block_env, state, spec_id, true, &mut NoOpInspector)?;
+    Ok(tx_result.result.into())
+}
+
+/// Executes a GenericTransaction as part of a simulated block and commits its result, so the following ones see its changes.
+/// Unless validating, the transaction is not checked against the base fee, the block's gas limit and the sender's nonce.
+/// Ether transfers are reported as `Transfer` logs when `trace_transfers` is set
+pub fn simulate_tx_and_commit(
+    tx: &GenericTransaction,
+    header: &BlockHeader,
+    state: &mut EvmState,
+    spec_id: SpecId,
+    validation: bool,
+    trace_transfers: bool,
+) -> Result<ExecutionResult, EvmError> {
+    let (tx_env, block_env) = simulation_env(tx, header, validation);
+    if trace_transfers {
+        let mut transfer_logger = TransferLogger::default();
+        run_and_commit(
+            tx_env,
+            block_env,
+            state,
+            spec_id,
+            !validation,
+            &mut transfer_logger,
+        )
+    } else {
+        run_and_commit(
+            tx_env,
+            block_env,
+            state,
+            spec_id,
+            !validation,
+            &mut NoOpInspector,
+        )
+    }
+}
+
+/// Builds the environment a GenericTransaction is simulated in.
+/// Unless validating, the transaction's nonce is not checked and no fees are charged if it doesn't set any prices
+fn simulation_env(
+    tx: &GenericTransaction,
+    header: &BlockHeader,
+    validation: bool,
+) -> (TxEnv, BlockEnv) {
+    let mut tx_env = tx_env_from_generic(tx, header.base_fee_per_gas.unwrap_or(INITIAL_BASE_FEE));
+    let mut block_env = block_env(header);
+    if !validation {
+        tx_env.nonce = None;
+        adjust_disabled_base_fee(
+            &mut block_env,
+            tx_env.gas_price,
+            tx_env.max_fee_per_blob_gas,
+        );
+    }
+    (tx_env, block_env)
 }
 
 /// When basefee tracking is disabled  (ie. env.disable_base_fee = true; env.disable_block_gas_limit = true;)
@@ -124,21 +429,24 @@ fn run_evm(
     state: &mut EvmState,
     spec_id: SpecId,
 ) -> Result<ExecutionResult, EvmError> {
-    let tx_result = {
-        let chain_spec = state.database().get_chain_config()?;
-        let mut evm = Evm::builder()
-            .with_db(&mut state.0)
-            .with_block_env(block_env)
-            .with_tx_env(tx_env)
-            .modify_cfg_env(|cfg| cfg.chain_id = chain_spec.chain_id)
-            .with_spec_id(spec_id)
-            .with_external_context(
-                TracerEip3155::new(Box::new(std::io::stderr())).without_summary(),
-            )
-            .build();
-        evm.transact_commit().map_err(EvmError::from)?
-    };
-    Ok(tx_result.into())
+    run_and_commit(tx_env, block_env, state, spec_id, false, &mut NoOpInspector)
+}
+
+/// Runs the transaction with the given inspector attached and stores its changes in the state
+fn run_and_commit<I>(
+    tx_env: TxEnv,
+    block_env: BlockEnv,
+    state: &mut EvmState,
+    spec_id: SpecId,
+    simulation: bool,
+    inspector: &mut I,
+) -> Result<ExecutionResult, EvmError>
+where
+    I: for<'db> Inspector<&'db mut revm::db::State<OverlayDb>>,
+{
+    let tx_result = inspect_tx(tx_env, block_env, state, spec_id, simulation, inspector)?;
+    state.0.commit(tx_result.state);
+    Ok(tx_result.result.into())
 }
 
 /// Runs the transaction and returns the access list and estimated gas use (when running the tx with said access list)
@@ -225,35 +533,47 @@ fn run_without_commit(
         tx_env.gas_price,
         tx_env.max_fee_per_blob_gas,
     );
-    let chain_config = state.database().get_chain_config()?;
-    let mut evm = Evm::builder()
-        .with_db(&mut state.0)
-        .with_block_env(block_env)
-        .with_tx_env(tx_env)
-        .with_spec_id(spec_id)
-        .modify_cfg_env(|env| {
-            env.disable_base_fee = true;
-            env.disable_block_gas_limit = true;
-            env.chain_id = chain_config.chain_id;
-        })
-        .build();
-    let tx_result = evm.transact().map_err(EvmError::from)?;
+    let tx_result = inspect_tx(tx_env, block_env, state, spec_id, true, &mut NoOpInspector)?;
     Ok(tx_result.result.into())
 }
 
 // Merges transitions stored when executing transactions and applies the resulting changes to the DB
-pub fn apply_state_transitions(state: &mut EvmState) -> Result<(), StoreError> {
+// Returns the applied changes along with the values they replaced, so they can be reverted later on
+pub fn apply_state_transitions(state: &mut EvmState) -> Result<StateDiff, StoreError> {
+    let state_diff = get_state_transitions(state)?;
+    state.database().apply_state_diff(&state_diff)?;
+    Ok(state_diff)
+}
+
+// Merges transitions stored when executing transactions and returns the resulting changes without applying them
+pub fn get_state_transitions(state: &mut EvmState) -> Result<StateDiff, StoreError> {
     state.0.merge_transitions(BundleRetention::PlainState);
     let bundle = state.0.take_bundle();
-    // Update accounts
+    state_diff(&bundle, &state.0.database.inner)
+}
+
+/// Merges transitions stored when executing transactions and returns every change made since the state was built,
+/// keeping them in the state so they are also part of the changes returned by the following calls
+pub fn get_accumulated_state_transitions(state: &mut EvmState) -> Result<StateDiff, StoreError> {
+    state.0.merge_transitions(BundleRetention::PlainState);
+    state_diff(&state.0.bundle_state, &state.0.database.inner)
+}
+
+/// Builds the diff of the changes held by the bundle.
+/// Previous values are read from the state the transactions were executed on, which may not be the current one
+fn state_diff(bundle: &BundleState, db: &StoreWrapper) -> Result<StateDiff, StoreError> {
+    let mut state_diff = StateDiff::default();
     for (address, account) in bundle.state() {
         if account.status.is_not_modified() {
             continue;
         }
         let address = Address::from_slice(address.0.as_slice());
+        let mut account_diff = AccountDiff::new(address, db.get_account_info(address)?);
         // Remove account from DB if destroyed
         if account.status.was_destroyed() {
-            state.database().remove_account(address)?;
+            account_diff.new_info = None;
+            account_diff.storage_wiped = true;
+            account_diff.wiped_storage = db.get_account_storage(address)?;
         }
 
         // If account is empty, do not add to the database
@@ -261,6 +581,9 @@ pub fn apply_state_transitions(state: &mut EvmState) -> Result<(), StoreError> {
             .account_info()
             .is_some_and(|acc_info| acc_info.is_empty())
         {
+            if account_diff.storage_wiped {
+                state_diff.accounts.push(account_diff);
+            }
             continue;
         }
 
@@ -270,19 +593,16 @@ pub fn apply_state_transitions(state: &mut EvmState) -> Result<(), StoreError> {
             // Update account info in DB
             if let Some(new_acc_info) = account.account_info() {
                 let code_hash = H256::from_slice(new_acc_info.code_hash.as_slice());
-                let account_info = AccountInfo {
+                account_diff.new_info = Some(AccountInfo {
                     code_hash,
                     balance: U256::from_little_endian(new_acc_info.balance.as_le_slice()),
                     nonce: new_acc_info.nonce,
-                };
-                state.database().add_account_info(address, account_info)?;
+                });
 
                 if account.is_contract_changed() {
                     // Update code in db
                     if let Some(code) = new_acc_info.code {
-                        state
-                            .database()
-                            .add_account_code(code_hash, code.original_bytes().clone().0)?;
+                        account_diff.new_code = Some((code_hash, code.original_bytes().clone().0));
                     }
                 }
             }
@@ -294,15 +614,22 @@ pub fn apply_state_transitions(state: &mut EvmState) -> Result<(), StoreError> {
                 // if slot.present_value().is_zero() {
                 //     state.database().remove_account_storage(address)
                 // }
-                state.database().add_storage_at(
-                    address,
-                    H256::from_uint(&U256::from_little_endian(key.as_le_slice())),
-                    U256::from_little_endian(slot.present_value().as_le_slice()),
-                )?;
+                let key = H256::from_uint(&U256::from_little_endian(key.as_le_slice()));
+                let previous_value = if account_diff.storage_wiped {
+                    U256::zero()
+                } else {
+                    db.get_storage_at(address, key)?.unwrap_or_default()
+                };
+                account_diff.storage.push(StorageSlotDiff {
+                    key,
+                    previous_value,
+                    new_value: U256::from_little_endian(slot.present_value().as_le_slice()),
+                });
             }
         }
+        state_diff.accounts.push(account_diff);
     }
-    Ok(())
+    Ok(state_diff)
 }
 
 /// Processes a block's withdrawals, updating the account balances in the state
@@ -328,9 +655,32 @@ pub fn process_withdrawals(
 
 /// Builds EvmState from a Store
 pub fn evm_state(store: Store) -> EvmState {
+    build_evm_state(OverlayDb {
+        inner: StoreWrapper {
+            store,
+            state_root: None,
+            block_hashes: HashMap::new(),
+        },
+        overrides: StateOverride::default(),
+    })
+}
+
+/// Builds EvmState from the world state with the given root instead of the current one
+pub fn evm_state_at(store: Store, state_root: H256) -> EvmState {
+    build_evm_state(OverlayDb {
+        inner: StoreWrapper {
+            store,
+            state_root: Some(state_root),
+            block_hashes: HashMap::new(),
+        },
+        overrides: StateOverride::default(),
+    })
+}
+
+fn build_evm_state(db: OverlayDb) -> EvmState {
     EvmState(
         revm::db::State::builder()
-            .with_database(StoreWrapper(store))
+            .with_database(db)
             .with_bundle_update()
             .without_state_clear()
             .build(),
@@ -0,0 +1,84 @@
+use c_kzg::{Blob as KzgBlob, Bytes48, KzgProof};
+use ethereum_rust_core::types::BlobsBundle;
+use revm::primitives::kzg::EnvKzgSettings;
+
+use crate::EvmError;
+
+/// Verifies the KZG proofs of a blob sidecar, checking that each blob matches its commitment.
+/// Proofs are verified against the Ethereum mainnet trusted setup
+pub fn verify_blobs_bundle(blobs_bundle: &BlobsBundle) -> Result<bool, EvmError> {
+    let blobs = blobs_bundle
+        .blobs
+        .iter()
+        .map(|blob| KzgBlob::from_bytes(blob))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(kzg_error)?;
+    let commitments = blobs_bundle
+        .commitments
+        .iter()
+        .map(|commitment| Bytes48::from_bytes(commitment))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(kzg_error)?;
+    let proofs = blobs_bundle
+        .proofs
+        .iter()
+        .map(|proof| Bytes48::from_bytes(proof))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(kzg_error)?;
+    KzgProof::verify_blob_kzg_proof_batch(
+        &blobs,
+        &commitments,
+        &proofs,
+        EnvKzgSettings::Default.get(),
+    )
+    .map_err(kzg_error)
+}
+
+fn kzg_error(error: c_kzg::Error) -> EvmError {
+    EvmError::Custom(format!("KZG error: {error:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_rust_core::types::BYTES_PER_BLOB;
+
+    use super::*;
+
+    /// Compressed point at infinity, the commitment and proof of the zero polynomial
+    const POINT_AT_INFINITY: [u8; 48] = {
+        let mut point = [0; 48];
+        point[0] = 0xc0;
+        point
+    };
+
+    /// Compressed generator of G1, a valid point which is not the commitment of the zero polynomial
+    const G1_GENERATOR: &str = "97f1d3a73197d7942695638c4fa9ac0fc3688c4f9774b905a14e3a3f171bac586c55e83ff97a1aeffb3af00adb22c6bb";
+
+    fn zero_blob_bundle(commitment: [u8; 48], proof: [u8; 48]) -> BlobsBundle {
+        BlobsBundle {
+            blobs: vec![[0; BYTES_PER_BLOB]],
+            commitments: vec![commitment],
+            proofs: vec![proof],
+        }
+    }
+
+    #[test]
+    fn verifies_matching_commitment_and_proof() {
+        // Same as the consensus spec's `verify_blob_kzg_proof_case_correct_proof_point_at_infinity_for_zero_poly`
+        let bundle = zero_blob_bundle(POINT_AT_INFINITY, POINT_AT_INFINITY);
+        assert!(verify_blobs_bundle(&bundle).unwrap());
+    }
+
+    #[test]
+    fn rejects_mismatching_commitment() {
+        let commitment = hex::decode(G1_GENERATOR).unwrap().try_into().unwrap();
+        let bundle = zero_blob_bundle(commitment, POINT_AT_INFINITY);
+        assert!(!verify_blobs_bundle(&bundle).unwrap());
+    }
+
+    #[test]
+    fn rejects_malformed_proof() {
+        let bundle = zero_blob_bundle(POINT_AT_INFINITY, [0xff; 48]);
+        assert!(verify_blobs_bundle(&bundle).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bytes::Bytes;
+use ethereum_rust_core::{Address, H256, U256};
+use revm::{
+    interpreter::{opcode, CallInputs, CallOutcome, CreateInputs, CreateOutcome, Interpreter},
+    primitives::{Account, Address as RevmAddress, Bytecode, U256 as RevmU256},
+    Database, EvmContext, Inspector,
+};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PrestateTracerConfig {
+    /// Return the changes made by the transaction instead of the state it accessed
+    pub diff_mode: bool,
+}
+
+/// State of an account, fields are left out when they are empty or, in diff mode, when they didn't change
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrestateAccount {
+    pub balance: Option<U256>,
+    pub nonce: Option<u64>,
+    pub code: Option<Bytes>,
+    pub storage: BTreeMap<H256, H256>,
+}
+
+#[derive(Debug, Clone)]
+pub enum PrestateTrace {
+    /// State of the accounts touched by the transaction, before executing it
+    Prestate(BTreeMap<Address, PrestateAccount>),
+    /// State of the accounts modified by the transaction, before and after executing it
+    Diff {
+        pre: BTreeMap<Address, PrestateAccount>,
+        post: BTreeMap<Address, PrestateAccount>,
+    },
+}
+
+#[derive(Debug, Default)]
+struct TouchedAccount {
+    exists: bool,
+    balance: RevmU256,
+    nonce: u64,
+    code: Bytes,
+    /// Value of each of the accessed storage slots before the transaction
+    storage: BTreeMap<RevmU256, RevmU256>,
+}
+
+/// Inspector recording the state of the accounts and storage slots touched by a transaction before it ran,
+/// as geth's prestateTracer does.
+/// The database the EVM reads from is not modified until the transaction is committed, so it still holds the pre-state
+#[derive(Debug, Default)]
+pub struct PrestateTracer {
+    config: PrestateTracerConfig,
+    touched: HashMap<RevmAddress, TouchedAccount>,
+}
+
+impl PrestateTracer {
+    pub fn new(config: PrestateTracerConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Builds the trace out of the touched accounts and the state the transaction left them in
+    pub fn into_trace(self, post_state: &HashMap<RevmAddress, Account>) -> PrestateTrace {
+        if !self.config.diff_mode {
+            let pre = self
+                .touched
+                .iter()
+                .map(|(address, account)| (core_address(*address), account.prestate()))
+                .collect();
+            return PrestateTrace::Prestate(pre);
+        }
+        let mut pre = BTreeMap::new();
+        let mut post = BTreeMap::new();
+        for (address, account) in &self.touched {
+            let Some(post_account) = post_state.get(address) else {
+                continue;
+            };
+            let destroyed = post_account.is_selfdestructed();
+            let post_code = post_account
+                .info
+                .code
+                .as_ref()
+                .map(|code| code.original_bytes().0)
+                .unwrap_or_default();
+            let mut diff = PrestateAccount {
+                balance: (post_account.info.balance != account.balance)
+                    .then(|| u256(post_account.info.balance)),
+                nonce: (post_account.info.nonce != account.nonce)
+                    .then_some(post_account.info.nonce),
+                code: (post_code != account.code).then_some(post_code),
+                storage: BTreeMap::new(),
+            };
+            let mut pre_storage = BTreeMap::new();
+            for (key, pre_value) in &account.storage {
+                let post_value = post_account
+                    .storage
+                    .get(key)
+                    .map(|slot| slot.present_value)
+                    .unwrap_or(*pre_value);
+                if post_value != *pre_value {
+                    pre_storage.insert(word(*key), word(*pre_value));
+                    diff.storage.insert(word(*key), word(post_value));
+                }
+            }
+            if !destroyed && diff == PrestateAccount::default() {
+                continue;
+            }
+            let address = core_address(*address);
+            if account.exists {
+                let mut pre_account = account.prestate();
+                pre_account.storage = pre_storage;
+                pre.insert(address, pre_account);
+            }
+            if !destroyed {
+                post.insert(address, diff);
+            }
+        }
+        PrestateTrace::Diff { pre, post }
+    }
+
+    /// Records the state of the account before the transaction, if it was not touched before
+    fn touch_account<DB: Database>(&mut self, context: &mut EvmContext<DB>, address: RevmAddress) {
+        if self.touched.contains_key(&address) {
+            return;
+        }
+        let mut account = TouchedAccount::default();
+        if let Ok(Some(info)) = context.db.basic(address) {
+            let code = match info.code {
+                Some(code) => Some(code),
+                None => context.db.code_by_hash(info.code_hash).ok(),
+            };
+            account = TouchedAccount {
+                exists: true,
+                balance: info.balance,
+                nonce: info.nonce,
+                code: code
+                    .map(|code: Bytecode| code.original_bytes().0)
+                    .unwrap_or_default(),
+                storage: BTreeMap::new(),
+            };
+        }
+        self.touched.insert(address, account);
+    }
+
+    /// Records the value of the storage slot before the transaction, if it was not touched before
+    fn touch_slot<DB: Database>(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        address: RevmAddress,
+        key: RevmU256,
+    ) {
+        self.touch_account(context, address);
+        let already_touched = self
+            .touched
+            .get(&address)
+            .is_some_and(|account| account.storage.contains_key(&key));
+        if already_touched {
+            return;
+        }
+        let value = context.db.storage(address, key).unwrap_or_default();
+        if let Some(account) = self.touched.get_mut(&address) {
+            account.storage.insert(key, value);
+        }
+    }
+}
+
+impl TouchedAccount {
+    fn prestate(&self) -> PrestateAccount {
+        PrestateAccount {
+            balance: Some(u256(self.balance)),
+            nonce: (self.nonce != 0).then_some(self.nonce),
+            code: (!self.code.is_empty()).then(|| self.code.clone()),
+            storage: self
+                .storage
+                .iter()
+                .map(|(key, value)| (word(*key), word(*value)))
+                .collect(),
+        }
+    }
+}
+
+impl<DB: Database> Inspector<DB> for PrestateTracer {
+    fn step(&mut self, interp: &mut Interpreter, context: &mut EvmContext<DB>) {
+        let address = interp.contract.target_address;
+        match interp.current_opcode() {
+            opcode::SLOAD | opcode::SSTORE => {
+                if let Ok(key) = interp.stack.peek(0) {
+                    self.touch_slot(context, address, key);
+                }
+            }
+            opcode::BALANCE
+            | opcode::EXTCODESIZE
+            | opcode::EXTCODECOPY
+            | opcode::EXTCODEHASH
+            | opcode::SELFDESTRUCT => {
+                if let Ok(target) = interp.stack.peek(0) {
+                    let target = RevmAddress::from_word(target.to_be_bytes().into());
+                    self.touch_account(context, target);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn call(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &mut CallInputs,
+    ) -> Option<CallOutcome> {
+        // The block's beneficiary is paid by every transaction
+        let coinbase = context.env.block.coinbase;
+        self.touch_account(context, coinbase);
+        self.touch_account(context, inputs.caller);
+        self.touch_account(context, inputs.target_address);
+        self.touch_account(context, inputs.bytecode_address);
+        None
+    }
+
+    fn create(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &mut CreateInputs,
+    ) -> Option<CreateOutcome> {
+        let coinbase = context.env.block.coinbase;
+        self.touch_account(context, coinbase);
+        self.touch_account(context, inputs.caller);
+        None
+    }
+
+    fn create_end(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        _inputs: &CreateInputs,
+        outcome: CreateOutcome,
+    ) -> CreateOutcome {
+        if let Some(address) = outcome.address {
+            self.touch_account(context, address);
+        }
+        outcome
+    }
+}
+
+fn core_address(address: RevmAddress) -> Address {
+    Address::from_slice(address.0.as_slice())
+}
+
+fn u256(value: RevmU256) -> U256 {
+    U256(value.into_limbs())
+}
+
+fn word(value: RevmU256) -> H256 {
+    H256(value.to_be_bytes())
+}
@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bytes::Bytes;
+use ethereum_rust_core::{H256, U256};
+use revm::{
+    interpreter::{
+        opcode, CallInputs, CallOutcome, CreateInputs, CreateOutcome, Interpreter, OpCode,
+    },
+    primitives::{Address as RevmAddress, U256 as RevmU256},
+    Database, EvmContext, Inspector,
+};
+
+/// Parts of the execution state captured at each step by the struct logger
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StructLoggerConfig {
+    pub disable_stack: bool,
+    pub disable_memory: bool,
+    pub disable_storage: bool,
+}
+
+/// State of the EVM right before executing one of the steps of a transaction
+#[derive(Debug, Clone)]
+pub struct StructLog {
+    pub pc: u64,
+    pub op: String,
+    pub gas: u64,
+    pub gas_cost: u64,
+    pub depth: u64,
+    /// Refund counter of the whole transaction
+    pub refund: u64,
+    /// Stack items, from bottom to top
+    pub stack: Option<Vec<U256>>,
+    pub memory: Option<Bytes>,
+    /// Storage slots of the executing contract that were accessed so far, only captured on SLOAD and SSTORE
+    pub storage: Option<BTreeMap<H256, H256>>,
+    pub error: Option<String>,
+}
+
+/// Inspector recording a [StructLog] for each step of the execution, as geth's default tracer does
+#[derive(Debug, Default)]
+pub struct StructLogger {
+    config: StructLoggerConfig,
+    logs: Vec<StructLog>,
+    /// Storage slots accessed by each contract
+    storage: HashMap<RevmAddress, BTreeMap<H256, H256>>,
+    /// Slot read by the current step if it is an SLOAD, its value is only known once the step ends
+    pending_load: Option<(RevmAddress, H256)>,
+    /// Refund counter of the transaction when each of the open call frames started.
+    /// Each frame only tracks its own refunds, which are added to its parent's when it succeeds
+    refund_bases: Vec<i64>,
+    /// Refund counter of the transaction as of the last step
+    refund: i64,
+}
+
+impl StructLogger {
+    pub fn new(config: StructLoggerConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub fn into_logs(self) -> Vec<StructLog> {
+        self.logs
+    }
+
+    /// Goes back to the parent frame, whose own refunds already include the ones of the closed frame if it succeeded
+    fn close_frame(&mut self) {
+        if let Some(base) = self.refund_bases.pop() {
+            self.refund = base;
+        }
+    }
+}
+
+impl<DB: Database> Inspector<DB> for StructLogger {
+    fn step(&mut self, interp: &mut Interpreter, context: &mut EvmContext<DB>) {
+        let op = interp.current_opcode();
+        let address = interp.contract.target_address;
+        self.refund = self.refund_bases.last().copied().unwrap_or_default() + interp.gas.refunded();
+        let mut storage = None;
+        if !self.config.disable_storage {
+            match op {
+                opcode::SLOAD => {
+                    if let Ok(key) = interp.stack.peek(0) {
+                        self.pending_load = Some((address, word(key)));
+                    }
+                }
+                opcode::SSTORE => {
+                    if let (Ok(key), Ok(value)) = (interp.stack.peek(0), interp.stack.peek(1)) {
+                        self.storage
+                            .entry(address)
+                            .or_default()
+                            .insert(word(key), word(value));
+                        storage = self.storage.get(&address).cloned();
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.logs.push(StructLog {
+            pc: interp.program_counter() as u64,
+            op: match OpCode::new(op) {
+                Some(op) => op.as_str().to_string(),
+                None => format!("opcode {op:#x} not defined"),
+            },
+            gas: interp.gas.remaining(),
+            gas_cost: 0,
+            depth: context.journaled_state.depth() as u64,
+            refund: self.refund.max(0) as u64,
+            stack: (!self.config.disable_stack).then(|| {
+                interp
+                    .stack
+                    .data()
+                    .iter()
+                    .map(|item| U256(item.into_limbs()))
+                    .collect()
+            }),
+            memory: (!self.config.disable_memory)
+                .then(|| Bytes::copy_from_slice(interp.shared_memory.context_memory())),
+            storage,
+            error: None,
+        });
+    }
+
+    fn step_end(&mut self, interp: &mut Interpreter, _context: &mut EvmContext<DB>) {
+        let Some(log) = self.logs.last_mut() else {
+            return;
+        };
+        log.gas_cost = log.gas.saturating_sub(interp.gas.remaining());
+        let pending_load = self.pending_load.take();
+        if interp.instruction_result.is_error() {
+            log.error = Some(format!("{:?}", interp.instruction_result));
+            return;
+        }
+        // The value read by an SLOAD is left at the top of the stack
+        if let Some((address, key)) = pending_load {
+            if let Ok(value) = interp.stack.peek(0) {
+                let storage = self.storage.entry(address).or_default();
+                storage.insert(key, word(value));
+                log.storage = Some(storage.clone());
+            }
+        }
+    }
+
+    fn call(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        _inputs: &mut CallInputs,
+    ) -> Option<CallOutcome> {
+        self.refund_bases.push(self.refund);
+        None
+    }
+
+    fn call_end(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        _inputs: &CallInputs,
+        outcome: CallOutcome,
+    ) -> CallOutcome {
+        self.close_frame();
+        outcome
+    }
+
+    fn create(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        _inputs: &mut CreateInputs,
+    ) -> Option<CreateOutcome> {
+        self.refund_bases.push(self.refund);
+        None
+    }
+
+    fn create_end(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        _inputs: &CreateInputs,
+        outcome: CreateOutcome,
+    ) -> CreateOutcome {
+        self.close_frame();
+        outcome
+    }
+}
+
+fn word(value: RevmU256) -> H256 {
+    H256(value.to_be_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use revm::{
+        db::{CacheDB, EmptyDB},
+        inspector_handle_register,
+        primitives::{AccountInfo, Bytecode, SpecId, TxKind},
+        Evm,
+    };
+
+    use super::*;
+
+    /// Refund for clearing a storage slot that was set before the transaction (EIP-3529)
+    const CLEAR_REFUND: u64 = 4800;
+
+    #[test]
+    fn refund_counter_spans_call_frames() {
+        let (outer, inner) = (
+            RevmAddress::with_last_byte(0xa),
+            RevmAddress::with_last_byte(0xb),
+        );
+        // Both contracts clear their first slot, the outer one then calls the inner one
+        let clear_slot = "6000600055";
+        let call_inner = "60006000600060006000600b5af1";
+        let mut db = CacheDB::new(EmptyDB::default());
+        for (address, code) in [
+            (outer, format!("{clear_slot}{call_inner}00")),
+            (inner, format!("{clear_slot}00")),
+        ] {
+            let code = Bytecode::new_raw(hex::decode(code).unwrap().into());
+            db.insert_account_info(address, AccountInfo::from_bytecode(code));
+            db.insert_account_storage(address, RevmU256::ZERO, RevmU256::from(1))
+                .unwrap();
+        }
+        let mut evm = Evm::builder()
+            .with_db(db)
+            .with_spec_id(SpecId::CANCUN)
+            .modify_tx_env(|tx| {
+                tx.transact_to = TxKind::Call(outer);
+                tx.gas_limit = 1_000_000;
+            })
+            .with_external_context(StructLogger::default())
+            .append_handler_register(inspector_handle_register)
+            .build();
+        evm.transact().unwrap();
+        let logs = evm.into_context().external.into_logs();
+
+        let inner_depth = logs.iter().map(|log| log.depth).max().unwrap();
+        let inner_logs: Vec<_> = logs.iter().filter(|log| log.depth == inner_depth).collect();
+        // The inner frame starts with the refund of the outer one, and both add up once it returns
+        assert_eq!(inner_logs.first().unwrap().refund, CLEAR_REFUND);
+        assert_eq!(inner_logs.last().unwrap().refund, 2 * CLEAR_REFUND);
+        assert_eq!(logs.last().unwrap().op, "STOP");
+        assert_eq!(logs.last().unwrap().refund, 2 * CLEAR_REFUND);
+    }
+}
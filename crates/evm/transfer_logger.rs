@@ -0,0 +1,105 @@
+use revm::{
+    interpreter::{CallInputs, CallOutcome, CreateInputs, CreateOutcome},
+    primitives::{Address as RevmAddress, Log as RevmLog, B256, U256 as RevmU256},
+    Database, EvmContext, Inspector,
+};
+
+/// Address the transfer logs are emitted from, as geth does
+const TRANSFER_LOG_ADDRESS: [u8; 20] = [0xee; 20];
+/// Topic of the `Transfer(address,address,uint256)` event
+const TRANSFER_TOPIC: [u8; 32] = [
+    0xdd, 0xf2, 0x52, 0xad, 0x1b, 0xe2, 0xc8, 0x9b, 0x69, 0xc2, 0xb0, 0x68, 0xfc, 0x37, 0x8d, 0xaa,
+    0x95, 0x2b, 0xa7, 0xf1, 0x63, 0xc4, 0xa1, 0x16, 0x28, 0xf5, 0x5a, 0x4d, 0xf5, 0x23, 0xb3, 0xef,
+];
+
+/// Inspector emitting an ERC-20 like `Transfer` log for each transfer of ether made by a call or a contract creation.
+/// Each log is emitted before the logs of the call that made the transfer, as geth does. As it is emitted before the
+/// call's checkpoint is taken, it is removed by hand if the call fails, while a failure of any of its parent calls
+/// reverts it along with the rest of their logs
+#[derive(Debug, Default)]
+pub struct TransferLogger {
+    /// Index of the log emitted by each of the open calls and creations, if it transferred any value
+    open_transfers: Vec<Option<usize>>,
+}
+
+impl TransferLogger {
+    fn open<DB: Database>(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        from: RevmAddress,
+        to: RevmAddress,
+        value: RevmU256,
+    ) {
+        let transfer = (!value.is_zero()).then(|| {
+            let index = context.journaled_state.logs.len();
+            context.journaled_state.log(transfer_log(from, to, value));
+            index
+        });
+        self.open_transfers.push(transfer);
+    }
+
+    fn close<DB: Database>(&mut self, context: &mut EvmContext<DB>, succeeded: bool) {
+        if let Some(Some(index)) = self.open_transfers.pop() {
+            if !succeeded {
+                context.journaled_state.logs.truncate(index);
+            }
+        }
+    }
+}
+
+impl<DB: Database> Inspector<DB> for TransferLogger {
+    fn call(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &mut CallInputs,
+    ) -> Option<CallOutcome> {
+        let value = inputs.transfer_value().unwrap_or_default();
+        self.open(context, inputs.caller, inputs.target_address, value);
+        None
+    }
+
+    fn call_end(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        _inputs: &CallInputs,
+        outcome: CallOutcome,
+    ) -> CallOutcome {
+        self.close(context, outcome.result.result.is_ok());
+        outcome
+    }
+
+    fn create(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &mut CreateInputs,
+    ) -> Option<CreateOutcome> {
+        // The creator is already loaded, and its nonce is only increased once the creation starts
+        let nonce = context
+            .journaled_state
+            .state
+            .get(&inputs.caller)
+            .map(|account| account.info.nonce)
+            .unwrap_or_default();
+        let address = inputs.created_address(nonce);
+        self.open(context, inputs.caller, address, inputs.value);
+        None
+    }
+
+    fn create_end(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        _inputs: &CreateInputs,
+        outcome: CreateOutcome,
+    ) -> CreateOutcome {
+        self.close(context, outcome.result.result.is_ok());
+        outcome
+    }
+}
+
+fn transfer_log(from: RevmAddress, to: RevmAddress, value: RevmU256) -> RevmLog {
+    RevmLog::new_unchecked(
+        RevmAddress::from(TRANSFER_LOG_ADDRESS),
+        vec![B256::from(TRANSFER_TOPIC), from.into_word(), to.into_word()],
+        value.to_be_bytes::<32>().to_vec().into(),
+    )
+}
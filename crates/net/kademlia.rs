@@ -74,6 +74,16 @@ impl KademliaTable {
             .collect();
     }
 
+    /// Returns the nodes that answered our pings, which can be dialed to start an RLPx connection
+    pub fn get_proven_nodes(&self) -> Vec<Node> {
+        self.buckets
+            .iter()
+            .flatten()
+            .filter(|peer| peer.is_proven)
+            .map(|peer| peer.node)
+            .collect()
+    }
+
     pub fn get_closest_nodes(&self, node_id: H512) -> Vec<Node> {
         let mut nodes: Vec<(Node, usize)> = vec![];
 
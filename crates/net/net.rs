@@ -1,5 +1,6 @@
 use std::{
     net::SocketAddr,
+    sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
@@ -9,50 +10,76 @@ use discv4::{
     NeighborsMessage, Packet, PingMessage, PongMessage,
 };
 use ethereum_rust_core::{H256, H512};
+use ethereum_rust_storage::Store;
 use k256::{
-    ecdsa::{RecoveryId, Signature, SigningKey, VerifyingKey},
+    ecdsa::SigningKey,
     elliptic_curve::{sec1::ToEncodedPoint, PublicKey},
-    SecretKey,
 };
 use kademlia::{KademliaTable, PeerData, MAX_NODES_PER_BUCKET};
+use peer_manager::{PeerInfo, PeerManager};
 use rlpx::{
-    connection::SUPPORTED_CAPABILITIES, handshake::RLPxLocalClient,
-    message::Message as RLPxMessage, p2p,
+    connection::{self, RLPxConnection},
+    error::RLPxError,
+    utils::{id2pubkey, pubkey2id},
 };
-use sha3::{Digest, Keccak256};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpSocket, UdpSocket},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::Mutex,
     try_join,
 };
-use tracing::{debug, info, warn};
+use tracing::{debug, error, info, warn};
 use types::{Endpoint, Node};
 
 pub mod bootnode;
 pub(crate) mod discv4;
 pub(crate) mod kademlia;
+pub mod peer_manager;
 pub mod rlpx;
+pub mod sync;
 pub mod types;
 
 const MAX_DISC_PACKET_SIZE: usize = 1280;
 
+/// Time between each round of dialing new peers
+const DIAL_INTERVAL: Duration = Duration::from_secs(5);
+
 pub async fn start_network(
     udp_addr: SocketAddr,
     tcp_addr: SocketAddr,
     bootnodes: Vec<BootNode>,
     signer: SigningKey,
+    peer_manager: PeerManager,
+    storage: Store,
 ) {
     info!("Starting discovery service at {udp_addr}");
     info!("Listening for requests at {tcp_addr}");
 
-    let discovery_handle = tokio::spawn(discover_peers(udp_addr, signer.clone(), bootnodes));
-    let server_handle = tokio::spawn(serve_requests(tcp_addr, signer));
-    try_join!(discovery_handle, server_handle).unwrap();
+    let local_node_id = node_id_from_signing_key(&signer);
+    let table = Arc::new(Mutex::new(KademliaTable::new(local_node_id)));
+
+    let discovery_handle = tokio::spawn(discover_peers(
+        udp_addr,
+        signer.clone(),
+        table.clone(),
+        bootnodes.clone(),
+    ));
+    let server_handle = tokio::spawn(serve_requests(
+        tcp_addr,
+        signer.clone(),
+        peer_manager.clone(),
+        storage.clone(),
+    ));
+    let dialer_handle = tokio::spawn(dial_peers(table, bootnodes, signer, peer_manager, storage));
+    try_join!(discovery_handle, server_handle, dialer_handle).unwrap();
 }
 
-async fn discover_peers(udp_addr: SocketAddr, signer: SigningKey, bootnodes: Vec<BootNode>) {
+async fn discover_peers(
+    udp_addr: SocketAddr,
+    signer: SigningKey,
+    table: Arc<Mutex<KademliaTable>>,
+    bootnodes: Vec<BootNode>,
+) {
     let udp_socket = UdpSocket::bind(udp_addr).await.unwrap();
-    let local_node_id = node_id_from_signing_key(&signer);
 
     // TODO implement this right
     if let Some(b) = bootnodes.first() {
@@ -60,7 +87,6 @@ async fn discover_peers(udp_addr: SocketAddr, signer: SigningKey, bootnodes: Vec
     };
 
     let mut buf = vec![0; MAX_DISC_PACKET_SIZE];
-    let mut table = KademliaTable::new(local_node_id);
     loop {
         let (read, from) = udp_socket.recv_from(&mut buf).await.unwrap();
         info!("Received {read} bytes from {from}");
@@ -75,6 +101,7 @@ async fn discover_peers(udp_addr: SocketAddr, signer: SigningKey, bootnodes: Vec
         let msg = packet.get_message();
         info!("Message: {:?} from {}", msg, packet.get_node_id());
 
+        // The table is only locked while it is accessed, so that it isn't held while sending messages
         match msg {
             Message::Ping(msg) => {
                 if is_expired(msg.expiration) {
@@ -84,29 +111,31 @@ async fn discover_peers(udp_addr: SocketAddr, signer: SigningKey, bootnodes: Vec
                 let ping_hash = packet.get_hash();
                 pong(&udp_socket, from, ping_hash, &signer).await;
 
-                let node = table.get_by_node_id_mut(packet.get_node_id());
-                if let Some(peer) = node {
-                    // send a a ping to get an endpoint proof
-                    if time_since_in_hs(peer.last_ping) > 12 {
-                        let hash = ping(&udp_socket, udp_addr, from, &signer).await;
-                        if let Some(hash) = hash {
-                            peer.last_ping_hash = Some(hash);
-                        }
+                // Whether to send a ping to get an endpoint proof, and whether to keep its hash to check the answer
+                let (ping_back, track_ping) = {
+                    let mut table = table.lock().await;
+                    if let Some(peer) = table.get_by_node_id_mut(packet.get_node_id()) {
+                        let ping_back = time_since_in_hs(peer.last_ping) > 12;
+                        peer.last_ping = time_now_unix();
+                        (ping_back, true)
+                    } else {
+                        let (_, inserted_to_table) = table.insert_node(Node {
+                            ip: from.ip(),
+                            udp_port: from.port(),
+                            tcp_port: msg.from.tcp_port,
+                            node_id: packet.get_node_id(),
+                        });
+                        (true, inserted_to_table)
                     }
-                    peer.last_ping = time_now_unix();
-                } else {
-                    // send a ping to get the endpoint proof from our end
-                    let (peer, inserted_to_table) = table.insert_node(Node {
-                        ip: from.ip(),
-                        udp_port: from.port(),
-                        tcp_port: 0,
-                        node_id: packet.get_node_id(),
-                    });
-                    let hash = ping(&udp_socket, udp_addr, from, &signer).await;
-                    if let Some(hash) = hash {
-                        if inserted_to_table {
-                            peer.last_ping_hash = Some(hash);
-                        }
+                };
+                if !ping_back {
+                    continue;
+                }
+                let hash = ping(&udp_socket, udp_addr, from, &signer).await;
+                if let Some(hash) = hash.filter(|_| track_ping) {
+                    let mut table = table.lock().await;
+                    if let Some(peer) = table.get_by_node_id_mut(packet.get_node_id()) {
+                        peer.last_ping_hash = Some(hash);
                     }
                 }
             }
@@ -116,6 +145,7 @@ async fn discover_peers(udp_addr: SocketAddr, signer: SigningKey, bootnodes: Vec
                     continue;
                 }
 
+                let mut table = table.lock().await;
                 if let Some(peer) = table.get_by_node_id_mut(packet.get_node_id()) {
                     if peer.last_ping_hash.is_none() {
                         debug!("Discarding pong as the node did not send a previous ping");
@@ -138,23 +168,25 @@ async fn discover_peers(udp_addr: SocketAddr, signer: SigningKey, bootnodes: Vec
                     debug!("Ignoring find node msg as it is expired.");
                     continue;
                 };
-                let node = table.get_by_node_id(packet.get_node_id());
-                if let Some(node) = node {
-                    if node.is_proven {
-                        let nodes = table.get_closest_nodes(node.node.node_id);
-                        let expiration = get_expiration(20);
-                        let neighbors =
-                            discv4::Message::Neighbors(NeighborsMessage::new(nodes, expiration));
-                        let mut buf = Vec::new();
-                        neighbors.encode_with_header(&mut buf, &signer);
-                        debug!("Sending neighbors!");
-                        udp_socket.send_to(&buf, from).await.unwrap();
-                    } else {
+                let nodes = {
+                    let table = table.lock().await;
+                    let Some(node) = table.get_by_node_id(packet.get_node_id()) else {
+                        debug!("Ignoring find node message as it is not a known node");
+                        continue;
+                    };
+                    if !node.is_proven {
                         debug!("Ignoring find node message as the node isn't proven!");
+                        continue;
                     }
-                } else {
-                    debug!("Ignoring find node message as it is not a known node");
-                }
+                    table.get_closest_nodes(node.node.node_id)
+                };
+                let expiration = get_expiration(20);
+                let neighbors =
+                    discv4::Message::Neighbors(NeighborsMessage::new(nodes, expiration));
+                let mut buf = Vec::new();
+                neighbors.encode_with_header(&mut buf, &signer);
+                debug!("Sending neighbors!");
+                udp_socket.send_to(&buf, from).await.unwrap();
             }
             Message::Neighbors(neighbors_msg) => {
                 if is_expired(neighbors_msg.expiration) {
@@ -162,6 +194,7 @@ async fn discover_peers(udp_addr: SocketAddr, signer: SigningKey, bootnodes: Vec
                     continue;
                 };
 
+                let mut table = table.lock().await;
                 let mut nodes_to_insert = None;
                 if let Some(node) = table.get_by_node_id_mut(packet.get_node_id()) {
                     if let Some(req) = &mut node.find_node_request {
@@ -185,12 +218,16 @@ async fn discover_peers(udp_addr: SocketAddr, signer: SigningKey, bootnodes: Vec
                     debug!("Ignoring neighbor msg as it is not a known node");
                 }
 
-                if let Some(nodes) = nodes_to_insert {
-                    for node in nodes {
-                        table.insert_node(node);
-                        let node_addr = SocketAddr::new(node.ip.to_canonical(), node.udp_port);
-                        ping(&udp_socket, udp_addr, node_addr, &signer).await;
-                    }
+                let Some(nodes) = nodes_to_insert else {
+                    continue;
+                };
+                for node in &nodes {
+                    table.insert_node(*node);
+                }
+                drop(table);
+                for node in nodes {
+                    let node_addr = SocketAddr::new(node.ip.to_canonical(), node.udp_port);
+                    ping(&udp_socket, udp_addr, node_addr, &signer).await;
                 }
             }
             _ => {}
@@ -291,112 +328,159 @@ async fn pong(socket: &UdpSocket, to_addr: SocketAddr, ping_hash: H256, signer:
     socket.send_to(&buf, to_addr).await.unwrap();
 }
 
-async fn serve_requests(tcp_addr: SocketAddr, signer: SigningKey) {
-    let secret_key: SecretKey = signer.clone().into();
-    let tcp_socket = TcpSocket::new_v4().unwrap();
-    tcp_socket.bind(tcp_addr).unwrap();
-
-    let mut udp_addr = tcp_addr;
-    udp_addr.set_port(tcp_addr.port() + 1);
-    let udp_socket = UdpSocket::bind(udp_addr).await.unwrap();
-
-    // Try contacting a known peer
-    // TODO: this is just an example, and we should do this dynamically
-    let str_tcp_addr = "127.0.0.1:30307";
-    let str_udp_addr = "127.0.0.1:30307";
-
-    let udp_addr: SocketAddr = str_udp_addr.parse().unwrap();
-
-    let mut buf = vec![0; MAX_DISC_PACKET_SIZE];
-
-    let (msg, sig_bytes, endpoint) = loop {
-        ping(&udp_socket, tcp_addr, udp_addr, &signer).await;
-
-        let (read, from) = udp_socket.recv_from(&mut buf).await.unwrap();
-        info!("RLPx: Received {read} bytes from {from}");
-        let packet = Packet::decode(&buf[..read]).unwrap();
-        info!("RLPx: Message: {:?}", packet);
-
-        match packet.get_message() {
-            Message::Pong(pong) => {
-                break (&buf[32 + 65..read], &buf[32..32 + 65], pong.to);
-            }
-            Message::Ping(ping) => {
-                break (&buf[32 + 65..read], &buf[32..32 + 65], ping.from);
-            }
-            _ => {
-                warn!("Unexpected message type");
-            }
-        };
+async fn serve_requests(
+    tcp_addr: SocketAddr,
+    signer: SigningKey,
+    peer_manager: PeerManager,
+    storage: Store,
+) {
+    let listener = match TcpListener::bind(tcp_addr).await {
+        Ok(listener) => listener,
+        Err(error) => {
+            error!("Failed to listen for RLPx connections at {tcp_addr}: {error}");
+            return;
+        }
     };
 
-    let digest = Keccak256::digest(msg);
-    let signature = &Signature::from_bytes(sig_bytes[..64].into()).unwrap();
-    let rid = RecoveryId::from_byte(sig_bytes[64]).unwrap();
-
-    let peer_pk = VerifyingKey::recover_from_prehash(&digest, signature, rid).unwrap();
-
-    let mut client = RLPxLocalClient::random();
-    let mut auth_message = vec![];
-    client.encode_auth_message(&secret_key, &peer_pk.into(), &mut auth_message);
-
-    // NOTE: for some reason kurtosis peers don't publish their active TCP port
-    let tcp_addr = endpoint
-        .tcp_address()
-        .unwrap_or(str_tcp_addr.parse().unwrap());
-
-    let mut stream = TcpSocket::new_v4()
-        .unwrap()
-        .connect(tcp_addr)
-        .await
-        .unwrap();
-
-    stream.write_all(&auth_message).await.unwrap();
-    info!("Sent auth message correctly!");
-    // Read the ack message's size
-    stream.read_exact(&mut buf[..2]).await.unwrap();
-    let auth_data = buf[..2].try_into().unwrap();
-    let msg_size = u16::from_be_bytes(auth_data) as usize;
-
-    // Read the rest of the ack message
-    stream.read_exact(&mut buf[2..msg_size + 2]).await.unwrap();
-
-    let msg = &mut buf[2..msg_size + 2];
-    let mut pending_conn = client.decode_ack_message(&secret_key, msg, auth_data);
-    info!("Completed handshake!");
-
-    let hello_msg = RLPxMessage::Hello(p2p::HelloMessage::new(
-        SUPPORTED_CAPABILITIES
-            .into_iter()
-            .map(|(name, version)| (name.to_string(), version))
-            .collect(),
-        PublicKey::from(signer.verifying_key()),
-    ));
-
-    pending_conn.send(hello_msg, &mut stream).await;
-
-    // Receive Hello message
-    let mut conn = pending_conn.receive(&mut stream).await;
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer_addr)) => {
+                tokio::spawn(accept_peer(
+                    stream,
+                    peer_addr,
+                    signer.clone(),
+                    peer_manager.clone(),
+                    storage.clone(),
+                ));
+            }
+            Err(error) => warn!("Failed to accept RLPx connection: {error}"),
+        }
+    }
+}
 
-    info!("Completed Hello roundtrip!");
+/// Periodically dials the bootnodes and the nodes found by the discovery service,
+/// as long as there are free outbound slots
+async fn dial_peers(
+    table: Arc<Mutex<KademliaTable>>,
+    bootnodes: Vec<BootNode>,
+    signer: SigningKey,
+    peer_manager: PeerManager,
+    storage: Store,
+) {
+    let bootnodes: Vec<Node> = bootnodes
+        .iter()
+        .map(|bootnode| Node {
+            ip: bootnode.socket_address.ip(),
+            udp_port: bootnode.socket_address.port(),
+            tcp_port: bootnode.socket_address.port(),
+            node_id: bootnode.node_id,
+        })
+        .collect();
+
+    let mut interval = tokio::time::interval(DIAL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let discovered_nodes = table.lock().await.get_proven_nodes();
+        let candidates = bootnodes.iter().copied().chain(discovered_nodes);
+        for node in peer_manager.dial_candidates(candidates) {
+            tokio::spawn(dial_peer(
+                node,
+                signer.clone(),
+                peer_manager.clone(),
+                storage.clone(),
+            ));
+        }
+    }
+}
 
-    // Send Ping
-    conn.send(RLPxMessage::Ping(p2p::PingMessage::new()), &mut stream)
-        .await;
+/// Completes a connection started by a peer and handles it until it is closed
+async fn accept_peer(
+    mut stream: TcpStream,
+    peer_addr: SocketAddr,
+    signer: SigningKey,
+    peer_manager: PeerManager,
+    storage: Store,
+) {
+    match connection::accept(&mut stream, &signer, &storage).await {
+        Ok((conn, remote_pubkey)) => {
+            debug!(
+                "Accepted connection from node {:x}",
+                pubkey2id(&remote_pubkey)
+            );
+            handle_peer(conn, stream, peer_addr, true, peer_manager, storage).await;
+        }
+        Err(error) => debug!("Failed to accept RLPx connection from {peer_addr}: {error}"),
+    }
+}
 
-    // Receive three messages
-    // TODO implement listen loop instead
-    conn.receive(&mut stream).await;
+/// Starts a connection with a node and handles it until it is closed
+async fn dial_peer(node: Node, signer: SigningKey, peer_manager: PeerManager, storage: Store) {
+    let peer_addr = SocketAddr::new(node.ip.to_canonical(), node.tcp_port);
+    match connect(peer_addr, node.node_id, &signer, &storage).await {
+        Ok((conn, stream)) => {
+            handle_peer(
+                conn,
+                stream,
+                peer_addr,
+                false,
+                peer_manager.clone(),
+                storage,
+            )
+            .await
+        }
+        Err(error) => debug!("Failed to start RLPx connection with {peer_addr}: {error}"),
+    }
+    peer_manager.end_dial(node.node_id);
+}
 
-    // Testing disconnect message
-    // conn.send(
-    //     RLPxMessage::Disconnect(p2p::DisconnectMessage::new(Some(3))),
-    //     &mut stream,
-    // )
-    // .await;
+async fn connect(
+    peer_addr: SocketAddr,
+    node_id: H512,
+    signer: &SigningKey,
+    storage: &Store,
+) -> Result<(RLPxConnection, TcpStream), RLPxError> {
+    let remote_pubkey =
+        id2pubkey(node_id).ok_or(RLPxError::Handshake("invalid node id".to_string()))?;
+    let mut stream = TcpStream::connect(peer_addr).await?;
+    let conn = connection::initiate(&mut stream, signer, &remote_pubkey, storage).await?;
+    Ok((conn, stream))
+}
 
-    conn.receive(&mut stream).await;
-    conn.receive(&mut stream).await;
+/// Registers the peer and handles its messages until the connection is closed
+async fn handle_peer(
+    mut conn: RLPxConnection,
+    mut stream: TcpStream,
+    peer_addr: SocketAddr,
+    inbound: bool,
+    peer_manager: PeerManager,
+    storage: Store,
+) {
+    let node_id = conn.node_id();
+    let peer = PeerInfo {
+        node_id,
+        remote_addr: peer_addr,
+        inbound,
+        client_id: conn.client_id().to_string(),
+        capabilities: conn.capabilities().to_vec(),
+        head: Some(conn.head()),
+    };
+    let result = match peer_manager.add_peer(peer) {
+        Ok(()) => {
+            info!("Established RLPx connection with {peer_addr}");
+            let requests = peer_manager.request_channel(node_id);
+            let result = conn
+                .handle_messages(&mut stream, requests, &peer_manager, &storage)
+                .await;
+            peer_manager.remove_peer(node_id);
+            result
+        }
+        Err(rejection) => Err(rejection.into()),
+    };
+    if let Err(error) = &result {
+        debug!("RLPx connection with {peer_addr} failed: {error}");
+    }
+    conn.close(&mut stream, result.err().as_ref()).await;
+    info!("Closed RLPx connection with {peer_addr}");
 }
 
 pub fn node_id_from_signing_key(signer: &SigningKey) -> H512 {
@@ -404,3 +488,67 @@ pub fn node_id_from_signing_key(signer: &SigningKey) -> H512 {
     let encoded = public_key.to_encoded_point(false);
     H512::from_slice(&encoded.as_bytes()[1..])
 }
+
+#[cfg(test)]
+mod tests {
+    use ethereum_rust_core::types::{BlockHeader, ChainConfig};
+    use ethereum_rust_storage::EngineType;
+    use peer_manager::PeerLimits;
+    use rand::rngs::OsRng;
+    use rlpx::message::Message as RLPxMessage;
+
+    use super::*;
+
+    fn test_store() -> Store {
+        let store = Store::new("temp.db", EngineType::InMemory).unwrap();
+        store
+            .set_chain_config(&ChainConfig {
+                chain_id: 1,
+                ..Default::default()
+            })
+            .unwrap();
+        store.add_block_header(0, BlockHeader::default()).unwrap();
+        store.update_latest_block_number(0).unwrap();
+        store
+    }
+
+    #[tokio::test]
+    async fn connections_to_self_are_closed_with_the_spec_reason() {
+        let signer = SigningKey::random(&mut OsRng);
+        let store = test_store();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local_addr = listener.local_addr().unwrap();
+        let local_node = Node {
+            ip: local_addr.ip(),
+            udp_port: local_addr.port(),
+            tcp_port: local_addr.port(),
+            node_id: node_id_from_signing_key(&signer),
+        };
+        let peer_manager = PeerManager::new(local_node, PeerLimits::default());
+
+        let server = async {
+            let (stream, peer_addr) = listener.accept().await.unwrap();
+            accept_peer(
+                stream,
+                peer_addr,
+                signer.clone(),
+                peer_manager.clone(),
+                store.clone(),
+            )
+            .await;
+        };
+        // We dial our own listening address, as a node finding itself through discovery would
+        let client = async {
+            let (mut conn, mut stream) = connect(local_addr, local_node.node_id, &signer, &store)
+                .await
+                .unwrap();
+            conn.receive(&mut stream).await.unwrap()
+        };
+        let (_, message) = tokio::join!(server, client);
+        let RLPxMessage::Disconnect(msg) = message else {
+            panic!("expected a Disconnect message");
+        };
+        assert_eq!(msg.reason(), Some(0x0a));
+        assert_eq!(peer_manager.peer_count(), 0);
+    }
+}
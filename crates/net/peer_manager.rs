@@ -0,0 +1,341 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use ethereum_rust_core::{H256, H512};
+use thiserror::Error;
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::timeout,
+};
+
+use crate::{
+    rlpx::{connection::PeerRequest, error::RLPxError, eth::EthMessage},
+    types::Node,
+};
+
+/// Time to wait before dialing a node again after trying to connect to it
+const DIAL_BACKOFF: Duration = Duration::from_secs(30);
+/// Amount of requests to a peer that can be waiting to be sent
+const REQUEST_BUFFER_SIZE: usize = 32;
+/// Time a peer has to answer a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Limits on the amount of peers the node keeps connections with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerLimits {
+    /// Maximum amount of connected peers, both inbound and outbound
+    pub max_peers: usize,
+    /// Only one out of `dial_ratio` peers can be dialed by us, the rest of the slots are left for inbound connections
+    pub dial_ratio: usize,
+}
+
+impl Default for PeerLimits {
+    fn default() -> Self {
+        Self {
+            max_peers: 50,
+            dial_ratio: 3,
+        }
+    }
+}
+
+impl PeerLimits {
+    pub fn max_outbound(&self) -> usize {
+        self.max_peers / self.dial_ratio.max(1)
+    }
+
+    pub fn max_inbound(&self) -> usize {
+        self.max_peers - self.max_outbound()
+    }
+}
+
+/// Information about a connected peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerInfo {
+    pub node_id: H512,
+    pub remote_addr: SocketAddr,
+    /// Whether the peer started the connection
+    pub inbound: bool,
+    pub client_id: String,
+    pub capabilities: Vec<(String, u8)>,
+    /// Hash of the peer's head block, once it tells us
+    pub head: Option<H256>,
+}
+
+/// Reasons to refuse a connection with a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum PeerRejected {
+    #[error("Too many peers")]
+    TooManyPeers,
+    #[error("Already connected")]
+    AlreadyConnected,
+    #[error("Connected to self")]
+    SelfConnection,
+}
+
+impl PeerRejected {
+    /// Reason sent to the peer when disconnecting it, as numbered by the devp2p spec
+    pub fn disconnect_reason(&self) -> u8 {
+        match self {
+            PeerRejected::TooManyPeers => 0x04,
+            PeerRejected::AlreadyConnected => 0x05,
+            PeerRejected::SelfConnection => 0x0a,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct PeerSet {
+    peers: HashMap<H512, PeerInfo>,
+    /// Nodes we are currently dialing
+    dialing: HashSet<H512>,
+    /// Last time each node was dialed
+    last_dialed: HashMap<H512, Instant>,
+    /// Channels to send requests to each of the peers through
+    request_channels: HashMap<H512, mpsc::Sender<PeerRequest>>,
+}
+
+impl PeerSet {
+    fn count(&self, inbound: bool) -> usize {
+        self.peers
+            .values()
+            .filter(|peer| peer.inbound == inbound)
+            .count()
+    }
+}
+
+/// Keeps track of the peers the node is connected to, deciding which nodes to dial and which connections to accept
+/// so that the peer limits are respected
+#[derive(Debug, Clone)]
+pub struct PeerManager {
+    local_node: Node,
+    limits: PeerLimits,
+    peers: Arc<Mutex<PeerSet>>,
+}
+
+impl PeerManager {
+    pub fn new(local_node: Node, limits: PeerLimits) -> Self {
+        Self {
+            local_node,
+            limits,
+            peers: Default::default(),
+        }
+    }
+
+    pub fn local_node(&self) -> Node {
+        self.local_node
+    }
+
+    pub fn limits(&self) -> PeerLimits {
+        self.limits
+    }
+
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        self.peers.lock().unwrap().peers.values().cloned().collect()
+    }
+
+    pub fn peer_count(&self) -> usize {
+        self.peers.lock().unwrap().peers.len()
+    }
+
+    /// Registers a peer after completing the connection with it.
+    /// Fails if the connection has to be closed, either because there are no free slots left or it is a duplicate
+    pub fn add_peer(&self, peer: PeerInfo) -> Result<(), PeerRejected> {
+        let mut set = self.peers.lock().unwrap();
+        if !peer.inbound {
+            set.dialing.remove(&peer.node_id);
+        }
+        if peer.node_id == self.local_node.node_id {
+            return Err(PeerRejected::SelfConnection);
+        }
+        if set.peers.contains_key(&peer.node_id) {
+            return Err(PeerRejected::AlreadyConnected);
+        }
+        let max_peers = if peer.inbound {
+            self.limits.max_inbound()
+        } else {
+            self.limits.max_outbound()
+        };
+        if set.count(peer.inbound) >= max_peers {
+            return Err(PeerRejected::TooManyPeers);
+        }
+        set.peers.insert(peer.node_id, peer);
+        Ok(())
+    }
+
+    pub fn remove_peer(&self, node_id: H512) {
+        let mut set = self.peers.lock().unwrap();
+        set.peers.remove(&node_id);
+        set.request_channels.remove(&node_id);
+    }
+
+    pub fn update_head(&self, node_id: H512, head: H256) {
+        if let Some(peer) = self.peers.lock().unwrap().peers.get_mut(&node_id) {
+            peer.head = Some(head);
+        }
+    }
+
+    /// Opens the channel requests to a peer are sent through, whose receiving end is handled by the peer's connection
+    pub(crate) fn request_channel(&self, node_id: H512) -> mpsc::Receiver<PeerRequest> {
+        let (sender, receiver) = mpsc::channel(REQUEST_BUFFER_SIZE);
+        self.peers
+            .lock()
+            .unwrap()
+            .request_channels
+            .insert(node_id, sender);
+        receiver
+    }
+
+    /// Sends a request to a connected peer and waits for its response.
+    /// Fails if the peer isn't connected, or if it doesn't answer within [`REQUEST_TIMEOUT`]
+    pub(crate) async fn request(
+        &self,
+        node_id: H512,
+        message: EthMessage,
+    ) -> Result<EthMessage, RLPxError> {
+        let sender = self
+            .peers
+            .lock()
+            .unwrap()
+            .request_channels
+            .get(&node_id)
+            .cloned()
+            .ok_or_else(|| RLPxError::RequestFailed("peer not connected".to_string()))?;
+        let (response, response_receiver) = oneshot::channel();
+        sender
+            .send(PeerRequest { message, response })
+            .await
+            .map_err(|_| RLPxError::RequestFailed("connection closed".to_string()))?;
+        timeout(REQUEST_TIMEOUT, response_receiver)
+            .await?
+            .map_err(|_| RLPxError::RequestFailed("connection closed".to_string()))
+    }
+
+    /// Picks the nodes to dial out of the given candidates, as many as free outbound slots there are.
+    /// Nodes already connected, being dialed, or dialed recently are skipped.
+    /// The returned nodes are marked as being dialed until either [`Self::add_peer`] or [`Self::end_dial`] is called
+    pub fn dial_candidates(&self, candidates: impl IntoIterator<Item = Node>) -> Vec<Node> {
+        let mut set = self.peers.lock().unwrap();
+        let busy_slots = set.count(false) + set.dialing.len();
+        let free_slots = self.limits.max_outbound().saturating_sub(busy_slots);
+
+        let now = Instant::now();
+        let mut nodes = vec![];
+        for node in candidates {
+            if nodes.len() == free_slots {
+                break;
+            }
+            let recently_dialed = set
+                .last_dialed
+                .get(&node.node_id)
+                .is_some_and(|last_dialed| now.duration_since(*last_dialed) < DIAL_BACKOFF);
+            if node.tcp_port == 0
+                || node.node_id == self.local_node.node_id
+                || recently_dialed
+                || set.peers.contains_key(&node.node_id)
+                || !set.dialing.insert(node.node_id)
+            {
+                continue;
+            }
+            set.last_dialed.insert(node.node_id, now);
+            nodes.push(node);
+        }
+        set.last_dialed
+            .retain(|_, last_dialed| now.duration_since(*last_dialed) < DIAL_BACKOFF);
+        nodes
+    }
+
+    /// Frees the outbound slot taken by a node whose dial failed
+    pub fn end_dial(&self, node_id: H512) {
+        self.peers.lock().unwrap().dialing.remove(&node_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: u64) -> Node {
+        Node {
+            ip: "127.0.0.1".parse().unwrap(),
+            udp_port: 30303,
+            tcp_port: 30303,
+            node_id: H512::from_low_u64_be(id),
+        }
+    }
+
+    fn peer(id: u64, inbound: bool) -> PeerInfo {
+        PeerInfo {
+            node_id: H512::from_low_u64_be(id),
+            remote_addr: "127.0.0.1:30303".parse().unwrap(),
+            inbound,
+            client_id: "test".to_string(),
+            capabilities: vec![("p2p".to_string(), 5)],
+            head: None,
+        }
+    }
+
+    #[test]
+    fn peer_limits_are_enforced_per_direction() {
+        let limits = PeerLimits {
+            max_peers: 3,
+            dial_ratio: 3,
+        };
+        let manager = PeerManager::new(node(0), limits);
+
+        assert_eq!(manager.add_peer(peer(1, false)), Ok(()));
+        assert_eq!(
+            manager.add_peer(peer(2, false)),
+            Err(PeerRejected::TooManyPeers)
+        );
+        assert_eq!(manager.add_peer(peer(2, true)), Ok(()));
+        assert_eq!(manager.add_peer(peer(3, true)), Ok(()));
+        assert_eq!(
+            manager.add_peer(peer(4, true)),
+            Err(PeerRejected::TooManyPeers)
+        );
+        assert_eq!(
+            manager.add_peer(peer(0, true)),
+            Err(PeerRejected::SelfConnection)
+        );
+        assert_eq!(manager.peer_count(), 3);
+
+        manager.remove_peer(H512::from_low_u64_be(3));
+        assert_eq!(
+            manager.add_peer(peer(1, true)),
+            Err(PeerRejected::AlreadyConnected)
+        );
+        assert_eq!(manager.add_peer(peer(4, true)), Ok(()));
+    }
+
+    #[test]
+    fn dial_candidates_fill_free_outbound_slots() {
+        let limits = PeerLimits {
+            max_peers: 6,
+            dial_ratio: 2,
+        };
+        let manager = PeerManager::new(node(0), limits);
+        manager.add_peer(peer(1, false)).unwrap();
+
+        let mut unreachable = node(2);
+        unreachable.tcp_port = 0;
+        let candidates = vec![node(0), node(1), unreachable, node(3), node(4), node(5)];
+        let dialed: Vec<_> = manager
+            .dial_candidates(candidates.clone())
+            .iter()
+            .map(|node| node.node_id)
+            .collect();
+        assert_eq!(
+            dialed,
+            vec![H512::from_low_u64_be(3), H512::from_low_u64_be(4)]
+        );
+
+        // Nodes aren't dialed again while they are being dialed or right after it
+        manager.end_dial(H512::from_low_u64_be(3));
+        let dialed = manager.dial_candidates(candidates);
+        assert_eq!(dialed, vec![node(5)]);
+    }
+}
@@ -1,4 +1,6 @@
 pub mod connection;
+pub(crate) mod error;
+pub(crate) mod eth;
 pub mod handshake;
 pub mod message;
 pub mod p2p;
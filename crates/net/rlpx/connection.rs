@@ -4,37 +4,292 @@ use aes::{
 };
 use ethereum_rust_core::{
     rlp::{decode::RLPDecode, encode::RLPEncode},
-    H128, H256,
+    H128, H256, H512,
 };
+use ethereum_rust_storage::Store;
+use k256::{ecdsa::SigningKey, PublicKey, SecretKey};
 use sha3::{Digest, Keccak256};
-use std::pin::pin;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::{collections::HashMap, pin::pin, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, oneshot},
+    time::{interval_at, timeout, Instant},
+};
+use tracing::debug;
+
+use crate::peer_manager::PeerManager;
+
+use super::{
+    error::RLPxError,
+    eth::{status::StatusMessage, EthMessage},
+    handshake::RLPxLocalClient,
+    message::{self as rlpx, shared_capabilities, SharedCapability},
+    p2p::{self, DisconnectMessage, PingMessage, PongMessage},
+    utils::pubkey2id,
+};
 
-use super::message as rlpx;
+pub const SUPPORTED_CAPABILITIES: [(&str, u8); 2] = [("p2p", 5), ("eth", 68)];
 
-pub const SUPPORTED_CAPABILITIES: [(&str, u8); 1] = [("p2p", 5)];
-// pub const SUPPORTED_CAPABILITIES: [(&str, u8); 3] = [("p2p", 5), ("eth", 68), ("snap", 1)];
+/// Time a peer has to complete the handshake, the Hello exchange and the Status exchange
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time a peer can stay without sending any message before the connection is closed.
+/// Peers send a Ping every 15 seconds, so this is only reached when the connection is dead
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time between each Ping sent to the peer. The peer has until the next one is due to answer with a Pong
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+/// Amount of messages read from the peer that can be waiting to be handled
+const MESSAGE_BUFFER_SIZE: usize = 16;
 
 pub(crate) type Aes256Ctr64BE = ctr::Ctr64BE<aes::Aes256>;
 
 /// Fully working RLPx connection.
 pub(crate) struct RLPxConnection {
-    #[allow(unused)]
     state: RLPxState,
-    // ...capabilities information
+    /// Id of the peer's node, as sent in its Hello message and checked against the key used in the handshake
+    node_id: H512,
+    /// Client the peer is running, as sent in its Hello message
+    client_id: String,
+    /// Capabilities advertised by the peer in its Hello message
+    capabilities: Vec<(String, u8)>,
+    /// Capabilities supported by both ends, which message ids are assigned to
+    shared_capabilities: Vec<SharedCapability>,
+    /// Hash of the peer's head block, as sent in its Status message
+    head: H256,
+    /// Id given to the next request sent to the peer
+    next_request_id: u64,
+    /// Requests waiting for their responses, by request id
+    pending_requests: HashMap<u64, PendingRequest>,
+}
+
+/// Request sent to the peer, along with the channel to send its response through
+struct PendingRequest {
+    /// Code of the message the peer has to answer with
+    response_code: u8,
+    response: oneshot::Sender<EthMessage>,
+}
+
+/// Request to send to a peer, along with the channel to send its response through
+pub(crate) struct PeerRequest {
+    pub message: EthMessage,
+    pub response: oneshot::Sender<EthMessage>,
 }
 
 impl RLPxConnection {
-    pub async fn send<S: AsyncWrite>(&mut self, message: rlpx::Message, stream: S) {
-        let mut frame_buffer = vec![];
-        message.encode(&mut frame_buffer);
-        write_frame(frame_buffer, stream, &mut self.state).await;
+    pub fn node_id(&self) -> H512 {
+        self.node_id
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    pub fn capabilities(&self) -> &[(String, u8)] {
+        &self.capabilities
+    }
+
+    pub fn head(&self) -> H256 {
+        self.head
+    }
+
+    pub async fn send<S: AsyncWrite>(
+        &mut self,
+        message: rlpx::Message,
+        stream: S,
+    ) -> Result<(), RLPxError> {
+        send_message(message, stream, &mut self.state, &self.shared_capabilities).await
+    }
+
+    pub async fn receive<S: AsyncRead>(&mut self, stream: S) -> Result<rlpx::Message, RLPxError> {
+        receive_message(stream, &mut self.state, &self.shared_capabilities).await
+    }
+
+    /// Exchanges Status messages with the peer, checking that it is on the same chain as us
+    async fn exchange_status<S: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        mut stream: S,
+        storage: &Store,
+    ) -> Result<(), RLPxError> {
+        if !self
+            .shared_capabilities
+            .iter()
+            .any(|capability| capability.name == "eth")
+        {
+            return Err(RLPxError::NoSharedCapabilities);
+        }
+        let status = StatusMessage::new(storage)?;
+        self.send(rlpx::Message::Eth(EthMessage::Status(status)), &mut stream)
+            .await?;
+        match self.receive(&mut stream).await? {
+            rlpx::Message::Eth(EthMessage::Status(remote_status)) => {
+                remote_status.validate(storage)?;
+                self.head = remote_status.block_hash;
+                Ok(())
+            }
+            rlpx::Message::Disconnect(msg) => {
+                debug!("Peer disconnected with reason {:?}", msg.reason());
+                Err(RLPxError::Disconnected)
+            }
+            _ => Err(RLPxError::UnexpectedMessage(
+                "expected Status message".to_string(),
+            )),
+        }
+    }
+
+    /// Handles the messages sent by the peer, answering them with the data in the store when needed,
+    /// and sends it the requests received through the channel, until the peer disconnects.
+    /// The peer's head is updated in the peer manager as it announces new blocks.
+    /// Fails if the peer doesn't send anything for [`IDLE_TIMEOUT`], or doesn't answer a Ping within [`PING_INTERVAL`]
+    pub async fn handle_messages<S: AsyncRead + AsyncWrite>(
+        &mut self,
+        stream: S,
+        mut requests: mpsc::Receiver<PeerRequest>,
+        peer_manager: &PeerManager,
+        storage: &Store,
+    ) -> Result<(), RLPxError> {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let (messages_sender, mut messages) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+        // Reading is done on its own so that a message is never left half read while writing.
+        // It only uses the ingress half of the state, which isn't touched by the connection from now on
+        let mut incoming = pin!(read_messages(
+            reader,
+            self.state.clone(),
+            self.shared_capabilities.clone(),
+            messages_sender,
+        ));
+        let mut ping_interval = interval_at(Instant::now() + PING_INTERVAL, PING_INTERVAL);
+        let mut awaiting_pong = false;
+
+        loop {
+            tokio::select! {
+                // Messages already read are handled before a read error is reported
+                biased;
+                Some(message) = messages.recv() => match message {
+                    rlpx::Message::Disconnect(msg) => {
+                        debug!("Peer disconnected with reason {:?}", msg.reason());
+                        return Ok(());
+                    }
+                    rlpx::Message::Ping(_) => {
+                        self.send(rlpx::Message::Pong(PongMessage::new()), &mut writer)
+                            .await?
+                    }
+                    rlpx::Message::Pong(_) => awaiting_pong = false,
+                    rlpx::Message::Hello(_) => {
+                        return Err(RLPxError::UnexpectedMessage("Hello".to_string()))
+                    }
+                    rlpx::Message::Eth(msg) => {
+                        self.handle_eth_message(msg, &mut writer, peer_manager, storage)
+                            .await?
+                    }
+                },
+                Some(request) = requests.recv() => self.send_request(request, &mut writer).await?,
+                result = &mut incoming => return result,
+                _ = ping_interval.tick() => {
+                    if awaiting_pong {
+                        return Err(RLPxError::PingTimeout);
+                    }
+                    self.send(rlpx::Message::Ping(PingMessage::new()), &mut writer)
+                        .await?;
+                    awaiting_pong = true;
+                }
+            }
+        }
     }
 
-    pub async fn receive<S: AsyncRead>(&mut self, stream: S) -> rlpx::Message {
-        let frame_data = read_frame(stream, &mut self.state).await;
-        let (msg_id, msg_data): (u8, _) = RLPDecode::decode_unfinished(&frame_data).unwrap();
-        rlpx::Message::decode(msg_id, msg_data).unwrap()
+    async fn handle_eth_message<S: AsyncWrite>(
+        &mut self,
+        message: EthMessage,
+        stream: S,
+        peer_manager: &PeerManager,
+        storage: &Store,
+    ) -> Result<(), RLPxError> {
+        match message.request_id() {
+            Some(id) if !message.is_request() => {
+                match self.pending_requests.remove(&id) {
+                    Some(pending) if pending.response_code != message.code() => {
+                        return Err(RLPxError::UnexpectedMessage(format!(
+                            "message {:#x} answering request {id}, which expects {:#x}",
+                            message.code(),
+                            pending.response_code
+                        )));
+                    }
+                    // The requester may have given up on the response already
+                    Some(pending) => {
+                        let _ = pending.response.send(message);
+                    }
+                    None => debug!("Ignoring response to unknown request {id}"),
+                }
+                Ok(())
+            }
+            _ => match message {
+                EthMessage::Status(_) => Err(RLPxError::UnexpectedMessage("Status".to_string())),
+                EthMessage::NewBlockHashes(announcement) => {
+                    // The newest announced block becomes the peer's head
+                    if let Some((hash, _)) = announcement
+                        .block_hashes
+                        .iter()
+                        .max_by_key(|(_, number)| *number)
+                    {
+                        self.head = *hash;
+                        peer_manager.update_head(self.node_id, *hash);
+                    }
+                    Ok(())
+                }
+                _ => {
+                    let code = message.code();
+                    let storage = storage.clone();
+                    // Reading from the store blocks, so it is kept off the async runtime
+                    match tokio::task::spawn_blocking(move || message.answer(&storage)).await?? {
+                        Some(response) => self.send(rlpx::Message::Eth(response), stream).await,
+                        // TODO: handle the peer's transaction announcements
+                        None => {
+                            debug!("Ignoring eth message {code:#x}");
+                            Ok(())
+                        }
+                    }
+                }
+            },
+        }
+    }
+
+    async fn send_request<S: AsyncWrite>(
+        &mut self,
+        request: PeerRequest,
+        stream: S,
+    ) -> Result<(), RLPxError> {
+        let PeerRequest {
+            mut message,
+            response,
+        } = request;
+        let Some(response_code) = message.response_code() else {
+            // Dropping the response channel lets the requester know
+            debug!("Not sending eth message {:#x} as a request", message.code());
+            return Ok(());
+        };
+        let id = self.next_request_id;
+        self.next_request_id += 1;
+        message.set_request_id(id);
+
+        self.pending_requests
+            .retain(|_, pending| !pending.response.is_closed());
+        self.pending_requests.insert(
+            id,
+            PendingRequest {
+                response_code,
+                response,
+            },
+        );
+        self.send(rlpx::Message::Eth(message), stream).await
+    }
+
+    /// Closes the connection. When closing it because of an error, the peer is told the reason if the error allows it
+    pub async fn close<S: AsyncWrite + Unpin>(&mut self, mut stream: S, error: Option<&RLPxError>) {
+        if let Some(reason) = error.and_then(RLPxError::disconnect_reason) {
+            let message = rlpx::Message::Disconnect(DisconnectMessage::new(Some(reason)));
+            if let Err(error) = self.send(message, &mut stream).await {
+                debug!("Failed to send Disconnect message: {error}");
+            }
+        }
+        let _ = stream.shutdown().await;
     }
 }
 
@@ -48,26 +303,182 @@ impl RLPxConnectionPending {
         Self { state }
     }
 
-    pub async fn send<S: AsyncWrite>(&mut self, message: rlpx::Message, stream: S) {
-        let mut frame_buffer = vec![];
-        message.encode(&mut frame_buffer);
-        write_frame(frame_buffer, stream, &mut self.state).await;
+    pub async fn send<S: AsyncWrite>(
+        &mut self,
+        message: rlpx::Message,
+        stream: S,
+    ) -> Result<(), RLPxError> {
+        send_message(message, stream, &mut self.state, &[]).await
     }
 
-    pub async fn receive<S: AsyncRead>(self, stream: S) -> RLPxConnection {
+    pub async fn receive<S: AsyncRead>(self, stream: S) -> Result<RLPxConnection, RLPxError> {
         let Self { mut state } = self;
-        let frame_data = read_frame(stream, &mut state).await;
-        let (msg_id, msg_data): (u8, _) = RLPDecode::decode_unfinished(&frame_data).unwrap();
-        let message = rlpx::Message::decode(msg_id, msg_data).unwrap();
-        assert!(
-            matches!(message, rlpx::Message::Hello(_)),
-            "Expected Hello message"
-        );
-        RLPxConnection { state }
+        match receive_message(stream, &mut state, &[]).await? {
+            rlpx::Message::Hello(hello) => Ok(RLPxConnection {
+                state,
+                node_id: pubkey2id(&hello.node_id),
+                client_id: hello.client_id,
+                shared_capabilities: shared_capabilities(
+                    &SUPPORTED_CAPABILITIES,
+                    &hello.capabilities,
+                ),
+                capabilities: hello.capabilities,
+                head: H256::zero(),
+                next_request_id: 0,
+                pending_requests: HashMap::new(),
+            }),
+            rlpx::Message::Disconnect(msg) => {
+                debug!("Peer disconnected with reason {:?}", msg.reason());
+                Err(RLPxError::Disconnected)
+            }
+            _ => Err(RLPxError::UnexpectedMessage(
+                "expected Hello message".to_string(),
+            )),
+        }
+    }
+
+    /// Sends our Hello message and waits for the peer's one, completing the connection.
+    /// Fails if the node id in the peer's Hello isn't the key it used in the handshake
+    async fn exchange_hello<S: AsyncRead + AsyncWrite + Unpin>(
+        mut self,
+        mut stream: S,
+        signer: &SigningKey,
+        remote_pubkey: &PublicKey,
+    ) -> Result<RLPxConnection, RLPxError> {
+        let hello_msg = rlpx::Message::Hello(p2p::HelloMessage::new(
+            SUPPORTED_CAPABILITIES
+                .into_iter()
+                .map(|(name, version)| (name.to_string(), version))
+                .collect(),
+            PublicKey::from(signer.verifying_key()),
+        ));
+        self.send(hello_msg, &mut stream).await?;
+        let conn = self.receive(&mut stream).await?;
+        if conn.node_id != pubkey2id(remote_pubkey) {
+            return Err(RLPxError::Handshake(
+                "node id in Hello doesn't match the handshake's key".to_string(),
+            ));
+        }
+        Ok(conn)
     }
 }
 
-async fn write_frame<S: AsyncWrite>(mut frame_data: Vec<u8>, stream: S, state: &mut RLPxState) {
+/// Accepts a connection started by a peer, performing the responder side of the handshake, followed by
+/// the Hello and Status exchanges. Fails if they aren't completed within [`HANDSHAKE_TIMEOUT`]
+pub(crate) async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    signer: &SigningKey,
+    storage: &Store,
+) -> Result<(RLPxConnection, PublicKey), RLPxError> {
+    timeout(HANDSHAKE_TIMEOUT, async {
+        let secret_key: SecretKey = signer.clone().into();
+        let (auth_data, msg) = read_handshake_message(&mut stream).await?;
+
+        let mut ack_message = vec![];
+        let (pending_conn, remote_pubkey) = RLPxLocalClient::random()
+            .decode_auth_message_and_encode_ack(&secret_key, &msg, auth_data, &mut ack_message)?;
+        stream.write_all(&ack_message).await?;
+
+        let mut conn = pending_conn
+            .exchange_hello(&mut stream, signer, &remote_pubkey)
+            .await?;
+        conn.exchange_status(&mut stream, storage).await?;
+        Ok::<_, RLPxError>((conn, remote_pubkey))
+    })
+    .await?
+}
+
+/// Starts a connection with a peer, performing the initiator side of the handshake, followed by
+/// the Hello and Status exchanges. Fails if they aren't completed within [`HANDSHAKE_TIMEOUT`]
+pub(crate) async fn initiate<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    signer: &SigningKey,
+    remote_pubkey: &PublicKey,
+    storage: &Store,
+) -> Result<RLPxConnection, RLPxError> {
+    timeout(HANDSHAKE_TIMEOUT, async {
+        let secret_key: SecretKey = signer.clone().into();
+        let mut client = RLPxLocalClient::random();
+
+        let mut auth_message = vec![];
+        client.encode_auth_message(&secret_key, remote_pubkey, &mut auth_message);
+        stream.write_all(&auth_message).await?;
+
+        let (auth_data, msg) = read_handshake_message(&mut stream).await?;
+        let pending_conn = client.decode_ack_message(&secret_key, &msg, auth_data)?;
+
+        let mut conn = pending_conn
+            .exchange_hello(&mut stream, signer, remote_pubkey)
+            .await?;
+        conn.exchange_status(&mut stream, storage).await?;
+        Ok(conn)
+    })
+    .await?
+}
+
+/// Reads an Auth or Ack message, returning its size prefix along with the rest of the message
+async fn read_handshake_message<S: AsyncRead + Unpin>(
+    mut stream: S,
+) -> Result<([u8; 2], Vec<u8>), RLPxError> {
+    let mut auth_data = [0; 2];
+    stream.read_exact(&mut auth_data).await?;
+    let msg_size = u16::from_be_bytes(auth_data) as usize;
+
+    let mut msg = vec![0; msg_size];
+    stream.read_exact(&mut msg).await?;
+    Ok((auth_data, msg))
+}
+
+/// Reads the messages sent by the peer, passing them on through the channel until it is closed.
+/// Fails if the peer doesn't send anything for [`IDLE_TIMEOUT`]
+async fn read_messages<S: AsyncRead + Unpin>(
+    mut stream: S,
+    mut state: RLPxState,
+    capabilities: Vec<SharedCapability>,
+    messages: mpsc::Sender<rlpx::Message>,
+) -> Result<(), RLPxError> {
+    loop {
+        let message = timeout(
+            IDLE_TIMEOUT,
+            receive_message(&mut stream, &mut state, &capabilities),
+        )
+        .await??;
+        if messages.send(message).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+async fn send_message<S: AsyncWrite>(
+    message: rlpx::Message,
+    stream: S,
+    state: &mut RLPxState,
+    capabilities: &[SharedCapability],
+) -> Result<(), RLPxError> {
+    let msg_id = message.id(capabilities).ok_or_else(|| {
+        RLPxError::InvalidMessage("capability not shared with the peer".to_string())
+    })?;
+    let mut frame_buffer = vec![];
+    msg_id.encode(&mut frame_buffer);
+    message.encode(&mut frame_buffer);
+    write_frame(frame_buffer, stream, state).await
+}
+
+async fn receive_message<S: AsyncRead>(
+    stream: S,
+    state: &mut RLPxState,
+    capabilities: &[SharedCapability],
+) -> Result<rlpx::Message, RLPxError> {
+    let frame_data = read_frame(stream, state).await?;
+    let (msg_id, msg_data): (u8, _) = RLPDecode::decode_unfinished(&frame_data)?;
+    Ok(rlpx::Message::decode(msg_id, msg_data, capabilities)?)
+}
+
+async fn write_frame<S: AsyncWrite>(
+    mut frame_data: Vec<u8>,
+    stream: S,
+    state: &mut RLPxState,
+) -> Result<(), RLPxError> {
     let mut stream = pin!(stream);
 
     let egress_aes = &mut state.egress_aes;
@@ -98,7 +509,7 @@ async fn write_frame<S: AsyncWrite>(mut frame_data: Vec<u8>, stream: S, state: &
     header.extend_from_slice(&header_mac[..16]);
 
     // Write header
-    stream.write_all(&header).await.unwrap();
+    stream.write_all(&header).await?;
 
     // Pad to next multiple of 16
     frame_data.resize(frame_data.len().next_multiple_of(16), 0);
@@ -106,7 +517,7 @@ async fn write_frame<S: AsyncWrite>(mut frame_data: Vec<u8>, stream: S, state: &
     let frame_ciphertext = frame_data;
 
     // Send frame
-    stream.write_all(&frame_ciphertext).await.unwrap();
+    stream.write_all(&frame_ciphertext).await?;
 
     // Compute frame-mac
     egress_mac.update(&frame_ciphertext);
@@ -122,10 +533,14 @@ async fn write_frame<S: AsyncWrite>(mut frame_data: Vec<u8>, stream: S, state: &
     let frame_mac = egress_mac.clone().finalize();
 
     // Send frame-mac
-    stream.write_all(&frame_mac[..16]).await.unwrap();
+    stream.write_all(&frame_mac[..16]).await?;
+    Ok(())
 }
 
-pub(crate) async fn read_frame<S: AsyncRead>(stream: S, state: &mut RLPxState) -> Vec<u8> {
+pub(crate) async fn read_frame<S: AsyncRead>(
+    stream: S,
+    state: &mut RLPxState,
+) -> Result<Vec<u8>, RLPxError> {
     let mut stream = pin!(stream);
 
     let ingress_aes = &mut state.ingress_aes;
@@ -135,7 +550,7 @@ pub(crate) async fn read_frame<S: AsyncRead>(stream: S, state: &mut RLPxState) -
 
     // Receive the message's frame header
     let mut frame_header = [0; 32];
-    stream.read_exact(&mut frame_header).await.unwrap();
+    stream.read_exact(&mut frame_header).await?;
     // Both are padded to the block's size (16 bytes)
     let (header_ciphertext, header_mac) = frame_header.split_at_mut(16);
 
@@ -154,14 +569,20 @@ pub(crate) async fn read_frame<S: AsyncRead>(stream: S, state: &mut RLPxState) -
     // header-mac = keccak256.digest(egress-mac)[:16]
     let expected_header_mac = H128(ingress_mac.clone().finalize()[..16].try_into().unwrap());
 
-    assert_eq!(header_mac, expected_header_mac.0);
+    if header_mac != expected_header_mac.0 {
+        return Err(RLPxError::InvalidMac);
+    }
 
     let header_text = header_ciphertext;
     ingress_aes.apply_keystream(header_text);
 
     // header-data = [capability-id, context-id]
     // Both are unused, and always zero
-    assert_eq!(&header_text[3..6], &(0_u8, 0_u8).encode_to_vec());
+    if header_text[3..6] != (0_u8, 0_u8).encode_to_vec() {
+        return Err(RLPxError::InvalidMessage(
+            "invalid frame header data".to_string(),
+        ));
+    }
 
     let frame_size: usize = u32::from_be_bytes([0, header_text[0], header_text[1], header_text[2]])
         .try_into()
@@ -169,7 +590,7 @@ pub(crate) async fn read_frame<S: AsyncRead>(stream: S, state: &mut RLPxState) -
     // Receive the hello message
     let padded_size = frame_size.next_multiple_of(16);
     let mut frame_data = vec![0; padded_size + 16];
-    stream.read_exact(&mut frame_data).await.unwrap();
+    stream.read_exact(&mut frame_data).await?;
     let (frame_ciphertext, frame_mac) = frame_data.split_at_mut(padded_size);
 
     // check MAC
@@ -184,14 +605,16 @@ pub(crate) async fn read_frame<S: AsyncRead>(stream: S, state: &mut RLPxState) -
     ingress_mac.update(frame_mac_seed);
     let expected_frame_mac: [u8; 16] = ingress_mac.clone().finalize()[..16].try_into().unwrap();
 
-    assert_eq!(frame_mac, expected_frame_mac);
+    if frame_mac != expected_frame_mac {
+        return Err(RLPxError::InvalidMac);
+    }
 
     // decrypt frame
     ingress_aes.apply_keystream(frame_ciphertext);
 
     let (frame_data, _padding) = frame_ciphertext.split_at(frame_size);
 
-    frame_data.to_vec()
+    Ok(frame_data.to_vec())
 }
 
 /// The current state of an RLPx connection
@@ -243,9 +666,87 @@ impl RLPxState {
 
 #[cfg(test)]
 mod tests {
-    use crate::rlpx::handshake::RLPxLocalClient;
+    use crate::{
+        node_id_from_signing_key,
+        peer_manager::{PeerInfo, PeerLimits, PeerManager},
+        rlpx::{
+            connection::{accept, initiate, read_handshake_message, PeerRequest, RLPxConnection},
+            error::RLPxError,
+            eth::{
+                blocks::{
+                    BlockBodies, BlockHeaders, GetBlockHeaders, HashOrNumber, NewBlockHashes,
+                },
+                EthMessage,
+            },
+            handshake::RLPxLocalClient,
+            message::Message,
+            p2p::{DisconnectMessage, PingMessage},
+        },
+        types::Node,
+    };
+    use ethereum_rust_core::{
+        types::{BlockHeader, ChainConfig},
+        H256, H512,
+    };
+    use ethereum_rust_storage::{EngineType, Store};
     use hex_literal::hex;
-    use k256::SecretKey;
+    use k256::{ecdsa::SigningKey, PublicKey, SecretKey};
+    use rand::rngs::OsRng;
+    use tokio::{
+        io::{AsyncWriteExt, DuplexStream},
+        sync::{mpsc, oneshot},
+    };
+
+    fn test_store() -> Store {
+        let store = Store::new("temp.db", EngineType::InMemory).unwrap();
+        store
+            .set_chain_config(&ChainConfig {
+                chain_id: 1,
+                ..Default::default()
+            })
+            .unwrap();
+        store.add_block_header(0, BlockHeader::default()).unwrap();
+        store.update_latest_block_number(0).unwrap();
+        store
+    }
+
+    fn test_peer_manager() -> PeerManager {
+        let local_node = Node {
+            ip: "127.0.0.1".parse().unwrap(),
+            udp_port: 30303,
+            tcp_port: 30303,
+            node_id: H512::zero(),
+        };
+        PeerManager::new(local_node, PeerLimits::default())
+    }
+
+    /// Connects two peers through an in-memory stream, returning the initiator and the responder
+    async fn connected_peers(
+        store: &Store,
+    ) -> (
+        (RLPxConnection, DuplexStream),
+        (RLPxConnection, DuplexStream),
+    ) {
+        let initiator_signer = SigningKey::random(&mut OsRng);
+        let responder_signer = SigningKey::random(&mut OsRng);
+        let responder_pubkey = PublicKey::from(responder_signer.verifying_key());
+        let (mut initiator_stream, mut responder_stream) = tokio::io::duplex(4096);
+
+        let (initiator, responder) = tokio::join!(
+            initiate(
+                &mut initiator_stream,
+                &initiator_signer,
+                &responder_pubkey,
+                store
+            ),
+            accept(&mut responder_stream, &responder_signer, store),
+        );
+        let (responder, _) = responder.unwrap();
+        (
+            (initiator.unwrap(), initiator_stream),
+            (responder, responder_stream),
+        )
+    }
 
     #[test]
     fn test_ack_decoding() {
@@ -267,11 +768,13 @@ mod tests {
 
         client.auth_message = Some(vec![]);
 
-        let conn = client.decode_ack_message(
-            &SecretKey::from_slice(&static_key).unwrap(),
-            &msg[2..],
-            auth_data,
-        );
+        let conn = client
+            .decode_ack_message(
+                &SecretKey::from_slice(&static_key).unwrap(),
+                &msg[2..],
+                auth_data,
+            )
+            .unwrap();
 
         let state = conn.state;
 
@@ -283,4 +786,321 @@ mod tests {
         assert_eq!(state.aes_key.0, expected_aes_secret);
         assert_eq!(state.mac_key.0, expected_mac_secret);
     }
+
+    #[tokio::test]
+    async fn accepted_connection_answers_pings_until_disconnect() {
+        let initiator_signer = SigningKey::random(&mut OsRng);
+        let responder_signer = SigningKey::random(&mut OsRng);
+        let responder_pubkey = PublicKey::from(responder_signer.verifying_key());
+        let (mut initiator_stream, mut responder_stream) = tokio::io::duplex(4096);
+
+        let store = test_store();
+        let (initiator, responder) = tokio::join!(
+            initiate(
+                &mut initiator_stream,
+                &initiator_signer,
+                &responder_pubkey,
+                &store
+            ),
+            accept(&mut responder_stream, &responder_signer, &store),
+        );
+        let mut initiator = initiator.unwrap();
+        let (mut responder, remote_pubkey) = responder.unwrap();
+        assert_eq!(
+            remote_pubkey,
+            PublicKey::from(initiator_signer.verifying_key())
+        );
+        assert_eq!(initiator.capabilities, responder.capabilities);
+        assert_eq!(
+            initiator.node_id(),
+            node_id_from_signing_key(&responder_signer)
+        );
+        assert_eq!(initiator.shared_capabilities.len(), 1);
+        assert_eq!(
+            initiator.head(),
+            BlockHeader::default().compute_block_hash()
+        );
+
+        let (_requests, requests_receiver) = mpsc::channel(1);
+        let peer_manager = test_peer_manager();
+        let (_, result) = tokio::join!(
+            async {
+                initiator
+                    .send(Message::Ping(PingMessage::new()), &mut initiator_stream)
+                    .await
+                    .unwrap();
+                let response = initiator.receive(&mut initiator_stream).await.unwrap();
+                assert!(matches!(response, Message::Pong(_)));
+                initiator
+                    .send(
+                        Message::Disconnect(DisconnectMessage::new(Some(0x00))),
+                        &mut initiator_stream,
+                    )
+                    .await
+                    .unwrap();
+            },
+            responder.handle_messages(
+                &mut responder_stream,
+                requests_receiver,
+                &peer_manager,
+                &store
+            ),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn requests_are_matched_with_their_responses() {
+        let store = test_store();
+        let ((mut peer, mut peer_stream), (mut conn, mut conn_stream)) =
+            connected_peers(&store).await;
+        let (requests, requests_receiver) = mpsc::channel(1);
+        let peer_manager = test_peer_manager();
+        let block_headers = |id| {
+            EthMessage::BlockHeaders(BlockHeaders {
+                id,
+                block_headers: vec![BlockHeader {
+                    number: id,
+                    ..Default::default()
+                }],
+            })
+        };
+
+        let request = async {
+            let (response, response_receiver) = oneshot::channel();
+            let message = EthMessage::GetBlockHeaders(GetBlockHeaders {
+                id: 0,
+                startblock: HashOrNumber::Number(0),
+                limit: 1,
+                skip: 0,
+                reverse: false,
+            });
+            requests
+                .send(PeerRequest { message, response })
+                .await
+                .unwrap();
+            response_receiver.await.unwrap()
+        };
+        let peer_side = async {
+            let Message::Eth(EthMessage::GetBlockHeaders(request)) =
+                peer.receive(&mut peer_stream).await.unwrap()
+            else {
+                panic!("expected GetBlockHeaders");
+            };
+            // Responses to unknown requests are ignored
+            for id in [request.id + 1, request.id] {
+                peer.send(Message::Eth(block_headers(id)), &mut peer_stream)
+                    .await
+                    .unwrap();
+            }
+            request.id
+        };
+        let (response, request_id) = tokio::select! {
+            result = conn.handle_messages(&mut conn_stream, requests_receiver, &peer_manager, &store) => {
+                panic!("connection closed: {:?}", result.err())
+            }
+            responses = async { tokio::join!(request, peer_side) } => responses,
+        };
+        assert_eq!(response, block_headers(request_id));
+    }
+
+    #[tokio::test]
+    async fn peer_requests_are_answered_from_the_store() {
+        let store = test_store();
+        let ((mut peer, mut peer_stream), (mut conn, mut conn_stream)) =
+            connected_peers(&store).await;
+        let (_requests, requests_receiver) = mpsc::channel(1);
+        let peer_manager = test_peer_manager();
+
+        let peer_side = async {
+            let request = EthMessage::GetBlockHeaders(GetBlockHeaders {
+                id: 7,
+                startblock: HashOrNumber::Number(0),
+                limit: 2,
+                skip: 0,
+                reverse: false,
+            });
+            peer.send(Message::Eth(request), &mut peer_stream)
+                .await
+                .unwrap();
+            let response = peer.receive(&mut peer_stream).await.unwrap();
+            peer.send(
+                Message::Disconnect(DisconnectMessage::new(Some(0x00))),
+                &mut peer_stream,
+            )
+            .await
+            .unwrap();
+            response
+        };
+        let (result, response) = tokio::join!(
+            conn.handle_messages(&mut conn_stream, requests_receiver, &peer_manager, &store),
+            peer_side
+        );
+        assert!(result.is_ok());
+        let Message::Eth(response) = response else {
+            panic!("expected an eth message");
+        };
+        // Only the stored header is sent
+        assert_eq!(
+            response,
+            EthMessage::BlockHeaders(BlockHeaders {
+                id: 7,
+                block_headers: vec![BlockHeader::default()],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn responses_of_the_wrong_kind_are_a_protocol_breach() {
+        let store = test_store();
+        let ((mut peer, mut peer_stream), (mut conn, mut conn_stream)) =
+            connected_peers(&store).await;
+        let (requests, requests_receiver) = mpsc::channel(1);
+        let peer_manager = test_peer_manager();
+        let (response, _response_receiver) = oneshot::channel();
+        let message = EthMessage::GetBlockHeaders(GetBlockHeaders {
+            id: 0,
+            startblock: HashOrNumber::Number(0),
+            limit: 1,
+            skip: 0,
+            reverse: false,
+        });
+        requests
+            .send(PeerRequest { message, response })
+            .await
+            .unwrap();
+
+        let peer_side = async {
+            let Message::Eth(EthMessage::GetBlockHeaders(request)) =
+                peer.receive(&mut peer_stream).await.unwrap()
+            else {
+                panic!("expected GetBlockHeaders");
+            };
+            let bodies = EthMessage::BlockBodies(BlockBodies {
+                id: request.id,
+                block_bodies: vec![],
+            });
+            peer.send(Message::Eth(bodies), &mut peer_stream)
+                .await
+                .unwrap();
+        };
+        let (result, _) = tokio::join!(
+            conn.handle_messages(&mut conn_stream, requests_receiver, &peer_manager, &store),
+            peer_side
+        );
+        assert!(matches!(result, Err(RLPxError::UnexpectedMessage(_))));
+    }
+
+    #[tokio::test]
+    async fn hello_must_come_from_the_handshake_key() {
+        let initiator_signer = SigningKey::random(&mut OsRng);
+        let impostor_signer = SigningKey::random(&mut OsRng);
+        let responder_signer = SigningKey::random(&mut OsRng);
+        let responder_pubkey = PublicKey::from(responder_signer.verifying_key());
+        let (mut initiator_stream, mut responder_stream) = tokio::io::duplex(4096);
+        let store = test_store();
+
+        let initiator = async {
+            let secret_key: SecretKey = initiator_signer.clone().into();
+            let mut client = RLPxLocalClient::random();
+            let mut auth_message = vec![];
+            client.encode_auth_message(&secret_key, &responder_pubkey, &mut auth_message);
+            initiator_stream.write_all(&auth_message).await.unwrap();
+            let (auth_data, msg) = read_handshake_message(&mut initiator_stream).await.unwrap();
+            let pending_conn = client
+                .decode_ack_message(&secret_key, &msg, auth_data)
+                .unwrap();
+            // The Hello claims to come from a node other than the one that did the handshake
+            pending_conn
+                .exchange_hello(&mut initiator_stream, &impostor_signer, &responder_pubkey)
+                .await
+        };
+        let (initiator, responder) = tokio::join!(
+            initiator,
+            accept(&mut responder_stream, &responder_signer, &store)
+        );
+        assert!(initiator.is_ok());
+        assert!(matches!(responder, Err(RLPxError::Handshake(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn peers_not_answering_pings_are_disconnected() {
+        let store = test_store();
+        let ((mut peer, mut peer_stream), (mut conn, mut conn_stream)) =
+            connected_peers(&store).await;
+        let (_requests, requests_receiver) = mpsc::channel(1);
+        let peer_manager = test_peer_manager();
+
+        let conn_side = async {
+            let error = conn
+                .handle_messages(&mut conn_stream, requests_receiver, &peer_manager, &store)
+                .await
+                .unwrap_err();
+            conn.close(&mut conn_stream, Some(&error)).await;
+            error
+        };
+        let peer_side = async {
+            // The peer keeps the connection busy with Pings of its own, but never answers ours
+            let mut pings = 0;
+            loop {
+                match peer.receive(&mut peer_stream).await.unwrap() {
+                    Message::Ping(_) => {
+                        pings += 1;
+                        peer.send(Message::Ping(PingMessage::new()), &mut peer_stream)
+                            .await
+                            .unwrap();
+                    }
+                    Message::Pong(_) => {}
+                    Message::Disconnect(msg) => return (pings, msg.reason()),
+                    message => panic!("unexpected message {message:?}"),
+                }
+            }
+        };
+        let (error, (pings, reason)) = tokio::join!(conn_side, peer_side);
+        assert!(matches!(error, RLPxError::PingTimeout));
+        assert_eq!(pings, 1);
+        assert_eq!(reason, Some(0x0b));
+    }
+
+    #[tokio::test]
+    async fn announced_blocks_update_the_peer_head() {
+        let store = test_store();
+        let ((mut peer, mut peer_stream), (mut conn, mut conn_stream)) =
+            connected_peers(&store).await;
+        let (_requests, requests_receiver) = mpsc::channel(1);
+        let peer_manager = test_peer_manager();
+        peer_manager
+            .add_peer(PeerInfo {
+                node_id: conn.node_id(),
+                remote_addr: "127.0.0.1:30303".parse().unwrap(),
+                inbound: false,
+                client_id: conn.client_id().to_string(),
+                capabilities: conn.capabilities().to_vec(),
+                head: Some(conn.head()),
+            })
+            .unwrap();
+        let newest = H256::from_low_u64_be(2);
+
+        let peer_side = async {
+            let announcement = EthMessage::NewBlockHashes(NewBlockHashes {
+                block_hashes: vec![(newest, 2), (H256::from_low_u64_be(1), 1)],
+            });
+            peer.send(Message::Eth(announcement), &mut peer_stream)
+                .await
+                .unwrap();
+            peer.send(
+                Message::Disconnect(DisconnectMessage::new(Some(0x00))),
+                &mut peer_stream,
+            )
+            .await
+            .unwrap();
+        };
+        let (result, _) = tokio::join!(
+            conn.handle_messages(&mut conn_stream, requests_receiver, &peer_manager, &store),
+            peer_side
+        );
+        assert!(result.is_ok());
+        assert_eq!(conn.head(), newest);
+        assert_eq!(peer_manager.peers()[0].head, Some(newest));
+    }
 }
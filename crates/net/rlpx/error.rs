@@ -0,0 +1,60 @@
+use ethereum_rust_core::rlp::error::RLPDecodeError;
+use ethereum_rust_storage::error::StoreError;
+use thiserror::Error;
+
+use crate::peer_manager::PeerRejected;
+
+// TODO improve errors
+#[derive(Debug, Error)]
+pub(crate) enum RLPxError {
+    #[error("Invalid handshake: {0}")]
+    Handshake(String),
+    #[error("Invalid MAC")]
+    InvalidMac,
+    #[error("Invalid message: {0}")]
+    InvalidMessage(String),
+    #[error("Unexpected message: {0}")]
+    UnexpectedMessage(String),
+    #[error("Invalid status: {0}")]
+    InvalidStatus(String),
+    #[error("No capabilities in common")]
+    NoSharedCapabilities,
+    #[error("Request failed: {0}")]
+    RequestFailed(String),
+    #[error("Peer disconnected")]
+    Disconnected,
+    #[error("Peer rejected: {0}")]
+    Rejected(#[from] PeerRejected),
+    #[error("Connection timed out")]
+    Timeout(#[from] tokio::time::error::Elapsed),
+    #[error("Peer didn't answer our Ping")]
+    PingTimeout,
+    #[error("Failed to answer request: {0}")]
+    Answer(#[from] tokio::task::JoinError),
+    #[error("Decode error: {0}")]
+    Decode(#[from] RLPDecodeError),
+    #[error("Store error: {0}")]
+    Store(#[from] StoreError),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl RLPxError {
+    /// Reason sent to the peer when closing the connection because of this error, if the connection is still usable
+    pub fn disconnect_reason(&self) -> Option<u8> {
+        match self {
+            // Breach of protocol
+            RLPxError::InvalidMessage(_)
+            | RLPxError::UnexpectedMessage(_)
+            | RLPxError::Decode(_) => Some(0x02),
+            // Timeout on receiving a message
+            RLPxError::Timeout(_) | RLPxError::PingTimeout => Some(0x0b),
+            // Useless peer
+            RLPxError::NoSharedCapabilities => Some(0x03),
+            // Subprotocol error
+            RLPxError::InvalidStatus(_) => Some(0x10),
+            RLPxError::Rejected(rejection) => Some(rejection.disconnect_reason()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use bytes::BufMut;
+use ethereum_rust_core::rlp::{decode::RLPDecode, encode::RLPEncode, error::RLPDecodeError};
+use ethereum_rust_storage::{error::StoreError, Store};
+
+use super::utils::{snappy_compress, snappy_decompress};
+
+pub(crate) mod blocks;
+pub(crate) mod receipts;
+pub(crate) mod status;
+pub(crate) mod transactions;
+
+use blocks::{BlockBodies, BlockHeaders, GetBlockBodies, GetBlockHeaders, NewBlockHashes};
+use receipts::{GetReceipts, Receipts};
+use status::StatusMessage;
+use transactions::{
+    GetPooledTransactions, NewPooledTransactionHashes, PooledTransactions, Transactions,
+};
+
+/// Amount of message ids reserved by eth/68, from Status (0x00) to Receipts (0x10)
+pub(crate) const ETH_MESSAGE_COUNT: u8 = 0x11;
+/// Size in bytes after which no more items are added to a response, the same one geth uses
+pub(crate) const SOFT_RESPONSE_LIMIT: usize = 2 * 1024 * 1024;
+
+/// Messages of the eth capability, as described in https://github.com/ethereum/devp2p/blob/master/caps/eth.md
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum EthMessage {
+    Status(StatusMessage),
+    NewBlockHashes(NewBlockHashes),
+    Transactions(Transactions),
+    GetBlockHeaders(GetBlockHeaders),
+    BlockHeaders(BlockHeaders),
+    GetBlockBodies(GetBlockBodies),
+    BlockBodies(BlockBodies),
+    NewPooledTransactionHashes(NewPooledTransactionHashes),
+    GetPooledTransactions(GetPooledTransactions),
+    PooledTransactions(PooledTransactions),
+    GetReceipts(GetReceipts),
+    Receipts(Receipts),
+}
+
+impl EthMessage {
+    /// Id of the message, relative to the offset of the eth capability
+    pub fn code(&self) -> u8 {
+        match self {
+            EthMessage::Status(_) => 0x00,
+            EthMessage::NewBlockHashes(_) => 0x01,
+            EthMessage::Transactions(_) => 0x02,
+            EthMessage::GetBlockHeaders(_) => 0x03,
+            EthMessage::BlockHeaders(_) => 0x04,
+            EthMessage::GetBlockBodies(_) => 0x05,
+            EthMessage::BlockBodies(_) => 0x06,
+            EthMessage::NewPooledTransactionHashes(_) => 0x08,
+            EthMessage::GetPooledTransactions(_) => 0x09,
+            EthMessage::PooledTransactions(_) => 0x0a,
+            EthMessage::GetReceipts(_) => 0x0f,
+            EthMessage::Receipts(_) => 0x10,
+        }
+    }
+
+    pub fn decode(code: u8, msg_data: &[u8]) -> Result<Self, RLPDecodeError> {
+        let data = snappy_decompress(msg_data)?;
+        match code {
+            0x00 => Ok(EthMessage::Status(RLPDecode::decode(&data)?)),
+            0x01 => Ok(EthMessage::NewBlockHashes(RLPDecode::decode(&data)?)),
+            0x02 => Ok(EthMessage::Transactions(RLPDecode::decode(&data)?)),
+            0x03 => Ok(EthMessage::GetBlockHeaders(RLPDecode::decode(&data)?)),
+            0x04 => Ok(EthMessage::BlockHeaders(RLPDecode::decode(&data)?)),
+            0x05 => Ok(EthMessage::GetBlockBodies(RLPDecode::decode(&data)?)),
+            0x06 => Ok(EthMessage::BlockBodies(RLPDecode::decode(&data)?)),
+            0x08 => Ok(EthMessage::NewPooledTransactionHashes(RLPDecode::decode(
+                &data,
+            )?)),
+            0x09 => Ok(EthMessage::GetPooledTransactions(RLPDecode::decode(&data)?)),
+            0x0a => Ok(EthMessage::PooledTransactions(RLPDecode::decode(&data)?)),
+            0x0f => Ok(EthMessage::GetReceipts(RLPDecode::decode(&data)?)),
+            0x10 => Ok(EthMessage::Receipts(RLPDecode::decode(&data)?)),
+            _ => Err(RLPDecodeError::Custom(format!(
+                "unknown eth message code {code:#x}"
+            ))),
+        }
+    }
+
+    /// Encodes the message data, without its id
+    pub fn encode(&self, buf: &mut dyn BufMut) {
+        let encoded_data = match self {
+            EthMessage::Status(msg) => msg.encode_to_vec(),
+            EthMessage::NewBlockHashes(msg) => msg.encode_to_vec(),
+            EthMessage::Transactions(msg) => msg.encode_to_vec(),
+            EthMessage::GetBlockHeaders(msg) => msg.encode_to_vec(),
+            EthMessage::BlockHeaders(msg) => msg.encode_to_vec(),
+            EthMessage::GetBlockBodies(msg) => msg.encode_to_vec(),
+            EthMessage::BlockBodies(msg) => msg.encode_to_vec(),
+            EthMessage::NewPooledTransactionHashes(msg) => msg.encode_to_vec(),
+            EthMessage::GetPooledTransactions(msg) => msg.encode_to_vec(),
+            EthMessage::PooledTransactions(msg) => msg.encode_to_vec(),
+            EthMessage::GetReceipts(msg) => msg.encode_to_vec(),
+            EthMessage::Receipts(msg) => msg.encode_to_vec(),
+        };
+        buf.put_slice(&snappy_compress(&encoded_data));
+    }
+
+    /// Whether the message is a request the peer has to answer
+    pub fn is_request(&self) -> bool {
+        self.response_code().is_some()
+    }
+
+    /// Code of the message answering the request, None for other messages
+    pub fn response_code(&self) -> Option<u8> {
+        match self {
+            EthMessage::GetBlockHeaders(_) => Some(0x04),
+            EthMessage::GetBlockBodies(_) => Some(0x06),
+            EthMessage::GetPooledTransactions(_) => Some(0x0a),
+            EthMessage::GetReceipts(_) => Some(0x10),
+            _ => None,
+        }
+    }
+
+    /// Id of the request the message is or answers, None for announcements
+    pub fn request_id(&self) -> Option<u64> {
+        match self {
+            EthMessage::GetBlockHeaders(msg) => Some(msg.id),
+            EthMessage::BlockHeaders(msg) => Some(msg.id),
+            EthMessage::GetBlockBodies(msg) => Some(msg.id),
+            EthMessage::BlockBodies(msg) => Some(msg.id),
+            EthMessage::GetPooledTransactions(msg) => Some(msg.id),
+            EthMessage::PooledTransactions(msg) => Some(msg.id),
+            EthMessage::GetReceipts(msg) => Some(msg.id),
+            EthMessage::Receipts(msg) => Some(msg.id),
+            _ => None,
+        }
+    }
+
+    /// Builds the response to a request from a peer out of the chain data and the mempool in the store.
+    /// Returns None if the message isn't a request we serve
+    pub fn answer(&self, storage: &Store) -> Result<Option<EthMessage>, StoreError> {
+        let response = match self {
+            EthMessage::GetBlockHeaders(request) => EthMessage::BlockHeaders(BlockHeaders {
+                id: request.id,
+                block_headers: request.fetch_headers(storage)?,
+            }),
+            EthMessage::GetBlockBodies(request) => EthMessage::BlockBodies(BlockBodies {
+                id: request.id,
+                block_bodies: request.fetch_bodies(storage)?,
+            }),
+            EthMessage::GetPooledTransactions(request) => {
+                EthMessage::PooledTransactions(PooledTransactions {
+                    id: request.id,
+                    pooled_transactions: request.fetch_transactions(storage),
+                })
+            }
+            EthMessage::GetReceipts(request) => EthMessage::Receipts(Receipts {
+                id: request.id,
+                receipts: request.fetch_receipts(storage)?,
+            }),
+            _ => return Ok(None),
+        };
+        Ok(Some(response))
+    }
+
+    /// Sets the id of a request, so that its response can be matched with it. Does nothing for other messages
+    pub fn set_request_id(&mut self, id: u64) {
+        match self {
+            EthMessage::GetBlockHeaders(msg) => msg.id = id,
+            EthMessage::GetBlockBodies(msg) => msg.id = id,
+            EthMessage::GetPooledTransactions(msg) => msg.id = id,
+            EthMessage::GetReceipts(msg) => msg.id = id,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_rust_core::H256;
+
+    use super::*;
+
+    #[test]
+    fn eth_message_roundtrip() {
+        let msg = EthMessage::GetBlockBodies(GetBlockBodies {
+            id: 1,
+            block_hashes: vec![H256::from_low_u64_be(1), H256::from_low_u64_be(2)],
+        });
+        let mut buf = vec![];
+        msg.encode(&mut buf);
+        assert_eq!(EthMessage::decode(msg.code(), &buf).unwrap(), msg);
+        assert!(EthMessage::decode(0x07, &buf).is_err());
+    }
+}
@@ -0,0 +1,403 @@
+use bytes::BufMut;
+use ethereum_rust_core::{
+    rlp::{
+        decode::RLPDecode,
+        encode::RLPEncode,
+        error::RLPDecodeError,
+        structs::{Decoder, Encoder},
+    },
+    types::{BlockBody, BlockHash, BlockHeader, BlockNumber},
+};
+use ethereum_rust_storage::{error::StoreError, Store};
+
+use super::SOFT_RESPONSE_LIMIT;
+
+/// Maximum amount of headers sent in a single response
+const MAX_HEADERS_SERVE: u64 = 1024;
+/// Maximum amount of bodies sent in a single response
+const MAX_BODIES_SERVE: usize = 1024;
+
+/// Block a GetBlockHeaders request starts from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HashOrNumber {
+    Hash(BlockHash),
+    Number(BlockNumber),
+}
+
+impl RLPEncode for HashOrNumber {
+    fn encode(&self, buf: &mut dyn BufMut) {
+        match self {
+            HashOrNumber::Hash(hash) => hash.encode(buf),
+            HashOrNumber::Number(number) => number.encode(buf),
+        }
+    }
+}
+
+impl RLPDecode for HashOrNumber {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        // Hashes are encoded as 32 byte strings, which numbers can't be
+        if rlp.first() == Some(&0xa0) {
+            let (hash, rest) = BlockHash::decode_unfinished(rlp)?;
+            Ok((HashOrNumber::Hash(hash), rest))
+        } else {
+            let (number, rest) = BlockNumber::decode_unfinished(rlp)?;
+            Ok((HashOrNumber::Number(number), rest))
+        }
+    }
+}
+
+// https://github.com/ethereum/devp2p/blob/master/caps/eth.md#newblockhashes-0x01
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct NewBlockHashes {
+    pub block_hashes: Vec<(BlockHash, BlockNumber)>,
+}
+
+impl RLPEncode for NewBlockHashes {
+    fn encode(&self, buf: &mut dyn BufMut) {
+        self.block_hashes.encode(buf);
+    }
+}
+
+impl RLPDecode for NewBlockHashes {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        let (block_hashes, rest) = RLPDecode::decode_unfinished(rlp)?;
+        Ok((Self { block_hashes }, rest))
+    }
+}
+
+// https://github.com/ethereum/devp2p/blob/master/caps/eth.md#getblockheaders-0x03
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GetBlockHeaders {
+    pub id: u64,
+    pub startblock: HashOrNumber,
+    pub limit: u64,
+    pub skip: u64,
+    pub reverse: bool,
+}
+
+impl GetBlockHeaders {
+    /// Fetches the requested headers, stopping at the first one missing.
+    /// The response is cut short once it reaches [`SOFT_RESPONSE_LIMIT`] or [`MAX_HEADERS_SERVE`] headers
+    pub fn fetch_headers(&self, storage: &Store) -> Result<Vec<BlockHeader>, StoreError> {
+        let start = match self.startblock {
+            HashOrNumber::Number(number) => number,
+            HashOrNumber::Hash(hash) => match storage.get_block_number(hash)? {
+                Some(number) => number,
+                None => return Ok(vec![]),
+            },
+        };
+        let limit = self.limit.min(MAX_HEADERS_SERVE) as usize;
+        let step = self.skip.saturating_add(1);
+
+        let mut block_headers = vec![];
+        let mut response_size = 0;
+        let mut next = Some(start);
+        while let Some(number) = next {
+            if block_headers.len() >= limit || response_size >= SOFT_RESPONSE_LIMIT {
+                break;
+            }
+            let Some(header) = storage.get_block_header(number)? else {
+                break;
+            };
+            response_size += header.length();
+            block_headers.push(header);
+            next = if self.reverse {
+                number.checked_sub(step)
+            } else {
+                number.checked_add(step)
+            };
+        }
+        Ok(block_headers)
+    }
+}
+
+impl RLPEncode for GetBlockHeaders {
+    fn encode(&self, buf: &mut dyn BufMut) {
+        Encoder::new(buf)
+            .encode_field(&self.id)
+            .encode_field(&(self.startblock, self.limit, self.skip, self.reverse))
+            .finish();
+    }
+}
+
+impl RLPDecode for GetBlockHeaders {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        let decoder = Decoder::new(rlp)?;
+        let (id, decoder) = decoder.decode_field("request-id")?;
+        let (request, decoder) = decoder.get_encoded_item()?;
+
+        let request_decoder = Decoder::new(&request)?;
+        let (startblock, request_decoder) = request_decoder.decode_field("startblock")?;
+        let (limit, request_decoder) = request_decoder.decode_field("limit")?;
+        let (skip, request_decoder) = request_decoder.decode_field("skip")?;
+        let (reverse, request_decoder) = request_decoder.decode_field("reverse")?;
+        request_decoder.finish()?;
+
+        let msg = GetBlockHeaders {
+            id,
+            startblock,
+            limit,
+            skip,
+            reverse,
+        };
+        Ok((msg, decoder.finish()?))
+    }
+}
+
+// https://github.com/ethereum/devp2p/blob/master/caps/eth.md#blockheaders-0x04
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BlockHeaders {
+    pub id: u64,
+    pub block_headers: Vec<BlockHeader>,
+}
+
+impl RLPEncode for BlockHeaders {
+    fn encode(&self, buf: &mut dyn BufMut) {
+        Encoder::new(buf)
+            .encode_field(&self.id)
+            .encode_field(&self.block_headers)
+            .finish();
+    }
+}
+
+impl RLPDecode for BlockHeaders {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        let decoder = Decoder::new(rlp)?;
+        let (id, decoder) = decoder.decode_field("request-id")?;
+        let (block_headers, decoder) = decoder.decode_field("headers")?;
+        Ok((Self { id, block_headers }, decoder.finish()?))
+    }
+}
+
+// https://github.com/ethereum/devp2p/blob/master/caps/eth.md#getblockbodies-0x05
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GetBlockBodies {
+    pub id: u64,
+    pub block_hashes: Vec<BlockHash>,
+}
+
+impl GetBlockBodies {
+    /// Fetches the bodies of the requested blocks, stopping at the first unknown one so the response
+    /// matches the start of the request.
+    /// The response is cut short once it reaches [`SOFT_RESPONSE_LIMIT`] or [`MAX_BODIES_SERVE`] bodies
+    pub fn fetch_bodies(&self, storage: &Store) -> Result<Vec<BlockBody>, StoreError> {
+        let mut block_bodies = vec![];
+        let mut response_size = 0;
+        for block_hash in self.block_hashes.iter().take(MAX_BODIES_SERVE) {
+            if response_size >= SOFT_RESPONSE_LIMIT {
+                break;
+            }
+            let Some(block_number) = storage.get_block_number(*block_hash)? else {
+                break;
+            };
+            let Some(block_body) = storage.get_block_body(block_number)? else {
+                break;
+            };
+            response_size += block_body.length();
+            block_bodies.push(block_body);
+        }
+        Ok(block_bodies)
+    }
+}
+
+impl RLPEncode for GetBlockBodies {
+    fn encode(&self, buf: &mut dyn BufMut) {
+        Encoder::new(buf)
+            .encode_field(&self.id)
+            .encode_field(&self.block_hashes)
+            .finish();
+    }
+}
+
+impl RLPDecode for GetBlockBodies {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        let decoder = Decoder::new(rlp)?;
+        let (id, decoder) = decoder.decode_field("request-id")?;
+        let (block_hashes, decoder) = decoder.decode_field("blockhashes")?;
+        Ok((Self { id, block_hashes }, decoder.finish()?))
+    }
+}
+
+// https://github.com/ethereum/devp2p/blob/master/caps/eth.md#blockbodies-0x06
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BlockBodies {
+    pub id: u64,
+    pub block_bodies: Vec<BlockBody>,
+}
+
+impl RLPEncode for BlockBodies {
+    fn encode(&self, buf: &mut dyn BufMut) {
+        Encoder::new(buf)
+            .encode_field(&self.id)
+            .encode_field(&self.block_bodies)
+            .finish();
+    }
+}
+
+impl RLPDecode for BlockBodies {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        let decoder = Decoder::new(rlp)?;
+        let (id, decoder) = decoder.decode_field("request-id")?;
+        let (block_bodies, decoder) = decoder.decode_field("bodies")?;
+        Ok((Self { id, block_bodies }, decoder.finish()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_rust_core::{
+        types::{LegacyTransaction, Receipt, Transaction, TxKind, TxType},
+        Address, H256, U256,
+    };
+    use ethereum_rust_storage::EngineType;
+
+    use super::*;
+    use crate::rlpx::eth::receipts::GetReceipts;
+
+    /// Store with a chain of empty blocks numbered from zero to nine
+    fn test_store() -> Store {
+        let store = Store::new("temp.db", EngineType::InMemory).unwrap();
+        for number in 0..10 {
+            let header = BlockHeader {
+                number,
+                ..Default::default()
+            };
+            store
+                .add_block_number(header.compute_block_hash(), number)
+                .unwrap();
+            store.add_block_header(number, header).unwrap();
+            store.add_block_body(number, BlockBody::empty()).unwrap();
+        }
+        store
+    }
+
+    fn fetched_numbers(
+        store: &Store,
+        startblock: HashOrNumber,
+        limit: u64,
+        skip: u64,
+        reverse: bool,
+    ) -> Vec<u64> {
+        let request = GetBlockHeaders {
+            id: 0,
+            startblock,
+            limit,
+            skip,
+            reverse,
+        };
+        request
+            .fetch_headers(store)
+            .unwrap()
+            .iter()
+            .map(|header| header.number)
+            .collect()
+    }
+
+    #[test]
+    fn serve_headers_and_bodies() {
+        let store = test_store();
+        let hash_of = |number| {
+            let header = store.get_block_header(number).unwrap().unwrap();
+            HashOrNumber::Hash(header.compute_block_hash())
+        };
+
+        assert_eq!(
+            fetched_numbers(&store, HashOrNumber::Number(2), 3, 1, false),
+            vec![2, 4, 6]
+        );
+        assert_eq!(
+            fetched_numbers(&store, hash_of(3), 10, 0, true),
+            vec![3, 2, 1, 0]
+        );
+        // The response stops at the end of the chain
+        assert_eq!(
+            fetched_numbers(&store, HashOrNumber::Number(7), 5, 0, false),
+            vec![7, 8, 9]
+        );
+        assert!(fetched_numbers(&store, HashOrNumber::Hash(H256::zero()), 5, 0, false).is_empty());
+
+        let request = GetBlockBodies {
+            id: 0,
+            block_hashes: vec![
+                store
+                    .get_block_header(1)
+                    .unwrap()
+                    .unwrap()
+                    .compute_block_hash(),
+                H256::zero(),
+                store
+                    .get_block_header(2)
+                    .unwrap()
+                    .unwrap()
+                    .compute_block_hash(),
+            ],
+        };
+        assert_eq!(
+            request.fetch_bodies(&store).unwrap(),
+            vec![BlockBody::empty()]
+        );
+    }
+
+    #[test]
+    fn serve_receipts() {
+        let store = test_store();
+        let hash_of = |number| {
+            let header = store.get_block_header(number).unwrap().unwrap();
+            header.compute_block_hash()
+        };
+        let transaction = Transaction::LegacyTransaction(LegacyTransaction {
+            nonce: 0,
+            gas_price: 1,
+            gas: 21_000,
+            to: TxKind::Call(Address::from_low_u64_be(1)),
+            value: U256::zero(),
+            data: Default::default(),
+            v: U256::from(27),
+            r: U256::one(),
+            s: U256::one(),
+        });
+        let receipt = Receipt::new(TxType::Legacy, true, 21_000, vec![]);
+        // Blocks 3 and 4 have one transaction each, but only the receipt of the first one is stored
+        for number in [3, 4] {
+            let body = BlockBody {
+                transactions: vec![transaction.clone()],
+                ..BlockBody::empty()
+            };
+            store.add_block_body(number, body).unwrap();
+        }
+        store.add_receipt(3, 0, receipt.clone()).unwrap();
+
+        let request = GetReceipts {
+            id: 0,
+            block_hashes: vec![hash_of(1), hash_of(3), hash_of(4), hash_of(5)],
+        };
+        assert_eq!(
+            request.fetch_receipts(&store).unwrap(),
+            vec![vec![], vec![receipt]]
+        );
+        let request = GetReceipts {
+            id: 0,
+            block_hashes: vec![H256::zero(), hash_of(1)],
+        };
+        assert!(request.fetch_receipts(&store).unwrap().is_empty());
+    }
+
+    #[test]
+    fn get_block_headers_roundtrip() {
+        for startblock in [
+            HashOrNumber::Number(0),
+            HashOrNumber::Number(1_000_000),
+            HashOrNumber::Hash(H256::from_low_u64_be(7)),
+        ] {
+            let msg = GetBlockHeaders {
+                id: 42,
+                startblock,
+                limit: 192,
+                skip: 1,
+                reverse: true,
+            };
+            let encoded = msg.encode_to_vec();
+            assert_eq!(GetBlockHeaders::decode(&encoded).unwrap(), msg);
+        }
+    }
+}
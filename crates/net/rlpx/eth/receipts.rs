@@ -0,0 +1,158 @@
+use bytes::{BufMut, Bytes};
+use ethereum_rust_core::{
+    rlp::{
+        decode::{is_encoded_as_bytes, RLPDecode},
+        encode::RLPEncode,
+        error::RLPDecodeError,
+        structs::{Decoder, Encoder},
+    },
+    types::{BlockHash, Receipt, TxType},
+};
+use ethereum_rust_storage::{error::StoreError, Store};
+
+use super::SOFT_RESPONSE_LIMIT;
+
+/// Maximum amount of blocks whose receipts are sent in a single response
+const MAX_RECEIPTS_SERVE: usize = 1024;
+
+// https://github.com/ethereum/devp2p/blob/master/caps/eth.md#getreceipts-0x0f
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GetReceipts {
+    pub id: u64,
+    pub block_hashes: Vec<BlockHash>,
+}
+
+impl GetReceipts {
+    /// Fetches the receipts of the requested blocks, stopping at the first unknown one, or the first one missing any of
+    /// its receipts, so the response matches the start of the request.
+    /// The response is cut short once it reaches [`SOFT_RESPONSE_LIMIT`] or [`MAX_RECEIPTS_SERVE`] blocks
+    pub fn fetch_receipts(&self, storage: &Store) -> Result<Vec<Vec<Receipt>>, StoreError> {
+        let mut receipts = vec![];
+        let mut response_size = 0;
+        for block_hash in self.block_hashes.iter().take(MAX_RECEIPTS_SERVE) {
+            if response_size >= SOFT_RESPONSE_LIMIT {
+                break;
+            }
+            let Some(block_number) = storage.get_block_number(*block_hash)? else {
+                break;
+            };
+            let Some(block_body) = storage.get_block_body(block_number)? else {
+                break;
+            };
+            let block_receipts: Option<Vec<Receipt>> = (0..block_body.transactions.len() as u64)
+                .map(|index| storage.get_receipt(block_number, index))
+                .collect::<Result<_, _>>()?;
+            let Some(block_receipts) = block_receipts else {
+                break;
+            };
+            response_size += block_receipts.iter().map(Receipt::length).sum::<usize>();
+            receipts.push(block_receipts);
+        }
+        Ok(receipts)
+    }
+}
+
+impl RLPEncode for GetReceipts {
+    fn encode(&self, buf: &mut dyn BufMut) {
+        Encoder::new(buf)
+            .encode_field(&self.id)
+            .encode_field(&self.block_hashes)
+            .finish();
+    }
+}
+
+impl RLPDecode for GetReceipts {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        let decoder = Decoder::new(rlp)?;
+        let (id, decoder) = decoder.decode_field("request-id")?;
+        let (block_hashes, decoder) = decoder.decode_field("blockhashes")?;
+        Ok((Self { id, block_hashes }, decoder.finish()?))
+    }
+}
+
+// https://github.com/ethereum/devp2p/blob/master/caps/eth.md#receipts-0x10
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Receipts {
+    pub id: u64,
+    /// Receipts of each of the requested blocks
+    pub receipts: Vec<Vec<Receipt>>,
+}
+
+impl RLPEncode for Receipts {
+    fn encode(&self, buf: &mut dyn BufMut) {
+        let receipts: Vec<Vec<WireReceipt>> = self
+            .receipts
+            .iter()
+            .map(|block_receipts| block_receipts.iter().cloned().map(WireReceipt).collect())
+            .collect();
+        Encoder::new(buf)
+            .encode_field(&self.id)
+            .encode_field(&receipts)
+            .finish();
+    }
+}
+
+impl RLPDecode for Receipts {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        let decoder = Decoder::new(rlp)?;
+        let (id, decoder) = decoder.decode_field("request-id")?;
+        let (receipts, decoder): (Vec<Vec<WireReceipt>>, _) = decoder.decode_field("receipts")?;
+        let receipts = receipts
+            .into_iter()
+            .map(|block_receipts| block_receipts.into_iter().map(|r| r.0).collect())
+            .collect();
+        Ok((Self { id, receipts }, decoder.finish()?))
+    }
+}
+
+/// Receipt as sent over the wire, where typed receipts are wrapped as byte strings the same way typed transactions are
+struct WireReceipt(Receipt);
+
+impl RLPEncode for WireReceipt {
+    fn encode(&self, buf: &mut dyn BufMut) {
+        match self.0.tx_type {
+            TxType::Legacy => self.0.encode(buf),
+            _ => Bytes::from(self.0.encode_to_vec()).encode(buf),
+        }
+    }
+}
+
+impl RLPDecode for WireReceipt {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        if is_encoded_as_bytes(rlp) {
+            let (encoded_receipt, rest) = Bytes::decode_unfinished(rlp)?;
+            Ok((WireReceipt(Receipt::decode(&encoded_receipt)?), rest))
+        } else {
+            let (receipt, rest) = Receipt::decode_unfinished(rlp)?;
+            Ok((WireReceipt(receipt), rest))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_rust_core::{types::Log, Address};
+
+    use super::*;
+
+    #[test]
+    fn receipts_roundtrip() {
+        let log = Log {
+            address: Address::from_low_u64_be(1),
+            topics: vec![],
+            data: Bytes::from_static(b"data"),
+        };
+        let msg = Receipts {
+            id: 3,
+            receipts: vec![
+                vec![
+                    Receipt::new(TxType::Legacy, true, 21000, vec![]),
+                    Receipt::new(TxType::EIP1559, false, 42000, vec![log]),
+                ],
+                vec![],
+            ],
+        };
+        let encoded = msg.encode_to_vec();
+        assert_eq!(Receipts::decode(&encoded).unwrap(), msg);
+    }
+}
@@ -0,0 +1,392 @@
+use bytes::BufMut;
+use ethereum_rust_core::{
+    rlp::{
+        decode::RLPDecode,
+        encode::RLPEncode,
+        error::RLPDecodeError,
+        structs::{Decoder, Encoder},
+    },
+    types::{BlockHash, BlockHeader, BlockNumber, ChainConfig},
+    H256, H32, U256,
+};
+use ethereum_rust_storage::{error::StoreError, Store};
+
+use crate::rlpx::error::RLPxError;
+
+/// Version of the eth protocol supported
+pub(crate) const ETH_VERSION: u32 = 68;
+
+/// Forks scheduled at a value above this are activated by timestamp instead of by block number
+const TIMESTAMP_THRESHOLD: u64 = 1438269973;
+
+// https://github.com/ethereum/devp2p/blob/master/caps/eth.md#status-0x00
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StatusMessage {
+    pub eth_version: u32,
+    pub network_id: u64,
+    pub total_difficulty: U256,
+    pub block_hash: BlockHash,
+    pub genesis: BlockHash,
+    pub fork_id: ForkId,
+}
+
+impl StatusMessage {
+    /// Builds our status from the current state of the chain
+    pub fn new(storage: &Store) -> Result<Self, RLPxError> {
+        let chain = LocalChain::load(storage)?;
+        Ok(Self {
+            eth_version: ETH_VERSION,
+            network_id: chain.config.chain_id,
+            // Post merge the total difficulty is frozen at the terminal one
+            total_difficulty: chain.config.terminal_total_difficulty.unwrap_or(0).into(),
+            block_hash: chain.head.compute_block_hash(),
+            genesis: chain.genesis_hash,
+            fork_id: chain
+                .forks()
+                .fork_id(chain.head.number, chain.head.timestamp),
+        })
+    }
+
+    /// Checks that the peer sending this status is on the same chain as us
+    pub fn validate(&self, storage: &Store) -> Result<(), RLPxError> {
+        let chain = LocalChain::load(storage)?;
+        if self.eth_version != ETH_VERSION {
+            return Err(RLPxError::InvalidStatus(format!(
+                "unsupported eth version {}",
+                self.eth_version
+            )));
+        }
+        if self.network_id != chain.config.chain_id {
+            return Err(RLPxError::InvalidStatus(format!(
+                "network id mismatch: {}",
+                self.network_id
+            )));
+        }
+        if self.genesis != chain.genesis_hash {
+            return Err(RLPxError::InvalidStatus(format!(
+                "genesis mismatch: {:#x}",
+                self.genesis
+            )));
+        }
+        if !chain
+            .forks()
+            .is_compatible(self.fork_id, chain.head.number, chain.head.timestamp)
+        {
+            return Err(RLPxError::InvalidStatus(format!(
+                "incompatible fork id: {:?}",
+                self.fork_id
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl RLPEncode for StatusMessage {
+    fn encode(&self, buf: &mut dyn BufMut) {
+        Encoder::new(buf)
+            .encode_field(&self.eth_version)
+            .encode_field(&self.network_id)
+            .encode_field(&self.total_difficulty)
+            .encode_field(&self.block_hash)
+            .encode_field(&self.genesis)
+            .encode_field(&self.fork_id)
+            .finish();
+    }
+}
+
+impl RLPDecode for StatusMessage {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        let decoder = Decoder::new(rlp)?;
+        let (eth_version, decoder) = decoder.decode_field("version")?;
+        let (network_id, decoder) = decoder.decode_field("networkid")?;
+        let (total_difficulty, decoder) = decoder.decode_field("td")?;
+        let (block_hash, decoder) = decoder.decode_field("blockhash")?;
+        let (genesis, decoder) = decoder.decode_field("genesis")?;
+        let (fork_id, decoder) = decoder.decode_field("forkid")?;
+        let status = StatusMessage {
+            eth_version,
+            network_id,
+            total_difficulty,
+            block_hash,
+            genesis,
+            fork_id,
+        };
+        // Implementations must ignore any additional list elements
+        Ok((status, decoder.finish_unchecked()))
+    }
+}
+
+/// Identifier of the forks a node has passed and the next one it will pass, as described in EIP-2124
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ForkId {
+    /// CRC32 checksum of the genesis hash and the forks passed
+    pub fork_hash: H32,
+    /// Block number or timestamp of the next fork, zero if none is scheduled
+    pub fork_next: u64,
+}
+
+impl RLPEncode for ForkId {
+    fn encode(&self, buf: &mut dyn BufMut) {
+        Encoder::new(buf)
+            .encode_field(&self.fork_hash)
+            .encode_field(&self.fork_next)
+            .finish();
+    }
+}
+
+impl RLPDecode for ForkId {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        let decoder = Decoder::new(rlp)?;
+        let (fork_hash, decoder) = decoder.decode_field("forkHash")?;
+        let (fork_next, decoder) = decoder.decode_field("forkNext")?;
+        let fork_id = ForkId {
+            fork_hash,
+            fork_next,
+        };
+        Ok((fork_id, decoder.finish()?))
+    }
+}
+
+/// Forks scheduled for a chain, in the order they are activated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Forks {
+    genesis_hash: H256,
+    block_forks: Vec<BlockNumber>,
+    time_forks: Vec<u64>,
+}
+
+impl Forks {
+    pub fn new(genesis_hash: H256, genesis_time: u64, config: &ChainConfig) -> Self {
+        // Forks active at genesis don't change the fork id
+        let mut block_forks: Vec<BlockNumber> = [
+            config.homestead_block,
+            config.dao_fork_block,
+            config.eip150_block,
+            config.eip155_block,
+            config.eip158_block,
+            config.byzantium_block,
+            config.constantinople_block,
+            config.petersburg_block,
+            config.istanbul_block,
+            config.muir_glacier_block,
+            config.berlin_block,
+            config.london_block,
+            config.arrow_glacier_block,
+            config.gray_glacier_block,
+            config.merge_netsplit_block,
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|block| *block != 0)
+        .collect();
+        block_forks.sort_unstable();
+        block_forks.dedup();
+
+        let mut time_forks: Vec<u64> = [
+            config.shanghai_time,
+            config.cancun_time,
+            config.prague_time,
+            config.verkle_time,
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|time| *time > genesis_time)
+        .collect();
+        time_forks.sort_unstable();
+        time_forks.dedup();
+
+        Self {
+            genesis_hash,
+            block_forks,
+            time_forks,
+        }
+    }
+
+    /// Fork id of a node whose head is at the given block number and timestamp
+    pub fn fork_id(&self, head_number: BlockNumber, head_time: u64) -> ForkId {
+        let next = self.next_fork_index(head_number, head_time);
+        ForkId {
+            fork_hash: self.checksums()[next],
+            fork_next: self.fork(next).unwrap_or(0),
+        }
+    }
+
+    /// Checks if a peer with the given fork id can be on the same chain as us, following the rules in EIP-2124
+    pub fn is_compatible(&self, remote: ForkId, head_number: BlockNumber, head_time: u64) -> bool {
+        let checksums = self.checksums();
+        let next = self.next_fork_index(head_number, head_time);
+
+        // Same forks passed, the peer must not announce a fork we already passed without them
+        if checksums[next] == remote.fork_hash {
+            let passed_remote_next = remote.fork_next > 0
+                && (head_number >= remote.fork_next
+                    || (remote.fork_next > TIMESTAMP_THRESHOLD && head_time >= remote.fork_next));
+            return !passed_remote_next;
+        }
+        // The peer is behind us, it has to know about the fork that follows its current one
+        if let Some(index) = checksums[..next]
+            .iter()
+            .position(|checksum| *checksum == remote.fork_hash)
+        {
+            return self.fork(index) == Some(remote.fork_next);
+        }
+        // The peer is ahead of us, we may still have to sync up to it
+        checksums[next + 1..].contains(&remote.fork_hash)
+    }
+
+    /// Checksum of the genesis hash, followed by the checksums after each of the forks
+    fn checksums(&self) -> Vec<H32> {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(self.genesis_hash.as_bytes());
+        let mut checksums = vec![H32(hasher.clone().finalize().to_be_bytes())];
+        for fork in self.block_forks.iter().chain(&self.time_forks) {
+            hasher.update(&fork.to_be_bytes());
+            checksums.push(H32(hasher.clone().finalize().to_be_bytes()));
+        }
+        checksums
+    }
+
+    /// Index of the first fork not passed at the given head, or the amount of forks if all of them were passed
+    fn next_fork_index(&self, head_number: BlockNumber, head_time: u64) -> usize {
+        let passed_blocks = self
+            .block_forks
+            .iter()
+            .take_while(|block| **block <= head_number)
+            .count();
+        if passed_blocks < self.block_forks.len() {
+            return passed_blocks;
+        }
+        let passed_times = self
+            .time_forks
+            .iter()
+            .take_while(|time| **time <= head_time)
+            .count();
+        passed_blocks + passed_times
+    }
+
+    fn fork(&self, index: usize) -> Option<u64> {
+        self.block_forks
+            .iter()
+            .chain(&self.time_forks)
+            .nth(index)
+            .copied()
+    }
+}
+
+/// Parts of the local chain needed to build and check Status messages
+struct LocalChain {
+    config: ChainConfig,
+    genesis_hash: H256,
+    genesis_time: u64,
+    head: BlockHeader,
+}
+
+impl LocalChain {
+    fn load(storage: &Store) -> Result<Self, RLPxError> {
+        let config = storage.get_chain_config()?;
+        let genesis = storage
+            .get_block_header(0)?
+            .ok_or(StoreError::Custom("missing genesis block".to_string()))?;
+        let head_number = storage.get_latest_block_number()?.unwrap_or(0);
+        let head = storage
+            .get_block_header(head_number)?
+            .ok_or(StoreError::Custom(format!("missing block {head_number}")))?;
+        Ok(Self {
+            config,
+            genesis_hash: genesis.compute_block_hash(),
+            genesis_time: genesis.timestamp,
+            head,
+        })
+    }
+
+    fn forks(&self) -> Forks {
+        Forks::new(self.genesis_hash, self.genesis_time, &self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    fn mainnet_forks() -> Forks {
+        let config = ChainConfig {
+            chain_id: 1,
+            homestead_block: Some(1150000),
+            dao_fork_block: Some(1920000),
+            eip150_block: Some(2463000),
+            eip155_block: Some(2675000),
+            eip158_block: Some(2675000),
+            byzantium_block: Some(4370000),
+            constantinople_block: Some(7280000),
+            petersburg_block: Some(7280000),
+            ..Default::default()
+        };
+        let genesis_hash = H256(hex!(
+            "d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3"
+        ));
+        Forks::new(genesis_hash, 0, &config)
+    }
+
+    fn fork_id(fork_hash: [u8; 4], fork_next: u64) -> ForkId {
+        ForkId {
+            fork_hash: H32(fork_hash),
+            fork_next,
+        }
+    }
+
+    #[test]
+    fn mainnet_fork_ids() {
+        // Test cases from EIP-2124
+        let forks = mainnet_forks();
+        let cases = [
+            (0, fork_id(hex!("fc64ec04"), 1150000)),
+            (1149999, fork_id(hex!("fc64ec04"), 1150000)),
+            (1150000, fork_id(hex!("97c2c34c"), 1920000)),
+            (1920000, fork_id(hex!("91d1f948"), 2463000)),
+            (2463000, fork_id(hex!("7a64da13"), 2675000)),
+            (2675000, fork_id(hex!("3edd5b10"), 4370000)),
+            (4370000, fork_id(hex!("a00bc324"), 7280000)),
+            (7280000, fork_id(hex!("668db0af"), 0)),
+        ];
+        for (head, expected) in cases {
+            assert_eq!(forks.fork_id(head, 0), expected, "head {head}");
+        }
+    }
+
+    #[test]
+    fn fork_id_validation() {
+        // Test cases from EIP-2124, with our head at the last Byzantium block
+        let forks = mainnet_forks();
+        let head = 7279999;
+        // Same forks, and the peer knows about the next one
+        assert!(forks.is_compatible(fork_id(hex!("a00bc324"), 7280000), head, 0));
+        // Same forks, and the peer doesn't know about the next one
+        assert!(forks.is_compatible(fork_id(hex!("a00bc324"), 0), head, 0));
+        // Same forks, but the peer announces a fork we already passed
+        assert!(!forks.is_compatible(fork_id(hex!("a00bc324"), 4370000), head, 0));
+        // The peer is behind and knows about the fork it has to pass next
+        assert!(forks.is_compatible(fork_id(hex!("3edd5b10"), 4370000), head, 0));
+        // The peer is behind and doesn't know about the fork it has to pass next
+        assert!(!forks.is_compatible(fork_id(hex!("3edd5b10"), 4369999), head, 0));
+        // The peer is ahead of us
+        assert!(forks.is_compatible(fork_id(hex!("668db0af"), 0), head, 0));
+        // The peer is on another chain
+        assert!(!forks.is_compatible(fork_id(hex!("afec6b27"), 0), head, 0));
+    }
+
+    #[test]
+    fn status_message_roundtrip() {
+        let status = StatusMessage {
+            eth_version: ETH_VERSION,
+            network_id: 1,
+            total_difficulty: U256::from(17),
+            block_hash: H256::from_low_u64_be(1),
+            genesis: H256::from_low_u64_be(2),
+            fork_id: fork_id(hex!("fc64ec04"), 1150000),
+        };
+        let encoded = status.encode_to_vec();
+        assert_eq!(StatusMessage::decode(&encoded).unwrap(), status);
+    }
+}
@@ -0,0 +1,327 @@
+use bytes::{BufMut, Bytes};
+use ethereum_rust_core::{
+    rlp::{
+        decode::RLPDecode,
+        encode::RLPEncode,
+        error::RLPDecodeError,
+        structs::{Decoder, Encoder},
+    },
+    types::{Transaction, TxType, WrappedEIP4844Transaction},
+    H256,
+};
+use ethereum_rust_storage::Store;
+
+use super::SOFT_RESPONSE_LIMIT;
+
+/// Maximum amount of transactions sent in a single PooledTransactions response
+const MAX_POOLED_TRANSACTIONS_SERVE: usize = 256;
+
+// https://github.com/ethereum/devp2p/blob/master/caps/eth.md#transactions-0x02
+// Blob transactions can't be sent in this message, they have to be announced and requested instead
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Transactions {
+    pub transactions: Vec<Transaction>,
+}
+
+impl RLPEncode for Transactions {
+    fn encode(&self, buf: &mut dyn BufMut) {
+        self.transactions.encode(buf);
+    }
+}
+
+impl RLPDecode for Transactions {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        let (transactions, rest) = RLPDecode::decode_unfinished(rlp)?;
+        Ok((Self { transactions }, rest))
+    }
+}
+
+// https://github.com/ethereum/devp2p/blob/master/caps/eth.md#newpooledtransactionhashes-0x08
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct NewPooledTransactionHashes {
+    /// Type of each announced transaction, one byte per transaction
+    pub transaction_types: Bytes,
+    /// Size of each announced transaction, as it would be sent in a PooledTransactions message
+    pub transaction_sizes: Vec<usize>,
+    pub transaction_hashes: Vec<H256>,
+}
+
+impl RLPEncode for NewPooledTransactionHashes {
+    fn encode(&self, buf: &mut dyn BufMut) {
+        Encoder::new(buf)
+            .encode_field(&self.transaction_types)
+            .encode_field(&self.transaction_sizes)
+            .encode_field(&self.transaction_hashes)
+            .finish();
+    }
+}
+
+impl RLPDecode for NewPooledTransactionHashes {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        let decoder = Decoder::new(rlp)?;
+        let (transaction_types, decoder): (Bytes, _) = decoder.decode_field("types")?;
+        let (transaction_sizes, decoder): (Vec<usize>, _) = decoder.decode_field("sizes")?;
+        let (transaction_hashes, decoder): (Vec<H256>, _) = decoder.decode_field("hashes")?;
+        if transaction_types.len() != transaction_hashes.len()
+            || transaction_sizes.len() != transaction_hashes.len()
+        {
+            return Err(RLPDecodeError::Custom(
+                "announced types, sizes and hashes have different lengths".to_string(),
+            ));
+        }
+        let msg = NewPooledTransactionHashes {
+            transaction_types,
+            transaction_sizes,
+            transaction_hashes,
+        };
+        Ok((msg, decoder.finish()?))
+    }
+}
+
+// https://github.com/ethereum/devp2p/blob/master/caps/eth.md#getpooledtransactions-0x09
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GetPooledTransactions {
+    pub id: u64,
+    pub transaction_hashes: Vec<H256>,
+}
+
+impl GetPooledTransactions {
+    /// Fetches the requested transactions from the mempool, skipping the ones it doesn't hold.
+    /// Blob transactions are only sent along with their blobs bundle.
+    /// The response is cut short once it reaches [`SOFT_RESPONSE_LIMIT`] or [`MAX_POOLED_TRANSACTIONS_SERVE`] transactions
+    pub fn fetch_transactions(&self, storage: &Store) -> Vec<PooledTransaction> {
+        let mut pooled_transactions = vec![];
+        let mut response_size = 0;
+        for transaction_hash in &self.transaction_hashes {
+            if pooled_transactions.len() >= MAX_POOLED_TRANSACTIONS_SERVE
+                || response_size >= SOFT_RESPONSE_LIMIT
+            {
+                break;
+            }
+            let Some(mempool_transaction) = storage.get_transaction_from_pool(*transaction_hash)
+            else {
+                continue;
+            };
+            let pooled_transaction = match mempool_transaction.transaction {
+                Transaction::EIP4844Transaction(tx) => {
+                    let Some(blobs_bundle) = storage.get_blobs_bundle_from_pool(*transaction_hash)
+                    else {
+                        continue;
+                    };
+                    PooledTransaction::Blob(WrappedEIP4844Transaction { tx, blobs_bundle })
+                }
+                transaction => PooledTransaction::Transaction(transaction),
+            };
+            response_size += pooled_transaction.length();
+            pooled_transactions.push(pooled_transaction);
+        }
+        pooled_transactions
+    }
+}
+
+impl RLPEncode for GetPooledTransactions {
+    fn encode(&self, buf: &mut dyn BufMut) {
+        Encoder::new(buf)
+            .encode_field(&self.id)
+            .encode_field(&self.transaction_hashes)
+            .finish();
+    }
+}
+
+impl RLPDecode for GetPooledTransactions {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        let decoder = Decoder::new(rlp)?;
+        let (id, decoder) = decoder.decode_field("request-id")?;
+        let (transaction_hashes, decoder) = decoder.decode_field("txhashes")?;
+        let msg = GetPooledTransactions {
+            id,
+            transaction_hashes,
+        };
+        Ok((msg, decoder.finish()?))
+    }
+}
+
+// https://github.com/ethereum/devp2p/blob/master/caps/eth.md#pooledtransactions-0x0a
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PooledTransactions {
+    pub id: u64,
+    pub pooled_transactions: Vec<PooledTransaction>,
+}
+
+/// Transaction as sent in a PooledTransactions message.
+/// Blob transactions are sent in their network form, along with their blobs, commitments and proofs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum PooledTransaction {
+    Transaction(Transaction),
+    Blob(WrappedEIP4844Transaction),
+}
+
+impl RLPEncode for PooledTransaction {
+    fn encode(&self, buf: &mut dyn BufMut) {
+        match self {
+            PooledTransaction::Transaction(transaction) => transaction.encode(buf),
+            PooledTransaction::Blob(transaction) => {
+                Bytes::from(transaction.encode_canonical_to_vec()).encode(buf)
+            }
+        }
+    }
+}
+
+impl RLPDecode for PooledTransaction {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        // Typed transactions are encoded as a byte string starting with their type, legacy ones as a list
+        if let Ok((bytes, rest)) = Bytes::decode_unfinished(rlp) {
+            if bytes.first() == Some(&(TxType::EIP4844 as u8)) {
+                let transaction = WrappedEIP4844Transaction::decode_canonical(&bytes)?;
+                return Ok((PooledTransaction::Blob(transaction), rest));
+            }
+        }
+        let (transaction, rest) = Transaction::decode_unfinished(rlp)?;
+        Ok((PooledTransaction::Transaction(transaction), rest))
+    }
+}
+
+impl RLPEncode for PooledTransactions {
+    fn encode(&self, buf: &mut dyn BufMut) {
+        Encoder::new(buf)
+            .encode_field(&self.id)
+            .encode_field(&self.pooled_transactions)
+            .finish();
+    }
+}
+
+impl RLPDecode for PooledTransactions {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        let decoder = Decoder::new(rlp)?;
+        let (id, decoder) = decoder.decode_field("request-id")?;
+        let (pooled_transactions, decoder) = decoder.decode_field("txs")?;
+        let msg = PooledTransactions {
+            id,
+            pooled_transactions,
+        };
+        Ok((msg, decoder.finish()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_rust_core::{
+        types::{BlobsBundle, EIP1559Transaction, EIP4844Transaction, TxKind, BYTES_PER_BLOB},
+        Address, U256,
+    };
+    use ethereum_rust_storage::{EngineType, MempoolTransaction};
+
+    use super::*;
+
+    fn blob_transaction() -> WrappedEIP4844Transaction {
+        WrappedEIP4844Transaction {
+            tx: EIP4844Transaction {
+                chain_id: 1,
+                nonce: 0,
+                max_priority_fee_per_gas: 1,
+                max_fee_per_gas: 1,
+                gas: 21_000,
+                to: Address::from_low_u64_be(1),
+                value: U256::zero(),
+                data: Bytes::new(),
+                access_list: vec![],
+                max_fee_per_blob_gas: U256::one(),
+                blob_versioned_hashes: vec![H256::from_low_u64_be(1)],
+                signature_y_parity: false,
+                signature_r: U256::one(),
+                signature_s: U256::one(),
+            },
+            blobs_bundle: BlobsBundle {
+                blobs: vec![[1; BYTES_PER_BLOB]],
+                commitments: vec![[2; 48]],
+                proofs: vec![[3; 48]],
+            },
+        }
+    }
+
+    fn transaction() -> Transaction {
+        Transaction::EIP1559Transaction(EIP1559Transaction {
+            chain_id: 1,
+            nonce: 0,
+            max_priority_fee_per_gas: 1,
+            max_fee_per_gas: 1,
+            gas_limit: 21_000,
+            to: TxKind::Call(Address::from_low_u64_be(1)),
+            value: U256::zero(),
+            data: Bytes::new(),
+            access_list: vec![],
+            signature_y_parity: false,
+            signature_r: U256::one(),
+            signature_s: U256::one(),
+        })
+    }
+
+    #[test]
+    fn pooled_blob_transactions_carry_their_sidecar() {
+        let blob_transaction = blob_transaction();
+        let transaction = transaction();
+        let msg = PooledTransactions {
+            id: 1,
+            pooled_transactions: vec![
+                PooledTransaction::Transaction(transaction),
+                PooledTransaction::Blob(blob_transaction.clone()),
+            ],
+        };
+        assert_eq!(
+            PooledTransactions::decode(&msg.encode_to_vec()).unwrap(),
+            msg
+        );
+
+        // A blob transaction without its sidecar is rejected
+        let bare = PooledTransactions {
+            id: 1,
+            pooled_transactions: vec![PooledTransaction::Transaction(
+                Transaction::EIP4844Transaction(blob_transaction.tx),
+            )],
+        };
+        assert!(PooledTransactions::decode(&bare.encode_to_vec()).is_err());
+    }
+
+    #[test]
+    fn pooled_transactions_are_served_from_the_mempool() {
+        let store = Store::new("temp.db", EngineType::InMemory).unwrap();
+        let blob_transaction = blob_transaction();
+        let blob_hash = Transaction::EIP4844Transaction(blob_transaction.tx.clone()).compute_hash();
+        let mempool_transaction = MempoolTransaction {
+            hash: blob_hash,
+            sender: Address::from_low_u64_be(2),
+            transaction: Transaction::EIP4844Transaction(blob_transaction.tx.clone()),
+        };
+        store
+            .add_blob_transaction_to_pool(
+                mempool_transaction,
+                blob_transaction.blobs_bundle.clone(),
+                0,
+                |_, _| Ok::<_, ()>(()),
+            )
+            .unwrap();
+        let transaction = transaction();
+        let hash = transaction.compute_hash();
+        let mempool_transaction = MempoolTransaction {
+            hash,
+            sender: Address::from_low_u64_be(3),
+            transaction: transaction.clone(),
+        };
+        store
+            .add_transaction_to_pool(mempool_transaction, 0, |_, _| Ok::<_, ()>(()))
+            .unwrap();
+
+        let request = GetPooledTransactions {
+            id: 1,
+            transaction_hashes: vec![blob_hash, H256::from_low_u64_be(1), hash],
+        };
+        // Unknown transactions are left out, and blob transactions are sent in their network form
+        assert_eq!(
+            request.fetch_transactions(&store),
+            vec![
+                PooledTransaction::Blob(blob_transaction),
+                PooledTransaction::Transaction(transaction),
+            ]
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use crate::rlpx::{
     connection::{RLPxConnectionPending, RLPxState},
+    error::RLPxError,
     utils::{ecdh_xchng, id2pubkey, kdf, pubkey2id, sha256, sha256_hmac},
 };
 
@@ -14,7 +15,11 @@ use ethereum_rust_core::{
     },
     Signature, H128, H256, H512,
 };
-use k256::{ecdsa::SigningKey, elliptic_curve::sec1::ToEncodedPoint, PublicKey, SecretKey};
+use k256::{
+    ecdsa::{RecoveryId, Signature as EcdsaSignature, SigningKey, VerifyingKey},
+    elliptic_curve::sec1::ToEncodedPoint,
+    PublicKey, SecretKey,
+};
 use rand::Rng;
 use sha3::{Digest, Keccak256};
 
@@ -50,11 +55,6 @@ impl RLPxLocalClient {
         remote_static_pubkey: &PublicKey,
         buf: &mut dyn BufMut,
     ) {
-        const SIGNATURE_SIZE: usize = 65;
-        const IV_SIZE: usize = 16;
-        const MAC_FOOTER_SIZE: usize = 32;
-
-        let mut rng = rand::thread_rng();
         let node_id = pubkey2id(&static_key.public_key());
 
         // Derive a shared secret from the static keys.
@@ -66,62 +66,14 @@ impl RLPxLocalClient {
         // Compose the auth message.
         let auth = AuthMessage::new(signature, node_id, self.nonce);
 
-        // RLP-encode the message.
-        let mut encoded_auth_msg = auth.encode_to_vec();
-
-        // Pad with random amount of data. the amount needs to be at least 100 bytes to make
-        // the message distinguishable from pre-EIP-8 handshakes.
-        let padding_length = rng.gen_range(100..=300);
-        encoded_auth_msg.resize(encoded_auth_msg.len() + padding_length, 0);
-
-        // Precompute the size of the message. This is needed for computing the MAC.
-        let ecies_overhead = SIGNATURE_SIZE + IV_SIZE + MAC_FOOTER_SIZE;
-        let auth_size: u16 = (encoded_auth_msg.len() + ecies_overhead)
-            .try_into()
-            .unwrap();
-        let auth_size_bytes = auth_size.to_be_bytes();
-
-        // Generate a keypair just for this message.
-        let message_secret_key = SecretKey::random(&mut rng);
+        // Encrypt the message for the remote node.
+        let auth_message = encrypt_message(auth.encode_to_vec(), remote_static_pubkey);
 
-        // Derive a shared secret for this message.
-        let message_secret = ecdh_xchng(&message_secret_key, remote_static_pubkey);
-
-        // Derive the AES and MAC keys from the message secret.
-        let mut secret_keys = [0; 32];
-        kdf(&message_secret, &mut secret_keys);
-        let aes_key = &secret_keys[..16];
-        let mac_key = sha256(&secret_keys[16..]);
-
-        // Use the AES secret to encrypt the auth message.
-        let iv = H128::random_using(&mut rng);
-        let mut aes_cipher = Aes128Ctr64BE::new_from_slices(aes_key, &iv.0).unwrap();
-        aes_cipher
-            .try_apply_keystream(&mut encoded_auth_msg)
-            .unwrap();
-        let encrypted_auth_msg = encoded_auth_msg;
-
-        // Use the MAC secret to compute the MAC.
-        let r_public_key = message_secret_key.public_key().to_encoded_point(false);
-        let mac_footer = sha256_hmac(&mac_key, &[&iv.0, &encrypted_auth_msg], &auth_size_bytes);
+        // Write everything into the buffer.
+        buf.put_slice(&auth_message);
 
         // Save the Auth message for the egress-mac initialization
-        let auth_message = [
-            &auth_size_bytes,
-            r_public_key.as_bytes(),
-            &iv.0,
-            &encrypted_auth_msg,
-            &mac_footer,
-        ]
-        .concat();
         self.auth_message = Some(auth_message);
-
-        // Write everything into the buffer.
-        buf.put_slice(&auth_size_bytes);
-        buf.put_slice(r_public_key.as_bytes());
-        buf.put_slice(&iv.0);
-        buf.put_slice(&encrypted_auth_msg);
-        buf.put_slice(&mac_footer);
     }
 
     fn sign_shared_secret(&self, shared_secret: H256) -> Signature {
@@ -142,43 +94,26 @@ impl RLPxLocalClient {
         static_key: &SecretKey,
         msg: &[u8],
         auth_data: [u8; 2],
-    ) -> RLPxConnectionPending {
-        // TODO: return errors instead of panicking
-        let sent_auth = self.auth_message.is_some();
-        assert!(sent_auth, "received Ack without having sent Auth");
-        assert!(msg.len() > 65 + 16 + 32, "message is too short");
-
-        // Split the message into its components. General layout is:
-        // public-key (65) || iv (16) || ciphertext || mac (32)
-        let (pk, rest) = msg.split_at(65);
-        let (iv, rest) = rest.split_at(16);
-        let (c, d) = rest.split_at(rest.len() - 32);
-
-        // Derive the message shared secret.
-        let shared_secret = ecdh_xchng(static_key, &PublicKey::from_sec1_bytes(pk).unwrap());
-
-        // Derive the AES and MAC keys from the message shared secret.
-        let mut buf = [0; 32];
-        kdf(&shared_secret, &mut buf);
-        let aes_key = &buf[..16];
-        let mac_key = sha256(&buf[16..]);
-
-        // Verify the MAC.
-        let expected_d = sha256_hmac(&mac_key, &[iv, c], &auth_data);
-        assert_eq!(d, expected_d);
-
-        // Decrypt the message with the AES key.
-        let mut stream_cipher = Aes128Ctr64BE::new_from_slices(aes_key, iv).unwrap();
-        let decoded_payload = {
-            let mut decoded = c.to_vec();
-            stream_cipher.try_apply_keystream(&mut decoded).unwrap();
-            decoded
+    ) -> Result<RLPxConnectionPending, RLPxError> {
+        let Some(auth_message) = self.auth_message else {
+            return Err(RLPxError::Handshake(
+                "received Ack without having sent Auth".to_string(),
+            ));
         };
 
-        // RLP-decode the message.
-        let (ack, _padding) = AckMessage::decode_unfinished(&decoded_payload).unwrap();
+        // Decrypt and RLP-decode the message.
+        let decoded_payload = decrypt_message(static_key, msg, auth_data)?;
+        let (ack, _padding) = AckMessage::decode_unfinished(&decoded_payload)?;
 
-        let (aes_key, mac_key) = self.derive_secrets(&ack);
+        let remote_ephemeral_key = ack
+            .get_ephemeral_pubkey()
+            .ok_or(RLPxError::Handshake("invalid ephemeral key".to_string()))?;
+        let (aes_key, mac_key) = derive_secrets(
+            &self.ephemeral_key,
+            &remote_ephemeral_key,
+            ack.nonce,
+            self.nonce,
+        );
 
         let ack_message = [&auth_data, msg].concat();
 
@@ -186,44 +121,190 @@ impl RLPxLocalClient {
             aes_key,
             mac_key,
             self.nonce,
-            self.auth_message.as_ref().unwrap(),
+            &auth_message,
             ack.nonce,
             &ack_message,
         );
 
-        RLPxConnectionPending::new(state)
+        Ok(RLPxConnectionPending::new(state))
     }
 
-    fn derive_secrets(&self, ack: &AckMessage) -> (H256, H256) {
-        // TODO: don't panic
-        let ephemeral_key_secret =
-            ecdh_xchng(&self.ephemeral_key, &ack.get_ephemeral_pubkey().unwrap());
+    /// Decodes an Auth message and writes the Ack sent in response into the buffer, completing a handshake.
+    /// Consumes `self` and returns an [`RLPxConnectionPending`] along with the initiator's public key
+    pub fn decode_auth_message_and_encode_ack(
+        self,
+        static_key: &SecretKey,
+        msg: &[u8],
+        auth_data: [u8; 2],
+        buf: &mut dyn BufMut,
+    ) -> Result<(RLPxConnectionPending, PublicKey), RLPxError> {
+        // Decrypt and RLP-decode the message.
+        let decoded_payload = decrypt_message(static_key, msg, auth_data)?;
+        let (auth, _padding) = AuthMessage::decode_unfinished(&decoded_payload)?;
+
+        let remote_static_pubkey =
+            id2pubkey(auth.node_id).ok_or(RLPxError::Handshake("invalid node id".to_string()))?;
+
+        // The initiator signed `static-shared-secret ^ initiator-nonce` with its ephemeral key,
+        // so the key can be recovered from the signature.
+        let static_shared_secret = ecdh_xchng(static_key, &remote_static_pubkey);
+        let signature_prehash = H256(static_shared_secret) ^ auth.nonce;
+        let remote_ephemeral_key = recover_pubkey(&auth.signature, signature_prehash)?;
+
+        // Compose the ack message and encrypt it for the initiator.
+        let ack = AckMessage::new(pubkey2id(&self.ephemeral_key.public_key()), self.nonce);
+        let ack_message = encrypt_message(ack.encode_to_vec(), &remote_static_pubkey);
+        buf.put_slice(&ack_message);
+
+        let (aes_key, mac_key) = derive_secrets(
+            &self.ephemeral_key,
+            &remote_ephemeral_key,
+            self.nonce,
+            auth.nonce,
+        );
+
+        let auth_message = [&auth_data, msg].concat();
 
-        println!(
-            "local_ephemeral_public_key: {:x}",
-            pubkey2id(&self.ephemeral_key.public_key())
+        let state = RLPxState::new(
+            aes_key,
+            mac_key,
+            self.nonce,
+            &ack_message,
+            auth.nonce,
+            &auth_message,
         );
-        // ad57a6adc787e0db24d734e4fc0f9de9add333e17dc09677719ff8ae86b5741725a7f8467a56b4a190808c42f83a833e0543a842a2b6684df847609b009e97e9
-        // ba202bf05245e569da208c195957cd0354e33ac429dcd1f596b59ce84977a40e 16718c27ef912689639dc28bc77dcf978803e0017093dc67c79852cdcb79352a
-        println!("ephemeral_key_secret: {:x}", H256(ephemeral_key_secret));
-
-        // keccak256(nonce || initiator-nonce)
-        let nonce = ack.nonce.0;
-        let initiator_nonce = self.nonce.0;
-        let hashed_nonces = Keccak256::digest([nonce, initiator_nonce].concat()).into();
-        // shared-secret = keccak256(ephemeral-key || keccak256(nonce || initiator-nonce))
-        let shared_secret =
-            Keccak256::digest([ephemeral_key_secret, hashed_nonces].concat()).into();
-
-        // aes-secret = keccak256(ephemeral-key || shared-secret)
-        let aes_key = Keccak256::digest([ephemeral_key_secret, shared_secret].concat()).into();
-        // mac-secret = keccak256(ephemeral-key || aes-secret)
-        let mac_key = Keccak256::digest([ephemeral_key_secret, aes_key].concat());
-
-        (H256(aes_key), H256(mac_key.into()))
+
+        Ok((RLPxConnectionPending::new(state), remote_static_pubkey))
     }
 }
 
+/// Encrypts the RLP-encoded handshake message for the remote node, as specified by EIP-8.
+/// Returns the whole message, prefixed by its size
+fn encrypt_message(mut encoded_msg: Vec<u8>, remote_static_pubkey: &PublicKey) -> Vec<u8> {
+    const SIGNATURE_SIZE: usize = 65;
+    const IV_SIZE: usize = 16;
+    const MAC_FOOTER_SIZE: usize = 32;
+
+    let mut rng = rand::thread_rng();
+
+    // Pad with random amount of data. the amount needs to be at least 100 bytes to make
+    // the message distinguishable from pre-EIP-8 handshakes.
+    let padding_length = rng.gen_range(100..=300);
+    encoded_msg.resize(encoded_msg.len() + padding_length, 0);
+
+    // Precompute the size of the message. This is needed for computing the MAC.
+    let ecies_overhead = SIGNATURE_SIZE + IV_SIZE + MAC_FOOTER_SIZE;
+    let msg_size: u16 = (encoded_msg.len() + ecies_overhead).try_into().unwrap();
+    let msg_size_bytes = msg_size.to_be_bytes();
+
+    // Generate a keypair just for this message.
+    let message_secret_key = SecretKey::random(&mut rng);
+
+    // Derive a shared secret for this message.
+    let message_secret = ecdh_xchng(&message_secret_key, remote_static_pubkey);
+
+    // Derive the AES and MAC keys from the message secret.
+    let mut secret_keys = [0; 32];
+    kdf(&message_secret, &mut secret_keys);
+    let aes_key = &secret_keys[..16];
+    let mac_key = sha256(&secret_keys[16..]);
+
+    // Use the AES secret to encrypt the message.
+    let iv = H128::random_using(&mut rng);
+    let mut aes_cipher = Aes128Ctr64BE::new_from_slices(aes_key, &iv.0).unwrap();
+    aes_cipher.try_apply_keystream(&mut encoded_msg).unwrap();
+    let encrypted_msg = encoded_msg;
+
+    // Use the MAC secret to compute the MAC.
+    let r_public_key = message_secret_key.public_key().to_encoded_point(false);
+    let mac_footer = sha256_hmac(&mac_key, &[&iv.0, &encrypted_msg], &msg_size_bytes);
+
+    [
+        &msg_size_bytes,
+        r_public_key.as_bytes(),
+        &iv.0,
+        &encrypted_msg,
+        &mac_footer,
+    ]
+    .concat()
+}
+
+/// Decrypts a handshake message sent to us, returning its RLP-encoded payload.
+/// `msg` is the message without its size prefix, which is passed as `auth_data`
+fn decrypt_message(
+    static_key: &SecretKey,
+    msg: &[u8],
+    auth_data: [u8; 2],
+) -> Result<Vec<u8>, RLPxError> {
+    if msg.len() <= 65 + 16 + 32 {
+        return Err(RLPxError::Handshake("message is too short".to_string()));
+    }
+
+    // Split the message into its components. General layout is:
+    // public-key (65) || iv (16) || ciphertext || mac (32)
+    let (pk, rest) = msg.split_at(65);
+    let (iv, rest) = rest.split_at(16);
+    let (c, d) = rest.split_at(rest.len() - 32);
+
+    // Derive the message shared secret.
+    let message_pubkey = PublicKey::from_sec1_bytes(pk)
+        .map_err(|_| RLPxError::Handshake("invalid message public key".to_string()))?;
+    let shared_secret = ecdh_xchng(static_key, &message_pubkey);
+
+    // Derive the AES and MAC keys from the message shared secret.
+    let mut buf = [0; 32];
+    kdf(&shared_secret, &mut buf);
+    let aes_key = &buf[..16];
+    let mac_key = sha256(&buf[16..]);
+
+    // Verify the MAC.
+    let expected_d = sha256_hmac(&mac_key, &[iv, c], &auth_data);
+    if d != expected_d {
+        return Err(RLPxError::InvalidMac);
+    }
+
+    // Decrypt the message with the AES key.
+    let mut stream_cipher = Aes128Ctr64BE::new_from_slices(aes_key, iv).unwrap();
+    let mut decoded = c.to_vec();
+    stream_cipher.try_apply_keystream(&mut decoded).unwrap();
+    Ok(decoded)
+}
+
+/// Recovers the public key of the key that signed the prehash
+fn recover_pubkey(signature: &Signature, prehash: H256) -> Result<PublicKey, RLPxError> {
+    let invalid_signature = |_| RLPxError::Handshake("invalid signature".to_string());
+    let signature_bytes = signature.as_bytes();
+    let rid = RecoveryId::from_byte(signature_bytes[64])
+        .ok_or(RLPxError::Handshake("invalid recovery id".to_string()))?;
+    let signature =
+        EcdsaSignature::from_slice(&signature_bytes[..64]).map_err(invalid_signature)?;
+    let verifying_key = VerifyingKey::recover_from_prehash(&prehash.0, &signature, rid)
+        .map_err(invalid_signature)?;
+    Ok(verifying_key.into())
+}
+
+/// Derives the AES and MAC secrets of the connection from the ephemeral keys and both nonces
+fn derive_secrets(
+    ephemeral_key: &SecretKey,
+    remote_ephemeral_key: &PublicKey,
+    recipient_nonce: H256,
+    initiator_nonce: H256,
+) -> (H256, H256) {
+    let ephemeral_key_secret = ecdh_xchng(ephemeral_key, remote_ephemeral_key);
+
+    // keccak256(nonce || initiator-nonce)
+    let hashed_nonces = Keccak256::digest([recipient_nonce.0, initiator_nonce.0].concat()).into();
+    // shared-secret = keccak256(ephemeral-key || keccak256(nonce || initiator-nonce))
+    let shared_secret = Keccak256::digest([ephemeral_key_secret, hashed_nonces].concat()).into();
+
+    // aes-secret = keccak256(ephemeral-key || shared-secret)
+    let aes_key = Keccak256::digest([ephemeral_key_secret, shared_secret].concat()).into();
+    // mac-secret = keccak256(ephemeral-key || aes-secret)
+    let mac_key = Keccak256::digest([ephemeral_key_secret, aes_key].concat());
+
+    (H256(aes_key), H256(mac_key.into()))
+}
+
 #[derive(Debug)]
 pub(crate) struct AuthMessage {
     /// The signature of the message.
@@ -263,11 +344,11 @@ impl RLPEncode for AuthMessage {
 impl RLPDecode for AuthMessage {
     // NOTE: discards any extra data in the list after the known fields.
     fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
-        let decoder = Decoder::new(rlp).unwrap();
-        let (signature, decoder) = decoder.decode_field("signature").unwrap();
-        let (node_id, decoder) = decoder.decode_field("node_id").unwrap();
-        let (nonce, decoder) = decoder.decode_field("nonce").unwrap();
-        let (version, decoder) = decoder.decode_field("version").unwrap();
+        let decoder = Decoder::new(rlp)?;
+        let (signature, decoder) = decoder.decode_field("signature")?;
+        let (node_id, decoder) = decoder.decode_field("node_id")?;
+        let (nonce, decoder) = decoder.decode_field("nonce")?;
+        let (version, decoder) = decoder.decode_field("version")?;
 
         let rest = decoder.finish_unchecked();
         let this = Self {
@@ -292,6 +373,14 @@ pub(crate) struct AckMessage {
 }
 
 impl AckMessage {
+    pub fn new(ephemeral_pubkey: H512, nonce: H256) -> Self {
+        Self {
+            ephemeral_pubkey,
+            nonce,
+            version: 5,
+        }
+    }
+
     pub fn get_ephemeral_pubkey(&self) -> Option<PublicKey> {
         id2pubkey(self.ephemeral_pubkey)
     }
@@ -310,10 +399,10 @@ impl RLPEncode for AckMessage {
 impl RLPDecode for AckMessage {
     // NOTE: discards any extra data in the list after the known fields.
     fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
-        let decoder = Decoder::new(rlp).unwrap();
-        let (ephemeral_pubkey, decoder) = decoder.decode_field("ephemeral_pubkey").unwrap();
-        let (nonce, decoder) = decoder.decode_field("nonce").unwrap();
-        let (version, decoder) = decoder.decode_field("version").unwrap();
+        let decoder = Decoder::new(rlp)?;
+        let (ephemeral_pubkey, decoder) = decoder.decode_field("ephemeral_pubkey")?;
+        let (nonce, decoder) = decoder.decode_field("nonce")?;
+        let (version, decoder) = decoder.decode_field("version")?;
 
         let rest = decoder.finish_unchecked();
         let this = Self {
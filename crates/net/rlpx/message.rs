@@ -1,33 +1,149 @@
 use bytes::BufMut;
 use ethereum_rust_core::rlp::error::RLPDecodeError;
 
+use super::eth::{EthMessage, ETH_MESSAGE_COUNT};
 use super::p2p::{DisconnectMessage, HelloMessage, PingMessage, PongMessage, RLPxMessage as _};
 
+/// Message ids below this one are reserved for the p2p capability
+const P2P_MESSAGE_COUNT: u8 = 0x10;
+
+/// Capability supported by both ends of a connection, along with the message id its messages start at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SharedCapability {
+    pub name: String,
+    pub offset: u8,
+}
+
+impl SharedCapability {
+    fn contains(&self, msg_id: u8) -> bool {
+        msg_id >= self.offset && msg_id - self.offset < message_count(&self.name)
+    }
+}
+
+/// Amount of message ids used by each of the capabilities besides p2p
+fn message_count(capability: &str) -> u8 {
+    match capability {
+        "eth" => ETH_MESSAGE_COUNT,
+        _ => 0,
+    }
+}
+
+/// Picks the capabilities supported by both ends and assigns them consecutive message ids
+/// in alphabetical order, as the RLPx spec requires
+pub(crate) fn shared_capabilities(
+    local: &[(&str, u8)],
+    remote: &[(String, u8)],
+) -> Vec<SharedCapability> {
+    let mut shared: Vec<String> = remote
+        .iter()
+        .filter(|(name, version)| {
+            local.contains(&(name.as_str(), *version)) && message_count(name) > 0
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+    shared.sort();
+    shared.dedup();
+
+    let mut offset = P2P_MESSAGE_COUNT;
+    shared
+        .into_iter()
+        .map(|name| {
+            let capability = SharedCapability { offset, name };
+            offset += message_count(&capability.name);
+            capability
+        })
+        .collect()
+}
+
 pub(crate) enum Message {
     Hello(HelloMessage),
     Disconnect(DisconnectMessage),
     Ping(PingMessage),
     Pong(PongMessage),
+    Eth(EthMessage),
 }
 
 impl Message {
-    pub fn decode(msg_id: u8, msg_data: &[u8]) -> Result<Message, RLPDecodeError> {
+    /// Decodes a message, using the capabilities shared with the peer to tell which one its id belongs to
+    pub fn decode(
+        msg_id: u8,
+        msg_data: &[u8],
+        capabilities: &[SharedCapability],
+    ) -> Result<Message, RLPDecodeError> {
         match msg_id {
             0x00 => Ok(Message::Hello(HelloMessage::decode(msg_data)?)),
             0x01 => Ok(Message::Disconnect(DisconnectMessage::decode(msg_data)?)),
             0x02 => Ok(Message::Ping(PingMessage::decode(msg_data)?)),
             0x03 => Ok(Message::Pong(PongMessage::decode(msg_data)?)),
-            0x10 => Ok(Message::Pong(PongMessage::decode(msg_data)?)),
-            _ => Err(RLPDecodeError::MalformedData),
+            _ => {
+                let capability = capabilities
+                    .iter()
+                    .find(|capability| capability.contains(msg_id))
+                    .ok_or_else(|| {
+                        RLPDecodeError::Custom(format!("unknown message id {msg_id:#x}"))
+                    })?;
+                match capability.name.as_str() {
+                    "eth" => Ok(Message::Eth(EthMessage::decode(
+                        msg_id - capability.offset,
+                        msg_data,
+                    )?)),
+                    _ => Err(RLPDecodeError::MalformedData),
+                }
+            }
+        }
+    }
+
+    /// Id of the message given the capabilities shared with the peer, None if its capability isn't shared
+    pub fn id(&self, capabilities: &[SharedCapability]) -> Option<u8> {
+        match self {
+            Message::Hello(_) => Some(0x00),
+            Message::Disconnect(_) => Some(0x01),
+            Message::Ping(_) => Some(0x02),
+            Message::Pong(_) => Some(0x03),
+            Message::Eth(msg) => capabilities
+                .iter()
+                .find(|capability| capability.name == "eth")
+                .map(|capability| capability.offset + msg.code()),
         }
     }
 
+    /// Encodes the message data, without its id
     pub fn encode(&self, buf: &mut dyn BufMut) {
         match self {
             Message::Hello(msg) => msg.encode(buf),
             Message::Disconnect(msg) => msg.encode(buf),
             Message::Ping(msg) => msg.encode(buf),
             Message::Pong(msg) => msg.encode(buf),
+            Message::Eth(msg) => msg.encode(buf),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_capabilities_are_offset_alphabetically() {
+        let remote = vec![
+            ("snap".to_string(), 1),
+            ("eth".to_string(), 67),
+            ("eth".to_string(), 68),
+            ("p2p".to_string(), 5),
+        ];
+        let shared = shared_capabilities(&[("p2p", 5), ("eth", 68)], &remote);
+        assert_eq!(
+            shared,
+            vec![SharedCapability {
+                name: "eth".to_string(),
+                offset: 0x10,
+            }]
+        );
+        assert!(shared[0].contains(0x10));
+        assert!(shared[0].contains(0x20));
+        assert!(!shared[0].contains(0x21));
+
+        let remote = vec![("eth".to_string(), 67)];
+        assert!(shared_capabilities(&[("p2p", 5), ("eth", 68)], &remote).is_empty());
+    }
+}
@@ -1,8 +1,14 @@
-use ethereum_rust_core::H512;
+use ethereum_rust_core::{rlp::error::RLPDecodeError, H512};
 use k256::{
     elliptic_curve::sec1::{FromEncodedPoint, ToEncodedPoint},
     EncodedPoint, PublicKey, SecretKey,
 };
+use snap::raw::{
+    decompress_len, max_compress_len, Decoder as SnappyDecoder, Encoder as SnappyEncoder,
+};
+
+/// Maximum size of a decompressed message, as set by the devp2p spec
+const MAX_DECOMPRESSED_SIZE: usize = 16 * 1024 * 1024;
 
 pub fn sha256(data: &[u8]) -> [u8; 32] {
     use k256::sha2::Digest;
@@ -48,6 +54,34 @@ pub fn id2pubkey(id: H512) -> Option<PublicKey> {
     PublicKey::from_encoded_point(&point).into_option()
 }
 
+/// Compresses the data of a message after the Hello exchange, as required by the devp2p spec
+pub fn snappy_compress(encoded_data: &[u8]) -> Vec<u8> {
+    let mut snappy_encoder = SnappyEncoder::new();
+    let mut msg_data = vec![0; max_compress_len(encoded_data.len()) + 1];
+
+    let compressed_size = snappy_encoder
+        .compress(encoded_data, &mut msg_data)
+        .unwrap();
+
+    msg_data.truncate(compressed_size);
+    msg_data
+}
+
+/// Decompresses the data of a message, refusing messages that would be bigger than [`MAX_DECOMPRESSED_SIZE`]
+pub fn snappy_decompress(msg_data: &[u8]) -> Result<Vec<u8>, RLPDecodeError> {
+    let decompressed_size =
+        decompress_len(msg_data).map_err(|e| RLPDecodeError::Custom(e.to_string()))?;
+    if decompressed_size > MAX_DECOMPRESSED_SIZE {
+        return Err(RLPDecodeError::Custom(format!(
+            "message too big: {decompressed_size} bytes"
+        )));
+    }
+    let mut snappy_decoder = SnappyDecoder::new();
+    snappy_decoder
+        .decompress_vec(msg_data)
+        .map_err(|e| RLPDecodeError::Custom(e.to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
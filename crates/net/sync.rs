@@ -0,0 +1,526 @@
+use std::sync::{Arc, Mutex};
+
+use ethereum_rust_chain::{error::ChainError, Blockchain};
+use ethereum_rust_core::{
+    rlp::encode::RLPEncode,
+    types::{
+        compute_transactions_root, compute_withdrawals_root, Block, BlockBody, BlockHash,
+        BlockHeader, BlockNumber,
+    },
+    H256, H512,
+};
+use ethereum_rust_storage::{error::StoreError, Store};
+use sha3::{Digest, Keccak256};
+use thiserror::Error;
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn};
+
+use crate::{
+    peer_manager::PeerManager,
+    rlpx::eth::{
+        blocks::{BlockBodies, BlockHeaders, GetBlockBodies, GetBlockHeaders, HashOrNumber},
+        EthMessage,
+    },
+};
+
+/// Amount of headers requested to a peer at once
+const HEADER_BATCH_SIZE: u64 = 192;
+/// Amount of bodies requested to a peer at once
+const BODY_BATCH_SIZE: usize = 128;
+/// Amount of requests in a row that can fail before giving up on the sync
+const MAX_FAILED_REQUESTS: usize = 10;
+
+#[derive(Debug, Error)]
+pub enum SyncError {
+    #[error("No peers to sync from")]
+    NoPeers,
+    #[error("Peers failed to serve the chain: {0}")]
+    PeersFailed(String),
+    #[error("Target block doesn't descend from our finalized chain")]
+    UnknownChain,
+    #[error("Chain error: {0}")]
+    Chain(#[from] ChainError),
+    #[error("Store error: {0}")]
+    Store(#[from] StoreError),
+    #[error("Block import task failed: {0}")]
+    Import(#[from] tokio::task::JoinError),
+}
+
+/// Progress of a sync, as reported by `eth_syncing`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncStatus {
+    /// Block the sync started at
+    pub starting_block: BlockNumber,
+    /// Latest block imported
+    pub current_block: BlockNumber,
+    /// Block the sync is heading to, known once its header is downloaded
+    pub highest_block: BlockNumber,
+}
+
+/// Downloads the blocks between the local chain and a target head from connected peers.
+/// Headers are downloaded first, going back from the target until reaching a block we already have. Then the blocks are
+/// imported going up from that block in segments: the segment's bodies are fetched in parallel from different peers and
+/// its blocks are imported in order before moving on to the next segment, so only the bodies of one segment are held in
+/// memory at a time
+#[derive(Debug, Clone)]
+pub struct SyncManager {
+    peer_manager: PeerManager,
+    storage: Store,
+    /// Progress of the sync in course, None while not syncing
+    status: Arc<Mutex<Option<SyncStatus>>>,
+}
+
+impl SyncManager {
+    pub fn new(peer_manager: PeerManager, storage: Store) -> Self {
+        Self {
+            peer_manager,
+            storage,
+            status: Default::default(),
+        }
+    }
+
+    /// Progress of the sync in course, None while not syncing
+    pub fn status(&self) -> Option<SyncStatus> {
+        *self.status.lock().unwrap()
+    }
+
+    /// Starts syncing up to the given block in the background, unless a sync is already in course.
+    /// Once all blocks are imported the target becomes the head of the canonical chain
+    pub fn start_sync(&self, target: BlockHash) -> Result<(), StoreError> {
+        let mut status = self.status.lock().unwrap();
+        if status.is_some() {
+            debug!("Already syncing, ignoring sync target {target:#x}");
+            return Ok(());
+        }
+        let latest_block_number = self.storage.get_latest_block_number()?.unwrap_or_default();
+        *status = Some(SyncStatus {
+            starting_block: latest_block_number,
+            current_block: latest_block_number,
+            highest_block: latest_block_number,
+        });
+        info!("Starting sync to block {target:#x}");
+
+        let syncer = self.clone();
+        tokio::spawn(async move {
+            match syncer.sync(target).await {
+                Ok(()) => info!("Synced up to block {target:#x}"),
+                Err(error) => warn!("Sync to block {target:#x} failed: {error}"),
+            }
+            *syncer.status.lock().unwrap() = None;
+        });
+        Ok(())
+    }
+
+    async fn sync(&self, target: BlockHash) -> Result<(), SyncError> {
+        let blockchain = Blockchain::new(self.storage.clone());
+        let headers = self.download_headers(&blockchain, target).await?;
+        if let Some(first_header) = headers.first() {
+            debug!(
+                "Downloaded headers down to block {}, fetching bodies",
+                first_header.number
+            );
+        }
+        for segment in headers.chunks(HEADER_BATCH_SIZE as usize) {
+            self.download_blocks(&blockchain, segment.to_vec()).await?;
+        }
+        blockchain.set_head(target)?;
+        Ok(())
+    }
+
+    /// Downloads the headers of the blocks we are missing, from the target down to the first block we already have.
+    /// Headers are requested in segments going back from the target, following the parent hashes, so only the blocks
+    /// the target descends from are accepted. Returns the headers in ascending order, so the parent of the first one
+    /// is a block in the store
+    async fn download_headers(
+        &self,
+        blockchain: &Blockchain,
+        target: BlockHash,
+    ) -> Result<Vec<BlockHeader>, SyncError> {
+        let finalized = self
+            .storage
+            .get_finalized_block_number()?
+            .unwrap_or_default();
+        let mut headers = vec![];
+        let mut next = target;
+        let mut failed_requests = 0;
+        let mut round = 0;
+        while blockchain.get_block_header_by_hash(next)?.is_none() {
+            let peer = self.pick_peer(round)?;
+            round += 1;
+            let segment = match self
+                .request_headers(peer, HashOrNumber::Hash(next), HEADER_BATCH_SIZE)
+                .await
+            {
+                Some(segment)
+                    if segment.len() as u64 <= HEADER_BATCH_SIZE
+                        && is_header_chain(next, &segment) =>
+                {
+                    segment
+                }
+                _ => {
+                    failed_requests += 1;
+                    if failed_requests >= MAX_FAILED_REQUESTS {
+                        return Err(SyncError::PeersFailed(format!(
+                            "no valid headers for block {next:#x}"
+                        )));
+                    }
+                    continue;
+                }
+            };
+            failed_requests = 0;
+            if headers.is_empty() {
+                self.update_status(|status| status.highest_block = segment[0].number);
+            }
+            for header in segment {
+                // Finalized blocks can't be reorganized, so a chain forking off before them can't be followed
+                if header.number <= finalized {
+                    return Err(SyncError::UnknownChain);
+                }
+                next = header.parent_hash;
+                headers.push(header);
+                if blockchain.get_block_header_by_hash(next)?.is_some() {
+                    break;
+                }
+            }
+        }
+        headers.reverse();
+        Ok(headers)
+    }
+
+    /// Fetches the bodies of the given blocks and imports them in order.
+    /// Each round requests consecutive chunks of the missing bodies to different peers at the same time
+    async fn download_blocks(
+        &self,
+        blockchain: &Blockchain,
+        headers: Vec<BlockHeader>,
+    ) -> Result<(), SyncError> {
+        let hashes: Vec<BlockHash> = headers
+            .iter()
+            .map(|header| header.compute_block_hash())
+            .collect();
+        let mut bodies: Vec<Option<BlockBody>> = vec![None; headers.len()];
+        let mut imported = 0;
+        let mut failed_requests = 0;
+        while imported < headers.len() {
+            let peers = self.peer_ids();
+            if peers.is_empty() {
+                return Err(SyncError::NoPeers);
+            }
+            let missing: Vec<usize> = (imported..headers.len())
+                .filter(|index| bodies[*index].is_none())
+                .take(peers.len() * BODY_BATCH_SIZE)
+                .collect();
+
+            let mut requests = JoinSet::new();
+            for (peer, chunk) in peers.into_iter().zip(missing.chunks(BODY_BATCH_SIZE)) {
+                let peer_manager = self.peer_manager.clone();
+                let request = EthMessage::GetBlockBodies(GetBlockBodies {
+                    id: 0,
+                    block_hashes: chunk.iter().map(|index| hashes[*index]).collect(),
+                });
+                let chunk = chunk.to_vec();
+                requests
+                    .spawn(async move { (peer, chunk, peer_manager.request(peer, request).await) });
+            }
+
+            let mut received = 0;
+            while let Some(result) = requests.join_next().await {
+                let Ok((peer, chunk, response)) = result else {
+                    continue;
+                };
+                let block_bodies = match response {
+                    Ok(EthMessage::BlockBodies(BlockBodies { block_bodies, .. })) => block_bodies,
+                    Ok(_) => {
+                        debug!("Peer {peer:#x} answered a bodies request with another message");
+                        continue;
+                    }
+                    Err(error) => {
+                        debug!("Bodies request to peer {peer:#x} failed: {error}");
+                        continue;
+                    }
+                };
+                // Peers may answer with just the start of the requested bodies, the rest is requested again
+                for (index, body) in chunk.into_iter().zip(block_bodies) {
+                    if !body_matches_header(&body, &headers[index]) {
+                        debug!("Peer {peer:#x} sent a body that doesn't match its header");
+                        break;
+                    }
+                    bodies[index] = Some(body);
+                    received += 1;
+                }
+            }
+            if received == 0 {
+                failed_requests += 1;
+                if failed_requests >= MAX_FAILED_REQUESTS {
+                    return Err(SyncError::PeersFailed(format!(
+                        "no valid bodies for block {:#x}",
+                        hashes[imported]
+                    )));
+                }
+                continue;
+            }
+            failed_requests = 0;
+
+            // Import the blocks whose bodies we have, stopping at the first one still missing
+            let mut blocks = vec![];
+            while let Some(body) = bodies.get_mut(imported).and_then(Option::take) {
+                blocks.push(Block {
+                    header: headers[imported].clone(),
+                    body,
+                });
+                imported += 1;
+            }
+            self.import_blocks(blockchain, blocks).await?;
+        }
+        Ok(())
+    }
+
+    /// Adds the blocks to the block tree in order. Execution is blocking, so it is done outside of the async runtime
+    async fn import_blocks(
+        &self,
+        blockchain: &Blockchain,
+        blocks: Vec<Block>,
+    ) -> Result<(), SyncError> {
+        let blockchain = blockchain.clone();
+        let syncer = self.clone();
+        tokio::task::spawn_blocking(move || {
+            for block in blocks {
+                blockchain.add_block(&block)?;
+                syncer.update_status(|status| status.current_block = block.header.number);
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    /// Requests consecutive headers going back from the given block, None if the peer fails to answer
+    async fn request_headers(
+        &self,
+        peer: H512,
+        start: HashOrNumber,
+        limit: u64,
+    ) -> Option<Vec<BlockHeader>> {
+        let request = EthMessage::GetBlockHeaders(GetBlockHeaders {
+            id: 0,
+            startblock: start,
+            limit,
+            skip: 0,
+            reverse: true,
+        });
+        match self.peer_manager.request(peer, request).await {
+            Ok(EthMessage::BlockHeaders(BlockHeaders { block_headers, .. })) => Some(block_headers),
+            Ok(_) => {
+                debug!("Peer {peer:#x} answered a headers request with another message");
+                None
+            }
+            Err(error) => {
+                debug!("Headers request to peer {peer:#x} failed: {error}");
+                None
+            }
+        }
+    }
+
+    /// Picks the peer to send the request of the given round to, going through all peers in turn
+    fn pick_peer(&self, round: usize) -> Result<H512, SyncError> {
+        let peers = self.peer_ids();
+        if peers.is_empty() {
+            return Err(SyncError::NoPeers);
+        }
+        Ok(peers[round % peers.len()])
+    }
+
+    fn peer_ids(&self) -> Vec<H512> {
+        let mut peers: Vec<H512> = self
+            .peer_manager
+            .peers()
+            .iter()
+            .map(|peer| peer.node_id)
+            .collect();
+        // Keep the same order between calls so that peers are picked in turn
+        peers.sort();
+        peers
+    }
+
+    fn update_status(&self, update: impl FnOnce(&mut SyncStatus)) {
+        if let Some(status) = self.status.lock().unwrap().as_mut() {
+            update(status);
+        }
+    }
+}
+
+/// Returns true if the headers are consecutive blocks going back from the given one, each one being the parent of the
+/// previous one
+fn is_header_chain(start: BlockHash, headers: &[BlockHeader]) -> bool {
+    headers
+        .first()
+        .is_some_and(|header| header.compute_block_hash() == start)
+        && headers.windows(2).all(|pair| {
+            pair[0].parent_hash == pair[1].compute_block_hash()
+                && pair[0].number == pair[1].number + 1
+        })
+}
+
+/// Returns true if the body's transactions, ommers and withdrawals are the ones committed to in the header
+fn body_matches_header(body: &BlockBody, header: &BlockHeader) -> bool {
+    let ommers_hash = H256::from_slice(&Keccak256::digest(body.ommers.encode_to_vec()));
+    let withdrawals_root = body
+        .withdrawals
+        .as_ref()
+        .map(|withdrawals| compute_withdrawals_root(withdrawals));
+    header.transactions_root == compute_transactions_root(&body.transactions)
+        && header.ommers_hash == ommers_hash
+        && header.withdrawals_root == withdrawals_root
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_rust_chain::payload::{build_payload, create_payload, BuildPayloadArgs};
+    use ethereum_rust_core::{
+        types::{Genesis, DEFAULT_OMMERS_HASH},
+        Address,
+    };
+    use ethereum_rust_storage::EngineType;
+
+    use super::*;
+    use crate::{peer_manager::PeerInfo, types::Node};
+
+    fn test_store() -> Store {
+        const GENESIS_KURTOSIS: &str = include_str!("../../test_data/genesis-kurtosis.json");
+        let genesis: Genesis =
+            serde_json::from_str(GENESIS_KURTOSIS).expect("deserialize genesis-kurtosis.json");
+        let mut store = Store::new("", EngineType::InMemory).unwrap();
+        store.add_initial_state(genesis).unwrap();
+        store
+    }
+
+    /// Adds the given amount of empty blocks on top of the store's head, returning the hash of the last one
+    fn extend_chain(store: &Store, blocks: u64, fee_recipient: Address) -> BlockHash {
+        let blockchain = Blockchain::new(store.clone());
+        let mut parent = store
+            .get_block_header(store.get_latest_block_number().unwrap().unwrap())
+            .unwrap()
+            .unwrap();
+        for _ in 0..blocks {
+            let args = BuildPayloadArgs {
+                parent: parent.compute_block_hash(),
+                timestamp: parent.timestamp + 12,
+                fee_recipient,
+                random: H256::zero(),
+                withdrawals: Vec::new(),
+                beacon_root: Some(H256::zero()),
+                version: 3,
+            };
+            let mut block = create_payload(&args, store).unwrap();
+            build_payload(&mut block, store, Vec::new()).unwrap();
+            blockchain.add_block(&block).unwrap();
+            parent = block.header;
+        }
+        parent.compute_block_hash()
+    }
+
+    /// Connects a peer that answers the requests sent to it from the given store
+    fn add_serving_peer(peer_manager: &PeerManager, id: u64, store: Store) {
+        let node_id = H512::from_low_u64_be(id);
+        peer_manager
+            .add_peer(PeerInfo {
+                node_id,
+                remote_addr: "127.0.0.1:30303".parse().unwrap(),
+                inbound: false,
+                client_id: "test".to_string(),
+                capabilities: vec![("eth".to_string(), 68)],
+                head: None,
+            })
+            .unwrap();
+        let mut requests = peer_manager.request_channel(node_id);
+        tokio::spawn(async move {
+            while let Some(request) = requests.recv().await {
+                if let Some(response) = request.message.answer(&store).unwrap() {
+                    let _ = request.response.send(response);
+                }
+            }
+        });
+    }
+
+    /// Chain of empty headers numbered from five to one, in descending order
+    fn header_chain() -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = vec![];
+        let mut parent_hash = H256::zero();
+        for number in 1..=5 {
+            let header = BlockHeader {
+                number,
+                parent_hash,
+                ..Default::default()
+            };
+            parent_hash = header.compute_block_hash();
+            headers.push(header);
+        }
+        headers.reverse();
+        headers
+    }
+
+    #[test]
+    fn validate_downloaded_data() {
+        let headers = header_chain();
+        let start = headers[0].compute_block_hash();
+        assert!(is_header_chain(start, &headers));
+        assert!(is_header_chain(
+            headers[1].compute_block_hash(),
+            &headers[1..]
+        ));
+        assert!(!is_header_chain(start, &headers[1..]));
+        assert!(!is_header_chain(start, &[]));
+        let mut gap = headers.clone();
+        gap.remove(2);
+        assert!(!is_header_chain(start, &gap));
+        let mut reversed = headers.clone();
+        reversed.reverse();
+        assert!(!is_header_chain(
+            reversed[0].compute_block_hash(),
+            &reversed
+        ));
+
+        let header = BlockHeader {
+            transactions_root: compute_transactions_root(&[]),
+            ommers_hash: *DEFAULT_OMMERS_HASH,
+            withdrawals_root: Some(compute_withdrawals_root(&[])),
+            ..Default::default()
+        };
+        let mut body = BlockBody::empty();
+        assert!(body_matches_header(&body, &header));
+        body.withdrawals = None;
+        assert!(!body_matches_header(&body, &header));
+    }
+
+    #[tokio::test]
+    async fn sync_from_peers() {
+        let remote_store = test_store();
+        // Long enough to be downloaded in more than one segment
+        let target = extend_chain(&remote_store, HEADER_BATCH_SIZE + 8, Address::zero());
+        // The local chain shares its first blocks with the peers' one, and then forks off it
+        let local_store = test_store();
+        extend_chain(&local_store, 5, Address::zero());
+        extend_chain(&local_store, 3, Address::repeat_byte(1));
+
+        let local_node = Node {
+            ip: "127.0.0.1".parse().unwrap(),
+            udp_port: 30303,
+            tcp_port: 30303,
+            node_id: H512::zero(),
+        };
+        let peer_manager = PeerManager::new(local_node, Default::default());
+        add_serving_peer(&peer_manager, 1, remote_store.clone());
+        add_serving_peer(&peer_manager, 2, remote_store);
+        let syncer = SyncManager::new(peer_manager, local_store.clone());
+
+        syncer.sync(target).await.unwrap();
+        assert_eq!(
+            local_store.get_latest_block_number().unwrap(),
+            Some(HEADER_BATCH_SIZE + 8)
+        );
+        let head = local_store
+            .get_block_header(HEADER_BATCH_SIZE + 8)
+            .unwrap()
+            .unwrap();
+        assert_eq!(head.compute_block_hash(), target);
+    }
+}
@@ -1,5 +1,5 @@
-use ethereum_rust_core::types::ChainConfig;
-use ethereum_rust_net::types::Node;
+use ethereum_rust_core::{types::ChainConfig, H256};
+use ethereum_rust_net::{peer_manager::PeerManager, types::Node};
 use ethereum_rust_storage::Store;
 use serde::Serialize;
 use serde_json::Value;
@@ -29,6 +29,30 @@ enum Protocol {
     Eth(ChainConfig),
 }
 
+#[derive(Serialize, Debug)]
+struct PeerData {
+    enode: String,
+    id: String,
+    name: String,
+    caps: Vec<String>,
+    network: PeerNetwork,
+    protocols: HashMap<String, PeerProtocol>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PeerNetwork {
+    remote_address: String,
+    inbound: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct PeerProtocol {
+    version: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    head: Option<H256>,
+}
+
 pub fn node_info(storage: Store, local_node: Node) -> Result<Value, RpcErr> {
     let enode_url = local_node.enode_url();
     let mut protocols = HashMap::new();
@@ -49,3 +73,43 @@ pub fn node_info(storage: Store, local_node: Node) -> Result<Value, RpcErr> {
     };
     serde_json::to_value(node_info).map_err(|_| RpcErr::Internal)
 }
+
+pub fn peers(peer_manager: &PeerManager) -> Result<Value, RpcErr> {
+    let peers: Vec<PeerData> = peer_manager
+        .peers()
+        .into_iter()
+        .map(|peer| {
+            let id = hex::encode(peer.node_id);
+            let mut protocols = HashMap::new();
+            let eth_version = peer
+                .capabilities
+                .iter()
+                .filter(|(name, _)| name == "eth")
+                .map(|(_, version)| *version)
+                .max();
+            if let Some(version) = eth_version {
+                let protocol = PeerProtocol {
+                    version,
+                    head: peer.head,
+                };
+                protocols.insert("eth".to_string(), protocol);
+            }
+            PeerData {
+                enode: format!("enode://{id}@{}", peer.remote_addr),
+                id,
+                name: peer.client_id,
+                caps: peer
+                    .capabilities
+                    .iter()
+                    .map(|(name, version)| format!("{name}/{version}"))
+                    .collect(),
+                network: PeerNetwork {
+                    remote_address: peer.remote_addr.to_string(),
+                    inbound: peer.inbound,
+                },
+                protocols,
+            }
+        })
+        .collect();
+    serde_json::to_value(peers).map_err(|_| RpcErr::Internal)
+}
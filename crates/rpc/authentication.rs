@@ -1,4 +1,3 @@
-use crate::RpcErr;
 use axum_extra::{
     headers::{authorization::Bearer, Authorization},
     TypedHeader,
@@ -8,7 +7,7 @@ use jsonwebtoken::{decode, Algorithm, DecodingKey, TokenData, Validation};
 use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum AuthenticationError {
     InvalidIssuedAtClaim,
     TokenDecodingError,
@@ -18,15 +17,13 @@ pub enum AuthenticationError {
 pub fn authenticate(
     secret: Bytes,
     auth_header: Option<TypedHeader<Authorization<Bearer>>>,
-) -> Result<(), RpcErr> {
+) -> Result<(), AuthenticationError> {
     match auth_header {
         Some(TypedHeader(auth_header)) => {
             let token = auth_header.token();
-            validate_jwt_authentication(token, secret).map_err(RpcErr::AuthenticationError)
+            validate_jwt_authentication(token, secret)
         }
-        None => Err(RpcErr::AuthenticationError(
-            AuthenticationError::MissingAuthentication,
-        )),
+        None => Err(AuthenticationError::MissingAuthentication),
     }
 }
 
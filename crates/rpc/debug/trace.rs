@@ -0,0 +1,564 @@
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
+use ethereum_rust_chain::find_parent_header;
+use ethereum_rust_core::{
+    serde_utils,
+    types::{Block, BlockHash, BlockHeader, GenericTransaction},
+    Address, H256, U256,
+};
+use ethereum_rust_evm::{
+    selected_backend, CallFrame, CallLog, CallTracerConfig, ExecutionResult, PrestateAccount,
+    PrestateTrace, PrestateTracerConfig, StructLog, StructLoggerConfig, Trace, Tracer,
+};
+use ethereum_rust_storage::Store;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::info;
+
+use crate::{
+    eth::transaction::state_root_for_block, types::block_identifier::BlockIdentifier,
+    utils::RpcErr, RpcHandler,
+};
+
+/// Options of the tracing endpoints.
+/// The struct logger is used unless one of the built-in tracers is selected
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TraceConfig {
+    tracer: Option<String>,
+    tracer_config: TracerConfig,
+    disable_stack: bool,
+    disable_memory: bool,
+    disable_storage: bool,
+}
+
+/// Options of the built-in tracers
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct TracerConfig {
+    only_top_call: bool,
+    with_log: bool,
+    diff_mode: bool,
+}
+
+pub struct TraceTransactionRequest {
+    pub transaction_hash: H256,
+    pub config: TraceConfig,
+}
+
+pub struct TraceCallRequest {
+    pub transaction: GenericTransaction,
+    pub block: BlockIdentifier,
+    pub config: TraceConfig,
+}
+
+pub struct TraceBlockByNumberRequest {
+    pub block: BlockIdentifier,
+    pub config: TraceConfig,
+}
+
+pub struct TraceBlockByHashRequest {
+    pub block_hash: BlockHash,
+    pub config: TraceConfig,
+}
+
+/// Result of tracing a transaction with the struct logger
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StructLogTrace {
+    gas: u64,
+    failed: bool,
+    return_value: String,
+    struct_logs: Vec<RpcStructLog>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcStructLog {
+    pc: u64,
+    op: String,
+    gas: u64,
+    gas_cost: u64,
+    depth: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stack: Option<Vec<U256>>,
+    /// Memory split in 32 byte words
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    storage: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "is_zero")]
+    refund: u64,
+}
+
+/// Result of tracing a transaction with the callTracer
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcCallFrame {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    from: Address,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<Address>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<U256>,
+    #[serde(with = "serde_utils::u64::hex_str")]
+    gas: u64,
+    #[serde(with = "serde_utils::u64::hex_str")]
+    gas_used: u64,
+    #[serde(with = "serde_utils::bytes")]
+    input: Bytes,
+    #[serde(with = "serde_utils::bytes", skip_serializing_if = "Bytes::is_empty")]
+    output: Bytes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revert_reason: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    logs: Vec<RpcCallLog>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    calls: Vec<RpcCallFrame>,
+}
+
+#[derive(Serialize)]
+struct RpcCallLog {
+    address: Address,
+    topics: Vec<H256>,
+    #[serde(with = "serde_utils::bytes")]
+    data: Bytes,
+    #[serde(with = "serde_utils::u64::hex_str")]
+    position: u64,
+}
+
+/// State of an account as returned by the prestateTracer
+#[derive(Serialize)]
+struct RpcPrestateAccount {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    balance: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    storage: BTreeMap<H256, H256>,
+}
+
+#[derive(Serialize)]
+struct RpcPrestateDiff {
+    pre: BTreeMap<Address, RpcPrestateAccount>,
+    post: BTreeMap<Address, RpcPrestateAccount>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TransactionTrace {
+    tx_hash: H256,
+    result: Value,
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+impl TraceConfig {
+    /// Parses the optional config found at the given position of the params
+    fn parse(params: &[Value], index: usize) -> Result<Self, RpcErr> {
+        match params.get(index) {
+            None | Some(Value::Null) => Ok(TraceConfig::default()),
+            Some(value) => Ok(serde_json::from_value(value.clone())?),
+        }
+    }
+
+    fn tracer(&self) -> Result<Tracer, RpcErr> {
+        match self.tracer.as_deref() {
+            None | Some("") => Ok(Tracer::StructLogger(StructLoggerConfig {
+                disable_stack: self.disable_stack,
+                disable_memory: self.disable_memory,
+                disable_storage: self.disable_storage,
+            })),
+            Some("callTracer") => Ok(Tracer::CallTracer(CallTracerConfig {
+                only_top_call: self.tracer_config.only_top_call,
+                with_log: self.tracer_config.with_log,
+            })),
+            Some("prestateTracer") => Ok(Tracer::PrestateTracer(PrestateTracerConfig {
+                diff_mode: self.tracer_config.diff_mode,
+            })),
+            Some(_) => Err(RpcErr::BadParams),
+        }
+    }
+}
+
+/// Formats the output of a tracer the way geth does
+fn trace_value(result: ExecutionResult, trace: Trace) -> Result<Value, RpcErr> {
+    let value = match trace {
+        Trace::StructLogs(logs) => serde_json::to_value(StructLogTrace::new(result, logs)),
+        Trace::Call(frame) => serde_json::to_value(RpcCallFrame::from(frame)),
+        Trace::Prestate(PrestateTrace::Prestate(accounts)) => {
+            serde_json::to_value(prestate_accounts(accounts))
+        }
+        Trace::Prestate(PrestateTrace::Diff { pre, post }) => {
+            serde_json::to_value(RpcPrestateDiff {
+                pre: prestate_accounts(pre),
+                post: prestate_accounts(post),
+            })
+        }
+    };
+    value.map_err(|_| RpcErr::Internal)
+}
+
+fn prestate_accounts(
+    accounts: BTreeMap<Address, PrestateAccount>,
+) -> BTreeMap<Address, RpcPrestateAccount> {
+    accounts
+        .into_iter()
+        .map(|(address, account)| (address, RpcPrestateAccount::from(account)))
+        .collect()
+}
+
+impl StructLogTrace {
+    fn new(result: ExecutionResult, logs: Vec<StructLog>) -> Self {
+        StructLogTrace {
+            gas: result.gas_used(),
+            failed: !result.is_success(),
+            return_value: hex::encode(result.output()),
+            struct_logs: logs.into_iter().map(RpcStructLog::from).collect(),
+        }
+    }
+}
+
+impl From<StructLog> for RpcStructLog {
+    fn from(log: StructLog) -> Self {
+        RpcStructLog {
+            pc: log.pc,
+            op: log.op,
+            gas: log.gas,
+            gas_cost: log.gas_cost,
+            depth: log.depth,
+            error: log.error,
+            stack: log.stack,
+            memory: log
+                .memory
+                .map(|memory| memory.chunks(32).map(hex::encode).collect()),
+            storage: log.storage.map(|storage| {
+                storage
+                    .into_iter()
+                    .map(|(key, value)| (hex::encode(key), hex::encode(value)))
+                    .collect()
+            }),
+            refund: log.refund,
+        }
+    }
+}
+
+impl From<CallFrame> for RpcCallFrame {
+    fn from(frame: CallFrame) -> Self {
+        RpcCallFrame {
+            kind: frame.kind.as_str(),
+            from: frame.from,
+            to: frame.to,
+            value: frame.value,
+            gas: frame.gas,
+            gas_used: frame.gas_used,
+            input: frame.input,
+            output: frame.output,
+            error: frame.error,
+            revert_reason: frame.revert_reason,
+            logs: frame.logs.into_iter().map(RpcCallLog::from).collect(),
+            calls: frame.calls.into_iter().map(RpcCallFrame::from).collect(),
+        }
+    }
+}
+
+impl From<CallLog> for RpcCallLog {
+    fn from(log: CallLog) -> Self {
+        RpcCallLog {
+            address: log.address,
+            topics: log.topics,
+            data: log.data,
+            position: log.position,
+        }
+    }
+}
+
+impl From<PrestateAccount> for RpcPrestateAccount {
+    fn from(account: PrestateAccount) -> Self {
+        RpcPrestateAccount {
+            balance: account.balance,
+            nonce: account.nonce,
+            code: account.code.map(|code| format!("0x{}", hex::encode(code))),
+            storage: account.storage,
+        }
+    }
+}
+
+impl RpcHandler for TraceTransactionRequest {
+    fn parse(params: &Option<Vec<Value>>) -> Result<Self, RpcErr> {
+        let params = params.as_ref().ok_or(RpcErr::BadParams)?;
+        if params.is_empty() || params.len() > 2 {
+            return Err(RpcErr::BadParams);
+        };
+        Ok(TraceTransactionRequest {
+            transaction_hash: serde_json::from_value(params[0].clone())?,
+            config: TraceConfig::parse(params, 1)?,
+        })
+    }
+
+    fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        info!(
+            "Requested trace of transaction {:#x}",
+            self.transaction_hash
+        );
+        let tracer = self.config.tracer()?;
+        let Some((block_number, index)) =
+            storage.get_transaction_location(self.transaction_hash)?
+        else {
+            return Ok(Value::Null);
+        };
+        let block = get_block(block_number, &storage)?;
+        // Re-execute the transactions preceding it to reach its pre-state
+        let state_root = parent_state_root(&block.header, &storage)?;
+        let (result, trace) =
+            selected_backend().trace_tx(&block, index as usize, storage, state_root, tracer)?;
+        trace_value(result, trace)
+    }
+}
+
+impl RpcHandler for TraceCallRequest {
+    fn parse(params: &Option<Vec<Value>>) -> Result<Self, RpcErr> {
+        let params = params.as_ref().ok_or(RpcErr::BadParams)?;
+        if params.is_empty() || params.len() > 3 {
+            return Err(RpcErr::BadParams);
+        };
+        let block = match params.get(1) {
+            Some(value) => BlockIdentifier::parse(value.clone(), 1)?,
+            None => BlockIdentifier::default(),
+        };
+        Ok(TraceCallRequest {
+            transaction: serde_json::from_value(params[0].clone())?,
+            block,
+            config: TraceConfig::parse(params, 2)?,
+        })
+    }
+
+    fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        info!("Requested trace of call on block: {}", self.block);
+        let tracer = self.config.tracer()?;
+        let Some(header) = self.block.resolve_block_header(&storage.snapshot())? else {
+            return Ok(Value::Null);
+        };
+        let spec_id = ethereum_rust_evm::spec_id(&storage, header.timestamp)?;
+        let state_root = state_root_for_block(&header, &storage)?;
+        let (result, trace) = selected_backend().trace_call(
+            &self.transaction,
+            &header,
+            storage,
+            state_root,
+            spec_id,
+            tracer,
+        )?;
+        trace_value(result, trace)
+    }
+}
+
+impl RpcHandler for TraceBlockByNumberRequest {
+    fn parse(params: &Option<Vec<Value>>) -> Result<Self, RpcErr> {
+        let params = params.as_ref().ok_or(RpcErr::BadParams)?;
+        if params.is_empty() || params.len() > 2 {
+            return Err(RpcErr::BadParams);
+        };
+        Ok(TraceBlockByNumberRequest {
+            block: BlockIdentifier::parse(params[0].clone(), 0)?,
+            config: TraceConfig::parse(params, 1)?,
+        })
+    }
+
+    fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        info!("Requested trace of block: {}", self.block);
+        let Some(block_number) = self.block.resolve_block_number(&storage.snapshot())? else {
+            return Ok(Value::Null);
+        };
+        trace_block(&get_block(block_number, &storage)?, &storage, &self.config)
+    }
+}
+
+impl RpcHandler for TraceBlockByHashRequest {
+    fn parse(params: &Option<Vec<Value>>) -> Result<Self, RpcErr> {
+        let params = params.as_ref().ok_or(RpcErr::BadParams)?;
+        if params.is_empty() || params.len() > 2 {
+            return Err(RpcErr::BadParams);
+        };
+        Ok(TraceBlockByHashRequest {
+            block_hash: serde_json::from_value(params[0].clone())?,
+            config: TraceConfig::parse(params, 1)?,
+        })
+    }
+
+    fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        info!("Requested trace of block with hash: {:#x}", self.block_hash);
+        let Some(block_number) = storage.get_block_number(self.block_hash)? else {
+            return Ok(Value::Null);
+        };
+        trace_block(&get_block(block_number, &storage)?, &storage, &self.config)
+    }
+}
+
+/// Traces each of the block's transactions on top of the state left by the previous ones
+fn trace_block(block: &Block, storage: &Store, config: &TraceConfig) -> Result<Value, RpcErr> {
+    let tracer = config.tracer()?;
+    let state_root = parent_state_root(&block.header, storage)?;
+    let results = selected_backend().trace_block(block, storage.clone(), state_root, tracer)?;
+    let mut traces = Vec::with_capacity(block.body.transactions.len());
+    for (transaction, (result, trace)) in block.body.transactions.iter().zip(results) {
+        traces.push(TransactionTrace {
+            tx_hash: transaction.compute_hash(),
+            result: trace_value(result, trace)?,
+        });
+    }
+    serde_json::to_value(traces).map_err(|_| RpcErr::Internal)
+}
+
+fn get_block(block_number: u64, storage: &Store) -> Result<Block, RpcErr> {
+    let header = storage
+        .get_block_header(block_number)?
+        .ok_or(RpcErr::Internal)?;
+    let body = storage
+        .get_block_body(block_number)?
+        .ok_or(RpcErr::Internal)?;
+    Ok(Block { header, body })
+}
+
+/// Returns the root of the state the transactions of the given block are executed on top of,
+/// or None if it is the current state
+fn parent_state_root(block_header: &BlockHeader, storage: &Store) -> Result<Option<H256>, RpcErr> {
+    let parent_header = find_parent_header(block_header, storage).map_err(|_| RpcErr::Internal)?;
+    state_root_for_block(&parent_header, storage)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_rust_evm::CallKind;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parse_trace_config() {
+        let params = vec![
+            json!(H256::zero()),
+            json!({ "disableStack": true, "disableStorage": true }),
+        ];
+        let Tracer::StructLogger(config) =
+            TraceConfig::parse(&params, 1).unwrap().tracer().unwrap()
+        else {
+            panic!("Expected the struct logger");
+        };
+        assert!(config.disable_stack && config.disable_storage && !config.disable_memory);
+        assert!(TraceConfig::parse(&params, 2).unwrap().tracer.is_none());
+
+        let params = vec![json!({ "tracer": "callTracer", "tracerConfig": { "withLog": true } })];
+        let Tracer::CallTracer(config) = TraceConfig::parse(&params, 0).unwrap().tracer().unwrap()
+        else {
+            panic!("Expected the call tracer");
+        };
+        assert!(config.with_log && !config.only_top_call);
+
+        let params =
+            vec![json!({ "tracer": "prestateTracer", "tracerConfig": { "diffMode": true } })];
+        assert!(matches!(
+            TraceConfig::parse(&params, 0).unwrap().tracer().unwrap(),
+            Tracer::PrestateTracer(PrestateTracerConfig { diff_mode: true })
+        ));
+
+        let params = vec![json!({ "tracer": "someTracer" })];
+        assert!(TraceConfig::parse(&params, 0).unwrap().tracer().is_err());
+    }
+
+    #[test]
+    fn call_frames_are_serialized_like_geth() {
+        let caller = Address::from_low_u64_be(1);
+        let callee = Address::from_low_u64_be(2);
+        let frame = CallFrame {
+            kind: CallKind::Call,
+            from: caller,
+            to: Some(callee),
+            value: Some(U256::zero()),
+            gas: 50_000,
+            gas_used: 30_000,
+            input: Bytes::from_static(&[0xca, 0xfe]),
+            output: Bytes::new(),
+            error: None,
+            revert_reason: None,
+            logs: vec![],
+            calls: vec![CallFrame {
+                kind: CallKind::StaticCall,
+                from: callee,
+                to: Some(caller),
+                value: None,
+                gas: 1000,
+                gas_used: 1000,
+                input: Bytes::new(),
+                output: Bytes::new(),
+                error: Some("out of gas".to_string()),
+                revert_reason: None,
+                logs: vec![],
+                calls: vec![],
+            }],
+        };
+        assert_eq!(
+            serde_json::to_value(RpcCallFrame::from(frame)).unwrap(),
+            json!({
+                "type": "CALL",
+                "from": caller,
+                "to": callee,
+                "value": "0x0",
+                "gas": "0xc350",
+                "gasUsed": "0x7530",
+                "input": "0xcafe",
+                "calls": [{
+                    "type": "STATICCALL",
+                    "from": callee,
+                    "to": caller,
+                    "gas": "0x3e8",
+                    "gasUsed": "0x3e8",
+                    "input": "0x",
+                    "error": "out of gas",
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn struct_logs_are_serialized_like_geth() {
+        let mut storage = BTreeMap::new();
+        storage.insert(H256::zero(), H256::from_low_u64_be(1));
+        let slot = hex::encode(H256::zero());
+        let log = RpcStructLog::from(StructLog {
+            pc: 4,
+            op: "SSTORE".to_string(),
+            gas: 100,
+            gas_cost: 20,
+            depth: 1,
+            refund: 0,
+            stack: Some(vec![U256::from(1), U256::zero()]),
+            memory: Some([[0; 32], [0xff; 32]].concat().into()),
+            storage: Some(storage),
+            error: None,
+        });
+        assert_eq!(
+            serde_json::to_value(log).unwrap(),
+            json!({
+                "pc": 4,
+                "op": "SSTORE",
+                "gas": 100,
+                "gasCost": 20,
+                "depth": 1,
+                "stack": ["0x1", "0x0"],
+                "memory": [hex::encode([0; 32]), hex::encode([0xff; 32])],
+                "storage": { slot: hex::encode(H256::from_low_u64_be(1)) },
+            })
+        );
+    }
+}
@@ -1,15 +1,26 @@
+use ethereum_rust_chain::{
+    error::ChainError,
+    mempool::transactions_for_payload,
+    payload::{build_payload, create_payload, BuildPayloadArgs},
+    Blockchain,
+};
+use ethereum_rust_core::{types::BlockHeader, H256, U256};
+use ethereum_rust_net::sync::SyncManager;
 use ethereum_rust_storage::Store;
-use serde_json::{json, Value};
+use serde_json::Value;
+use tracing::{info, warn};
 
 use crate::{
-    types::fork_choice::{ForkChoiceState, PayloadAttributesV3},
+    types::{
+        fork_choice::{ForkChoiceResponse, ForkChoiceState, PayloadAttributesV3},
+        payload::PayloadStatus,
+    },
     RpcErr,
 };
 
 #[derive(Debug)]
 pub struct ForkChoiceUpdatedV3 {
     pub fork_choice_state: ForkChoiceState,
-    #[allow(unused)]
     pub payload_attributes: Option<PayloadAttributesV3>,
 }
 
@@ -29,27 +40,303 @@ impl ForkChoiceUpdatedV3 {
 pub fn forkchoice_updated_v3(
     request: ForkChoiceUpdatedV3,
     storage: Store,
+    syncer: &SyncManager,
 ) -> Result<Value, RpcErr> {
-    // Just a minimal implementation to pass rpc-compat Hive tests.
-    // TODO (#50): Implement `engine_forkchoiceUpdatedV3`
-    let safe = storage.get_block_number(request.fork_choice_state.safe_block_hash);
-    let finalized = storage.get_block_number(request.fork_choice_state.finalized_block_hash);
-
-    // Check if we already have the blocks stored.
-    let (safe_block_number, finalized_block_number) = match (safe, finalized) {
-        (Ok(Some(safe)), Ok(Some(finalized))) => (safe, finalized),
-        _ => return Err(RpcErr::Internal),
+    let response = forkchoice_response(request, storage, syncer)?;
+    serde_json::to_value(response).map_err(|_| RpcErr::Internal)
+}
+
+fn forkchoice_response(
+    request: ForkChoiceUpdatedV3,
+    storage: Store,
+    syncer: &SyncManager,
+) -> Result<ForkChoiceResponse, RpcErr> {
+    let ForkChoiceState {
+        head_block_hash,
+        safe_block_hash,
+        finalized_block_hash,
+    } = request.fork_choice_state;
+    info!("Received forkchoice update with head: {head_block_hash:#x}, safe: {safe_block_hash:#x}, finalized: {finalized_block_hash:#x}");
+
+    if head_block_hash.is_zero() {
+        return Err(RpcErr::InvalidForkChoiceState(
+            "Head block hash cannot be zero".to_string(),
+        ));
+    }
+    // Reject heads built on top of a block we already know is invalid
+    if let Some(latest_valid_hash) = storage.get_latest_valid_ancestor(head_block_hash)? {
+        return Ok(ForkChoiceResponse::from_status(
+            PayloadStatus::invalid_with_hash(latest_valid_hash),
+        ));
+    }
+
+    let blockchain = Blockchain::new(storage.clone());
+    // The checks below stay valid until the head is updated, as the syncer can't change the chain in between
+    let blockchain = blockchain.write_lock();
+    // We can't update the head until we have it (and its whole branch), so we download it from our peers
+    let Some(head) = blockchain
+        .get_block_header_by_hash(head_block_hash)
+        .map_err(|_| RpcErr::Internal)?
+    else {
+        syncer.start_sync(head_block_hash)?;
+        return Ok(ForkChoiceResponse::from_status(PayloadStatus::syncing()));
     };
 
-    storage.update_finalized_block_number(finalized_block_number)?;
-    storage.update_safe_block_number(safe_block_number)?;
-    serde_json::to_value(json!({
-        "payloadId": null,
-        "payloadStatus": {
-            "latestValidHash": null,
-            "status": "SYNCING",
-            "validationError": null
+    // The consensus client is probably resyncing, the chain is not rewound to an ancestor of the head
+    let latest_block_number = storage.get_latest_block_number()?.unwrap_or_default();
+    if head.number < latest_block_number
+        && blockchain
+            .is_canonical(head_block_hash)
+            .map_err(|_| RpcErr::Internal)?
+    {
+        info!("Ignoring forkchoice update to canonical ancestor {head_block_hash:#x}");
+        return Ok(ForkChoiceResponse::from_status(
+            PayloadStatus::valid_with_hash(head_block_hash),
+        ));
+    }
+
+    // Finalized and safe blocks must be part of the head's chain
+    let mut finalized_block_number = None;
+    let mut safe_block_number = None;
+    for (name, block_hash, block_number) in [
+        (
+            "finalized",
+            finalized_block_hash,
+            &mut finalized_block_number,
+        ),
+        ("safe", safe_block_hash, &mut safe_block_number),
+    ] {
+        if block_hash.is_zero() {
+            continue;
+        }
+        // All ancestors of a known head are known, so an unknown block can't be part of its chain
+        let header = blockchain
+            .get_block_header_by_hash(block_hash)
+            .map_err(|_| RpcErr::Internal)?;
+        let is_ancestor = match &header {
+            Some(header) => blockchain
+                .is_ancestor(header, &head)
+                .map_err(|_| RpcErr::Internal)?,
+            None => false,
+        };
+        match header {
+            Some(header) if is_ancestor => *block_number = Some(header.number),
+            _ => {
+                return Err(RpcErr::InvalidForkChoiceState(format!(
+                    "The {name} block is not part of the head block's chain"
+                )))
+            }
         }
-    }))
-    .map_err(|_| RpcErr::Internal)
+    }
+
+    // Update the canonical chain
+    blockchain
+        .set_head(head_block_hash)
+        .map_err(|error| match error {
+            ChainError::FinalizedBlockUnwind(_) => {
+                RpcErr::InvalidForkChoiceState(error.to_string())
+            }
+            error => {
+                warn!("Failed to set head {head_block_hash:#x}: {error}");
+                RpcErr::Internal
+            }
+        })?;
+    if let Some(block_number) = finalized_block_number {
+        storage.update_finalized_block_number(block_number)?;
+    }
+    if let Some(block_number) = safe_block_number {
+        storage.update_safe_block_number(block_number)?;
+    }
+    info!("Head updated to block {head_block_hash:#x}");
+
+    let mut response =
+        ForkChoiceResponse::from_status(PayloadStatus::valid_with_hash(head_block_hash));
+
+    // Start building a payload on top of the new head if requested
+    if let Some(attributes) = request.payload_attributes {
+        response.payload_id = Some(start_payload_job(
+            &attributes,
+            &head,
+            head_block_hash,
+            &storage,
+        )?);
+    }
+    Ok(response)
+}
+
+/// Validates the payload attributes and creates a new payload on top of the head block, which is then filled with
+/// the mempool's transactions in the background. Until that is done, the payload is stored empty.
+/// Returns the id under which the payload was stored
+fn start_payload_job(
+    attributes: &PayloadAttributesV3,
+    head: &BlockHeader,
+    head_block_hash: H256,
+    storage: &Store,
+) -> Result<u64, RpcErr> {
+    if attributes.timestamp <= head.timestamp {
+        return Err(RpcErr::InvalidPayloadAttributes(
+            "Timestamp must be greater than the head block's timestamp".to_string(),
+        ));
+    }
+    let Some(withdrawals) = attributes.withdrawals.clone() else {
+        return Err(RpcErr::InvalidPayloadAttributes(
+            "Missing withdrawals".to_string(),
+        ));
+    };
+    let Some(beacon_root) = attributes.parent_beacon_block_root else {
+        return Err(RpcErr::InvalidPayloadAttributes(
+            "Missing parent beacon block root".to_string(),
+        ));
+    };
+    let chain_config = storage.get_chain_config()?;
+    if !chain_config.is_cancun_activated(attributes.timestamp) {
+        return Err(RpcErr::UnsuportedFork);
+    }
+
+    let args = BuildPayloadArgs {
+        parent: head_block_hash,
+        timestamp: attributes.timestamp,
+        fee_recipient: attributes.suggested_fee_recipient,
+        random: attributes.prev_randao,
+        withdrawals,
+        beacon_root: Some(beacon_root),
+        version: 3,
+    };
+    let payload_id = args.id();
+    let mut payload = create_payload(&args, storage).map_err(|error| {
+        warn!("Failed to create payload: {error}");
+        RpcErr::Internal
+    })?;
+    storage.add_payload(payload_id, payload.clone(), U256::zero())?;
+    let storage = storage.clone();
+    tokio::task::spawn_blocking(move || {
+        let transactions = transactions_for_payload(&storage, payload.header.base_fee_per_gas);
+        let result = build_payload(&mut payload, &storage, transactions)
+            .map_err(|error| error.to_string())
+            .and_then(|block_value| {
+                storage
+                    .add_payload(payload_id, payload, block_value)
+                    .map_err(|error| error.to_string())
+            });
+        if let Err(error) = result {
+            warn!("Failed to build payload with id {payload_id:#018x}: {error}");
+        }
+    });
+    info!("Started building payload with id {payload_id:#018x}");
+    Ok(payload_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_rust_core::{
+        types::{Block, Genesis},
+        Address, H512,
+    };
+    use ethereum_rust_net::{
+        peer_manager::{PeerLimits, PeerManager},
+        types::Node,
+    };
+    use ethereum_rust_storage::EngineType;
+
+    use super::*;
+
+    fn test_store() -> Store {
+        const GENESIS_KURTOSIS: &str = include_str!("../../../test_data/genesis-kurtosis.json");
+        let genesis: Genesis =
+            serde_json::from_str(GENESIS_KURTOSIS).expect("deserialize genesis-kurtosis.json");
+        let mut store = Store::new("", EngineType::InMemory).unwrap();
+        store.add_initial_state(genesis).unwrap();
+        store
+    }
+
+    fn test_syncer(store: &Store) -> SyncManager {
+        let local_node = Node {
+            ip: "127.0.0.1".parse().unwrap(),
+            udp_port: 30303,
+            tcp_port: 30303,
+            node_id: H512::random(),
+        };
+        SyncManager::new(
+            PeerManager::new(local_node, PeerLimits::default()),
+            store.clone(),
+        )
+    }
+
+    /// Builds an empty block on top of the given parent and adds it to the block tree
+    fn add_block(store: &Store, parent: &BlockHeader, fee_recipient: Address) -> Block {
+        let args = BuildPayloadArgs {
+            parent: parent.compute_block_hash(),
+            timestamp: parent.timestamp + 12,
+            fee_recipient,
+            random: H256::zero(),
+            withdrawals: Vec::new(),
+            beacon_root: Some(H256::zero()),
+            version: 3,
+        };
+        let mut block = create_payload(&args, store).unwrap();
+        build_payload(&mut block, store, Vec::new()).unwrap();
+        Blockchain::new(store.clone()).add_block(&block).unwrap();
+        block
+    }
+
+    fn request(head_block_hash: H256) -> ForkChoiceUpdatedV3 {
+        ForkChoiceUpdatedV3 {
+            fork_choice_state: ForkChoiceState {
+                head_block_hash,
+                safe_block_hash: H256::zero(),
+                finalized_block_hash: H256::zero(),
+            },
+            payload_attributes: None,
+        }
+    }
+
+    #[test]
+    fn canonical_ancestor_head_is_not_rewound() {
+        let store = test_store();
+        let genesis = store.get_block_header(0).unwrap().unwrap();
+        let block_1 = add_block(&store, &genesis, Address::zero());
+        add_block(&store, &block_1.header, Address::zero());
+
+        let block_hash = block_1.header.compute_block_hash();
+        let response =
+            forkchoice_response(request(block_hash), store.clone(), &test_syncer(&store)).unwrap();
+        let response = serde_json::to_value(response).unwrap();
+        assert_eq!(response["payloadStatus"]["status"], "VALID");
+        assert_eq!(
+            response["payloadStatus"]["latestValidHash"],
+            serde_json::to_value(block_hash).unwrap()
+        );
+        assert_eq!(store.get_latest_block_number().unwrap(), Some(2));
+    }
+
+    #[test]
+    fn head_unwinding_finalized_block_is_rejected() {
+        let store = test_store();
+        let genesis = store.get_block_header(0).unwrap().unwrap();
+        let canonical = add_block(&store, &genesis, Address::zero());
+        let side = add_block(&store, &genesis, Address::random());
+        store.update_finalized_block_number(1).unwrap();
+
+        let result = forkchoice_response(
+            request(side.header.compute_block_hash()),
+            store.clone(),
+            &test_syncer(&store),
+        );
+        assert!(matches!(result, Err(RpcErr::InvalidForkChoiceState(_))));
+        assert_eq!(store.get_block_header(1).unwrap(), Some(canonical.header));
+    }
+
+    #[tokio::test]
+    async fn unknown_head_starts_a_sync() {
+        let store = test_store();
+        let syncer = test_syncer(&store);
+
+        let response =
+            forkchoice_response(request(H256::random()), store.clone(), &syncer).unwrap();
+        let response = serde_json::to_value(response).unwrap();
+        assert_eq!(response["payloadStatus"]["status"], "SYNCING");
+        // The sync keeps running in the background, downloading the head from our peers
+        assert!(syncer.status().is_some());
+        assert_eq!(store.get_latest_block_number().unwrap(), Some(0));
+    }
 }
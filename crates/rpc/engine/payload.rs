@@ -1,14 +1,15 @@
+use bytes::Bytes;
 use ethereum_rust_chain::error::ChainError;
-use ethereum_rust_chain::{add_block, latest_valid_hash};
-use ethereum_rust_core::types::ForkId;
-use ethereum_rust_core::H256;
+use ethereum_rust_chain::Blockchain;
+use ethereum_rust_core::types::{ForkId, Transaction};
+use ethereum_rust_core::{serde_utils, H256};
 use ethereum_rust_storage::Store;
 use serde_json::Value;
 use tracing::{info, warn};
 
 use crate::{
-    types::payload::{ExecutionPayloadV3, PayloadStatus},
-    RpcErr,
+    types::payload::{BlobsBundleV1, ExecutionPayloadResponse, ExecutionPayloadV3, PayloadStatus},
+    RpcErr, RpcHandler,
 };
 
 pub struct NewPayloadV3Request {
@@ -70,35 +71,49 @@ pub fn new_payload_v3(
             "Invalid blob_versioned_hashes",
         ));
     }
-    // Check that the incoming block extends the current chain
-    let last_block_number = storage.get_latest_block_number()?.ok_or(RpcErr::Internal)?;
-    if block.header.number <= last_block_number {
-        // Check if we already have this block stored
-        if storage
-            .get_block_number(block_hash)
-            .map_err(|_| RpcErr::Internal)?
-            .is_some_and(|num| num == block.header.number)
-        {
-            return Ok(PayloadStatus::valid_with_hash(block_hash));
-        }
-        warn!("Should start reorg but it is not supported yet");
-        return Err(RpcErr::Internal);
-    } else if block.header.number != last_block_number + 1 {
+    // Check if we already have this block stored
+    let blockchain = Blockchain::new(storage.clone());
+    // The checks below stay valid until the block is added, as the syncer can't change the chain in between
+    let blockchain = blockchain.write_lock();
+    if blockchain
+        .get_block_header_by_hash(block_hash)
+        .map_err(|_| RpcErr::Internal)?
+        .is_some()
+    {
+        return Ok(PayloadStatus::valid_with_hash(block_hash));
+    }
+    // Descendants of invalid blocks are also invalid
+    if let Some(latest_valid_hash) = storage.get_latest_valid_ancestor(block.header.parent_hash)? {
+        storage.add_invalid_block(block_hash, latest_valid_hash)?;
+        return Ok(PayloadStatus::invalid_with_hash(latest_valid_hash));
+    }
+    // We can't execute the block until we have its parent
+    if blockchain
+        .get_block_header_by_hash(block.header.parent_hash)
+        .map_err(|_| RpcErr::Internal)?
+        .is_none()
+    {
         return Ok(PayloadStatus::syncing());
     }
 
-    let latest_valid_hash = latest_valid_hash(&storage).map_err(|_| RpcErr::Internal)?;
+    let latest_valid_hash = block.header.parent_hash;
 
     // Execute and store the block
     info!("Executing payload with block hash: {block_hash}");
-    match add_block(&block, &storage) {
-        Err(ChainError::NonCanonicalBlock) => Ok(PayloadStatus::syncing()),
-        Err(ChainError::ParentNotFound) => Ok(PayloadStatus::invalid_with_err(
-            "Could not reference parent block with parent_hash",
-        )),
+    match blockchain.add_block(&block) {
+        Err(ChainError::ParentNotFound) => Ok(PayloadStatus::syncing()),
         Err(ChainError::InvalidBlock(_)) => Ok(PayloadStatus::invalid_with_hash(latest_valid_hash)),
-        Err(ChainError::EvmError(error)) => Ok(PayloadStatus::invalid_with_err(&error.to_string())),
-        Err(ChainError::StoreError(_)) => Err(RpcErr::Internal),
+        Err(ChainError::InvalidAncestor(latest_valid_hash)) => {
+            Ok(PayloadStatus::invalid_with_hash(latest_valid_hash))
+        }
+        Err(error) if error.is_invalid_block() => {
+            Ok(PayloadStatus::invalid_with_err(&error.to_string()))
+        }
+        // The block may still be valid, so it can be sent again once the error is gone
+        Err(error) => {
+            warn!("Failed to execute payload {block_hash:#x}: {error}");
+            Err(RpcErr::Internal)
+        }
         Ok(()) => {
             info!("Block with hash {block_hash} executed succesfully");
             info!("Block with hash {block_hash} added to storage");
@@ -107,3 +122,107 @@ pub fn new_payload_v3(
         }
     }
 }
+
+pub struct GetPayloadV3Request {
+    pub payload_id: u64,
+}
+
+impl std::fmt::Display for GetPayloadV3Request {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "GetPayloadV3Request {{ payload_id: {:#018x} }}",
+            self.payload_id
+        )
+    }
+}
+
+impl RpcHandler for GetPayloadV3Request {
+    fn parse(params: &Option<Vec<Value>>) -> Result<Self, RpcErr> {
+        let params = params.as_ref().ok_or(RpcErr::BadParams)?;
+        if params.len() != 1 {
+            return Err(RpcErr::BadParams);
+        };
+        let payload_id = serde_utils::u64::hex_str_padding::deserialize(params[0].clone())?;
+        Ok(GetPayloadV3Request { payload_id })
+    }
+
+    fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        info!("Received new engine request: {self}");
+        // The first byte of the payload id is the version of the request that started the payload job
+        if self.payload_id >> 56 != 3 {
+            return Err(RpcErr::UnsuportedFork);
+        }
+        // Payloads are built in the background as soon as they are requested, so the latest version is returned
+        let Some((payload, block_value)) = storage.get_payload(self.payload_id)? else {
+            return Err(RpcErr::UnknownPayload(format!(
+                "Payload with id {:#018x} not found",
+                self.payload_id
+            )));
+        };
+        // Gather the sidecars of the blob transactions included in the payload
+        let mut blobs_bundle = BlobsBundleV1::default();
+        for transaction in payload.body.transactions.iter() {
+            if let Transaction::EIP4844Transaction(_) = transaction {
+                let transaction_hash = transaction.compute_hash();
+                let sidecar = storage
+                    .get_blobs_bundle_from_pool(transaction_hash)
+                    .ok_or_else(|| {
+                        warn!("Missing blobs bundle for transaction {transaction_hash:#x}");
+                        RpcErr::Internal
+                    })?;
+                blobs_bundle.extend(sidecar);
+            }
+        }
+        let response = ExecutionPayloadResponse {
+            execution_payload: ExecutionPayloadV3::from_block(payload),
+            block_value,
+            blobs_bundle,
+            should_override_builder: false,
+        };
+        serde_json::to_value(response).map_err(|_| RpcErr::Internal)
+    }
+}
+
+/// Maximum amount of blobs that can be requested in a single `engine_getBlobsV1` call
+const GET_BLOBS_MAX_REQUEST_SIZE: usize = 128;
+
+pub struct GetBlobsV1Request {
+    pub blob_versioned_hashes: Vec<H256>,
+}
+
+impl RpcHandler for GetBlobsV1Request {
+    fn parse(params: &Option<Vec<Value>>) -> Result<Self, RpcErr> {
+        let params = params.as_ref().ok_or(RpcErr::BadParams)?;
+        if params.len() != 1 {
+            return Err(RpcErr::BadParams);
+        };
+        Ok(GetBlobsV1Request {
+            blob_versioned_hashes: serde_json::from_value(params[0].clone())?,
+        })
+    }
+
+    fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        info!(
+            "Received new engine request: GetBlobsV1Request with {} versioned hashes",
+            self.blob_versioned_hashes.len()
+        );
+        if self.blob_versioned_hashes.len() > GET_BLOBS_MAX_REQUEST_SIZE {
+            return Err(RpcErr::TooLargeRequest);
+        }
+        // Blobs missing from the blob pool are returned as null
+        let blobs_and_proofs: Vec<Option<BlobAndProofV1>> = self
+            .blob_versioned_hashes
+            .iter()
+            .map(|versioned_hash| {
+                storage
+                    .get_blob_from_pool(*versioned_hash)
+                    .map(|(blob, proof)| BlobAndProofV1 {
+                        blob: Bytes::copy_from_slice(&blob),
+                        proof: Bytes::copy_from_slice(&proof),
+                    })
+            })
+            .collect();
+        serde_json::to_value(blobs_and_proofs).map_err(|_| RpcErr::Internal)
+    }
+}
@@ -1,10 +1,16 @@
-use ethereum_rust_storage::Store;
+use ethereum_rust_storage::{Store, StoreSnapshot, EMPTY_TRIE_HASH};
 use serde_json::Value;
 use tracing::info;
 
-use crate::types::block_identifier::BlockIdentifierOrHash;
+use crate::types::{
+    account_proof::{AccountProof, StorageProof},
+    block_identifier::BlockIdentifierOrHash,
+};
 use crate::{utils::RpcErr, RpcHandler};
-use ethereum_rust_core::{Address, BigEndianHash, H256};
+use ethereum_rust_core::{
+    types::{AccountInfo, AccountState, EMPTY_KECCACK_HASH},
+    Address, BigEndianHash, H256,
+};
 
 pub struct GetBalanceRequest {
     pub address: Address,
@@ -27,6 +33,12 @@ pub struct GetTransactionCountRequest {
     pub block: BlockIdentifierOrHash,
 }
 
+pub struct GetProofRequest {
+    pub address: Address,
+    pub storage_keys: Vec<H256>,
+    pub block: BlockIdentifierOrHash,
+}
+
 impl RpcHandler for GetBalanceRequest {
     fn parse(params: &Option<Vec<Value>>) -> Result<GetBalanceRequest, RpcErr> {
         let params = params.as_ref().ok_or(RpcErr::BadParams)?;
@@ -39,18 +51,13 @@ impl RpcHandler for GetBalanceRequest {
         })
     }
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        let snapshot = storage.snapshot();
         info!(
             "Requested balance of account {} at block {}",
             self.address, self.block
         );
 
-        // TODO: implement historical querying
-        let is_latest = self.block.is_latest(&storage)?;
-        if !is_latest {
-            return Err(RpcErr::Internal);
-        }
-
-        let account = storage.get_account_info(self.address)?;
+        let account = get_account_info(&self.block, self.address, &snapshot)?;
         let balance = account.map(|acc| acc.balance).unwrap_or_default();
 
         serde_json::to_value(format!("{:#x}", balance)).map_err(|_| RpcErr::Internal)
@@ -69,20 +76,18 @@ impl RpcHandler for GetCodeRequest {
         })
     }
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        let snapshot = storage.snapshot();
         info!(
             "Requested code of account {} at block {}",
             self.address, self.block
         );
 
-        // TODO: implement historical querying
-        let is_latest = self.block.is_latest(&storage)?;
-        if !is_latest {
-            return Err(RpcErr::Internal);
-        }
-
-        let code = storage
-            .get_code_by_account_address(self.address)?
-            .unwrap_or_default();
+        let code = match get_account_info(&self.block, self.address, &snapshot)? {
+            Some(account) => snapshot
+                .get_account_code(account.code_hash)?
+                .unwrap_or_default(),
+            None => Default::default(),
+        };
 
         serde_json::to_value(format!("0x{:x}", code)).map_err(|_| RpcErr::Internal)
     }
@@ -101,20 +106,19 @@ impl RpcHandler for GetStorageAtRequest {
         })
     }
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        let snapshot = storage.snapshot();
         info!(
             "Requested storage sot {} of account {} at block {}",
             self.storage_slot, self.address, self.block
         );
 
-        // TODO: implement historical querying
-        let is_latest = self.block.is_latest(&storage)?;
-        if !is_latest {
-            return Err(RpcErr::Internal);
+        let storage_value = if self.block.is_latest(&snapshot)? {
+            snapshot.get_storage_at(self.address, self.storage_slot)?
+        } else {
+            let state_root = get_state_root(&self.block, &snapshot)?;
+            snapshot.get_storage_by_state_root(state_root, self.address, self.storage_slot)?
         }
-
-        let storage_value = storage
-            .get_storage_at(self.address, self.storage_slot)?
-            .unwrap_or_default();
+        .unwrap_or_default();
         let storage_value = H256::from_uint(&storage_value);
         serde_json::to_value(format!("{:#x}", storage_value)).map_err(|_| RpcErr::Internal)
     }
@@ -132,21 +136,96 @@ impl RpcHandler for GetTransactionCountRequest {
         })
     }
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        let snapshot = storage.snapshot();
         info!(
             "Requested nonce of account {} at block {}",
             self.address, self.block
         );
 
-        // TODO: implement historical querying
-        let is_latest = self.block.is_latest(&storage)?;
-        if !is_latest {
-            return Err(RpcErr::Internal);
-        }
-
-        let nonce = storage
-            .get_nonce_by_account_address(self.address)?
+        let nonce = get_account_info(&self.block, self.address, &snapshot)?
+            .map(|acc| acc.nonce)
             .unwrap_or_default();
 
         serde_json::to_value(format!("0x{:x}", nonce)).map_err(|_| RpcErr::Internal)
     }
 }
+
+impl RpcHandler for GetProofRequest {
+    fn parse(params: &Option<Vec<Value>>) -> Result<GetProofRequest, RpcErr> {
+        let params = params.as_ref().ok_or(RpcErr::BadParams)?;
+        if params.len() != 3 {
+            return Err(RpcErr::BadParams);
+        };
+        Ok(GetProofRequest {
+            address: serde_json::from_value(params[0].clone())?,
+            storage_keys: serde_json::from_value(params[1].clone())?,
+            block: BlockIdentifierOrHash::parse(params[2].clone(), 2)?,
+        })
+    }
+    fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        let snapshot = storage.snapshot();
+        info!(
+            "Requested proof of account {} at block {}",
+            self.address, self.block
+        );
+
+        let state_root = get_state_root(&self.block, &snapshot)?;
+        let account_proof = snapshot.get_account_proof(state_root, self.address)?;
+        // Accounts that don't exist are proven empty
+        let account = snapshot
+            .get_account_state_by_state_root(state_root, self.address)?
+            .unwrap_or(AccountState {
+                nonce: 0,
+                balance: 0.into(),
+                storage_root: *EMPTY_TRIE_HASH,
+                code_hash: *EMPTY_KECCACK_HASH,
+            });
+        let mut storage_proof = Vec::with_capacity(self.storage_keys.len());
+        for key in self.storage_keys.iter() {
+            let value = snapshot
+                .get_storage_by_state_root(state_root, self.address, *key)?
+                .unwrap_or_default();
+            let proof = snapshot.get_storage_proof(account.storage_root, *key)?;
+            storage_proof.push(StorageProof {
+                key: *key,
+                value,
+                proof: proof.into_iter().map(Into::into).collect(),
+            });
+        }
+        let account_proof = AccountProof {
+            address: self.address,
+            account_proof: account_proof.into_iter().map(Into::into).collect(),
+            balance: account.balance,
+            code_hash: account.code_hash,
+            nonce: account.nonce,
+            storage_hash: account.storage_root,
+            storage_proof,
+        };
+
+        serde_json::to_value(account_proof).map_err(|_| RpcErr::Internal)
+    }
+}
+
+/// Obtains the account info of an address at the given block.
+/// The latest state is read directly, older states are read from the world state trie of the block
+fn get_account_info(
+    block: &BlockIdentifierOrHash,
+    address: Address,
+    snapshot: &StoreSnapshot,
+) -> Result<Option<AccountInfo>, RpcErr> {
+    if block.is_latest(snapshot)? {
+        return Ok(snapshot.get_account_info(address)?);
+    }
+    let state_root = get_state_root(block, snapshot)?;
+    Ok(snapshot.get_account_info_by_state_root(state_root, address)?)
+}
+
+/// Obtains the state root of the given block, failing if the block is unknown or its state is no longer kept
+fn get_state_root(block: &BlockIdentifierOrHash, snapshot: &StoreSnapshot) -> Result<H256, RpcErr> {
+    let Some(block_number) = block.resolve_block_number(snapshot)? else {
+        return Err(RpcErr::StateUnavailable(block.to_string()));
+    };
+    snapshot
+        .get_state_root_for_block(block_number)?
+        .ok_or_else(|| RpcErr::StateUnavailable(block.to_string()))
+}
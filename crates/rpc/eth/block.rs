@@ -1,4 +1,3 @@
-use ethereum_rust_chain::find_parent_header;
 use serde_json::Value;
 use tracing::info;
 
@@ -14,11 +13,11 @@ use crate::{
 use ethereum_rust_core::{
     rlp::encode::RLPEncode,
     types::{
-        calculate_base_fee_per_blob_gas, Block, BlockBody, BlockHash, BlockHeader, BlockNumber,
-        Receipt,
+        calculate_base_fee_per_blob_gas, calculate_next_base_fee_per_blob_gas, Block, BlockBody,
+        BlockHash, BlockHeader, BlockNumber, Receipt,
     },
 };
-use ethereum_rust_storage::Store;
+use ethereum_rust_storage::{Store, StoreSnapshot};
 
 pub struct GetBlockByNumberRequest {
     pub block: BlockIdentifier,
@@ -63,13 +62,14 @@ impl RpcHandler for GetBlockByNumberRequest {
         })
     }
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        let snapshot = storage.snapshot();
         info!("Requested block with number: {}", self.block);
-        let block_number = match self.block.resolve_block_number(&storage)? {
+        let block_number = match self.block.resolve_block_number(&snapshot)? {
             Some(block_number) => block_number,
             _ => return Ok(Value::Null),
         };
-        let header = storage.get_block_header(block_number)?;
-        let body = storage.get_block_body(block_number)?;
+        let header = snapshot.get_block_header(block_number)?;
+        let body = snapshot.get_block_body(block_number)?;
         let (header, body) = match (header, body) {
             (Some(header), Some(body)) => (header, body),
             // Block not found
@@ -94,13 +94,14 @@ impl RpcHandler for GetBlockByHashRequest {
         })
     }
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        let snapshot = storage.snapshot();
         info!("Requested block with hash: {}", self.block);
-        let block_number = match storage.get_block_number(self.block)? {
+        let block_number = match snapshot.get_block_number(self.block)? {
             Some(number) => number,
             _ => return Ok(Value::Null),
         };
-        let header = storage.get_block_header(block_number)?;
-        let body = storage.get_block_body(block_number)?;
+        let header = snapshot.get_block_header(block_number)?;
+        let body = snapshot.get_block_body(block_number)?;
         let (header, body) = match (header, body) {
             (Some(header), Some(body)) => (header, body),
             // Block not found
@@ -124,15 +125,16 @@ impl RpcHandler for GetBlockTransactionCountRequest {
     }
 
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        let snapshot = storage.snapshot();
         info!(
             "Requested transaction count for block with number: {}",
             self.block
         );
-        let block_number = match self.block.resolve_block_number(&storage)? {
+        let block_number = match self.block.resolve_block_number(&snapshot)? {
             Some(block_number) => block_number,
             _ => return Ok(Value::Null),
         };
-        let block_body = match storage.get_block_body(block_number)? {
+        let block_body = match snapshot.get_block_body(block_number)? {
             Some(block_body) => block_body,
             _ => return Ok(Value::Null),
         };
@@ -154,19 +156,20 @@ impl RpcHandler for GetBlockReceiptsRequest {
     }
 
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        let snapshot = storage.snapshot();
         info!("Requested receipts for block with number: {}", self.block);
-        let block_number = match self.block.resolve_block_number(&storage)? {
+        let block_number = match self.block.resolve_block_number(&snapshot)? {
             Some(block_number) => block_number,
             _ => return Ok(Value::Null),
         };
-        let header = storage.get_block_header(block_number)?;
-        let body = storage.get_block_body(block_number)?;
+        let header = snapshot.get_block_header(block_number)?;
+        let body = snapshot.get_block_body(block_number)?;
         let (header, body) = match (header, body) {
             (Some(header), Some(body)) => (header, body),
             // Block not found
             _ => return Ok(Value::Null),
         };
-        let receipts = get_all_block_rpc_receipts(block_number, header, body, &storage)?;
+        let receipts = get_all_block_rpc_receipts(block_number, header, body, &snapshot)?;
 
         serde_json::to_value(&receipts).map_err(|_| RpcErr::Internal)
     }
@@ -184,11 +187,12 @@ impl RpcHandler for GetRawHeaderRequest {
     }
 
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        let snapshot = storage.snapshot();
         info!(
             "Requested raw header for block with identifier: {}",
             self.block
         );
-        let block_number = match self.block.resolve_block_number(&storage)? {
+        let block_number = match self.block.resolve_block_number(&snapshot)? {
             Some(block_number) => block_number,
             _ => return Ok(Value::Null),
         };
@@ -214,13 +218,14 @@ impl RpcHandler for GetRawBlockRequest {
     }
 
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        let snapshot = storage.snapshot();
         info!("Requested raw block: {}", self.block);
-        let block_number = match self.block.resolve_block_number(&storage)? {
+        let block_number = match self.block.resolve_block_number(&snapshot)? {
             Some(block_number) => block_number,
             _ => return Ok(Value::Null),
         };
-        let header = storage.get_block_header(block_number)?;
-        let body = storage.get_block_body(block_number)?;
+        let header = snapshot.get_block_header(block_number)?;
+        let body = snapshot.get_block_body(block_number)?;
         let (header, body) = match (header, body) {
             (Some(header), Some(body)) => (header, body),
             _ => return Ok(Value::Null),
@@ -244,17 +249,18 @@ impl RpcHandler for GetRawReceipts {
     }
 
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
-        let block_number = match self.block.resolve_block_number(&storage)? {
+        let snapshot = storage.snapshot();
+        let block_number = match self.block.resolve_block_number(&snapshot)? {
             Some(block_number) => block_number,
             _ => return Ok(Value::Null),
         };
-        let header = storage.get_block_header(block_number)?;
-        let body = storage.get_block_body(block_number)?;
+        let header = snapshot.get_block_header(block_number)?;
+        let body = snapshot.get_block_body(block_number)?;
         let (header, body) = match (header, body) {
             (Some(header), Some(body)) => (header, body),
             _ => return Ok(Value::Null),
         };
-        let receipts: Vec<String> = get_all_block_receipts(block_number, header, body, &storage)?
+        let receipts: Vec<String> = get_all_block_receipts(block_number, header, body, &snapshot)?
             .iter()
             .map(|receipt| format!("0x{}", hex::encode(receipt.encode_to_vec())))
             .collect();
@@ -266,16 +272,15 @@ pub fn get_all_block_rpc_receipts(
     block_number: BlockNumber,
     header: BlockHeader,
     body: BlockBody,
-    storage: &Store,
+    snapshot: &StoreSnapshot,
 ) -> Result<Vec<RpcReceipt>, RpcErr> {
     let mut receipts = Vec::new();
     // Check if this is the genesis block
     if header.parent_hash.is_zero() {
         return Ok(receipts);
     }
-    let parent_header = match find_parent_header(&header, storage) {
-        Ok(header) => header,
-        _ => return Err(RpcErr::Internal),
+    let Some(parent_header) = snapshot.get_block_header(block_number - 1)? else {
+        return Err(RpcErr::Internal);
     };
     let blob_gas_price = calculate_base_fee_per_blob_gas(parent_header);
     // Fetch receipt info from block
@@ -285,7 +290,7 @@ pub fn get_all_block_rpc_receipts(
     let mut current_log_index = 0;
     for (index, tx) in body.transactions.iter().enumerate() {
         let index = index as u64;
-        let receipt = match storage.get_receipt(block_number, index)? {
+        let receipt = match snapshot.get_receipt(block_number, index)? {
             Some(receipt) => receipt,
             _ => return Err(RpcErr::Internal),
         };
@@ -309,7 +314,7 @@ pub fn get_all_block_receipts(
     block_number: BlockNumber,
     header: BlockHeader,
     body: BlockBody,
-    storage: &Store,
+    snapshot: &StoreSnapshot,
 ) -> Result<Vec<Receipt>, RpcErr> {
     let mut receipts = Vec::new();
     // Check if this is the genesis block
@@ -318,7 +323,7 @@ pub fn get_all_block_receipts(
     }
     for (index, _) in body.transactions.iter().enumerate() {
         let index = index as u64;
-        let receipt = match storage.get_receipt(block_number, index)? {
+        let receipt = match snapshot.get_receipt(block_number, index)? {
             Some(receipt) => receipt,
             _ => return Err(RpcErr::Internal),
         };
@@ -337,21 +342,16 @@ pub fn block_number(storage: Store) -> Result<Value, RpcErr> {
     }
 }
 
+/// Returns the base fee per blob gas of the block following the latest one
 pub fn get_blob_base_fee(storage: &Store) -> Result<Value, RpcErr> {
     info!("Requested blob gas price");
-    match storage.get_latest_block_number() {
-        Ok(Some(block_number)) => {
-            let header = match storage.get_block_header(block_number)? {
-                Some(header) => header,
-                _ => return Err(RpcErr::Internal),
-            };
-            let parent_header = match find_parent_header(&header, storage) {
-                Ok(header) => header,
-                _ => return Err(RpcErr::Internal),
-            };
-            let blob_base_fee = calculate_base_fee_per_blob_gas(parent_header);
-            serde_json::to_value(format!("{:#x}", blob_base_fee)).map_err(|_| RpcErr::Internal)
-        }
-        _ => Err(RpcErr::Internal),
-    }
+    let snapshot = storage.snapshot();
+    let Some(latest) = snapshot.get_latest_block_number()? else {
+        return Err(RpcErr::Internal);
+    };
+    let Some(header) = snapshot.get_block_header(latest)? else {
+        return Err(RpcErr::Internal);
+    };
+    let blob_base_fee = calculate_next_base_fee_per_blob_gas(&header);
+    serde_json::to_value(format!("{:#x}", blob_base_fee)).map_err(|_| RpcErr::Internal)
 }
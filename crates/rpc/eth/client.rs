@@ -1,15 +1,75 @@
+use ethereum_rust_net::sync::SyncManager;
 use ethereum_rust_storage::Store;
+use serde::Serialize;
 use serde_json::Value;
 use tracing::info;
 
 use crate::utils::RpcErr;
 
+/// Progress of the sync in course, as returned by `eth_syncing`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SyncProgress {
+    #[serde(with = "ethereum_rust_core::serde_utils::u64::hex_str")]
+    starting_block: u64,
+    #[serde(with = "ethereum_rust_core::serde_utils::u64::hex_str")]
+    current_block: u64,
+    #[serde(with = "ethereum_rust_core::serde_utils::u64::hex_str")]
+    highest_block: u64,
+}
+
 pub fn chain_id(storage: Store) -> Result<Value, RpcErr> {
     info!("Requested chain id");
     let chain_spec = storage.get_chain_config().map_err(|_| RpcErr::Internal)?;
     serde_json::to_value(format!("{:#x}", chain_spec.chain_id)).map_err(|_| RpcErr::Internal)
 }
 
-pub fn syncing() -> Result<Value, RpcErr> {
-    Ok(Value::Bool(false))
+/// Returns the progress of the sync in course, or false if the node isn't syncing
+pub fn syncing(syncer: &SyncManager) -> Result<Value, RpcErr> {
+    let Some(status) = syncer.status() else {
+        return Ok(Value::Bool(false));
+    };
+    serde_json::to_value(SyncProgress {
+        starting_block: status.starting_block,
+        current_block: status.current_block,
+        highest_block: status.highest_block,
+    })
+    .map_err(|_| RpcErr::Internal)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_rust_core::{H256, H512};
+    use ethereum_rust_net::{
+        peer_manager::{PeerLimits, PeerManager},
+        types::Node,
+    };
+    use ethereum_rust_storage::EngineType;
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn syncing_reports_sync_progress() {
+        let local_node = Node {
+            ip: "127.0.0.1".parse().unwrap(),
+            udp_port: 30303,
+            tcp_port: 30303,
+            node_id: H512::random(),
+        };
+        let store = Store::new("", EngineType::InMemory).unwrap();
+        let syncer = SyncManager::new(PeerManager::new(local_node, PeerLimits::default()), store);
+        assert_eq!(syncing(&syncer).unwrap(), Value::Bool(false));
+
+        // There are no peers to sync from, but the sync is reported until its task runs and gives up
+        syncer.start_sync(H256::random()).unwrap();
+        assert_eq!(
+            syncing(&syncer).unwrap(),
+            json!({
+                "startingBlock": "0x0",
+                "currentBlock": "0x0",
+                "highestBlock": "0x0",
+            })
+        );
+    }
 }
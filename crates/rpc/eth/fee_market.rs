@@ -0,0 +1,530 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use ethereum_rust_chain::constants::MAX_BLOB_GAS_PER_BLOCK;
+use ethereum_rust_core::{
+    types::{
+        calculate_base_fee_per_blob_gas, calculate_base_fee_per_gas,
+        calculate_next_base_fee_per_blob_gas, BlockHash, BlockHeader, BlockNumber,
+    },
+    U256,
+};
+use ethereum_rust_storage::{Store, StoreSnapshot};
+use serde::Serialize;
+use serde_json::Value;
+use tracing::info;
+
+use crate::{
+    types::block_identifier::{BlockIdentifier, BlockTag},
+    utils::RpcErr,
+};
+
+/// Amount of blocks whose fee data is kept in memory
+const ORACLE_CACHE_SIZE: usize = 1024;
+/// Maximum amount of blocks a fee history can span
+const MAX_FEE_HISTORY_BLOCKS: u64 = 1024;
+/// Maximum amount of reward percentiles a fee history can ask for
+const MAX_REWARD_PERCENTILES: usize = 100;
+/// Amount of recent blocks sampled to suggest a priority fee
+const SUGGESTION_BLOCKS: u64 = 20;
+/// Amount of the lowest tips of each block that are sampled
+const SUGGESTION_SAMPLES_PER_BLOCK: usize = 3;
+/// Percentile of the sampled tips that is suggested
+const SUGGESTION_PERCENTILE: usize = 60;
+/// Priority fee suggested when there are no recent transactions to sample
+const DEFAULT_PRIORITY_FEE: u64 = 1_000_000_000;
+
+/// Fee market data of a single block
+#[derive(Debug)]
+struct BlockFees {
+    base_fee_per_gas: u64,
+    next_base_fee_per_gas: u64,
+    gas_used_ratio: f64,
+    base_fee_per_blob_gas: u64,
+    next_base_fee_per_blob_gas: u64,
+    blob_gas_used_ratio: f64,
+    /// Effective tip and gas used of each of the block's transactions, sorted by tip
+    tips: Vec<(u64, u64)>,
+}
+
+impl BlockFees {
+    /// Computes the fees of the block with the given header, which must be read from the same snapshot
+    fn compute(header: &BlockHeader, snapshot: &StoreSnapshot) -> Result<Self, RpcErr> {
+        let body = snapshot
+            .get_block_body(header.number)?
+            .ok_or(RpcErr::Internal)?;
+        let mut tips = Vec::with_capacity(body.transactions.len());
+        let mut last_cumulative_gas_used = 0;
+        for (index, transaction) in body.transactions.iter().enumerate() {
+            let receipt = snapshot
+                .get_receipt(header.number, index as u64)?
+                .ok_or(RpcErr::Internal)?;
+            let gas_used = receipt.cumulative_gas_used - last_cumulative_gas_used;
+            last_cumulative_gas_used = receipt.cumulative_gas_used;
+            let tip = transaction
+                .effective_gas_tip(header.base_fee_per_gas)
+                .unwrap_or_default();
+            tips.push((tip, gas_used));
+        }
+        tips.sort_unstable();
+
+        let base_fee_per_gas = header.base_fee_per_gas.unwrap_or_default();
+        // The next block is assumed to keep the same gas limit
+        let next_base_fee_per_gas = match header.base_fee_per_gas {
+            Some(base_fee) => calculate_base_fee_per_gas(
+                header.gas_limit,
+                header.gas_limit,
+                header.gas_used,
+                base_fee,
+            )
+            .unwrap_or(base_fee),
+            None => 0,
+        };
+        let (base_fee_per_blob_gas, next_base_fee_per_blob_gas, blob_gas_used_ratio) =
+            match header.excess_blob_gas {
+                Some(_) => (
+                    calculate_base_fee_per_blob_gas(header.clone()),
+                    calculate_next_base_fee_per_blob_gas(header),
+                    header.blob_gas_used.unwrap_or_default() as f64 / MAX_BLOB_GAS_PER_BLOCK as f64,
+                ),
+                None => (0, 0, 0.0),
+            };
+        Ok(BlockFees {
+            base_fee_per_gas,
+            next_base_fee_per_gas,
+            gas_used_ratio: if header.gas_limit == 0 {
+                0.0
+            } else {
+                header.gas_used as f64 / header.gas_limit as f64
+            },
+            base_fee_per_blob_gas,
+            next_base_fee_per_blob_gas,
+            blob_gas_used_ratio,
+            tips,
+        })
+    }
+
+    /// Returns the tips at the given percentiles of the block's gas usage
+    fn rewards(&self, percentiles: &[f64]) -> Vec<u64> {
+        if self.tips.is_empty() {
+            return vec![0; percentiles.len()];
+        }
+        let total_gas_used: u64 = self.tips.iter().map(|(_, gas_used)| gas_used).sum();
+        percentiles
+            .iter()
+            .map(|percentile| {
+                let threshold = (total_gas_used as f64 * percentile / 100.0) as u64;
+                let mut accumulated_gas = 0;
+                self.tips
+                    .iter()
+                    .find(|(_, gas_used)| {
+                        accumulated_gas += gas_used;
+                        accumulated_gas >= threshold
+                    })
+                    .or(self.tips.last())
+                    .map(|(tip, _)| *tip)
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Default)]
+struct FeesCache {
+    blocks: HashMap<BlockHash, Arc<BlockFees>>,
+    /// Cached blocks from oldest to newest insertion, to evict the oldest ones first
+    order: VecDeque<BlockHash>,
+}
+
+/// Suggests fees from the recent history of the chain.
+/// The fee data of each block is computed once and cached by block hash, so reorgs don't serve stale data
+#[derive(Debug, Clone, Default)]
+pub struct GasPriceOracle {
+    cache: Arc<Mutex<FeesCache>>,
+}
+
+impl GasPriceOracle {
+    /// Returns the fees of the canonical block with the given number, None if there is no such block.
+    /// The block's header, body and receipts are read from a single snapshot, so a reorg can't mix blocks
+    fn block_fees(
+        &self,
+        block_number: BlockNumber,
+        storage: &Store,
+    ) -> Result<Option<Arc<BlockFees>>, RpcErr> {
+        let snapshot = storage.snapshot();
+        let Some(header) = snapshot.get_block_header(block_number)? else {
+            return Ok(None);
+        };
+        let block_hash = header.compute_block_hash();
+        if let Some(fees) = self.cache.lock().unwrap().blocks.get(&block_hash) {
+            return Ok(Some(fees.clone()));
+        }
+        let fees = Arc::new(BlockFees::compute(&header, &snapshot)?);
+        let mut cache = self.cache.lock().unwrap();
+        if cache.blocks.insert(block_hash, fees.clone()).is_none() {
+            cache.order.push_back(block_hash);
+        }
+        while cache.order.len() > ORACLE_CACHE_SIZE {
+            if let Some(evicted) = cache.order.pop_front() {
+                cache.blocks.remove(&evicted);
+            }
+        }
+        Ok(Some(fees))
+    }
+
+    /// Suggests a priority fee from the lowest tips paid by the transactions of recent blocks
+    fn suggest_priority_fee(&self, storage: &Store) -> Result<u64, RpcErr> {
+        let Some(latest) = storage.get_latest_block_number()? else {
+            return Ok(DEFAULT_PRIORITY_FEE);
+        };
+        let mut samples = vec![];
+        for number in latest.saturating_sub(SUGGESTION_BLOCKS - 1)..=latest {
+            let Some(fees) = self.block_fees(number, storage)? else {
+                continue;
+            };
+            samples.extend(
+                fees.tips
+                    .iter()
+                    .take(SUGGESTION_SAMPLES_PER_BLOCK)
+                    .map(|(tip, _)| *tip),
+            );
+        }
+        if samples.is_empty() {
+            return Ok(DEFAULT_PRIORITY_FEE);
+        }
+        samples.sort_unstable();
+        Ok(samples[(samples.len() - 1) * SUGGESTION_PERCENTILE / 100])
+    }
+
+    /// Returns the base fee of the block following the latest one
+    fn next_base_fee(&self, storage: &Store) -> Result<u64, RpcErr> {
+        let Some(latest) = storage.get_latest_block_number()? else {
+            return Ok(0);
+        };
+        let fees = self.block_fees(latest, storage)?.ok_or(RpcErr::Internal)?;
+        Ok(fees.next_base_fee_per_gas)
+    }
+}
+
+pub struct FeeHistoryRequest {
+    pub block_count: u64,
+    pub newest_block: BlockIdentifier,
+    pub reward_percentiles: Option<Vec<f64>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeHistory {
+    pub oldest_block: U256,
+    pub base_fee_per_gas: Vec<U256>,
+    pub gas_used_ratio: Vec<f64>,
+    pub base_fee_per_blob_gas: Vec<U256>,
+    pub blob_gas_used_ratio: Vec<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reward: Option<Vec<Vec<U256>>>,
+}
+
+impl FeeHistoryRequest {
+    pub fn parse(params: &Option<Vec<Value>>) -> Result<Self, RpcErr> {
+        let params = params.as_ref().ok_or(RpcErr::BadParams)?;
+        if params.len() < 2 || params.len() > 3 {
+            return Err(RpcErr::BadParams);
+        };
+        // The block count is usually a hex quantity, but some clients send a plain number
+        let block_count = match &params[0] {
+            Value::Number(number) => number.as_u64().ok_or(RpcErr::BadParams)?,
+            value => {
+                let hex_str: String = serde_json::from_value(value.clone())?;
+                let hex_str = hex_str.strip_prefix("0x").ok_or(RpcErr::BadHexFormat(0))?;
+                u64::from_str_radix(hex_str, 16).map_err(|_| RpcErr::BadHexFormat(0))?
+            }
+        };
+        let reward_percentiles: Option<Vec<f64>> = match params.get(2) {
+            Some(value) => serde_json::from_value(value.clone())?,
+            None => None,
+        };
+        if let Some(percentiles) = &reward_percentiles {
+            let in_range = percentiles
+                .iter()
+                .all(|percentile| (0.0..=100.0).contains(percentile));
+            let increasing = percentiles.windows(2).all(|pair| pair[0] <= pair[1]);
+            if !in_range || !increasing || percentiles.len() > MAX_REWARD_PERCENTILES {
+                return Err(RpcErr::BadParams);
+            }
+        }
+        Ok(FeeHistoryRequest {
+            block_count,
+            newest_block: BlockIdentifier::parse(params[1].clone(), 1)?,
+            reward_percentiles,
+        })
+    }
+}
+
+pub fn gas_price(storage: &Store, oracle: &GasPriceOracle) -> Result<Value, RpcErr> {
+    info!("Requested gas price");
+    let gas_price = oracle.next_base_fee(storage)? + oracle.suggest_priority_fee(storage)?;
+    serde_json::to_value(format!("{gas_price:#x}")).map_err(|_| RpcErr::Internal)
+}
+
+pub fn max_priority_fee_per_gas(storage: &Store, oracle: &GasPriceOracle) -> Result<Value, RpcErr> {
+    info!("Requested max priority fee per gas");
+    let priority_fee = oracle.suggest_priority_fee(storage)?;
+    serde_json::to_value(format!("{priority_fee:#x}")).map_err(|_| RpcErr::Internal)
+}
+
+pub fn fee_history(
+    request: &FeeHistoryRequest,
+    storage: &Store,
+    oracle: &GasPriceOracle,
+) -> Result<Value, RpcErr> {
+    info!(
+        "Requested fee history of {} blocks up to {}",
+        request.block_count, request.newest_block
+    );
+    let latest = storage.get_latest_block_number()?.unwrap_or_default();
+    // The pending block is not tracked, so fee histories end at the latest block at most
+    let newest_block = match request.newest_block {
+        BlockIdentifier::Tag(BlockTag::Pending) => latest,
+        ref block => block
+            .resolve_block_number(&storage.snapshot())?
+            .ok_or(RpcErr::BadParams)?,
+    };
+    if newest_block > latest {
+        return Err(RpcErr::BadParams);
+    }
+    let block_count = request.block_count.min(MAX_FEE_HISTORY_BLOCKS);
+    let mut history = FeeHistory {
+        oldest_block: U256::zero(),
+        base_fee_per_gas: vec![],
+        gas_used_ratio: vec![],
+        base_fee_per_blob_gas: vec![],
+        blob_gas_used_ratio: vec![],
+        reward: request.reward_percentiles.as_ref().map(|_| vec![]),
+    };
+    if block_count == 0 {
+        return serde_json::to_value(history).map_err(|_| RpcErr::Internal);
+    }
+    let oldest_block = (newest_block + 1).saturating_sub(block_count);
+    history.oldest_block = U256::from(oldest_block);
+    let mut next_fees = None;
+    for number in oldest_block..=newest_block {
+        let fees = oracle
+            .block_fees(number, storage)?
+            .ok_or(RpcErr::Internal)?;
+        history
+            .base_fee_per_gas
+            .push(U256::from(fees.base_fee_per_gas));
+        history.gas_used_ratio.push(fees.gas_used_ratio);
+        history
+            .base_fee_per_blob_gas
+            .push(U256::from(fees.base_fee_per_blob_gas));
+        history.blob_gas_used_ratio.push(fees.blob_gas_used_ratio);
+        if let (Some(rewards), Some(percentiles)) =
+            (history.reward.as_mut(), &request.reward_percentiles)
+        {
+            rewards.push(
+                fees.rewards(percentiles)
+                    .into_iter()
+                    .map(U256::from)
+                    .collect(),
+            );
+        }
+        next_fees = Some(fees);
+    }
+    // The fee histories also include the fees of the block following the newest one
+    if let Some(fees) = next_fees {
+        history
+            .base_fee_per_gas
+            .push(U256::from(fees.next_base_fee_per_gas));
+        history
+            .base_fee_per_blob_gas
+            .push(U256::from(fees.next_base_fee_per_blob_gas));
+    }
+    serde_json::to_value(history).map_err(|_| RpcErr::Internal)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_rust_core::types::{
+        Block, BlockBody, EIP1559Transaction, Receipt, Transaction, TxKind, TxType,
+    };
+    use ethereum_rust_storage::EngineType;
+    use serde_json::json;
+
+    use super::*;
+    use crate::eth::block::get_blob_base_fee;
+
+    const BASE_FEE: u64 = 1000;
+    const GAS_LIMIT: u64 = 30_000_000;
+
+    fn transaction(nonce: u64, max_priority_fee_per_gas: u64) -> Transaction {
+        Transaction::EIP1559Transaction(EIP1559Transaction {
+            chain_id: 1,
+            nonce,
+            max_priority_fee_per_gas,
+            max_fee_per_gas: 2 * BASE_FEE,
+            gas_limit: 100_000,
+            to: TxKind::Create,
+            value: U256::zero(),
+            data: Default::default(),
+            access_list: vec![],
+            signature_y_parity: false,
+            signature_r: U256::zero(),
+            signature_s: U256::zero(),
+        })
+    }
+
+    /// Adds a block whose transactions pay the given tips and use the given amounts of gas
+    fn add_block(storage: &Store, number: u64, transactions: &[(u64, u64)]) {
+        let mut cumulative_gas_used = 0;
+        let mut receipts = vec![];
+        for (_, gas_used) in transactions {
+            cumulative_gas_used += gas_used;
+            receipts.push(Receipt::new(
+                TxType::EIP1559,
+                true,
+                cumulative_gas_used,
+                vec![],
+            ));
+        }
+        let block = Block {
+            header: BlockHeader {
+                number,
+                gas_limit: GAS_LIMIT,
+                gas_used: cumulative_gas_used,
+                base_fee_per_gas: Some(BASE_FEE),
+                ..Default::default()
+            },
+            body: BlockBody {
+                transactions: transactions
+                    .iter()
+                    .enumerate()
+                    .map(|(nonce, (tip, _))| transaction(nonce as u64, *tip))
+                    .collect(),
+                ommers: vec![],
+                withdrawals: None,
+            },
+        };
+        for (index, receipt) in receipts.into_iter().enumerate() {
+            storage
+                .add_receipt(number, index as u64, receipt)
+                .expect("Failed to write to test DB");
+        }
+        storage
+            .add_block(block)
+            .expect("Failed to write to test DB");
+    }
+
+    fn test_store() -> Store {
+        let storage =
+            Store::new("temp.db", EngineType::InMemory).expect("Failed to create test DB");
+        add_block(&storage, 0, &[]);
+        add_block(&storage, 1, &[(50, 63_000), (10, 21_000)]);
+        storage
+    }
+
+    #[test]
+    fn fee_history_with_rewards() {
+        let storage = test_store();
+        let oracle = GasPriceOracle::default();
+        let params = Some(vec![json!("0x2"), json!("latest"), json!([0, 25, 50, 100])]);
+        let request = FeeHistoryRequest::parse(&params).unwrap();
+        let history = fee_history(&request, &storage, &oracle).unwrap();
+
+        let next_base_fee =
+            calculate_base_fee_per_gas(GAS_LIMIT, GAS_LIMIT, 84_000, BASE_FEE).unwrap();
+        assert_eq!(history["oldestBlock"], "0x0");
+        assert_eq!(
+            history["baseFeePerGas"],
+            json!([
+                U256::from(BASE_FEE),
+                U256::from(BASE_FEE),
+                U256::from(next_base_fee)
+            ])
+        );
+        assert_eq!(
+            history["gasUsedRatio"],
+            json!([0.0, 84_000.0 / GAS_LIMIT as f64])
+        );
+        assert_eq!(history["baseFeePerBlobGas"], json!(["0x0", "0x0", "0x0"]));
+        assert_eq!(history["reward"][0], json!(["0x0", "0x0", "0x0", "0x0"]));
+        // The cheaper transaction covers the first quarter of the block's gas
+        assert_eq!(history["reward"][1], json!(["0xa", "0xa", "0x32", "0x32"]));
+
+        let params = Some(vec![json!(1), json!("0x0")]);
+        let request = FeeHistoryRequest::parse(&params).unwrap();
+        let history = fee_history(&request, &storage, &oracle).unwrap();
+        assert_eq!(history["oldestBlock"], "0x0");
+        assert_eq!(history["gasUsedRatio"], json!([0.0]));
+        assert!(history.get("reward").is_none());
+
+        let params = Some(vec![json!("0x2"), json!("latest"), json!([50, 25])]);
+        assert!(FeeHistoryRequest::parse(&params).is_err());
+
+        // Fee histories can't end past the latest block
+        let params = Some(vec![json!("0x2"), json!("0x2")]);
+        let request = FeeHistoryRequest::parse(&params).unwrap();
+        assert!(matches!(
+            fee_history(&request, &storage, &oracle),
+            Err(RpcErr::BadParams)
+        ));
+    }
+
+    #[test]
+    fn blob_base_fee_of_next_block() {
+        let storage = test_store();
+        let header = BlockHeader {
+            number: 2,
+            parent_hash: storage
+                .get_block_header(1)
+                .unwrap()
+                .unwrap()
+                .compute_block_hash(),
+            gas_limit: GAS_LIMIT,
+            base_fee_per_gas: Some(BASE_FEE),
+            excess_blob_gas: Some(10 * MAX_BLOB_GAS_PER_BLOCK),
+            blob_gas_used: Some(MAX_BLOB_GAS_PER_BLOCK),
+            ..Default::default()
+        };
+        let next_base_fee_per_blob_gas = calculate_next_base_fee_per_blob_gas(&header);
+        assert!(next_base_fee_per_blob_gas > calculate_base_fee_per_blob_gas(header.clone()));
+        storage
+            .add_block(Block {
+                header,
+                body: BlockBody {
+                    transactions: vec![],
+                    ommers: vec![],
+                    withdrawals: None,
+                },
+            })
+            .unwrap();
+
+        let params = Some(vec![json!("0x1"), json!("latest")]);
+        let request = FeeHistoryRequest::parse(&params).unwrap();
+        let history = fee_history(&request, &storage, &GasPriceOracle::default()).unwrap();
+        let blob_base_fee = get_blob_base_fee(&storage).unwrap();
+        assert_eq!(history["baseFeePerBlobGas"][1], blob_base_fee);
+        assert_eq!(
+            blob_base_fee,
+            json!(format!("{next_base_fee_per_blob_gas:#x}"))
+        );
+    }
+
+    #[test]
+    fn suggested_fees() {
+        let storage = test_store();
+        let oracle = GasPriceOracle::default();
+        let next_base_fee =
+            calculate_base_fee_per_gas(GAS_LIMIT, GAS_LIMIT, 84_000, BASE_FEE).unwrap();
+        assert_eq!(
+            max_priority_fee_per_gas(&storage, &oracle).unwrap(),
+            json!("0xa")
+        );
+        assert_eq!(
+            gas_price(&storage, &oracle).unwrap(),
+            json!(format!("{:#x}", next_base_fee + 10))
+        );
+    }
+}
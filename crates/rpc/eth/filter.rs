@@ -0,0 +1,377 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use ethereum_rust_core::{types::BlockHash, H256};
+use ethereum_rust_storage::{ChainEvent, Store};
+use serde_json::Value;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{info, warn};
+
+use crate::{
+    eth::logs::{self, LogsFilter, LogsLimits},
+    types::receipt::RpcLog,
+    utils::RpcErr,
+};
+
+/// Filters that are not polled for this long are uninstalled
+pub const FILTER_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+pub type FilterId = u64;
+
+/// Changes collected by a filter since it was last polled
+#[derive(Debug)]
+enum FilterChanges {
+    Logs {
+        filter: LogsFilter,
+        logs: Vec<RpcLog>,
+    },
+    Blocks(Vec<BlockHash>),
+    PendingTransactions(Vec<H256>),
+}
+
+#[derive(Debug)]
+struct ActiveFilter {
+    changes: FilterChanges,
+    last_poll: Instant,
+}
+
+#[derive(Debug, Default)]
+struct FilterTable {
+    filters: HashMap<FilterId, ActiveFilter>,
+    last_id: FilterId,
+}
+
+/// Filters installed through the polling filter API.
+/// Each filter collects the changes published by the store until it is polled, and is uninstalled
+/// once it hasn't been polled for longer than the timeout
+#[derive(Debug, Clone)]
+pub struct ActiveFilters {
+    table: Arc<Mutex<FilterTable>>,
+    timeout: Duration,
+    /// Source of the current time, which tests can move forward
+    now: fn() -> Instant,
+}
+
+impl Default for ActiveFilters {
+    fn default() -> Self {
+        Self::new(FILTER_TIMEOUT)
+    }
+}
+
+impl ActiveFilters {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            table: Default::default(),
+            timeout,
+            now: Instant::now,
+        }
+    }
+
+    /// Feeds the filters with the events published by the store until it is dropped
+    pub async fn listen(self, mut events: broadcast::Receiver<ChainEvent>) {
+        loop {
+            match events.recv().await {
+                Ok(event) => self.handle_event(&event),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Filters fell behind the chain, {missed} events were missed")
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+
+    pub fn handle_event(&self, event: &ChainEvent) {
+        let mut table = self.table.lock().unwrap();
+        self.remove_expired(&mut table);
+        for active_filter in table.filters.values_mut() {
+            match (&mut active_filter.changes, event) {
+                (FilterChanges::Logs { filter, logs }, ChainEvent::NewHead { block, receipts }) => {
+                    let block_hash = block.header.compute_block_hash();
+                    if filter.includes_block(block.header.number, block_hash) {
+                        match logs::logs_in_block(filter, block, receipts, false) {
+                            Ok(block_logs) => logs.extend(block_logs),
+                            Err(error) => warn!(
+                                "Could not collect the logs of block {block_hash:#x}: {error:?}"
+                            ),
+                        }
+                    }
+                }
+                (
+                    FilterChanges::Logs { filter, logs },
+                    ChainEvent::RemovedBlock { block, receipts },
+                ) => {
+                    let block_hash = block.header.compute_block_hash();
+                    if filter.includes_block(block.header.number, block_hash) {
+                        match logs::logs_in_block(filter, block, receipts, true) {
+                            Ok(block_logs) => logs.extend(block_logs),
+                            Err(error) => warn!(
+                                "Could not collect the logs of block {block_hash:#x}: {error:?}"
+                            ),
+                        }
+                    }
+                }
+                (FilterChanges::Blocks(hashes), ChainEvent::NewHead { block, .. }) => {
+                    hashes.push(block.header.compute_block_hash())
+                }
+                (
+                    FilterChanges::PendingTransactions(hashes),
+                    ChainEvent::NewPendingTransaction(hash),
+                ) => hashes.push(*hash),
+                _ => {}
+            }
+        }
+    }
+
+    fn install(&self, changes: FilterChanges) -> FilterId {
+        let mut table = self.table.lock().unwrap();
+        self.remove_expired(&mut table);
+        table.last_id += 1;
+        let id = table.last_id;
+        table.filters.insert(
+            id,
+            ActiveFilter {
+                changes,
+                last_poll: (self.now)(),
+            },
+        );
+        id
+    }
+
+    fn uninstall(&self, id: FilterId) -> bool {
+        self.table.lock().unwrap().filters.remove(&id).is_some()
+    }
+
+    /// Returns the changes collected by the filter since it was last polled
+    fn take_changes(&self, id: FilterId) -> Result<Value, RpcErr> {
+        let mut table = self.table.lock().unwrap();
+        self.remove_expired(&mut table);
+        let filter = table.filters.get_mut(&id).ok_or(RpcErr::FilterNotFound)?;
+        filter.last_poll = (self.now)();
+        let changes = match &mut filter.changes {
+            FilterChanges::Logs { logs, .. } => serde_json::to_value(std::mem::take(logs)),
+            FilterChanges::Blocks(hashes) => serde_json::to_value(std::mem::take(hashes)),
+            FilterChanges::PendingTransactions(hashes) => {
+                serde_json::to_value(std::mem::take(hashes))
+            }
+        };
+        changes.map_err(|_| RpcErr::Internal)
+    }
+
+    /// Returns the criteria of a log filter
+    fn logs_filter(&self, id: FilterId) -> Result<LogsFilter, RpcErr> {
+        let mut table = self.table.lock().unwrap();
+        self.remove_expired(&mut table);
+        match table.filters.get_mut(&id) {
+            Some(ActiveFilter {
+                changes: FilterChanges::Logs { filter, .. },
+                last_poll,
+            }) => {
+                *last_poll = (self.now)();
+                Ok(filter.clone())
+            }
+            _ => Err(RpcErr::FilterNotFound),
+        }
+    }
+
+    fn remove_expired(&self, table: &mut FilterTable) {
+        let now = (self.now)();
+        table
+            .filters
+            .retain(|_, filter| now.duration_since(filter.last_poll) < self.timeout);
+    }
+}
+
+pub struct NewFilterRequest {
+    pub filter: LogsFilter,
+}
+
+pub struct FilterIdRequest {
+    pub id: FilterId,
+}
+
+impl NewFilterRequest {
+    pub fn parse(params: &Option<Vec<Value>>) -> Result<Self, RpcErr> {
+        let params = params.as_ref().ok_or(RpcErr::BadParams)?;
+        if params.len() != 1 {
+            return Err(RpcErr::BadParams);
+        };
+        Ok(NewFilterRequest {
+            filter: LogsFilter::parse(params[0].clone())?,
+        })
+    }
+}
+
+impl FilterIdRequest {
+    pub fn parse(params: &Option<Vec<Value>>) -> Result<Self, RpcErr> {
+        let params = params.as_ref().ok_or(RpcErr::BadParams)?;
+        if params.len() != 1 {
+            return Err(RpcErr::BadParams);
+        };
+        let hex_str: String = serde_json::from_value(params[0].clone())?;
+        let Some(hex_str) = hex_str.strip_prefix("0x") else {
+            return Err(RpcErr::BadHexFormat(0));
+        };
+        let id = FilterId::from_str_radix(hex_str, 16).map_err(|_| RpcErr::BadHexFormat(0))?;
+        Ok(FilterIdRequest { id })
+    }
+}
+
+fn filter_id_value(id: FilterId) -> Value {
+    Value::String(format!("{id:#x}"))
+}
+
+pub fn new_filter(request: &NewFilterRequest, filters: &ActiveFilters) -> Result<Value, RpcErr> {
+    info!("Installing log filter: {:?}", request.filter);
+    let id = filters.install(FilterChanges::Logs {
+        filter: request.filter.clone(),
+        logs: vec![],
+    });
+    Ok(filter_id_value(id))
+}
+
+pub fn new_block_filter(filters: &ActiveFilters) -> Result<Value, RpcErr> {
+    info!("Installing block filter");
+    Ok(filter_id_value(
+        filters.install(FilterChanges::Blocks(vec![])),
+    ))
+}
+
+pub fn new_pending_transaction_filter(filters: &ActiveFilters) -> Result<Value, RpcErr> {
+    info!("Installing pending transaction filter");
+    Ok(filter_id_value(
+        filters.install(FilterChanges::PendingTransactions(vec![])),
+    ))
+}
+
+pub fn get_filter_changes(
+    request: &FilterIdRequest,
+    filters: &ActiveFilters,
+) -> Result<Value, RpcErr> {
+    filters.take_changes(request.id)
+}
+
+pub fn get_filter_logs(
+    request: &FilterIdRequest,
+    filters: &ActiveFilters,
+    storage: &Store,
+    limits: LogsLimits,
+) -> Result<Value, RpcErr> {
+    let filter = filters.logs_filter(request.id)?;
+    let logs = logs::filter_logs(&filter, storage, limits)?;
+    serde_json::to_value(logs).map_err(|_| RpcErr::Internal)
+}
+
+pub fn uninstall_filter(
+    request: &FilterIdRequest,
+    filters: &ActiveFilters,
+) -> Result<Value, RpcErr> {
+    info!("Uninstalling filter {:#x}", request.id);
+    Ok(Value::Bool(filters.uninstall(request.id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_rust_core::{
+        types::{
+            Block, BlockBody, BlockHeader, LegacyTransaction, Log, Receipt, Transaction, TxKind,
+            TxType,
+        },
+        Address, Bloom, U256,
+    };
+    use serde_json::json;
+
+    use super::*;
+
+    fn block_with_log(number: u64, address: Address) -> (Block, Vec<Receipt>) {
+        let logs = vec![Log {
+            address,
+            topics: vec![],
+            data: Default::default(),
+        }];
+        let receipt = Receipt::new(TxType::Legacy, true, 21_000, logs);
+        let mut logs_bloom = Bloom::zero();
+        logs_bloom.accrue_bloom(&receipt.bloom);
+        let transaction = Transaction::LegacyTransaction(LegacyTransaction {
+            nonce: 0,
+            gas_price: 0,
+            gas: 21_000,
+            to: TxKind::Create,
+            value: U256::zero(),
+            data: Default::default(),
+            v: U256::zero(),
+            r: U256::zero(),
+            s: U256::zero(),
+        });
+        let block = Block {
+            header: BlockHeader {
+                number,
+                logs_bloom,
+                ..Default::default()
+            },
+            body: BlockBody {
+                transactions: vec![transaction],
+                ommers: vec![],
+                withdrawals: None,
+            },
+        };
+        (block, vec![receipt])
+    }
+
+    fn changes(filters: &ActiveFilters, id: FilterId) -> Vec<Value> {
+        serde_json::from_value(filters.take_changes(id).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn filters_collect_changes_until_polled() {
+        let filters = ActiveFilters::default();
+        let address = Address::from_low_u64_be(0xa);
+        let log_filter = filters.install(FilterChanges::Logs {
+            filter: LogsFilter::parse(json!({ "address": address, "fromBlock": "0x2" })).unwrap(),
+            logs: vec![],
+        });
+        let block_filter = filters.install(FilterChanges::Blocks(vec![]));
+        let transaction_filter = filters.install(FilterChanges::PendingTransactions(vec![]));
+
+        for number in 1..=3 {
+            let (block, receipts) = block_with_log(number, address);
+            filters.handle_event(&ChainEvent::new_head(block, receipts));
+        }
+        let (removed, receipts) = block_with_log(3, address);
+        filters.handle_event(&ChainEvent::removed_block(removed, receipts));
+        filters.handle_event(&ChainEvent::NewPendingTransaction(H256::zero()));
+
+        let logs = changes(&filters, log_filter);
+        assert_eq!(logs.len(), 3);
+        assert_eq!(logs[0]["blockNumber"], "0x2");
+        assert_eq!(logs[2]["removed"], true);
+        assert_eq!(changes(&filters, block_filter).len(), 3);
+        assert_eq!(changes(&filters, transaction_filter).len(), 1);
+
+        // Changes are only returned once
+        assert!(changes(&filters, log_filter).is_empty());
+        assert!(changes(&filters, block_filter).is_empty());
+
+        assert!(filters.uninstall(block_filter));
+        assert!(matches!(
+            filters.take_changes(block_filter),
+            Err(RpcErr::FilterNotFound)
+        ));
+        assert!(matches!(
+            filters.logs_filter(transaction_filter),
+            Err(RpcErr::FilterNotFound)
+        ));
+    }
+
+    #[test]
+    fn idle_filters_expire() {
+        let mut filters = ActiveFilters::default();
+        let id = filters.install(FilterChanges::Blocks(vec![]));
+        assert!(filters.take_changes(id).is_ok());
+        filters.now = || Instant::now() + FILTER_TIMEOUT;
+        filters.handle_event(&ChainEvent::NewPendingTransaction(H256::zero()));
+        assert!(!filters.uninstall(id));
+    }
+}
@@ -0,0 +1,479 @@
+use ethereum_rust_core::{
+    types::{Block, BlockBody, BlockHash, BlockHeader, BlockNumber, Log, Receipt},
+    Address, Bloom, BloomInput, H256,
+};
+use ethereum_rust_storage::{Store, StoreSnapshot};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::info;
+
+use crate::{
+    types::{
+        block_identifier::{BlockIdentifier, BlockTag},
+        receipt::RpcLog,
+    },
+    utils::RpcErr,
+};
+
+/// Limits applied to log queries so that a single request can't exhaust the node
+#[derive(Debug, Clone, Copy)]
+pub struct LogsLimits {
+    /// Maximum amount of blocks a query may span
+    pub max_block_range: u64,
+    /// Maximum amount of logs a query may return
+    pub max_results: usize,
+}
+
+impl Default for LogsLimits {
+    fn default() -> Self {
+        Self {
+            max_block_range: 10_000,
+            max_results: 10_000,
+        }
+    }
+}
+
+/// Blocks a log filter is applied to
+#[derive(Debug, Clone)]
+pub enum LogsBlockRange {
+    Range {
+        from: BlockIdentifier,
+        to: BlockIdentifier,
+    },
+    Hash(BlockHash),
+}
+
+#[derive(Debug, Clone)]
+pub struct LogsFilter {
+    pub blocks: LogsBlockRange,
+    /// The log must be emitted by one of these addresses, any address matches if empty
+    pub addresses: Vec<Address>,
+    /// The topic at each position must be one of the given ones, any topic matches an empty position
+    pub topics: Vec<Vec<H256>>,
+}
+
+pub struct GetLogsRequest {
+    pub filter: LogsFilter,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> From<OneOrMany<T>> for Vec<T> {
+    fn from(value: OneOrMany<T>) -> Self {
+        match value {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawLogsFilter {
+    from_block: Option<Value>,
+    to_block: Option<Value>,
+    block_hash: Option<BlockHash>,
+    address: Option<OneOrMany<Address>>,
+    topics: Option<Vec<Option<OneOrMany<H256>>>>,
+}
+
+impl LogsFilter {
+    pub fn parse(value: Value) -> Result<Self, RpcErr> {
+        let raw: RawLogsFilter = serde_json::from_value(value)?;
+        let parse_block = |value: Option<Value>| match value {
+            Some(value) => BlockIdentifier::parse(value, 0),
+            None => Ok(BlockIdentifier::Tag(BlockTag::Latest)),
+        };
+        let blocks = match raw.block_hash {
+            Some(_) if raw.from_block.is_some() || raw.to_block.is_some() => {
+                return Err(RpcErr::BadParams)
+            }
+            Some(hash) => LogsBlockRange::Hash(hash),
+            None => LogsBlockRange::Range {
+                from: parse_block(raw.from_block)?,
+                to: parse_block(raw.to_block)?,
+            },
+        };
+        Ok(LogsFilter {
+            blocks,
+            addresses: raw.address.map(Vec::from).unwrap_or_default(),
+            topics: raw
+                .topics
+                .unwrap_or_default()
+                .into_iter()
+                .map(|topics| topics.map(Vec::from).unwrap_or_default())
+                .collect(),
+        })
+    }
+
+    /// Returns false if the bloom proves that none of the logs it was built from match the filter
+    pub fn may_match(&self, bloom: &Bloom) -> bool {
+        let contains = |bytes: &[u8]| bloom.contains_input(BloomInput::Raw(bytes));
+        (self.addresses.is_empty()
+            || self
+                .addresses
+                .iter()
+                .any(|address| contains(address.as_bytes())))
+            && self.topics.iter().all(|topics| {
+                topics.is_empty() || topics.iter().any(|topic| contains(topic.as_bytes()))
+            })
+    }
+
+    /// Returns whether the filter's block range includes the given block.
+    /// Only explicit block numbers bound the range, as block tags are resolved at query time
+    pub fn includes_block(&self, number: BlockNumber, hash: BlockHash) -> bool {
+        match &self.blocks {
+            LogsBlockRange::Hash(block_hash) => *block_hash == hash,
+            LogsBlockRange::Range { from, to } => {
+                !matches!(from, BlockIdentifier::Number(from) if number < *from)
+                    && !matches!(to, BlockIdentifier::Number(to) if number > *to)
+            }
+        }
+    }
+
+    pub fn matches(&self, log: &Log) -> bool {
+        (self.addresses.is_empty() || self.addresses.contains(&log.address))
+            && self.topics.iter().enumerate().all(|(position, topics)| {
+                topics.is_empty()
+                    || log
+                        .topics
+                        .get(position)
+                        .is_some_and(|topic| topics.contains(topic))
+            })
+    }
+}
+
+impl GetLogsRequest {
+    pub fn parse(params: &Option<Vec<Value>>) -> Result<Self, RpcErr> {
+        let params = params.as_ref().ok_or(RpcErr::BadParams)?;
+        if params.len() != 1 {
+            return Err(RpcErr::BadParams);
+        };
+        Ok(GetLogsRequest {
+            filter: LogsFilter::parse(params[0].clone())?,
+        })
+    }
+}
+
+pub fn get_logs(
+    request: &GetLogsRequest,
+    storage: Store,
+    limits: LogsLimits,
+) -> Result<Value, RpcErr> {
+    info!("Requested logs with filter: {:?}", request.filter);
+    let logs = filter_logs(&request.filter, &storage, limits)?;
+    serde_json::to_value(logs).map_err(|_| RpcErr::Internal)
+}
+
+/// Collects the logs of canonical blocks matching the filter, failing if any of the limits is exceeded
+pub fn filter_logs(
+    filter: &LogsFilter,
+    storage: &Store,
+    limits: LogsLimits,
+) -> Result<Vec<RpcLog>, RpcErr> {
+    let (from, to) = match &filter.blocks {
+        LogsBlockRange::Hash(hash) => {
+            // The block is looked up and read from the same snapshot, so it can't be replaced in between
+            let snapshot = storage.snapshot();
+            let Some(number) = snapshot.get_block_number(*hash)? else {
+                return Err(RpcErr::BadParams);
+            };
+            let logs = block_logs(filter, number, &snapshot)?;
+            check_results_limit(&logs, limits)?;
+            return Ok(logs);
+        }
+        LogsBlockRange::Range { from, to } => {
+            let snapshot = storage.snapshot();
+            let Some(latest) = snapshot.get_latest_block_number()? else {
+                return Ok(vec![]);
+            };
+            let from = from.resolve_block_number(&snapshot)?.unwrap_or(latest);
+            let to = to
+                .resolve_block_number(&snapshot)?
+                .unwrap_or(latest)
+                .min(latest);
+            if from > to {
+                return Ok(vec![]);
+            }
+            if to - from >= limits.max_block_range {
+                return Err(RpcErr::LimitExceeded(format!(
+                    "query exceeds the limit of {} blocks",
+                    limits.max_block_range
+                )));
+            }
+            (from, to)
+        }
+    };
+    let mut logs = vec![];
+    for block_number in from..=to {
+        // Each block is read from its own snapshot, so writers aren't kept waiting for the whole range
+        logs.extend(block_logs(filter, block_number, &storage.snapshot())?);
+        check_results_limit(&logs, limits)?;
+    }
+    Ok(logs)
+}
+
+fn check_results_limit(logs: &[RpcLog], limits: LogsLimits) -> Result<(), RpcErr> {
+    if logs.len() > limits.max_results {
+        return Err(RpcErr::LimitExceeded(format!(
+            "query returned more than {} results",
+            limits.max_results
+        )));
+    }
+    Ok(())
+}
+
+/// Collects the logs of a canonical block matching the filter, reading the whole block from the given snapshot
+fn block_logs(
+    filter: &LogsFilter,
+    block_number: BlockNumber,
+    snapshot: &StoreSnapshot,
+) -> Result<Vec<RpcLog>, RpcErr> {
+    let Some(header) = snapshot.get_block_header(block_number)? else {
+        return Ok(vec![]);
+    };
+    // Avoid reading the block body if the block can't contain any matching log
+    if !filter.may_match(&header.logs_bloom) {
+        return Ok(vec![]);
+    }
+    let Some(body) = snapshot.get_block_body(block_number)? else {
+        return Ok(vec![]);
+    };
+    matching_logs(filter, &header, &body, false, |index| {
+        snapshot
+            .get_receipt(block_number, index)?
+            .ok_or(RpcErr::Internal)
+    })
+}
+
+/// Collects the logs of a block and its receipts matching the filter, flagging them as removed
+/// if the block is no longer part of the canonical chain.
+/// Fails if a transaction of the block has no receipt
+pub fn logs_in_block(
+    filter: &LogsFilter,
+    block: &Block,
+    receipts: &[Receipt],
+    removed: bool,
+) -> Result<Vec<RpcLog>, RpcErr> {
+    if !filter.may_match(&block.header.logs_bloom) {
+        return Ok(vec![]);
+    }
+    matching_logs(filter, &block.header, &block.body, removed, |index| {
+        receipts
+            .get(index as usize)
+            .cloned()
+            .ok_or(RpcErr::Internal)
+    })
+}
+
+/// Collects the matching logs of a block whose header bloom may contain them, using the receipt blooms to skip
+/// transactions that can't contain any of them
+fn matching_logs(
+    filter: &LogsFilter,
+    header: &BlockHeader,
+    body: &BlockBody,
+    removed: bool,
+    get_receipt: impl Fn(u64) -> Result<Receipt, RpcErr>,
+) -> Result<Vec<RpcLog>, RpcErr> {
+    let mut logs = vec![];
+    let block_hash = header.compute_block_hash();
+    // Log indexes are relative to the block, so non-matching logs must still be counted
+    let mut log_index = 0;
+    for (index, transaction) in body.transactions.iter().enumerate() {
+        let index = index as u64;
+        let receipt = get_receipt(index)?;
+        if filter.may_match(&receipt.bloom) {
+            let transaction_hash = transaction.compute_hash();
+            for (position, log) in receipt.logs.iter().enumerate() {
+                if filter.matches(log) {
+                    logs.push(RpcLog {
+                        log: log.clone().into(),
+                        log_index: log_index + position as u64,
+                        removed,
+                        transaction_hash,
+                        transaction_index: index,
+                        block_hash,
+                        block_number: header.number,
+                    });
+                }
+            }
+        }
+        log_index += receipt.logs.len() as u64;
+    }
+    Ok(logs)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_rust_core::{
+        types::{LegacyTransaction, Transaction, TxKind, TxType},
+        Bytes, U256,
+    };
+    use ethereum_rust_storage::EngineType;
+    use serde_json::json;
+
+    use super::*;
+
+    fn log(address: Address, topics: Vec<H256>) -> Log {
+        Log {
+            address,
+            topics,
+            data: Bytes::new(),
+        }
+    }
+
+    fn add_block_with_logs(storage: &Store, number: BlockNumber, logs: Vec<Vec<Log>>) {
+        let receipts: Vec<Receipt> = logs
+            .into_iter()
+            .map(|logs| Receipt::new(TxType::Legacy, true, 21_000, logs))
+            .collect();
+        let mut logs_bloom = Bloom::zero();
+        for receipt in receipts.iter() {
+            logs_bloom.accrue_bloom(&receipt.bloom);
+        }
+        let block = Block {
+            header: BlockHeader {
+                number,
+                logs_bloom,
+                ..Default::default()
+            },
+            body: BlockBody {
+                transactions: (0..receipts.len() as u64)
+                    .map(|nonce| {
+                        Transaction::LegacyTransaction(LegacyTransaction {
+                            nonce,
+                            gas_price: 0,
+                            gas: 21_000,
+                            to: TxKind::Create,
+                            value: U256::zero(),
+                            data: Bytes::new(),
+                            v: U256::zero(),
+                            r: U256::zero(),
+                            s: U256::zero(),
+                        })
+                    })
+                    .collect(),
+                ommers: vec![],
+                withdrawals: None,
+            },
+        };
+        for (index, receipt) in receipts.into_iter().enumerate() {
+            storage
+                .add_receipt(number, index as u64, receipt)
+                .expect("Failed to write to test DB");
+        }
+        storage
+            .add_block(block)
+            .expect("Failed to write to test DB");
+    }
+
+    fn query(storage: &Store, filter: Value, limits: LogsLimits) -> Result<Vec<RpcLog>, RpcErr> {
+        filter_logs(&LogsFilter::parse(filter)?, storage, limits)
+    }
+
+    #[test]
+    fn filter_logs_by_address_and_topics() {
+        let storage =
+            Store::new("temp.db", EngineType::InMemory).expect("Failed to create test DB");
+        let address_a = Address::from_low_u64_be(0xa);
+        let address_b = Address::from_low_u64_be(0xb);
+        let topic_1 = H256::from_low_u64_be(1);
+        let topic_2 = H256::from_low_u64_be(2);
+        add_block_with_logs(&storage, 0, vec![]);
+        add_block_with_logs(
+            &storage,
+            1,
+            vec![
+                vec![log(address_a, vec![topic_1]), log(address_b, vec![topic_2])],
+                vec![log(address_b, vec![topic_1, topic_2])],
+            ],
+        );
+        add_block_with_logs(&storage, 2, vec![vec![log(address_a, vec![topic_2])]]);
+        let limits = LogsLimits::default();
+
+        let logs = query(&storage, json!({ "fromBlock": "0x0" }), limits).unwrap();
+        assert_eq!(logs.len(), 4);
+
+        let logs = query(
+            &storage,
+            json!({ "fromBlock": "0x0", "address": address_b }),
+            limits,
+        )
+        .unwrap();
+        assert_eq!(logs.len(), 2);
+        assert_eq!((logs[0].log_index, logs[0].transaction_index), (1, 0));
+        assert_eq!((logs[1].log_index, logs[1].transaction_index), (2, 1));
+
+        let logs = query(
+            &storage,
+            json!({ "fromBlock": "0x0", "topics": [null, topic_2] }),
+            limits,
+        )
+        .unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].log.address, address_b);
+        assert_eq!(logs[0].block_number, 1);
+
+        let logs = query(
+            &storage,
+            json!({ "fromBlock": "0x0", "toBlock": "0x1", "topics": [[topic_1, topic_2]], "address": [address_a] }),
+            limits,
+        )
+        .unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].log.topics, vec![topic_1]);
+
+        let block_hash = storage
+            .get_block_header(2)
+            .unwrap()
+            .unwrap()
+            .compute_block_hash();
+        let logs = query(&storage, json!({ "blockHash": block_hash }), limits).unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].block_hash, block_hash);
+
+        assert!(query(
+            &storage,
+            json!({ "blockHash": block_hash, "fromBlock": "0x0" }),
+            limits
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn filter_logs_enforces_limits() {
+        let storage =
+            Store::new("temp.db", EngineType::InMemory).expect("Failed to create test DB");
+        let address = Address::from_low_u64_be(0xa);
+        for number in 0..4 {
+            add_block_with_logs(&storage, number, vec![vec![log(address, vec![])]]);
+        }
+        let limits = LogsLimits {
+            max_block_range: 2,
+            max_results: 2,
+        };
+        assert!(matches!(
+            query(&storage, json!({ "fromBlock": "0x0" }), limits),
+            Err(RpcErr::LimitExceeded(_))
+        ));
+        assert_eq!(
+            query(&storage, json!({ "fromBlock": "0x2" }), limits)
+                .unwrap()
+                .len(),
+            2
+        );
+        let limits = LogsLimits {
+            max_block_range: 4,
+            max_results: 3,
+        };
+        assert!(matches!(
+            query(&storage, json!({ "fromBlock": "0x0" }), limits),
+            Err(RpcErr::LimitExceeded(_))
+        ));
+    }
+}
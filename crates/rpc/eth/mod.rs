@@ -1,4 +1,8 @@
 pub(crate) mod account;
 pub(crate) mod block;
 pub(crate) mod client;
+pub(crate) mod fee_market;
+pub(crate) mod filter;
+pub(crate) mod logs;
+pub(crate) mod simulate;
 pub(crate) mod transaction;
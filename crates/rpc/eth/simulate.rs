@@ -0,0 +1,444 @@
+use ethereum_rust_core::{
+    serde_utils,
+    types::{
+        calc_excess_blob_gas, calculate_base_fee_per_gas, compute_receipts_root,
+        compute_transactions_root, compute_withdrawals_root, BlockBody, BlockHeader,
+        BlockOverrides, EIP1559Transaction, GenericTransaction, Receipt, StateOverride,
+        Transaction, TxType, INITIAL_BASE_FEE,
+    },
+    Address, Bloom, Bytes, H256, U256,
+};
+use ethereum_rust_evm::{selected_backend, BlockSimulation, EvmError, ExecutionResult};
+use ethereum_rust_storage::{Store, WriteBatch};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::info;
+
+use crate::{
+    eth::transaction::state_root_for_block,
+    types::{
+        block::RpcBlock,
+        block_identifier::BlockIdentifier,
+        receipt::{RpcLog, RpcReceipt, RpcReceiptBlockInfo, RpcReceiptTxInfo},
+        transaction::RpcTransaction,
+    },
+    utils::RpcErr,
+    RpcHandler,
+};
+
+/// Maximum amount of blocks a single simulation can span
+const MAX_SIMULATED_BLOCKS: usize = 256;
+/// Time between simulated blocks whose timestamp is not overridden
+const SIMULATED_BLOCK_TIME: u64 = 12;
+
+// Error codes given by the spec to each of the reasons a simulation can fail
+const INVALID_TRANSACTION_CODE: i32 = -32000;
+const BLOCK_GAS_LIMIT_REACHED_CODE: i32 = -38015;
+const BLOCK_NUMBER_INVALID_CODE: i32 = -38020;
+const BLOCK_TIMESTAMP_INVALID_CODE: i32 = -38021;
+const CLIENT_LIMIT_EXCEEDED_CODE: i32 = -38026;
+// Error codes of the calls that failed within a successful simulation
+const REVERTED_CALL_CODE: i32 = 3;
+const HALTED_CALL_CODE: i32 = -32015;
+
+pub struct SimulateV1Request {
+    block_state_calls: Vec<BlockStateCalls>,
+    trace_transfers: bool,
+    validation: bool,
+    return_full_transactions: bool,
+    block: BlockIdentifier,
+}
+
+/// Calls made in one of the simulated blocks, along with the overrides applied before making them
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct BlockStateCalls {
+    #[serde(default)]
+    block_overrides: BlockOverrides,
+    #[serde(default)]
+    state_overrides: StateOverride,
+    // Kept as values to tell which calls were given a nonce, as a missing one is taken from the state
+    #[serde(default)]
+    calls: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SimulationPayload {
+    block_state_calls: Vec<BlockStateCalls>,
+    #[serde(default)]
+    trace_transfers: bool,
+    #[serde(default)]
+    validation: bool,
+    #[serde(default)]
+    return_full_transactions: bool,
+}
+
+#[derive(Serialize)]
+struct SimulatedBlock {
+    #[serde(flatten)]
+    block: RpcBlock,
+    calls: Vec<SimulatedCallResult>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SimulatedCallResult {
+    #[serde(with = "serde_utils::bool")]
+    status: bool,
+    #[serde(with = "serde_utils::bytes")]
+    return_data: Bytes,
+    #[serde(with = "serde_utils::u64::hex_str")]
+    gas_used: u64,
+    logs: Vec<RpcLog>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<SimulatedCallError>,
+}
+
+#[derive(Serialize)]
+struct SimulatedCallError {
+    code: i32,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+}
+
+/// State shared by the blocks of a simulation
+struct Simulation {
+    storage: Store,
+    chain_id: u64,
+    state: Box<dyn BlockSimulation>,
+    /// Holds the tries of the simulated states, which are built on top of the base block's one
+    batch: WriteBatch,
+    base_state_root: H256,
+}
+
+/// Call of a simulated block, along with whether its nonce was given or has to be taken from the state
+struct SimulatedCall {
+    transaction: GenericTransaction,
+    nonce_given: bool,
+}
+
+impl RpcHandler for SimulateV1Request {
+    fn parse(params: &Option<Vec<Value>>) -> Result<SimulateV1Request, RpcErr> {
+        let params = params.as_ref().ok_or(RpcErr::BadParams)?;
+        if params.is_empty() || params.len() > 2 {
+            return Err(RpcErr::BadParams);
+        };
+        let payload: SimulationPayload = serde_json::from_value(params[0].clone())?;
+        let block = match params.get(1) {
+            Some(value) => BlockIdentifier::parse(value.clone(), 1)?,
+            None => BlockIdentifier::default(),
+        };
+        Ok(SimulateV1Request {
+            block_state_calls: payload.block_state_calls,
+            trace_transfers: payload.trace_transfers,
+            validation: payload.validation,
+            return_full_transactions: payload.return_full_transactions,
+            block,
+        })
+    }
+
+    fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        info!(
+            "Requested simulation of {} blocks on top of block: {}",
+            self.block_state_calls.len(),
+            self.block
+        );
+        check_simulated_blocks(self.block_state_calls.len())?;
+        let Some(base_header) = self.block.resolve_block_header(&storage.snapshot())? else {
+            return Ok(Value::Null);
+        };
+        // Every simulated block is executed on top of the state left by the previous one
+        let mut simulation = Simulation {
+            chain_id: storage.get_chain_config()?.chain_id,
+            state: selected_backend().simulation(
+                storage.clone(),
+                state_root_for_block(&base_header, &storage)?,
+            )?,
+            batch: storage.write_batch(),
+            base_state_root: base_header.state_root,
+            storage,
+        };
+        let mut parent = base_header;
+        let mut blocks = Vec::with_capacity(self.block_state_calls.len());
+        let empty_calls = BlockStateCalls::default();
+        for block_calls in &self.block_state_calls {
+            // Blocks skipped by a number override are simulated without any calls
+            let skipped_blocks = block_calls
+                .block_overrides
+                .number
+                .map_or(0, |number| number.saturating_sub(parent.number + 1));
+            for _ in 0..skipped_blocks {
+                let (block, header) =
+                    self.simulate_block(&mut simulation, &empty_calls, &parent)?;
+                blocks.push(block);
+                parent = header;
+                check_simulated_blocks(blocks.len() + 1)?;
+            }
+            let (block, header) = self.simulate_block(&mut simulation, block_calls, &parent)?;
+            blocks.push(block);
+            parent = header;
+        }
+        serde_json::to_value(blocks).map_err(|_| RpcErr::Internal)
+    }
+}
+
+impl SimulateV1Request {
+    /// Executes the calls of a simulated block on top of its parent, returning the block along with its header
+    fn simulate_block(
+        &self,
+        simulation: &mut Simulation,
+        block_calls: &BlockStateCalls,
+        parent: &BlockHeader,
+    ) -> Result<(SimulatedBlock, BlockHeader), RpcErr> {
+        let state = simulation.state.as_mut();
+        let mut header = simulated_header(parent, &block_calls.block_overrides, self.validation)?;
+        let spec_id = ethereum_rust_evm::spec_id(&simulation.storage, header.timestamp)?;
+        state.apply_state_override(&block_calls.state_overrides)?;
+
+        let mut transactions = Vec::with_capacity(block_calls.calls.len());
+        let mut senders = Vec::with_capacity(block_calls.calls.len());
+        let mut receipts = Vec::with_capacity(block_calls.calls.len());
+        let mut results = Vec::with_capacity(block_calls.calls.len());
+        let mut cumulative_gas_used = 0;
+        for call in &block_calls.calls {
+            let SimulatedCall {
+                mut transaction,
+                nonce_given,
+            } = parse_call(call)?;
+            if !nonce_given {
+                transaction.nonce = state.account_nonce(transaction.from)?;
+            }
+            let remaining_gas = header.gas_limit.saturating_sub(cumulative_gas_used);
+            let gas_limit = transaction.gas.unwrap_or(remaining_gas);
+            if self.validation && gas_limit > remaining_gas {
+                return Err(RpcErr::Simulation {
+                    code: BLOCK_GAS_LIMIT_REACHED_CODE,
+                    message: format!("block gas limit reached: {gas_limit} > {remaining_gas}"),
+                });
+            }
+            transaction.gas = Some(gas_limit);
+            transaction.chain_id.get_or_insert(simulation.chain_id);
+
+            let result = state
+                .simulate_tx(
+                    &transaction,
+                    &header,
+                    spec_id,
+                    self.validation,
+                    self.trace_transfers,
+                )
+                .map_err(|err| match err {
+                    EvmError::Transaction(message) => RpcErr::Simulation {
+                        code: INVALID_TRANSACTION_CODE,
+                        message,
+                    },
+                    err => err.into(),
+                })?;
+            cumulative_gas_used += result.gas_used();
+            receipts.push(Receipt::new(
+                TxType::EIP1559,
+                result.is_success(),
+                cumulative_gas_used,
+                result.logs(),
+            ));
+            senders.push(transaction.from);
+            transactions.push(unsigned_transaction(transaction));
+            results.push(result);
+        }
+
+        // The tries of the simulated state are built in a batch that is never committed
+        let state_diff = state.state_transitions()?;
+        header.state_root = simulation
+            .batch
+            .apply_state_diff_at(simulation.base_state_root, &state_diff)?;
+        header.gas_used = cumulative_gas_used;
+        header.transactions_root = compute_transactions_root(&transactions);
+        header.receipts_root = compute_receipts_root(&receipts);
+        header.logs_bloom = receipts
+            .iter()
+            .fold(Bloom::zero(), |bloom, receipt| bloom | receipt.bloom);
+        let block_hash = header.compute_block_hash();
+        state.add_block_hash(header.number, block_hash);
+        let block_info = RpcReceiptBlockInfo::from_block_header(header.clone());
+
+        let mut calls = Vec::with_capacity(results.len());
+        let mut log_index = 0;
+        for (index, (result, receipt)) in results.into_iter().zip(receipts).enumerate() {
+            let tx_info = RpcReceiptTxInfo::from_transaction_with_sender(
+                transactions[index].clone(),
+                senders[index],
+                index as u64,
+                result.gas_used(),
+                0,
+            );
+            let logs_len = receipt.logs.len() as u64;
+            let rpc_receipt = RpcReceipt::new(receipt, tx_info, block_info.clone(), log_index);
+            log_index += logs_len;
+            calls.push(call_result(result, rpc_receipt.logs));
+        }
+
+        let body = BlockBody {
+            transactions: transactions.clone(),
+            ommers: vec![],
+            withdrawals: parent.withdrawals_root.map(|_| vec![]),
+        };
+        let block = if self.return_full_transactions {
+            let transactions = transactions
+                .into_iter()
+                .zip(senders)
+                .enumerate()
+                .map(|(index, (transaction, from))| {
+                    RpcTransaction::build_with_sender(
+                        transaction,
+                        from,
+                        header.number,
+                        block_hash,
+                        index,
+                    )
+                })
+                .collect();
+            RpcBlock::build_with_transactions(header.clone(), body, block_hash, transactions)
+        } else {
+            RpcBlock::build(header.clone(), body, block_hash, false)
+        };
+        Ok((SimulatedBlock { block, calls }, header))
+    }
+}
+
+/// Fails if a simulation spans more blocks than allowed, counting the ones skipped between the given ones
+fn check_simulated_blocks(simulated_blocks: usize) -> Result<(), RpcErr> {
+    if simulated_blocks > MAX_SIMULATED_BLOCKS {
+        return Err(RpcErr::Simulation {
+            code: CLIENT_LIMIT_EXCEEDED_CODE,
+            message: format!("too many blocks, the limit is {MAX_SIMULATED_BLOCKS}"),
+        });
+    }
+    Ok(())
+}
+
+/// Builds the header of a simulated block on top of its parent, leaving the fields that depend on its calls empty
+fn simulated_header(
+    parent: &BlockHeader,
+    overrides: &BlockOverrides,
+    validation: bool,
+) -> Result<BlockHeader, RpcErr> {
+    let number = overrides.number.unwrap_or(parent.number + 1);
+    if number <= parent.number {
+        return Err(RpcErr::Simulation {
+            code: BLOCK_NUMBER_INVALID_CODE,
+            message: format!(
+                "block numbers must be in order: {number} <= {}",
+                parent.number
+            ),
+        });
+    }
+    let timestamp = overrides
+        .time
+        .unwrap_or(parent.timestamp + SIMULATED_BLOCK_TIME);
+    if timestamp <= parent.timestamp {
+        return Err(RpcErr::Simulation {
+            code: BLOCK_TIMESTAMP_INVALID_CODE,
+            message: format!(
+                "block timestamps must be in order: {timestamp} <= {}",
+                parent.timestamp
+            ),
+        });
+    }
+    let gas_limit = overrides.gas_limit.unwrap_or(parent.gas_limit);
+    let parent_base_fee = parent.base_fee_per_gas.unwrap_or(INITIAL_BASE_FEE);
+    // Fees are only charged when validating
+    let base_fee_per_gas = match overrides.base_fee {
+        Some(base_fee) => base_fee,
+        None if validation => calculate_base_fee_per_gas(
+            gas_limit,
+            parent.gas_limit,
+            parent.gas_used,
+            parent_base_fee,
+        )
+        .unwrap_or(parent_base_fee),
+        None => 0,
+    };
+    Ok(BlockHeader {
+        parent_hash: parent.compute_block_hash(),
+        coinbase: overrides.coinbase.unwrap_or(parent.coinbase),
+        state_root: H256::zero(),
+        number,
+        gas_limit,
+        gas_used: 0,
+        timestamp,
+        base_fee_per_gas: Some(base_fee_per_gas),
+        withdrawals_root: parent
+            .withdrawals_root
+            .map(|_| compute_withdrawals_root(&[])),
+        blob_gas_used: parent.blob_gas_used.map(|_| 0),
+        excess_blob_gas: parent.excess_blob_gas.map(|_| calc_excess_blob_gas(parent)),
+        ..parent.clone()
+    })
+}
+
+/// Parses one of the calls of a simulated block, which is sent from the zero address if no sender is given
+fn parse_call(call: &Value) -> Result<SimulatedCall, RpcErr> {
+    let mut call = call.clone();
+    let Value::Object(fields) = &mut call else {
+        return Err(RpcErr::BadParams);
+    };
+    let nonce_given = fields.contains_key("nonce");
+    fields
+        .entry("from")
+        .or_insert_with(|| serde_json::json!(Address::zero()));
+    Ok(SimulatedCall {
+        transaction: serde_json::from_value(call)?,
+        nonce_given,
+    })
+}
+
+/// Builds the transaction included in the simulated block for the given call, which is left unsigned
+fn unsigned_transaction(transaction: GenericTransaction) -> Transaction {
+    Transaction::EIP1559Transaction(EIP1559Transaction {
+        chain_id: transaction.chain_id.unwrap_or_default(),
+        nonce: transaction.nonce,
+        max_priority_fee_per_gas: transaction
+            .max_priority_fee_per_gas
+            .unwrap_or(transaction.gas_price),
+        max_fee_per_gas: transaction.max_fee_per_gas.unwrap_or(transaction.gas_price),
+        gas_limit: transaction.gas.unwrap_or_default(),
+        to: transaction.to,
+        value: transaction.value,
+        data: transaction.input,
+        access_list: transaction
+            .access_list
+            .into_iter()
+            .map(|entry| (entry.address, entry.storage_keys))
+            .collect(),
+        signature_y_parity: false,
+        signature_r: U256::zero(),
+        signature_s: U256::zero(),
+    })
+}
+
+fn call_result(result: ExecutionResult, logs: Vec<RpcLog>) -> SimulatedCallResult {
+    let gas_used = result.gas_used();
+    let return_data = result.output();
+    let error = match &result {
+        ExecutionResult::Success { .. } => None,
+        ExecutionResult::Revert { output, .. } => Some(SimulatedCallError {
+            code: REVERTED_CALL_CODE,
+            message: "execution reverted".to_string(),
+            data: Some(format!("0x{:x}", output)),
+        }),
+        ExecutionResult::Halt { reason, .. } => Some(SimulatedCallError {
+            code: HALTED_CALL_CODE,
+            message: reason.clone(),
+            data: None,
+        }),
+    };
+    SimulatedCallResult {
+        status: result.is_success(),
+        return_data,
+        gas_used,
+        logs,
+        error,
+    }
+}
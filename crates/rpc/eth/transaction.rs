@@ -6,13 +6,17 @@ use crate::{
 };
 use ethereum_rust_core::{
     rlp::encode::RLPEncode,
-    types::{AccessListEntry, BlockHash, BlockHeader, GenericTransaction, TxKind},
+    types::{
+        AccessListEntry, BlockHash, BlockHeader, BlockOverrides, GenericTransaction, StateOverride,
+        Transaction, TxKind, TxType, WrappedEIP4844Transaction,
+    },
     H256, U256,
 };
 
+use ethereum_rust_chain::mempool;
 use ethereum_rust_storage::Store;
 
-use ethereum_rust_evm::{evm_state, ExecutionResult, SpecId};
+use ethereum_rust_evm::{selected_backend, ExecutionResult, SpecId};
 use serde::Serialize;
 
 use serde_json::Value;
@@ -25,6 +29,8 @@ pub const TRANSACTION_GAS: u64 = 21_000; // Per transaction not creating a contr
 pub struct CallRequest {
     transaction: GenericTransaction,
     block: Option<BlockIdentifier>,
+    state_override: StateOverride,
+    block_overrides: BlockOverrides,
 }
 
 pub struct GetTransactionByBlockNumberAndIndexRequest {
@@ -52,12 +58,20 @@ pub struct CreateAccessListRequest {
 pub struct EstimateGasRequest {
     pub transaction: GenericTransaction,
     pub block: Option<BlockIdentifier>,
+    pub state_override: StateOverride,
+    pub block_overrides: BlockOverrides,
 }
 
 pub struct GetRawTransaction {
     pub transaction_hash: H256,
 }
 
+pub enum SendRawTransactionRequest {
+    Transaction(Transaction),
+    /// Blob transactions are sent along with their sidecar
+    EIP4844(WrappedEIP4844Transaction),
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AccessListResult {
@@ -71,7 +85,7 @@ pub struct AccessListResult {
 impl RpcHandler for CallRequest {
     fn parse(params: &Option<Vec<Value>>) -> Result<CallRequest, RpcErr> {
         let params = params.as_ref().ok_or(RpcErr::BadParams)?;
-        if params.is_empty() || params.len() > 2 {
+        if params.is_empty() || params.len() > 4 {
             return Err(RpcErr::BadParams);
         };
         let block = match params.get(1) {
@@ -79,21 +93,31 @@ impl RpcHandler for CallRequest {
             Some(value) => Some(BlockIdentifier::parse(value.clone(), 1)?),
             None => None,
         };
+        let (state_override, block_overrides) = parse_overrides(params)?;
         Ok(CallRequest {
             transaction: serde_json::from_value(params[0].clone())?,
             block,
+            state_override,
+            block_overrides,
         })
     }
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
         let block = self.block.clone().unwrap_or_default();
         info!("Requested call on block: {}", block);
-        let header = match block.resolve_block_header(&storage)? {
+        let header = match block.resolve_block_header(&storage.snapshot())? {
             Some(header) => header,
             // Block not found
             _ => return Ok(Value::Null),
         };
         // Run transaction
-        let result = simulate_tx(&self.transaction, &header, storage, SpecId::CANCUN)?;
+        let result = simulate_tx(
+            &self.transaction,
+            &header,
+            storage,
+            SpecId::CANCUN,
+            &self.state_override,
+            &self.block_overrides,
+        )?;
         serde_json::to_value(format!("0x{:#x}", result.output())).map_err(|_| RpcErr::Internal)
     }
 }
@@ -115,19 +139,20 @@ impl RpcHandler for GetTransactionByBlockNumberAndIndexRequest {
     }
 
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        let snapshot = storage.snapshot();
         info!(
             "Requested transaction at index: {} of block with number: {}",
             self.transaction_index, self.block,
         );
-        let block_number = match self.block.resolve_block_number(&storage)? {
+        let block_number = match self.block.resolve_block_number(&snapshot)? {
             Some(block_number) => block_number,
             _ => return Ok(Value::Null),
         };
-        let block_body = match storage.get_block_body(block_number)? {
+        let block_body = match snapshot.get_block_body(block_number)? {
             Some(block_body) => block_body,
             _ => return Ok(Value::Null),
         };
-        let block_header = match storage.get_block_header(block_number)? {
+        let block_header = match snapshot.get_block_header(block_number)? {
             Some(block_body) => block_body,
             _ => return Ok(Value::Null),
         };
@@ -161,15 +186,16 @@ impl RpcHandler for GetTransactionByBlockHashAndIndexRequest {
         })
     }
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        let snapshot = storage.snapshot();
         info!(
             "Requested transaction at index: {} of block with hash: {}",
             self.transaction_index, self.block,
         );
-        let block_number = match storage.get_block_number(self.block)? {
+        let block_number = match snapshot.get_block_number(self.block)? {
             Some(number) => number,
             _ => return Ok(Value::Null),
         };
-        let block_body = match storage.get_block_body(block_number)? {
+        let block_body = match snapshot.get_block_body(block_number)? {
             Some(block_body) => block_body,
             _ => return Ok(Value::Null),
         };
@@ -194,17 +220,19 @@ impl RpcHandler for GetTransactionByHashRequest {
         })
     }
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        let snapshot = storage.snapshot();
         info!("Requested transaction with hash: {}", self.transaction_hash,);
         let transaction: ethereum_rust_core::types::Transaction =
-            match storage.get_transaction_by_hash(self.transaction_hash)? {
+            match snapshot.get_transaction_by_hash(self.transaction_hash)? {
                 Some(transaction) => transaction,
                 _ => return Ok(Value::Null),
             };
-        let (block_number, index) = match storage.get_transaction_location(self.transaction_hash)? {
-            Some(location) => location,
-            _ => return Ok(Value::Null),
-        };
-        let block_header = match storage.get_block_header(block_number)? {
+        let (block_number, index) =
+            match snapshot.get_transaction_location(self.transaction_hash)? {
+                Some(location) => location,
+                _ => return Ok(Value::Null),
+            };
+        let block_header = match snapshot.get_block_header(block_number)? {
             Some(header) => header,
             _ => return Ok(Value::Null),
         };
@@ -226,24 +254,26 @@ impl RpcHandler for GetTransactionReceiptRequest {
         })
     }
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        let snapshot = storage.snapshot();
         info!(
             "Requested receipt for transaction {}",
             self.transaction_hash,
         );
-        let (block_number, index) = match storage.get_transaction_location(self.transaction_hash)? {
-            Some(location) => location,
-            _ => return Ok(Value::Null),
-        };
-        let block_header = match storage.get_block_header(block_number)? {
+        let (block_number, index) =
+            match snapshot.get_transaction_location(self.transaction_hash)? {
+                Some(location) => location,
+                _ => return Ok(Value::Null),
+            };
+        let block_header = match snapshot.get_block_header(block_number)? {
             Some(block_header) => block_header,
             _ => return Ok(Value::Null),
         };
-        let block_body = match storage.get_block_body(block_number)? {
+        let block_body = match snapshot.get_block_body(block_number)? {
             Some(block_body) => block_body,
             _ => return Ok(Value::Null),
         };
         let receipts =
-            block::get_all_block_rpc_receipts(block_number, block_header, block_body, &storage)?;
+            block::get_all_block_rpc_receipts(block_number, block_header, block_body, &snapshot)?;
         serde_json::to_value(receipts.get(index as usize)).map_err(|_| RpcErr::Internal)
     }
 }
@@ -267,7 +297,7 @@ impl RpcHandler for CreateAccessListRequest {
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
         let block = self.block.clone().unwrap_or_default();
         info!("Requested access list creation for tx on block: {}", block);
-        let block_number = match block.resolve_block_number(&storage)? {
+        let block_number = match block.resolve_block_number(&storage.snapshot())? {
             Some(block_number) => block_number,
             _ => return Ok(Value::Null),
         };
@@ -277,10 +307,12 @@ impl RpcHandler for CreateAccessListRequest {
             _ => return Ok(Value::Null),
         };
         // Run transaction and obtain access list
-        let (gas_used, access_list, error) = match ethereum_rust_evm::create_access_list(
+        let state_root = state_root_for_block(&header, &storage)?;
+        let (gas_used, access_list, error) = match selected_backend().create_access_list(
             &self.transaction,
             &header,
-            &mut evm_state(storage),
+            storage,
+            state_root,
             SpecId::CANCUN,
         )? {
             (
@@ -354,10 +386,50 @@ impl RpcHandler for GetRawTransaction {
     }
 }
 
+impl RpcHandler for SendRawTransactionRequest {
+    fn parse(params: &Option<Vec<Value>>) -> Result<Self, RpcErr> {
+        let params = params.as_ref().ok_or(RpcErr::BadParams)?;
+        if params.len() != 1 {
+            return Err(RpcErr::BadParams);
+        };
+
+        let transaction_str: String = serde_json::from_value(params[0].clone())?;
+        if !transaction_str.starts_with("0x") {
+            return Err(RpcErr::BadHexFormat(0));
+        }
+        let transaction_bytes =
+            hex::decode(&transaction_str[2..]).map_err(|_| RpcErr::BadParams)?;
+        let request = match transaction_bytes.first() {
+            Some(tx_type) if *tx_type == TxType::EIP4844 as u8 => {
+                WrappedEIP4844Transaction::decode_canonical(&transaction_bytes)
+                    .map(SendRawTransactionRequest::EIP4844)
+            }
+            _ => Transaction::decode_canonical(&transaction_bytes)
+                .map(SendRawTransactionRequest::Transaction),
+        };
+        request.map_err(|_| RpcErr::BadParams)
+    }
+
+    fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
+        let hash = match self {
+            SendRawTransactionRequest::Transaction(transaction) => {
+                mempool::add_transaction(transaction.clone(), &storage)?
+            }
+            SendRawTransactionRequest::EIP4844(wrapped) => mempool::add_blob_transaction(
+                wrapped.tx.clone(),
+                wrapped.blobs_bundle.clone(),
+                &storage,
+            )?,
+        };
+        info!("Added transaction {hash:#x} to the mempool");
+        serde_json::to_value(format!("{:#x}", hash)).map_err(|_| RpcErr::Internal)
+    }
+}
+
 impl RpcHandler for EstimateGasRequest {
     fn parse(params: &Option<Vec<Value>>) -> Result<EstimateGasRequest, RpcErr> {
         let params = params.as_ref().ok_or(RpcErr::BadParams)?;
-        if params.is_empty() || params.len() > 2 {
+        if params.is_empty() || params.len() > 4 {
             return Err(RpcErr::BadParams);
         };
         let block = match params.get(1) {
@@ -365,26 +437,34 @@ impl RpcHandler for EstimateGasRequest {
             Some(value) => Some(BlockIdentifier::parse(value.clone(), 1)?),
             None => None,
         };
+        let (state_override, block_overrides) = parse_overrides(params)?;
         Ok(EstimateGasRequest {
             transaction: serde_json::from_value(params[0].clone())?,
             block,
+            state_override,
+            block_overrides,
         })
     }
     fn handle(&self, storage: Store) -> Result<Value, RpcErr> {
         let block = self.block.clone().unwrap_or_default();
         info!("Requested estimate on block: {}", block);
-        let block_header = match block.resolve_block_header(&storage)? {
+        let block_header = match block.resolve_block_header(&storage.snapshot())? {
             Some(header) => header,
             // Block not found
             _ => return Ok(Value::Null),
         };
-        let spec_id = ethereum_rust_evm::spec_id(&storage, block_header.timestamp)?;
+        let overridden_header = self.block_overrides.apply(&block_header);
+        let spec_id = ethereum_rust_evm::spec_id(&storage, overridden_header.timestamp)?;
 
         // If the transaction is a plain value transfer, short circuit estimation.
         if let TxKind::Call(address) = self.transaction.to {
             let account_info = storage.get_account_info(address)?;
             let code = account_info.map(|info| storage.get_account_code(info.code_hash));
-            if code.is_none() {
+            let code_overridden = self
+                .state_override
+                .get(&address)
+                .is_some_and(|account_override| account_override.code.is_some());
+            if code.is_none() && !code_overridden {
                 let mut value_transfer_transaction = self.transaction.clone();
                 value_transfer_transaction.gas = Some(TRANSACTION_GAS);
                 let result: Result<ExecutionResult, RpcErr> = simulate_tx(
@@ -392,6 +472,8 @@ impl RpcHandler for EstimateGasRequest {
                     &block_header,
                     storage.clone(),
                     spec_id,
+                    &self.state_override,
+                    &self.block_overrides,
                 );
                 if let Ok(ExecutionResult::Success { .. }) = result {
                     return serde_json::to_value(format!("{:#x}", TRANSACTION_GAS))
@@ -402,19 +484,30 @@ impl RpcHandler for EstimateGasRequest {
 
         // Prepare binary search
         let mut highest_gas_limit = match self.transaction.gas {
-            Some(gas) => gas.min(block_header.gas_limit),
-            None => block_header.gas_limit,
+            Some(gas) => gas.min(overridden_header.gas_limit),
+            None => overridden_header.gas_limit,
         };
 
         if self.transaction.gas_price != 0 {
-            highest_gas_limit =
-                recap_with_account_balances(highest_gas_limit, &self.transaction, &storage)?;
+            highest_gas_limit = recap_with_account_balances(
+                highest_gas_limit,
+                &self.transaction,
+                &storage,
+                &self.state_override,
+            )?;
         }
 
         // Check whether the execution is possible
         let mut transaction = self.transaction.clone();
         transaction.gas = Some(highest_gas_limit);
-        let result = simulate_tx(&transaction, &block_header, storage.clone(), spec_id)?;
+        let result = simulate_tx(
+            &transaction,
+            &block_header,
+            storage.clone(),
+            spec_id,
+            &self.state_override,
+            &self.block_overrides,
+        )?;
 
         let gas_used = result.gas_used();
         let gas_refunded = result.gas_refunded();
@@ -437,7 +530,14 @@ impl RpcHandler for EstimateGasRequest {
             }
             transaction.gas = Some(middle_gas_limit);
 
-            let result = simulate_tx(&transaction, &block_header, storage.clone(), spec_id);
+            let result = simulate_tx(
+                &transaction,
+                &block_header,
+                storage.clone(),
+                spec_id,
+                &self.state_override,
+                &self.block_overrides,
+            );
             if let Ok(ExecutionResult::Success { .. }) = result {
                 highest_gas_limit = middle_gas_limit;
             } else {
@@ -454,27 +554,60 @@ fn recap_with_account_balances(
     highest_gas_limit: u64,
     transaction: &GenericTransaction,
     storage: &Store,
+    state_override: &StateOverride,
 ) -> Result<u64, RpcErr> {
-    let account_balance = storage
-        .get_account_info(transaction.from)?
-        .map(|acc| acc.balance)
-        .unwrap_or_default();
+    let overridden_balance = state_override
+        .get(&transaction.from)
+        .and_then(|account_override| account_override.balance);
+    let account_balance = match overridden_balance {
+        Some(balance) => balance,
+        None => storage
+            .get_account_info(transaction.from)?
+            .map(|acc| acc.balance)
+            .unwrap_or_default(),
+    };
     let account_gas =
         account_balance.saturating_sub(transaction.value) / U256::from(transaction.gas_price);
     Ok(highest_gas_limit.min(account_gas.as_u64()))
 }
 
+/// Parses the optional state override and block overrides given after the block identifier
+fn parse_overrides(params: &[Value]) -> Result<(StateOverride, BlockOverrides), RpcErr> {
+    let state_override: StateOverride = match params.get(2) {
+        Some(value) if !value.is_null() => serde_json::from_value(value.clone())?,
+        _ => StateOverride::default(),
+    };
+    if !state_override
+        .values()
+        .all(|account_override| account_override.is_valid())
+    {
+        return Err(RpcErr::BadParams);
+    }
+    let block_overrides = match params.get(3) {
+        Some(value) if !value.is_null() => serde_json::from_value(value.clone())?,
+        _ => BlockOverrides::default(),
+    };
+    Ok((state_override, block_overrides))
+}
+
+/// Runs the transaction on top of the given block, with the state and block fields replaced by the given overrides
 fn simulate_tx(
     transaction: &GenericTransaction,
     block_header: &BlockHeader,
     storage: Store,
     spec_id: SpecId,
+    state_override: &StateOverride,
+    block_overrides: &BlockOverrides,
 ) -> Result<ExecutionResult, RpcErr> {
-    match ethereum_rust_evm::simulate_tx_from_generic(
+    // The state is the one left by the block, regardless of the number it is overridden with
+    let state_root = state_root_for_block(block_header, &storage)?;
+    match selected_backend().simulate_tx(
         transaction,
-        block_header,
-        &mut evm_state(storage),
+        &block_overrides.apply(block_header),
+        storage,
+        state_root,
         spec_id,
+        state_override,
     )? {
         ExecutionResult::Revert {
             gas_used: _,
@@ -486,3 +619,19 @@ fn simulate_tx(
         success => Ok(success),
     }
 }
+
+/// Returns the root of the world state left by the given block, or None if it is the current state.
+/// The current state is read directly, the state of blocks older than the latest one is read from their world state trie
+pub(crate) fn state_root_for_block(
+    block_header: &BlockHeader,
+    storage: &Store,
+) -> Result<Option<H256>, RpcErr> {
+    let latest = storage.get_latest_block_number()?;
+    if !matches!(latest, Some(latest) if block_header.number < latest) {
+        return Ok(None);
+    }
+    let state_root = storage
+        .get_state_root_for_block(block_header.number)?
+        .ok_or_else(|| RpcErr::StateUnavailable(block_header.number.to_string()))?;
+    Ok(Some(state_root))
+}
@@ -0,0 +1,8 @@
+use ethereum_rust_net::peer_manager::PeerManager;
+use serde_json::Value;
+
+use crate::utils::RpcErr;
+
+pub fn peer_count(peer_manager: &PeerManager) -> Result<Value, RpcErr> {
+    serde_json::to_value(format!("{:#x}", peer_manager.peer_count())).map_err(|_| RpcErr::Internal)
+}
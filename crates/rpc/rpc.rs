@@ -2,52 +2,94 @@ use crate::authentication::authenticate;
 use bytes::Bytes;
 use std::{future::IntoFuture, net::SocketAddr};
 
-use axum::{routing::post, Json, Router};
+use axum::{
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
 use axum_extra::{
     headers::{authorization::Bearer, Authorization},
     TypedHeader,
 };
+use debug::trace::{
+    TraceBlockByHashRequest, TraceBlockByNumberRequest, TraceCallRequest, TraceTransactionRequest,
+};
 use engine::{
     exchange_transition_config::ExchangeTransitionConfigV1Req,
     fork_choice::{self, ForkChoiceUpdatedV3},
-    payload::{self, NewPayloadV3Request},
+    payload::{self, GetBlobsV1Request, GetPayloadV3Request, NewPayloadV3Request},
     ExchangeCapabilitiesRequest,
 };
 use eth::{
-    account::{GetBalanceRequest, GetCodeRequest, GetStorageAtRequest, GetTransactionCountRequest},
+    account::{
+        GetBalanceRequest, GetCodeRequest, GetProofRequest, GetStorageAtRequest,
+        GetTransactionCountRequest,
+    },
     block::{
         self, GetBlockByHashRequest, GetBlockByNumberRequest, GetBlockReceiptsRequest,
         GetBlockTransactionCountRequest, GetRawBlockRequest, GetRawHeaderRequest, GetRawReceipts,
     },
     client,
+    fee_market::{self, FeeHistoryRequest, GasPriceOracle},
+    filter::{self, ActiveFilters, FilterIdRequest, NewFilterRequest},
+    logs::{self, GetLogsRequest},
+    simulate::SimulateV1Request,
     transaction::{
         CallRequest, CreateAccessListRequest, EstimateGasRequest, GetRawTransaction,
         GetTransactionByBlockHashAndIndexRequest, GetTransactionByBlockNumberAndIndexRequest,
-        GetTransactionByHashRequest, GetTransactionReceiptRequest,
+        GetTransactionByHashRequest, GetTransactionReceiptRequest, SendRawTransactionRequest,
     },
 };
+use serde::Deserialize;
 use serde_json::Value;
 use tokio::net::TcpListener;
 use tracing::info;
 use utils::{
-    RpcErr, RpcErrorMetadata, RpcErrorResponse, RpcNamespace, RpcRequest, RpcSuccessResponse,
+    RpcErr, RpcErrorMetadata, RpcErrorResponse, RpcNamespace, RpcRequest, RpcRequestId,
+    RpcSuccessResponse,
 };
 mod admin;
 mod authentication;
+mod debug;
 mod engine;
 mod eth;
+mod net;
 mod types;
 mod utils;
+mod websocket;
 
 use axum::extract::State;
-use ethereum_rust_net::types::Node;
+use ethereum_rust_net::{peer_manager::PeerManager, sync::SyncManager};
 use ethereum_rust_storage::Store;
 
+pub use eth::logs::LogsLimits;
+
+/// Limits applied to requests so that a single client can't exhaust the node
+#[derive(Debug, Clone, Copy)]
+pub struct RpcLimits {
+    /// Maximum amount of requests in a batch
+    pub max_batch_size: usize,
+    pub logs: LogsLimits,
+}
+
+impl Default for RpcLimits {
+    fn default() -> Self {
+        Self {
+            max_batch_size: 1000,
+            logs: LogsLimits::default(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RpcApiContext {
     storage: Store,
     jwt_secret: Bytes,
-    local_p2p_node: Node,
+    peer_manager: PeerManager,
+    syncer: SyncManager,
+    limits: RpcLimits,
+    filters: ActiveFilters,
+    gas_price_oracle: GasPriceOracle,
 }
 
 trait RpcHandler: Sized {
@@ -61,23 +103,43 @@ trait RpcHandler: Sized {
     fn handle(&self, storage: Store) -> Result<Value, RpcErr>;
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn start_api(
     http_addr: SocketAddr,
+    ws_addr: SocketAddr,
     authrpc_addr: SocketAddr,
     storage: Store,
     jwt_secret: Bytes,
-    local_p2p_node: Node,
+    peer_manager: PeerManager,
+    syncer: SyncManager,
+    limits: RpcLimits,
 ) {
     let service_context = RpcApiContext {
         storage: storage.clone(),
         jwt_secret,
-        local_p2p_node,
+        peer_manager,
+        syncer,
+        limits,
+        filters: ActiveFilters::default(),
+        gas_price_oracle: GasPriceOracle::default(),
     };
+    // Polling filters collect the changes of the chain as blocks are imported
+    tokio::spawn(
+        service_context
+            .filters
+            .clone()
+            .listen(storage.subscribe_chain_events()),
+    );
     let http_router = Router::new()
         .route("/", post(handle_http_request))
         .with_state(service_context.clone());
     let http_listener = TcpListener::bind(http_addr).await.unwrap();
 
+    let ws_router = Router::new()
+        .route("/", get(websocket::handle_websocket))
+        .with_state(service_context.clone());
+    let ws_listener = TcpListener::bind(ws_addr).await.unwrap();
+
     let authrpc_router = Router::new()
         .route("/", post(handle_authrpc_request))
         .with_state(service_context);
@@ -89,11 +151,15 @@ pub async fn start_api(
     let http_server = axum::serve(http_listener, http_router)
         .with_graceful_shutdown(shutdown_signal())
         .into_future();
+    let ws_server = axum::serve(ws_listener, ws_router)
+        .with_graceful_shutdown(shutdown_signal())
+        .into_future();
 
     info!("Starting HTTP server at {http_addr}");
+    info!("Starting WebSocket server at {ws_addr}");
     info!("Starting Auth-RPC server at {}", authrpc_addr);
 
-    let _ = tokio::try_join!(authrpc_server, http_server)
+    let _ = tokio::try_join!(authrpc_server, http_server, ws_server)
         .inspect_err(|e| info!("Error shutting down servers: {:?}", e));
 }
 
@@ -106,64 +172,138 @@ async fn shutdown_signal() {
 pub async fn handle_http_request(
     State(service_context): State<RpcApiContext>,
     body: String,
-) -> Json<Value> {
-    let storage = service_context.storage;
-    let local_p2p_node = service_context.local_p2p_node;
-    let req: RpcRequest = serde_json::from_str(&body).unwrap();
-    let res = map_http_requests(&req, storage, local_p2p_node);
-    rpc_response(req.id, res)
+) -> Response {
+    let max_batch_size = service_context.limits.max_batch_size;
+    let response = handle_payload_blocking(move || {
+        handle_payload(&body, max_batch_size, |req| {
+            map_http_requests(req, service_context.clone())
+        })
+    })
+    .await;
+    http_response(response)
 }
 
 pub async fn handle_authrpc_request(
     State(service_context): State<RpcApiContext>,
     auth_header: Option<TypedHeader<Authorization<Bearer>>>,
     body: String,
-) -> Json<Value> {
-    let storage = service_context.storage;
-    let secret = service_context.jwt_secret;
-    let req: RpcRequest = serde_json::from_str(&body).unwrap();
-    match authenticate(secret, auth_header) {
-        Err(error) => rpc_response(req.id, Err(error)),
-        Ok(()) => {
+) -> Response {
+    let secret = service_context.jwt_secret.clone();
+    let authentication = authenticate(secret, auth_header);
+    let max_batch_size = service_context.limits.max_batch_size;
+    let response = handle_payload_blocking(move || {
+        handle_payload(&body, max_batch_size, |req| match authentication {
+            Err(error) => Err(RpcErr::AuthenticationError(error)),
             // Proceed with the request
-            let res = map_authrpc_requests(&req, storage);
-            rpc_response(req.id, res)
-        }
+            Ok(()) => map_authrpc_requests(req, service_context.clone()),
+        })
+    })
+    .await;
+    http_response(response)
+}
+
+/// Runs a payload handler on a blocking thread, as requests may execute blocks, trace transactions or wait for
+/// the chain writer lock, none of which should hold up the async runtime serving other requests and peers
+async fn handle_payload_blocking(
+    handler: impl FnOnce() -> Option<Value> + Send + 'static,
+) -> Option<Value> {
+    tokio::task::spawn_blocking(handler)
+        .await
+        .unwrap_or_else(|_| Some(rpc_response(RpcRequestId::Null, Err(RpcErr::Internal)).0))
+}
+
+/// Answers with an empty body when the payload only contained notifications
+fn http_response(response: Option<Value>) -> Response {
+    match response {
+        Some(response) => Json(response).into_response(),
+        None => ().into_response(),
     }
 }
 
+/// Handles a JSON-RPC payload, either a single request or a batch of them, answering each request
+/// with the given handler. Each request of a batch gets its own response, even if other ones fail.
+/// Returns None if there is nothing to answer, as notifications don't get a response
+pub fn handle_payload(
+    body: &str,
+    max_batch_size: usize,
+    mut handler: impl FnMut(&RpcRequest) -> Result<Value, RpcErr>,
+) -> Option<Value> {
+    let payload = match serde_json::from_str::<Value>(body) {
+        Ok(payload) => payload,
+        Err(_) => return Some(rpc_response(RpcRequestId::Null, Err(RpcErr::ParseError)).0),
+    };
+    let Value::Array(requests) = payload else {
+        return handle_request(payload, &mut handler);
+    };
+    if requests.is_empty() {
+        return Some(rpc_response(RpcRequestId::Null, Err(RpcErr::InvalidRequest)).0);
+    }
+    if requests.len() > max_batch_size {
+        let error = RpcErr::LimitExceeded(format!(
+            "batch of {} requests exceeds the limit of {max_batch_size}",
+            requests.len()
+        ));
+        return Some(rpc_response(RpcRequestId::Null, Err(error)).0);
+    }
+    let responses: Vec<Value> = requests
+        .into_iter()
+        .filter_map(|request| handle_request(request, &mut handler))
+        .collect();
+    (!responses.is_empty()).then_some(Value::Array(responses))
+}
+
+fn handle_request(
+    request: Value,
+    handler: &mut impl FnMut(&RpcRequest) -> Result<Value, RpcErr>,
+) -> Option<Value> {
+    let req = match RpcRequest::deserialize(&request) {
+        Ok(req) if req.jsonrpc == "2.0" => req,
+        _ => {
+            // Answer with the request's id if it has a valid one
+            let id = request
+                .get("id")
+                .and_then(|id| serde_json::from_value(id.clone()).ok())
+                .unwrap_or(RpcRequestId::Null);
+            return Some(rpc_response(id, Err(RpcErr::InvalidRequest)).0);
+        }
+    };
+    let res = handler(&req);
+    req.id.map(|id| rpc_response(id, res).0)
+}
+
 /// Handle requests that can come from either clients or other users
-pub fn map_http_requests(
-    req: &RpcRequest,
-    storage: Store,
-    local_p2p_node: Node,
-) -> Result<Value, RpcErr> {
+pub fn map_http_requests(req: &RpcRequest, context: RpcApiContext) -> Result<Value, RpcErr> {
     match req.namespace() {
-        Ok(RpcNamespace::Eth) => map_eth_requests(req, storage),
-        Ok(RpcNamespace::Admin) => map_admin_requests(req, storage, local_p2p_node),
-        Ok(RpcNamespace::Debug) => map_debug_requests(req, storage),
+        Ok(RpcNamespace::Eth) => map_eth_requests(req, context),
+        Ok(RpcNamespace::Admin) => map_admin_requests(req, context.storage, context.peer_manager),
+        Ok(RpcNamespace::Debug) => map_debug_requests(req, context.storage),
+        Ok(RpcNamespace::Net) => map_net_requests(req, context.peer_manager),
         _ => Err(RpcErr::MethodNotFound),
     }
 }
 
 /// Handle requests from consensus client
-pub fn map_authrpc_requests(req: &RpcRequest, storage: Store) -> Result<Value, RpcErr> {
+pub fn map_authrpc_requests(req: &RpcRequest, context: RpcApiContext) -> Result<Value, RpcErr> {
     match req.namespace() {
-        Ok(RpcNamespace::Engine) => map_engine_requests(req, storage),
-        Ok(RpcNamespace::Eth) => map_eth_requests(req, storage),
+        Ok(RpcNamespace::Engine) => map_engine_requests(req, context.storage, context.syncer),
+        Ok(RpcNamespace::Eth) => map_eth_requests(req, context),
         _ => Err(RpcErr::MethodNotFound),
     }
 }
 
-pub fn map_eth_requests(req: &RpcRequest, storage: Store) -> Result<Value, RpcErr> {
+pub fn map_eth_requests(req: &RpcRequest, context: RpcApiContext) -> Result<Value, RpcErr> {
+    let storage = context.storage;
+    let filters = context.filters;
+    let oracle = context.gas_price_oracle;
     match req.method.as_str() {
         "eth_chainId" => client::chain_id(storage),
-        "eth_syncing" => client::syncing(),
+        "eth_syncing" => client::syncing(&context.syncer),
         "eth_getBlockByNumber" => GetBlockByNumberRequest::call(req, storage),
         "eth_getBlockByHash" => GetBlockByHashRequest::call(req, storage),
         "eth_getBalance" => GetBalanceRequest::call(req, storage),
         "eth_getCode" => GetCodeRequest::call(req, storage),
         "eth_getStorageAt" => GetStorageAtRequest::call(req, storage),
+        "eth_getProof" => GetProofRequest::call(req, storage),
         "eth_getBlockTransactionCountByNumber" => {
             GetBlockTransactionCountRequest::call(req, storage)
         }
@@ -181,8 +321,38 @@ pub fn map_eth_requests(req: &RpcRequest, storage: Store) -> Result<Value, RpcEr
         "eth_blockNumber" => block::block_number(storage),
         "eth_call" => CallRequest::call(req, storage),
         "eth_blobBaseFee" => block::get_blob_base_fee(&storage),
+        "eth_gasPrice" => fee_market::gas_price(&storage, &oracle),
+        "eth_maxPriorityFeePerGas" => fee_market::max_priority_fee_per_gas(&storage, &oracle),
+        "eth_feeHistory" => {
+            let request = FeeHistoryRequest::parse(&req.params)?;
+            fee_market::fee_history(&request, &storage, &oracle)
+        }
         "eth_getTransactionCount" => GetTransactionCountRequest::call(req, storage),
         "eth_estimateGas" => EstimateGasRequest::call(req, storage),
+        "eth_simulateV1" => SimulateV1Request::call(req, storage),
+        "eth_sendRawTransaction" => SendRawTransactionRequest::call(req, storage),
+        "eth_getLogs" => {
+            let request = GetLogsRequest::parse(&req.params)?;
+            logs::get_logs(&request, storage, context.limits.logs)
+        }
+        "eth_newFilter" => {
+            let request = NewFilterRequest::parse(&req.params)?;
+            filter::new_filter(&request, &filters)
+        }
+        "eth_newBlockFilter" => filter::new_block_filter(&filters),
+        "eth_newPendingTransactionFilter" => filter::new_pending_transaction_filter(&filters),
+        "eth_getFilterChanges" => {
+            let request = FilterIdRequest::parse(&req.params)?;
+            filter::get_filter_changes(&request, &filters)
+        }
+        "eth_getFilterLogs" => {
+            let request = FilterIdRequest::parse(&req.params)?;
+            filter::get_filter_logs(&request, &filters, &storage, context.limits.logs)
+        }
+        "eth_uninstallFilter" => {
+            let request = FilterIdRequest::parse(&req.params)?;
+            filter::uninstall_filter(&request, &filters)
+        }
         _ => Err(RpcErr::MethodNotFound),
     }
 }
@@ -193,11 +363,19 @@ pub fn map_debug_requests(req: &RpcRequest, storage: Store) -> Result<Value, Rpc
         "debug_getRawBlock" => GetRawBlockRequest::call(req, storage),
         "debug_getRawTransaction" => GetRawTransaction::call(req, storage),
         "debug_getRawReceipts" => GetRawReceipts::call(req, storage),
+        "debug_traceTransaction" => TraceTransactionRequest::call(req, storage),
+        "debug_traceCall" => TraceCallRequest::call(req, storage),
+        "debug_traceBlockByNumber" => TraceBlockByNumberRequest::call(req, storage),
+        "debug_traceBlockByHash" => TraceBlockByHashRequest::call(req, storage),
         _ => Err(RpcErr::MethodNotFound),
     }
 }
 
-pub fn map_engine_requests(req: &RpcRequest, storage: Store) -> Result<Value, RpcErr> {
+pub fn map_engine_requests(
+    req: &RpcRequest,
+    storage: Store,
+    syncer: SyncManager,
+) -> Result<Value, RpcErr> {
     match req.method.as_str() {
         "engine_exchangeCapabilities" => {
             let capabilities: ExchangeCapabilitiesRequest = req
@@ -212,13 +390,15 @@ pub fn map_engine_requests(req: &RpcRequest, storage: Store) -> Result<Value, Rp
 
         "engine_forkchoiceUpdatedV3" => {
             let request = ForkChoiceUpdatedV3::parse(&req.params)?;
-            fork_choice::forkchoice_updated_v3(request, storage)
+            fork_choice::forkchoice_updated_v3(request, storage, &syncer)
         }
         "engine_newPayloadV3" => {
             let request = NewPayloadV3Request::parse(&req.params)?;
             serde_json::to_value(payload::new_payload_v3(request, storage)?)
                 .map_err(|_| RpcErr::Internal)
         }
+        "engine_getPayloadV3" => GetPayloadV3Request::call(req, storage),
+        "engine_getBlobsV1" => GetBlobsV1Request::call(req, storage),
         "engine_exchangeTransitionConfigurationV1" => {
             ExchangeTransitionConfigV1Req::call(req, storage)
         }
@@ -229,15 +409,23 @@ pub fn map_engine_requests(req: &RpcRequest, storage: Store) -> Result<Value, Rp
 pub fn map_admin_requests(
     req: &RpcRequest,
     storage: Store,
-    local_p2p_node: Node,
+    peer_manager: PeerManager,
 ) -> Result<Value, RpcErr> {
     match req.method.as_str() {
-        "admin_nodeInfo" => admin::node_info(storage, local_p2p_node),
+        "admin_nodeInfo" => admin::node_info(storage, peer_manager.local_node()),
+        "admin_peers" => admin::peers(&peer_manager),
+        _ => Err(RpcErr::MethodNotFound),
+    }
+}
+
+pub fn map_net_requests(req: &RpcRequest, peer_manager: PeerManager) -> Result<Value, RpcErr> {
+    match req.method.as_str() {
+        "net_peerCount" => net::peer_count(&peer_manager),
         _ => Err(RpcErr::MethodNotFound),
     }
 }
 
-fn rpc_response<E>(id: i32, res: Result<Value, E>) -> Json<Value>
+fn rpc_response<E>(id: RpcRequestId, res: Result<Value, E>) -> Json<Value>
 where
     E: Into<RpcErrorMetadata>,
 {
@@ -266,7 +454,11 @@ mod tests {
     use ethereum_rust_core::types::ChainConfig;
     use ethereum_rust_core::{
         types::{code_hash, AccountInfo, BlockHeader},
-        Address, Bytes, H512, U256,
+        Address, Bytes, H256, H512, U256,
+    };
+    use ethereum_rust_net::{
+        peer_manager::{PeerInfo, PeerLimits},
+        types::Node,
     };
     use ethereum_rust_storage::EngineType;
     use std::str::FromStr;
@@ -279,22 +471,150 @@ mod tests {
         serde_json::to_value(serde_json::from_str::<RpcSuccessResponse>(str).unwrap()).unwrap()
     }
 
+    fn echo_method(req: &RpcRequest) -> Result<Value, RpcErr> {
+        match req.method.as_str() {
+            "fail" => Err(RpcErr::MethodNotFound),
+            method => Ok(Value::String(method.to_string())),
+        }
+    }
+
+    #[test]
+    fn handle_single_requests() {
+        let response = handle_payload(
+            r#"{"jsonrpc":"2.0","id":"a","method":"eth_chainId"}"#,
+            10,
+            echo_method,
+        );
+        assert_eq!(
+            response,
+            Some(serde_json::json!({"jsonrpc":"2.0","id":"a","result":"eth_chainId"}))
+        );
+        // Requests with a null id are answered, while notifications are not
+        let response = handle_payload(
+            r#"{"jsonrpc":"2.0","id":null,"method":"fail"}"#,
+            10,
+            echo_method,
+        )
+        .unwrap();
+        assert_eq!(response["id"], Value::Null);
+        assert_eq!(response["error"]["code"], -32601);
+        assert_eq!(
+            handle_payload(
+                r#"{"jsonrpc":"2.0","method":"eth_chainId"}"#,
+                10,
+                echo_method
+            ),
+            None
+        );
+
+        let response = handle_payload(r#"{"jsonrpc":"2.0","#, 10, echo_method).unwrap();
+        assert_eq!(response["id"], Value::Null);
+        assert_eq!(response["error"]["code"], -32700);
+        let response = handle_payload(
+            r#"{"jsonrpc":"1.0","id":3,"method":"eth_chainId"}"#,
+            10,
+            echo_method,
+        )
+        .unwrap();
+        assert_eq!(response["id"], 3);
+        assert_eq!(response["error"]["code"], -32600);
+
+        // Numeric ids are echoed back as sent, even if they don't fit an integer
+        for id in ["18446744073709551615", "-1", "1.5"] {
+            let response = handle_payload(
+                &format!(r#"{{"jsonrpc":"2.0","id":{id},"method":"eth_chainId"}}"#),
+                10,
+                echo_method,
+            )
+            .unwrap();
+            assert_eq!(response["id"].to_string(), id);
+            assert_eq!(response["result"], "eth_chainId");
+        }
+    }
+
+    #[test]
+    fn handle_batch_requests() {
+        let batch = r#"[
+            {"jsonrpc":"2.0","id":1,"method":"eth_chainId"},
+            {"jsonrpc":"2.0","id":2,"method":"fail"},
+            {"jsonrpc":"2.0","method":"eth_blockNumber"},
+            {"id":4},
+            {"jsonrpc":"2.0","id":5,"method":"eth_blockNumber"}
+        ]"#;
+        let response = handle_payload(batch, 10, echo_method).unwrap();
+        let responses = response.as_array().unwrap();
+        assert_eq!(responses.len(), 4);
+        assert_eq!(responses[0]["result"], "eth_chainId");
+        assert_eq!(responses[1]["error"]["code"], -32601);
+        assert_eq!(responses[2]["id"], 4);
+        assert_eq!(responses[2]["error"]["code"], -32600);
+        assert_eq!(responses[3]["result"], "eth_blockNumber");
+
+        let notifications = r#"[{"jsonrpc":"2.0","method":"eth_chainId"}]"#;
+        assert_eq!(handle_payload(notifications, 10, echo_method), None);
+        let response = handle_payload("[]", 10, echo_method).unwrap();
+        assert_eq!(response["error"]["code"], -32600);
+        let response = handle_payload(batch, 4, echo_method).unwrap();
+        assert_eq!(response["error"]["code"], -32005);
+    }
+
     #[test]
     fn admin_nodeinfo_request() {
         let body = r#"{"jsonrpc":"2.0", "method":"admin_nodeInfo", "params":[], "id":1}"#;
         let request: RpcRequest = serde_json::from_str(body).unwrap();
-        let local_p2p_node = example_p2p_node();
         let storage =
             Store::new("temp.db", EngineType::InMemory).expect("Failed to create test DB");
         storage.set_chain_config(&example_chain_config()).unwrap();
-        let result = map_http_requests(&request, storage, local_p2p_node);
-        let rpc_response = rpc_response(request.id, result);
+        let result = map_http_requests(&request, example_context(storage));
+        let rpc_response = rpc_response(request.id.unwrap(), result);
         let expected_response = to_rpc_response_success_value(
             r#"{"jsonrpc":"2.0","id":1,"result":{"enode":"enode://d860a01f9722d78051619d1e2351aba3f43f943f6f00718d1b9baa4101932a1f5011f16bb2b1bb35db20d6fe28fa0bf09636d26a87d31de9ec6203eeedb1f666@127.0.0.1:30303?discport=30303","id":"d860a01f9722d78051619d1e2351aba3f43f943f6f00718d1b9baa4101932a1f5011f16bb2b1bb35db20d6fe28fa0bf09636d26a87d31de9ec6203eeedb1f666","ip":"127.0.0.1","name":"ethereum_rust/0.1.0/rust1.80","ports":{"discovery":30303,"listener":30303},"protocols":{"eth":{"chainId":3151908,"homesteadBlock":0,"daoForkBlock":null,"daoForkSupport":false,"eip150Block":0,"eip155Block":0,"eip158Block":0,"byzantiumBlock":0,"constantinopleBlock":0,"petersburgBlock":0,"istanbulBlock":0,"muirGlacierBlock":null,"berlinBlock":0,"londonBlock":0,"arrowGlacierBlock":null,"grayGlacierBlock":null,"mergeNetsplitBlock":0,"shanghaiTime":0,"cancunTime":0,"pragueTime":1718232101,"verkleTime":null,"terminalTotalDifficulty":0,"terminalTotalDifficultyPassed":true}}}}"#,
         );
         assert_eq!(rpc_response.to_string(), expected_response.to_string())
     }
 
+    #[test]
+    fn admin_peers_and_net_peer_count() {
+        let storage =
+            Store::new("temp.db", EngineType::InMemory).expect("Failed to create test DB");
+        let context = example_context(storage);
+        let peer_count = r#"{"jsonrpc":"2.0", "method":"net_peerCount", "params":[], "id":1}"#;
+        let request: RpcRequest = serde_json::from_str(peer_count).unwrap();
+        let result = map_http_requests(&request, context.clone()).unwrap();
+        assert_eq!(result, "0x0");
+
+        let node_id = H512::from_low_u64_be(1);
+        context
+            .peer_manager
+            .add_peer(PeerInfo {
+                node_id,
+                remote_addr: "10.0.0.1:30303".parse().unwrap(),
+                inbound: true,
+                client_id: "Geth/v1.14.8".to_string(),
+                capabilities: vec![("eth".to_string(), 68), ("snap".to_string(), 1)],
+                head: Some(H256::from_low_u64_be(2)),
+            })
+            .unwrap();
+        let result = map_http_requests(&request, context.clone()).unwrap();
+        assert_eq!(result, "0x1");
+
+        let peers = r#"{"jsonrpc":"2.0", "method":"admin_peers", "params":[], "id":1}"#;
+        let request: RpcRequest = serde_json::from_str(peers).unwrap();
+        let result = map_http_requests(&request, context).unwrap();
+        let id = hex::encode(node_id);
+        assert_eq!(
+            result,
+            serde_json::json!([{
+                "enode": format!("enode://{id}@10.0.0.1:30303"),
+                "id": id,
+                "name": "Geth/v1.14.8",
+                "caps": ["eth/68", "snap/1"],
+                "network": {"remoteAddress": "10.0.0.1:30303", "inbound": true},
+                "protocols": {"eth": {"version": 68, "head": H256::from_low_u64_be(2)}},
+            }])
+        );
+    }
+
     #[test]
     fn create_access_list_simple_transfer() {
         // Create Request
@@ -320,10 +640,9 @@ mod tests {
         storage
             .add_account_info(address, account_info)
             .expect("Failed to write to test DB");
-        let local_p2p_node = example_p2p_node();
         // Process request
-        let result = map_http_requests(&request, storage, local_p2p_node);
-        let response = rpc_response(request.id, result);
+        let result = map_http_requests(&request, example_context(storage));
+        let response = rpc_response(request.id.unwrap(), result);
         let expected_response = to_rpc_response_success_value(
             r#"{"jsonrpc":"2.0","id":1,"result":{"accessList":[],"gasUsed":"0x5208"}}"#,
         );
@@ -371,12 +690,12 @@ mod tests {
         storage
             .add_account_code(code_hash, code)
             .expect("Failed to write to test DB");
-        let local_p2p_node = example_p2p_node();
         // Process request
-        let result = map_http_requests(&request, storage, local_p2p_node);
-        let response =
-            serde_json::from_value::<RpcSuccessResponse>(rpc_response(request.id, result).0)
-                .expect("Request failed");
+        let result = map_http_requests(&request, example_context(storage));
+        let response = serde_json::from_value::<RpcSuccessResponse>(
+            rpc_response(request.id.unwrap(), result).0,
+        )
+        .expect("Request failed");
         let expected_response_string = r#"{"jsonrpc":"2.0","id":1,"result":{"accessList":[{"address":"0x7dcd17433742f4c0ca53122ab541d0ba67fc27df","storageKeys":["0x0000000000000000000000000000000000000000000000000000000000000000","0x13a08e3cd39a1bc7bf9103f63f83273cced2beada9f723945176d6b983c65bd2"]}],"gasUsed":"0xca3c"}}"#;
         let expected_response =
             serde_json::from_str::<RpcSuccessResponse>(expected_response_string).unwrap();
@@ -389,6 +708,226 @@ mod tests {
         )
     }
 
+    /// Store holding a contract that writes 1 to its first storage slot
+    fn sstore_contract_store() -> Store {
+        let storage =
+            Store::new("temp.db", EngineType::InMemory).expect("Failed to create test DB");
+        storage
+            .set_chain_config(&example_chain_config())
+            .expect("Failed to write to test DB");
+        storage
+            .add_block_header(0, BlockHeader::default())
+            .expect("Failed to write to test DB");
+        // PUSH1 0x01 PUSH1 0x00 SSTORE
+        let address = Address::from_str("7dcd17433742f4c0ca53122ab541d0ba67fc27df").unwrap();
+        let code = Bytes::copy_from_slice(&hex::decode("6001600055").unwrap());
+        let code_hash = code_hash(&code);
+        storage
+            .add_account_info(
+                address,
+                AccountInfo {
+                    code_hash,
+                    ..Default::default()
+                },
+            )
+            .expect("Failed to write to test DB");
+        storage
+            .add_account_code(code_hash, code)
+            .expect("Failed to write to test DB");
+        storage
+    }
+
+    #[test]
+    fn trace_call_struct_logs() {
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"debug_traceCall","params":[{"from":"0x0c2c51a0990aee1d73c1228de158688341557508","to":"0x7dcd17433742f4c0ca53122ab541d0ba67fc27df"},"0x00",{"disableMemory":true}]}"#;
+        let request: RpcRequest = serde_json::from_str(body).unwrap();
+        let result = map_http_requests(&request, example_context(sstore_contract_store())).unwrap();
+        assert_eq!(result["failed"], false);
+        let logs = result["structLogs"].as_array().unwrap();
+        let ops: Vec<&str> = logs.iter().map(|log| log["op"].as_str().unwrap()).collect();
+        assert_eq!(ops, vec!["PUSH1", "PUSH1", "SSTORE", "STOP"]);
+        assert_eq!(logs[2]["pc"], 4);
+        assert_eq!(logs[2]["depth"], 1);
+        assert_eq!(logs[2]["stack"], serde_json::json!(["0x1", "0x0"]));
+        assert_eq!(
+            logs[2]["storage"][hex::encode(H256::zero())],
+            hex::encode(H256::from_low_u64_be(1))
+        );
+        assert_eq!(logs[0]["gasCost"], 3);
+        assert!(logs[0].get("memory").is_none());
+        assert!(logs[0].get("storage").is_none());
+    }
+
+    #[test]
+    fn trace_call_with_builtin_tracers() {
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"debug_traceCall","params":[{"from":"0x0c2c51a0990aee1d73c1228de158688341557508","to":"0x7dcd17433742f4c0ca53122ab541d0ba67fc27df","gas":"0x186a0"},"0x00",{"tracer":"callTracer"}]}"#;
+        let request: RpcRequest = serde_json::from_str(body).unwrap();
+        let frame = map_http_requests(&request, example_context(sstore_contract_store())).unwrap();
+        assert_eq!(frame["type"], "CALL");
+        assert_eq!(frame["from"], "0x0c2c51a0990aee1d73c1228de158688341557508");
+        assert_eq!(frame["to"], "0x7dcd17433742f4c0ca53122ab541d0ba67fc27df");
+        assert_eq!(frame["gas"], "0x186a0");
+        assert!(frame.get("error").is_none());
+        assert!(frame.get("calls").is_none());
+
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"debug_traceCall","params":[{"from":"0x0c2c51a0990aee1d73c1228de158688341557508","to":"0x7dcd17433742f4c0ca53122ab541d0ba67fc27df"},"0x00",{"tracer":"prestateTracer","tracerConfig":{"diffMode":true}}]}"#;
+        let request: RpcRequest = serde_json::from_str(body).unwrap();
+        let diff = map_http_requests(&request, example_context(sstore_contract_store())).unwrap();
+        let contract = "0x7dcd17433742f4c0ca53122ab541d0ba67fc27df";
+        let slot = format!("{:#x}", H256::zero());
+        assert_eq!(
+            diff["post"][contract]["storage"][&slot],
+            format!("{:#x}", H256::from_low_u64_be(1))
+        );
+        // The slot was empty before the call
+        assert_eq!(
+            diff["pre"][contract]["storage"][&slot],
+            format!("{:#x}", H256::zero())
+        );
+        assert_eq!(diff["pre"][contract]["code"], "0x6001600055");
+    }
+
+    #[test]
+    fn call_with_state_and_block_overrides() {
+        // PUSH1 0x00 SLOAD PUSH1 0x00 MSTORE PUSH1 0x20 PUSH1 0x00 RETURN
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"eth_call","params":[{"from":"0x0c2c51a0990aee1d73c1228de158688341557508","to":"0x00000000000000000000000000000000000000aa"},"0x00",{"0x00000000000000000000000000000000000000aa":{"code":"0x60005460005260206000f3","stateDiff":{"0x0000000000000000000000000000000000000000000000000000000000000000":"0x000000000000000000000000000000000000000000000000000000000000002a"}}}]}"#;
+        let request: RpcRequest = serde_json::from_str(body).unwrap();
+        let storage = sstore_contract_store();
+        let result = map_http_requests(&request, example_context(storage.clone())).unwrap();
+        assert_eq!(result, format!("{:#x}", H256::from_low_u64_be(0x2a)));
+        // The store is left untouched
+        let address = Address::from_str("00000000000000000000000000000000000000aa").unwrap();
+        assert!(storage.get_account_info(address).unwrap().is_none());
+
+        // NUMBER PUSH1 0x00 MSTORE PUSH1 0x20 PUSH1 0x00 RETURN
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"eth_call","params":[{"from":"0x0c2c51a0990aee1d73c1228de158688341557508","to":"0x00000000000000000000000000000000000000aa"},"0x00",{"0x00000000000000000000000000000000000000aa":{"code":"0x4360005260206000f3"}},{"number":"0x64"}]}"#;
+        let request: RpcRequest = serde_json::from_str(body).unwrap();
+        let result = map_http_requests(&request, example_context(storage.clone())).unwrap();
+        assert_eq!(result, format!("{:#x}", H256::from_low_u64_be(100)));
+
+        // An account's storage can't be replaced both as a whole and slot by slot
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"eth_call","params":[{"to":"0x00000000000000000000000000000000000000aa"},"0x00",{"0x00000000000000000000000000000000000000aa":{"state":{},"stateDiff":{}}}]}"#;
+        let request: RpcRequest = serde_json::from_str(body).unwrap();
+        assert!(matches!(
+            map_http_requests(&request, example_context(storage)),
+            Err(RpcErr::BadParams)
+        ));
+    }
+
+    #[test]
+    fn simulate_chained_blocks() {
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"eth_simulateV1","params":[{"traceTransfers":true,"blockStateCalls":[{"stateOverrides":{"0x0c2c51a0990aee1d73c1228de158688341557508":{"balance":"0x100"}},"calls":[{"from":"0x0c2c51a0990aee1d73c1228de158688341557508","to":"0x00000000000000000000000000000000000000aa","value":"0x10","gas":"0x186a0"}]},{"blockOverrides":{"time":"0x64"},"calls":[{"from":"0x0c2c51a0990aee1d73c1228de158688341557508","to":"0x00000000000000000000000000000000000000aa","value":"0x10","gas":"0x186a0"}]}]},"0x00"]"#;
+        let request: RpcRequest = serde_json::from_str(body).unwrap();
+        let storage = sstore_contract_store();
+        let blocks = map_http_requests(&request, example_context(storage.clone())).unwrap();
+        let blocks = blocks.as_array().unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0]["number"], "0x1");
+        assert_eq!(blocks[1]["number"], "0x2");
+        assert_eq!(blocks[1]["timestamp"], "0x64");
+        assert_eq!(blocks[1]["parentHash"], blocks[0]["hash"]);
+
+        let transfer = &blocks[0]["calls"][0];
+        assert_eq!(transfer["status"], "0x1");
+        let log = &transfer["logs"][0];
+        assert_eq!(log["address"], "0xeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee");
+        assert_eq!(
+            log["topics"][0],
+            "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"
+        );
+        assert_eq!(log["data"], format!("{:#x}", H256::from_low_u64_be(0x10)));
+
+        // The second block sees the sender's nonce bumped by the first one, so the same call is a different transaction
+        let call = &blocks[1]["calls"][0];
+        assert_eq!(call["status"], "0x1");
+        assert_eq!(call["logs"][0]["blockNumber"], "0x2");
+        assert_ne!(blocks[0]["transactions"][0], blocks[1]["transactions"][0]);
+
+        // Nothing is written to the store
+        let sender = Address::from_str("0c2c51a0990aee1d73c1228de158688341557508").unwrap();
+        assert!(storage.get_account_info(sender).unwrap().is_none());
+    }
+
+    #[test]
+    fn simulate_blocks_out_of_order() {
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"eth_simulateV1","params":[{"blockStateCalls":[{"blockOverrides":{"number":"0x5"}},{"blockOverrides":{"number":"0x5"}}]},"0x00"]"#;
+        let request: RpcRequest = serde_json::from_str(body).unwrap();
+        let result = map_http_requests(&request, example_context(sstore_contract_store()));
+        assert!(matches!(
+            result,
+            Err(RpcErr::Simulation { code: -38020, .. })
+        ));
+    }
+
+    #[test]
+    fn get_proof_of_account_and_storage() {
+        let storage =
+            Store::new("temp.db", EngineType::InMemory).expect("Failed to create test DB");
+        let address = Address::from_str("0c2c51a0990aee1d73c1228de158688341557508").unwrap();
+        let account_info = AccountInfo {
+            balance: U256::from(10),
+            ..Default::default()
+        };
+        storage
+            .add_account_info(address, account_info)
+            .expect("Failed to write to test DB");
+        storage
+            .add_storage_at(address, H256::zero(), U256::from(7))
+            .expect("Failed to write to test DB");
+        let state_root = storage.world_state_root().unwrap();
+        storage
+            .add_block_header(
+                0,
+                BlockHeader {
+                    state_root,
+                    ..Default::default()
+                },
+            )
+            .expect("Failed to write to test DB");
+        storage
+            .update_latest_block_number(0)
+            .expect("Failed to write to test DB");
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"eth_getProof","params":["0x0c2c51a0990aee1d73c1228de158688341557508",["0x0000000000000000000000000000000000000000000000000000000000000000"],"latest"]}"#;
+        let request: RpcRequest = serde_json::from_str(body).unwrap();
+        let result = map_http_requests(&request, example_context(storage.clone())).unwrap();
+
+        assert_eq!(result["balance"], "0xa");
+        assert_eq!(result["nonce"], "0x0");
+        assert_eq!(result["storageProof"][0]["value"], "0x7");
+        let encode_proof = |proof: Vec<Vec<u8>>| -> Value {
+            proof
+                .iter()
+                .map(|node| format!("0x{}", hex::encode(node)))
+                .collect()
+        };
+        assert_eq!(
+            result["accountProof"],
+            encode_proof(storage.get_account_proof(state_root, address).unwrap())
+        );
+        let storage_hash: H256 = serde_json::from_value(result["storageHash"].clone()).unwrap();
+        assert_eq!(
+            result["storageProof"][0]["proof"],
+            encode_proof(
+                storage
+                    .get_storage_proof(storage_hash, H256::zero())
+                    .unwrap()
+            )
+        );
+    }
+
+    fn example_context(storage: Store) -> RpcApiContext {
+        let peer_manager = PeerManager::new(example_p2p_node(), PeerLimits::default());
+        RpcApiContext {
+            syncer: SyncManager::new(peer_manager.clone(), storage.clone()),
+            storage,
+            jwt_secret: Bytes::new(),
+            peer_manager,
+            limits: RpcLimits::default(),
+            filters: ActiveFilters::default(),
+            gas_price_oracle: GasPriceOracle::default(),
+        }
+    }
+
     fn example_p2p_node() -> Node {
         let node_id_1 = H512::from_str("d860a01f9722d78051619d1e2351aba3f43f943f6f00718d1b9baa4101932a1f5011f16bb2b1bb35db20d6fe28fa0bf09636d26a87d31de9ec6203eeedb1f666").unwrap();
         Node {
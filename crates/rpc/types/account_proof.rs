@@ -0,0 +1,26 @@
+use ethereum_rust_core::{serde_utils, Address, Bytes, H256, U256};
+use serde::Serialize;
+
+/// Account along with the Merkle proofs of its state and the requested storage slots,
+/// as defined in [EIP-1186](https://eips.ethereum.org/EIPS/eip-1186)
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountProof {
+    pub address: Address,
+    #[serde(with = "serde_utils::bytes::vec")]
+    pub account_proof: Vec<Bytes>,
+    pub balance: U256,
+    pub code_hash: H256,
+    #[serde(with = "serde_utils::u64::hex_str")]
+    pub nonce: u64,
+    pub storage_hash: H256,
+    pub storage_proof: Vec<StorageProof>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StorageProof {
+    pub key: H256,
+    pub value: U256,
+    #[serde(with = "serde_utils::bytes::vec")]
+    pub proof: Vec<Bytes>,
+}
@@ -20,6 +20,23 @@ pub struct RpcBlock {
     body: BlockBodyWrapper,
 }
 
+/// Header of a block along with its hash, as pushed to `newHeads` subscribers
+#[derive(Debug, Serialize)]
+pub struct RpcBlockHeader {
+    hash: H256,
+    #[serde(flatten)]
+    header: BlockHeader,
+}
+
+impl RpcBlockHeader {
+    pub fn new(header: BlockHeader) -> Self {
+        Self {
+            hash: header.compute_block_hash(),
+            header,
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 #[serde(untagged)]
 enum BlockBodyWrapper {
@@ -72,6 +89,32 @@ impl RpcBlock {
             body: body_wrapper,
         }
     }
+
+    /// Builds a block with its transactions already built, for blocks whose transactions' senders
+    /// can't be recovered from their signatures, such as simulated ones
+    pub fn build_with_transactions(
+        header: BlockHeader,
+        body: BlockBody,
+        hash: H256,
+        transactions: Vec<RpcTransaction>,
+    ) -> RpcBlock {
+        let size = Block {
+            header: header.clone(),
+            body: body.clone(),
+        }
+        .encode_to_vec()
+        .len();
+        RpcBlock {
+            hash,
+            size: size as u64,
+            header,
+            body: BlockBodyWrapper::Full(FullBlockBody {
+                transactions,
+                uncles: body.ommers,
+                withdrawals: body.withdrawals.unwrap_or_default(),
+            }),
+        }
+    }
 }
 
 impl FullBlockBody {
@@ -1,7 +1,7 @@
 use std::{fmt::Display, str::FromStr};
 
 use ethereum_rust_core::types::{BlockHash, BlockHeader, BlockNumber};
-use ethereum_rust_storage::{error::StoreError, Store};
+use ethereum_rust_storage::{error::StoreError, StoreSnapshot};
 use serde::Deserialize;
 use serde_json::Value;
 
@@ -31,15 +31,18 @@ pub enum BlockTag {
 }
 
 impl BlockIdentifier {
-    pub fn resolve_block_number(&self, storage: &Store) -> Result<Option<BlockNumber>, StoreError> {
+    pub fn resolve_block_number(
+        &self,
+        snapshot: &StoreSnapshot,
+    ) -> Result<Option<BlockNumber>, StoreError> {
         match self {
             BlockIdentifier::Number(num) => Ok(Some(*num)),
             BlockIdentifier::Tag(tag) => match tag {
-                BlockTag::Earliest => storage.get_earliest_block_number(),
-                BlockTag::Finalized => storage.get_finalized_block_number(),
-                BlockTag::Safe => storage.get_safe_block_number(),
-                BlockTag::Latest => storage.get_latest_block_number(),
-                BlockTag::Pending => storage.get_pending_block_number(),
+                BlockTag::Earliest => snapshot.get_earliest_block_number(),
+                BlockTag::Finalized => snapshot.get_finalized_block_number(),
+                BlockTag::Safe => snapshot.get_safe_block_number(),
+                BlockTag::Latest => snapshot.get_latest_block_number(),
+                BlockTag::Pending => snapshot.get_pending_block_number(),
             },
         }
     }
@@ -64,9 +67,12 @@ impl BlockIdentifier {
         Ok(BlockIdentifier::Number(block_number))
     }
 
-    pub fn resolve_block_header(&self, storage: &Store) -> Result<Option<BlockHeader>, StoreError> {
-        match self.resolve_block_number(storage)? {
-            Some(block_number) => storage.get_block_header(block_number),
+    pub fn resolve_block_header(
+        &self,
+        snapshot: &StoreSnapshot,
+    ) -> Result<Option<BlockHeader>, StoreError> {
+        match self.resolve_block_number(snapshot)? {
+            Some(block_number) => snapshot.get_block_header(block_number),
             _ => Ok(None),
         }
     }
@@ -74,20 +80,23 @@ impl BlockIdentifier {
 
 impl BlockIdentifierOrHash {
     #[allow(unused)]
-    pub fn resolve_block_number(&self, storage: &Store) -> Result<Option<BlockNumber>, StoreError> {
+    pub fn resolve_block_number(
+        &self,
+        snapshot: &StoreSnapshot,
+    ) -> Result<Option<BlockNumber>, StoreError> {
         match self {
-            BlockIdentifierOrHash::Identifier(id) => id.resolve_block_number(storage),
-            BlockIdentifierOrHash::Hash(block_hash) => storage.get_block_number(*block_hash),
+            BlockIdentifierOrHash::Identifier(id) => id.resolve_block_number(snapshot),
+            BlockIdentifierOrHash::Hash(block_hash) => snapshot.get_block_number(*block_hash),
         }
     }
 
-    pub fn is_latest(&self, storage: &Store) -> Result<bool, StoreError> {
+    pub fn is_latest(&self, snapshot: &StoreSnapshot) -> Result<bool, StoreError> {
         if self == &BlockTag::Latest {
             return Ok(true);
         }
 
-        let result = self.resolve_block_number(storage)?;
-        let latest = storage.get_latest_block_number()?;
+        let result = self.resolve_block_number(snapshot)?;
+        let latest = snapshot.get_latest_block_number()?;
         match (result, latest) {
             (Some(result), Some(latest)) => Ok(result == latest),
             _ => Ok(false),
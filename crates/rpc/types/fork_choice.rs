@@ -1,10 +1,11 @@
-use ethereum_rust_core::{types::Withdrawal, Address, H256, U256};
-use serde::Deserialize;
+use ethereum_rust_core::{serde_utils, types::Withdrawal, Address, H256};
+use serde::{Deserialize, Serialize};
+
+use super::payload::PayloadStatus;
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ForkChoiceState {
-    #[allow(unused)]
     pub head_block_hash: H256,
     pub safe_block_hash: H256,
     pub finalized_block_hash: H256,
@@ -12,11 +13,50 @@ pub struct ForkChoiceState {
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
-#[allow(unused)]
 pub struct PayloadAttributesV3 {
-    timestamp: U256,
-    prev_randao: H256,
-    suggested_fee_recipient: Address,
-    withdrawals: Vec<Withdrawal>,
-    parent_beacon_block_root: H256,
+    #[serde(with = "serde_utils::u64::hex_str")]
+    pub timestamp: u64,
+    pub prev_randao: H256,
+    pub suggested_fee_recipient: Address,
+    // Optional so that missing fields can be reported as invalid payload attributes
+    pub withdrawals: Option<Vec<Withdrawal>>,
+    pub parent_beacon_block_root: Option<H256>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForkChoiceResponse {
+    pub payload_status: PayloadStatus,
+    #[serde(with = "serde_utils::u64::hex_str_padding_opt")]
+    pub payload_id: Option<u64>,
+}
+
+impl ForkChoiceResponse {
+    /// Creates a response with the given payload status and no payload id
+    pub fn from_status(payload_status: PayloadStatus) -> Self {
+        ForkChoiceResponse {
+            payload_status,
+            payload_id: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_forkchoice_response() {
+        let response = ForkChoiceResponse {
+            payload_status: PayloadStatus::valid_with_hash(H256::zero()),
+            payload_id: Some(0x0300000000000001),
+        };
+        let value = serde_json::to_value(response).unwrap();
+        assert_eq!(value["payloadId"], "0x0300000000000001");
+        assert_eq!(value["payloadStatus"]["status"], "VALID");
+
+        let response = ForkChoiceResponse::from_status(PayloadStatus::syncing());
+        let value = serde_json::to_value(response).unwrap();
+        assert!(value["payloadId"].is_null());
+    }
 }
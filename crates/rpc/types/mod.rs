@@ -1,3 +1,4 @@
+pub mod account_proof;
 pub mod block;
 pub mod block_identifier;
 pub mod fork_choice;
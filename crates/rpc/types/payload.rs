@@ -5,13 +5,13 @@ use ethereum_rust_core::{
     rlp::error::RLPDecodeError,
     serde_utils,
     types::{
-        compute_transactions_root, compute_withdrawals_root, Block, BlockBody, BlockHash,
-        BlockHeader, Transaction, Withdrawal, DEFAULT_OMMERS_HASH,
+        compute_transactions_root, compute_withdrawals_root, BlobsBundle, Block, BlockBody,
+        BlockHash, BlockHeader, Transaction, Withdrawal, DEFAULT_OMMERS_HASH,
     },
-    Address, Bloom, H256,
+    Address, Bloom, H256, U256,
 };
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ExecutionPayloadV3 {
     parent_hash: H256,
@@ -44,6 +44,15 @@ pub struct ExecutionPayloadV3 {
 #[derive(Debug)]
 pub struct EncodedTransaction(pub Bytes);
 
+impl Serialize for EncodedTransaction {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serde_utils::bytes::serialize(&self.0, serializer)
+    }
+}
+
 impl<'de> Deserialize<'de> for EncodedTransaction {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -56,6 +65,11 @@ impl<'de> Deserialize<'de> for EncodedTransaction {
 }
 
 impl EncodedTransaction {
+    /// Encodes a transaction in the canonical format described by [EIP-2718]
+    pub fn encode(tx: &Transaction) -> Self {
+        Self(Bytes::from(tx.encode_canonical_to_vec()))
+    }
+
     /// Based on [EIP-2718]
     /// Transactions can be encoded in the following formats:
     /// A) `TransactionType || Transaction` (Where Transaction type is an 8-bit number between 0 and 0x7f, and Transaction is an rlp encoded transaction of type TransactionType)
@@ -66,6 +80,36 @@ impl EncodedTransaction {
 }
 
 impl ExecutionPayloadV3 {
+    /// Converts a block into an `ExecutionPayloadV3`, the parent beacon block root is left out
+    /// as it is not part of the payload
+    pub fn from_block(block: Block) -> Self {
+        let header = block.header;
+        ExecutionPayloadV3 {
+            parent_hash: header.parent_hash,
+            fee_recipient: header.coinbase,
+            state_root: header.state_root,
+            receipts_root: header.receipts_root,
+            logs_bloom: header.logs_bloom,
+            prev_randao: header.prev_randao,
+            block_number: header.number,
+            gas_limit: header.gas_limit,
+            gas_used: header.gas_used,
+            timestamp: header.timestamp,
+            base_fee_per_gas: header.base_fee_per_gas.unwrap_or_default(),
+            block_hash: header.compute_block_hash(),
+            extra_data: header.extra_data,
+            transactions: block
+                .body
+                .transactions
+                .iter()
+                .map(EncodedTransaction::encode)
+                .collect(),
+            withdrawals: block.body.withdrawals.unwrap_or_default(),
+            blob_gas_used: header.blob_gas_used.unwrap_or_default(),
+            excess_blob_gas: header.excess_blob_gas.unwrap_or_default(),
+        }
+    }
+
     /// Converts an `ExecutionPayloadV3` into a block (aka a BlockHeader and BlockBody)
     /// using the parentBeaconBlockRoot received along with the payload in the rpc call `engine_newPayloadV3`
     pub fn into_block(self, parent_beacon_block_root: H256) -> Result<Block, RLPDecodeError> {
@@ -108,6 +152,59 @@ impl ExecutionPayloadV3 {
     }
 }
 
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutionPayloadResponse {
+    pub execution_payload: ExecutionPayloadV3,
+    pub block_value: U256,
+    pub blobs_bundle: BlobsBundleV1,
+    pub should_override_builder: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobsBundleV1 {
+    #[serde(with = "serde_utils::bytes::vec")]
+    pub commitments: Vec<Bytes>,
+    #[serde(with = "serde_utils::bytes::vec")]
+    pub proofs: Vec<Bytes>,
+    #[serde(with = "serde_utils::bytes::vec")]
+    pub blobs: Vec<Bytes>,
+}
+
+impl BlobsBundleV1 {
+    /// Appends the blobs, commitments and proofs of a blob sidecar to the bundle
+    pub fn extend(&mut self, blobs_bundle: BlobsBundle) {
+        self.commitments.extend(
+            blobs_bundle
+                .commitments
+                .iter()
+                .map(|commitment| Bytes::copy_from_slice(commitment)),
+        );
+        self.proofs.extend(
+            blobs_bundle
+                .proofs
+                .iter()
+                .map(|proof| Bytes::copy_from_slice(proof)),
+        );
+        self.blobs.extend(
+            blobs_bundle
+                .blobs
+                .iter()
+                .map(|blob| Bytes::copy_from_slice(blob)),
+        );
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobAndProofV1 {
+    #[serde(with = "serde_utils::bytes")]
+    pub blob: Bytes,
+    #[serde(with = "serde_utils::bytes")]
+    pub proof: Bytes,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PayloadStatus {
@@ -176,4 +273,15 @@ mod test {
         let payload: ExecutionPayloadV3 = serde_json::from_str(json).unwrap();
         assert!(payload.into_block(H256::zero()).is_ok());
     }
+
+    #[test]
+    fn block_into_payload_roundtrip() {
+        let json = r#"{"baseFeePerGas":"0x7","blobGasUsed":"0x0","blockHash":"0x0000000000000000000000000000000000000000000000000000000000000000","blockNumber":"0x2","excessBlobGas":"0x0","extraData":"0x","feeRecipient":"0x8943545177806ed17b9f23f0a21ee5948ecaa776","gasLimit":"0x1c9c380","gasUsed":"0x0","logsBloom":"0x00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000","parentHash":"0x2971eefd1f71f3548728cad87c16cc91b979ef035054828c59a02e49ae300a84","prevRandao":"0x2971eefd1f71f3548728cad87c16cc91b979ef035054828c59a02e49ae300a84","receiptsRoot":"0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421","stateRoot":"0x0eb8fd0af53174e65bb660d0904e5016425a713d8f11c767c26148b526fc05f3","timestamp":"0x66846fb2","transactions":[],"withdrawals":[{"index":"0x0","validatorIndex":"0x1","address":"0x8943545177806ed17b9f23f0a21ee5948ecaa776","amount":"0x64"}]}"#;
+        let payload: ExecutionPayloadV3 = serde_json::from_str(json).unwrap();
+        let parent_beacon_block_root = H256::random();
+        let block = payload.into_block(parent_beacon_block_root).unwrap();
+        let payload = ExecutionPayloadV3::from_block(block.clone());
+        assert_eq!(payload.block_hash, block.header.compute_block_hash());
+        assert_eq!(payload.into_block(parent_beacon_block_root).unwrap(), block);
+    }
 }
@@ -172,8 +172,19 @@ impl RpcReceiptTxInfo {
         gas_used: u64,
         block_blob_gas_price: u64,
     ) -> Self {
-        let nonce = transaction.nonce();
         let from = transaction.sender();
+        Self::from_transaction_with_sender(transaction, from, index, gas_used, block_blob_gas_price)
+    }
+
+    /// Builds the info of a transaction whose sender is already known, such as an unsigned simulated one
+    pub fn from_transaction_with_sender(
+        transaction: Transaction,
+        from: Address,
+        index: u64,
+        gas_used: u64,
+        block_blob_gas_price: u64,
+    ) -> Self {
+        let nonce = transaction.nonce();
         let transaction_hash = transaction.compute_hash();
         let effective_gas_price = transaction.gas_price();
         let transaction_index = index;
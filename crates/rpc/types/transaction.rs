@@ -28,6 +28,18 @@ impl RpcTransaction {
         transaction_index: usize,
     ) -> Self {
         let from = tx.sender();
+        Self::build_with_sender(tx, from, block_number, block_hash, transaction_index)
+    }
+
+    /// Builds the transaction with the given sender instead of the one recovered from its signature,
+    /// for transactions that are not signed, such as simulated ones
+    pub fn build_with_sender(
+        tx: Transaction,
+        from: Address,
+        block_number: BlockNumber,
+        block_hash: BlockHash,
+        transaction_index: usize,
+    ) -> Self {
         let hash = tx.compute_hash();
         let transaction_index = transaction_index as u64;
         RpcTransaction {
@@ -1,3 +1,4 @@
+use ethereum_rust_chain::error::MempoolError;
 use ethereum_rust_evm::EvmError;
 use ethereum_rust_storage::error::StoreError;
 use serde::{Deserialize, Serialize};
@@ -7,20 +8,50 @@ use crate::authentication::AuthenticationError;
 
 #[derive(Debug)]
 pub enum RpcErr {
+    ParseError,
+    InvalidRequest,
     MethodNotFound,
     BadParams,
     BadHexFormat(u64),
     UnsuportedFork,
+    InvalidForkChoiceState(String),
+    InvalidPayloadAttributes(String),
+    UnknownPayload(String),
+    TooLargeRequest,
     Internal,
     Vm,
-    Revert { data: String },
-    Halt { reason: String, gas_used: u64 },
+    Revert {
+        data: String,
+    },
+    Halt {
+        reason: String,
+        gas_used: u64,
+    },
+    Mempool(String),
+    StateUnavailable(String),
+    LimitExceeded(String),
+    FilterNotFound,
+    /// Simulation request that can't be carried out, with the code given to the reason by the spec
+    Simulation {
+        code: i32,
+        message: String,
+    },
     AuthenticationError(AuthenticationError),
 }
 
 impl From<RpcErr> for RpcErrorMetadata {
     fn from(value: RpcErr) -> Self {
         match value {
+            RpcErr::ParseError => RpcErrorMetadata {
+                code: -32700,
+                data: None,
+                message: "Parse error".to_string(),
+            },
+            RpcErr::InvalidRequest => RpcErrorMetadata {
+                code: -32600,
+                data: None,
+                message: "Invalid request".to_string(),
+            },
             RpcErr::MethodNotFound => RpcErrorMetadata {
                 code: -32601,
                 data: None,
@@ -36,6 +67,26 @@ impl From<RpcErr> for RpcErrorMetadata {
                 data: None,
                 message: "Unsupported fork".to_string(),
             },
+            RpcErr::InvalidForkChoiceState(data) => RpcErrorMetadata {
+                code: -38002,
+                data: Some(data),
+                message: "Invalid forkchoice state".to_string(),
+            },
+            RpcErr::InvalidPayloadAttributes(data) => RpcErrorMetadata {
+                code: -38003,
+                data: Some(data),
+                message: "Invalid payload attributes".to_string(),
+            },
+            RpcErr::UnknownPayload(data) => RpcErrorMetadata {
+                code: -38001,
+                data: Some(data),
+                message: "Unknown payload".to_string(),
+            },
+            RpcErr::TooLargeRequest => RpcErrorMetadata {
+                code: -38004,
+                data: None,
+                message: "Too large request".to_string(),
+            },
             RpcErr::BadHexFormat(arg_number) => RpcErrorMetadata {
                 code: -32602,
                 data: None,
@@ -68,6 +119,31 @@ impl From<RpcErr> for RpcErrorMetadata {
                 data: None,
                 message: format!("execution halted: reason={}, gas_used={}", reason, gas_used),
             },
+            RpcErr::Mempool(message) => RpcErrorMetadata {
+                code: -32000,
+                data: None,
+                message,
+            },
+            RpcErr::StateUnavailable(block) => RpcErrorMetadata {
+                code: -32000,
+                data: None,
+                message: format!("state of block {block} is not available"),
+            },
+            RpcErr::LimitExceeded(message) => RpcErrorMetadata {
+                code: -32005,
+                data: None,
+                message,
+            },
+            RpcErr::FilterNotFound => RpcErrorMetadata {
+                code: -32000,
+                data: None,
+                message: "filter not found".to_string(),
+            },
+            RpcErr::Simulation { code, message } => RpcErrorMetadata {
+                code,
+                data: None,
+                message,
+            },
             RpcErr::AuthenticationError(auth_error) => match auth_error {
                 AuthenticationError::InvalidIssuedAtClaim => RpcErrorMetadata {
                     code: -32000,
@@ -100,16 +176,37 @@ pub enum RpcNamespace {
     Eth,
     Admin,
     Debug,
+    Net,
+}
+
+/// Id of a request, echoed back in its response.
+/// Requests without an id are notifications, which are not answered
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum RpcRequestId {
+    /// Kept as sent, so that any JSON number is echoed back unchanged
+    Number(serde_json::Number),
+    String(String),
+    Null,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RpcRequest {
-    pub id: i32,
+    #[serde(default, deserialize_with = "deserialize_request_id")]
+    pub id: Option<RpcRequestId>,
     pub jsonrpc: String,
     pub method: String,
     pub params: Option<Vec<Value>>,
 }
 
+/// Tells apart requests with a null id, which must be answered, from notifications, which don't have an id
+fn deserialize_request_id<'de, D>(deserializer: D) -> Result<Option<RpcRequestId>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    RpcRequestId::deserialize(deserializer).map(Some)
+}
+
 impl RpcRequest {
     pub fn namespace(&self) -> Result<RpcNamespace, RpcErr> {
         let mut parts = self.method.split('_');
@@ -119,6 +216,7 @@ impl RpcRequest {
                 "eth" => Ok(RpcNamespace::Eth),
                 "admin" => Ok(RpcNamespace::Admin),
                 "debug" => Ok(RpcNamespace::Debug),
+                "net" => Ok(RpcNamespace::Net),
                 _ => Err(RpcErr::MethodNotFound),
             }
         } else {
@@ -137,14 +235,14 @@ pub struct RpcErrorMetadata {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RpcSuccessResponse {
-    pub id: i32,
+    pub id: RpcRequestId,
     pub jsonrpc: String,
     pub result: Value,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RpcErrorResponse {
-    pub id: i32,
+    pub id: RpcRequestId,
     pub jsonrpc: String,
     pub error: RpcErrorMetadata,
 }
@@ -156,6 +254,15 @@ impl From<StoreError> for RpcErr {
     }
 }
 
+impl From<MempoolError> for RpcErr {
+    fn from(value: MempoolError) -> Self {
+        match value {
+            MempoolError::StoreError(_) => RpcErr::Internal,
+            other => RpcErr::Mempool(other.to_string()),
+        }
+    }
+}
+
 impl From<EvmError> for RpcErr {
     fn from(_value: EvmError) -> Self {
         RpcErr::Vm
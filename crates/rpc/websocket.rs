@@ -0,0 +1,457 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        State, WebSocketUpgrade,
+    },
+    response::Response,
+};
+use ethereum_rust_storage::ChainEvent;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc::{self, error::TrySendError},
+        Notify,
+    },
+    task::JoinHandle,
+};
+use tracing::{info, warn};
+
+use crate::{
+    eth::logs::{self, LogsFilter},
+    handle_payload, map_http_requests,
+    types::block::RpcBlockHeader,
+    utils::{RpcErr, RpcRequest},
+    RpcApiContext,
+};
+
+pub type SubscriptionId = u64;
+
+/// Notifications waiting to be sent to a connection, which is closed when a subscriber overflows them
+const MAX_PENDING_NOTIFICATIONS: usize = 10_000;
+
+/// Events a subscription is notified about
+#[derive(Debug)]
+pub enum SubscriptionKind {
+    NewHeads,
+    Logs(LogsFilter),
+    NewPendingTransactions,
+}
+
+pub struct SubscribeRequest {
+    pub kind: SubscriptionKind,
+}
+
+pub struct UnsubscribeRequest {
+    pub id: SubscriptionId,
+}
+
+#[derive(Serialize)]
+struct RpcSubscriptionNotification {
+    jsonrpc: String,
+    method: String,
+    params: RpcSubscriptionResult,
+}
+
+#[derive(Serialize)]
+struct RpcSubscriptionResult {
+    subscription: String,
+    result: Value,
+}
+
+impl SubscribeRequest {
+    pub fn parse(params: &Option<Vec<Value>>) -> Result<Self, RpcErr> {
+        let params = params.as_ref().ok_or(RpcErr::BadParams)?;
+        let kind: String =
+            serde_json::from_value(params.first().ok_or(RpcErr::BadParams)?.clone())?;
+        let kind = match (kind.as_str(), params.get(1)) {
+            ("newHeads", None) => SubscriptionKind::NewHeads,
+            ("logs", None) => {
+                SubscriptionKind::Logs(LogsFilter::parse(Value::Object(Default::default()))?)
+            }
+            ("logs", Some(filter)) => SubscriptionKind::Logs(LogsFilter::parse(filter.clone())?),
+            ("newPendingTransactions", None) => SubscriptionKind::NewPendingTransactions,
+            _ => return Err(RpcErr::BadParams),
+        };
+        if params.len() > 2 {
+            return Err(RpcErr::BadParams);
+        }
+        Ok(SubscribeRequest { kind })
+    }
+}
+
+impl UnsubscribeRequest {
+    pub fn parse(params: &Option<Vec<Value>>) -> Result<Self, RpcErr> {
+        let params = params.as_ref().ok_or(RpcErr::BadParams)?;
+        if params.len() != 1 {
+            return Err(RpcErr::BadParams);
+        };
+        let hex_str: String = serde_json::from_value(params[0].clone())?;
+        let Some(hex_str) = hex_str.strip_prefix("0x") else {
+            return Err(RpcErr::BadHexFormat(0));
+        };
+        let id =
+            SubscriptionId::from_str_radix(hex_str, 16).map_err(|_| RpcErr::BadHexFormat(0))?;
+        Ok(UnsubscribeRequest { id })
+    }
+}
+
+pub async fn handle_websocket(
+    State(service_context): State<RpcApiContext>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(|socket| serve_connection(socket, service_context))
+}
+
+/// Subscriptions of a single websocket connection, which are cancelled when the connection is closed
+struct Connection {
+    context: RpcApiContext,
+    subscriptions: HashMap<SubscriptionId, JoinHandle<()>>,
+    last_id: SubscriptionId,
+    notifications: mpsc::Sender<Value>,
+    /// Signaled by a subscriber when the connection falls too far behind its notifications
+    overflow: Arc<Notify>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        for task in self.subscriptions.values() {
+            task.abort();
+        }
+    }
+}
+
+/// Serves requests until the connection is closed, pushing subscription notifications as they come in
+async fn serve_connection(mut socket: WebSocket, context: RpcApiContext) {
+    let (sender, mut notifications) = mpsc::channel(MAX_PENDING_NOTIFICATIONS);
+    let overflow = Arc::new(Notify::new());
+    let mut connection = Connection {
+        context,
+        subscriptions: HashMap::new(),
+        last_id: 0,
+        notifications: sender,
+        overflow: overflow.clone(),
+    };
+    let max_batch_size = connection.context.limits.max_batch_size;
+    loop {
+        let outgoing = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    // Requests may block, e.g. executing transactions, so they are handled off the async runtime
+                    let handled = tokio::task::spawn_blocking(move || {
+                        let response = handle_payload(&text, max_batch_size, |req| {
+                            connection.handle_request(req)
+                        });
+                        (response, connection)
+                    })
+                    .await;
+                    let Ok((response, handled_connection)) = handled else {
+                        break;
+                    };
+                    connection = handled_connection;
+                    // Notifications are not answered
+                    let Some(response) = response else {
+                        continue;
+                    };
+                    response
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // Pings are answered by axum
+                Some(Ok(_)) => continue,
+            },
+            Some(notification) = notifications.recv() => notification,
+            _ = overflow.notified() => {
+                warn!("Closing websocket connection, it is not keeping up with its notifications");
+                break;
+            }
+        };
+        if socket
+            .send(Message::Text(outgoing.to_string()))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+impl Connection {
+    fn handle_request(&mut self, req: &RpcRequest) -> Result<Value, RpcErr> {
+        match req.method.as_str() {
+            "eth_subscribe" => {
+                SubscribeRequest::parse(&req.params).map(|request| self.subscribe(request))
+            }
+            "eth_unsubscribe" => {
+                UnsubscribeRequest::parse(&req.params).map(|request| self.unsubscribe(&request))
+            }
+            _ => map_http_requests(req, self.context.clone()),
+        }
+    }
+
+    fn subscribe(&mut self, request: SubscribeRequest) -> Value {
+        self.last_id += 1;
+        let id = self.last_id;
+        info!("Subscribing to {:?} with id {id:#x}", request.kind);
+        // Subscribe to the store right away so no event is missed before the task starts
+        let events = self.context.storage.subscribe_chain_events();
+        let task = tokio::spawn(notify_subscriber(
+            id,
+            request.kind,
+            events,
+            self.notifications.clone(),
+            self.overflow.clone(),
+        ));
+        self.subscriptions.insert(id, task);
+        subscription_id_value(id)
+    }
+
+    fn unsubscribe(&mut self, request: &UnsubscribeRequest) -> Value {
+        info!("Unsubscribing {:#x}", request.id);
+        let removed = self.subscriptions.remove(&request.id);
+        if let Some(task) = &removed {
+            task.abort();
+        }
+        Value::Bool(removed.is_some())
+    }
+}
+
+fn subscription_id_value(id: SubscriptionId) -> Value {
+    Value::String(format!("{id:#x}"))
+}
+
+/// Pushes a notification for each of the store's events the subscription is interested in.
+/// Stops and signals the overflow if the connection's notification buffer is full
+async fn notify_subscriber(
+    id: SubscriptionId,
+    kind: SubscriptionKind,
+    mut events: broadcast::Receiver<ChainEvent>,
+    notifications: mpsc::Sender<Value>,
+    overflow: Arc<Notify>,
+) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                warn!("Subscription {id:#x} fell behind the chain, {missed} events were missed");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        let results = match subscription_results(&kind, &event) {
+            Ok(results) => results,
+            Err(error) => {
+                warn!("Subscription {id:#x} could not process a chain event: {error:?}");
+                continue;
+            }
+        };
+        for result in results {
+            let notification = RpcSubscriptionNotification {
+                jsonrpc: "2.0".to_string(),
+                method: "eth_subscription".to_string(),
+                params: RpcSubscriptionResult {
+                    subscription: format!("{id:#x}"),
+                    result,
+                },
+            };
+            let Ok(notification) = serde_json::to_value(notification) else {
+                continue;
+            };
+            match notifications.try_send(notification) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    warn!("Subscription {id:#x} dropped, its connection is not keeping up");
+                    overflow.notify_one();
+                    return;
+                }
+                // The connection was closed
+                Err(TrySendError::Closed(_)) => return,
+            }
+        }
+    }
+}
+
+/// Returns the results pushed to a subscription for the given event, logs are pushed one by one
+fn subscription_results(kind: &SubscriptionKind, event: &ChainEvent) -> Result<Vec<Value>, RpcErr> {
+    let results = match (kind, event) {
+        (SubscriptionKind::NewHeads, ChainEvent::NewHead { block, .. }) => {
+            vec![serde_json::to_value(RpcBlockHeader::new(
+                block.header.clone(),
+            ))]
+        }
+        (SubscriptionKind::Logs(filter), ChainEvent::NewHead { block, receipts }) => {
+            logs::logs_in_block(filter, block, receipts, false)?
+                .into_iter()
+                .map(serde_json::to_value)
+                .collect()
+        }
+        (SubscriptionKind::Logs(filter), ChainEvent::RemovedBlock { block, receipts }) => {
+            logs::logs_in_block(filter, block, receipts, true)?
+                .into_iter()
+                .map(serde_json::to_value)
+                .collect()
+        }
+        (SubscriptionKind::NewPendingTransactions, ChainEvent::NewPendingTransaction(hash)) => {
+            vec![serde_json::to_value(hash)]
+        }
+        _ => vec![],
+    };
+    Ok(results.into_iter().filter_map(Result::ok).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_rust_core::{
+        types::{
+            Block, BlockBody, BlockHeader, LegacyTransaction, Log, Receipt, Transaction, TxKind,
+            TxType,
+        },
+        Address, H256, U256,
+    };
+    use serde_json::json;
+
+    use super::*;
+
+    fn params(values: Value) -> Option<Vec<Value>> {
+        serde_json::from_value(values).unwrap()
+    }
+
+    #[test]
+    fn parse_subscriptions() {
+        assert!(matches!(
+            SubscribeRequest::parse(&params(json!(["newHeads"])))
+                .unwrap()
+                .kind,
+            SubscriptionKind::NewHeads
+        ));
+        assert!(matches!(
+            SubscribeRequest::parse(&params(json!(["newPendingTransactions"])))
+                .unwrap()
+                .kind,
+            SubscriptionKind::NewPendingTransactions
+        ));
+        let SubscriptionKind::Logs(filter) = SubscribeRequest::parse(&params(json!([
+            "logs",
+            { "address": Address::from_low_u64_be(1), "topics": [H256::zero()] }
+        ])))
+        .unwrap()
+        .kind
+        else {
+            panic!("Expected a logs subscription");
+        };
+        assert_eq!(filter.addresses, vec![Address::from_low_u64_be(1)]);
+        assert!(SubscribeRequest::parse(&params(json!(["syncing"]))).is_err());
+        assert!(SubscribeRequest::parse(&params(json!(["newHeads", {}]))).is_err());
+        assert_eq!(
+            UnsubscribeRequest::parse(&params(json!(["0x1f"])))
+                .unwrap()
+                .id,
+            0x1f
+        );
+    }
+
+    #[test]
+    fn subscriptions_only_receive_their_events() {
+        let address = Address::from_low_u64_be(0xa);
+        let receipt = Receipt::new(
+            TxType::Legacy,
+            true,
+            21_000,
+            vec![Log {
+                address,
+                topics: vec![],
+                data: Default::default(),
+            }],
+        );
+        let transaction = Transaction::LegacyTransaction(LegacyTransaction {
+            nonce: 0,
+            gas_price: 0,
+            gas: 21_000,
+            to: TxKind::Create,
+            value: U256::zero(),
+            data: Default::default(),
+            v: U256::zero(),
+            r: U256::zero(),
+            s: U256::zero(),
+        });
+        let block = Block {
+            header: BlockHeader {
+                number: 1,
+                logs_bloom: receipt.bloom,
+                ..Default::default()
+            },
+            body: BlockBody {
+                transactions: vec![transaction],
+                ommers: vec![],
+                withdrawals: None,
+            },
+        };
+        let new_head = ChainEvent::new_head(block.clone(), vec![receipt.clone()]);
+        let removed_block = ChainEvent::removed_block(block.clone(), vec![receipt]);
+        let pending = ChainEvent::NewPendingTransaction(H256::zero());
+
+        let heads = subscription_results(&SubscriptionKind::NewHeads, &new_head).unwrap();
+        assert_eq!(heads.len(), 1);
+        assert_eq!(heads[0]["number"], "0x1");
+        assert_eq!(heads[0]["hash"], json!(block.header.compute_block_hash()));
+        assert!(subscription_results(&SubscriptionKind::NewHeads, &pending)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            subscription_results(&SubscriptionKind::NewPendingTransactions, &pending).unwrap(),
+            vec![json!(H256::zero())]
+        );
+        assert!(
+            subscription_results(&SubscriptionKind::NewPendingTransactions, &new_head)
+                .unwrap()
+                .is_empty()
+        );
+
+        let logs =
+            SubscriptionKind::Logs(LogsFilter::parse(json!({ "address": address })).unwrap());
+        let added = subscription_results(&logs, &new_head).unwrap();
+        assert_eq!(added.len(), 1);
+        assert_eq!(added[0]["removed"], false);
+        let removed = subscription_results(&logs, &removed_block).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0]["removed"], true);
+        let other_address = SubscriptionKind::Logs(
+            LogsFilter::parse(json!({ "address": Address::from_low_u64_be(0xb) })).unwrap(),
+        );
+        assert!(subscription_results(&other_address, &new_head)
+            .unwrap()
+            .is_empty());
+
+        // Logs can't be collected from a block missing its receipts
+        let missing_receipts = ChainEvent::new_head(block, vec![]);
+        assert!(subscription_results(&logs, &missing_receipts).is_err());
+    }
+
+    #[tokio::test]
+    async fn slow_connections_overflow() {
+        let (events_sender, events) = broadcast::channel(16);
+        let (sender, mut notifications) = mpsc::channel(2);
+        let overflow = Arc::new(Notify::new());
+        let subscriber = tokio::spawn(notify_subscriber(
+            1,
+            SubscriptionKind::NewPendingTransactions,
+            events,
+            sender,
+            overflow.clone(),
+        ));
+        for _ in 0..3 {
+            events_sender
+                .send(ChainEvent::NewPendingTransaction(H256::zero()))
+                .unwrap();
+        }
+
+        // The subscriber stops once the buffer is full, and the connection is told to close
+        subscriber.await.unwrap();
+        overflow.notified().await;
+        assert!(notifications.recv().await.is_some());
+        assert!(notifications.recv().await.is_some());
+        assert!(notifications.recv().await.is_none());
+    }
+}
@@ -0,0 +1,93 @@
+// Measures the throughput of store reads while a single writer keeps committing blocks
+// Run with `cargo bench -p ethereum_rust-storage --bench concurrent_reads`
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use ethereum_rust_core::types::{AccountInfo, Block, BlockBody, BlockHeader};
+use ethereum_rust_storage::{EngineType, Store};
+use ethereum_types::{Address, U256};
+
+const ACCOUNTS: u64 = 1_000;
+const BLOCKS: u64 = 200;
+const ACCOUNTS_PER_BLOCK: u64 = 50;
+const READERS: [usize; 4] = [1, 2, 4, 8];
+const DB_PATH: &str = "concurrent-reads-bench-db";
+
+fn main() {
+    #[cfg(feature = "in_memory")]
+    run(EngineType::InMemory);
+    #[cfg(feature = "libmdbx")]
+    run(EngineType::Libmdbx);
+}
+
+fn run(engine_type: EngineType) {
+    for readers in READERS {
+        let _ = std::fs::remove_dir_all(DB_PATH);
+        let store = new_store(engine_type);
+        let (reads, elapsed) = measure(&store, readers);
+        println!(
+            "{engine_type:?}: {readers} reader(s), {:.0} reads/s while committing {BLOCKS} blocks in {elapsed:?}",
+            reads as f64 / elapsed.as_secs_f64()
+        );
+        drop(store);
+        let _ = std::fs::remove_dir_all(DB_PATH);
+    }
+}
+
+fn new_store(engine_type: EngineType) -> Store {
+    let store = Store::new(DB_PATH, engine_type).expect("Failed to create bench store");
+    let mut batch = store.write_batch();
+    for i in 0..ACCOUNTS {
+        batch.add_account_info(
+            Address::from_low_u64_be(i),
+            AccountInfo {
+                balance: U256::from(i),
+                ..Default::default()
+            },
+        );
+    }
+    batch.commit().expect("Failed to store bench accounts");
+    store
+}
+
+/// Runs the readers until the writer is done committing blocks, returns the amount of reads made and the time taken
+fn measure(store: &Store, readers: usize) -> (u64, Duration) {
+    let writer_done = AtomicBool::new(false);
+    let reads = AtomicU64::new(0);
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        for reader in 0..readers {
+            let (writer_done, reads) = (&writer_done, &reads);
+            scope.spawn(move || {
+                let mut i = reader as u64;
+                while !writer_done.load(Ordering::Relaxed) {
+                    let address = Address::from_low_u64_be(i % ACCOUNTS);
+                    store.get_account_info(address).unwrap();
+                    if let Some(latest) = store.get_latest_block_number().unwrap() {
+                        store.get_block_header(latest).unwrap();
+                    }
+                    reads.fetch_add(2, Ordering::Relaxed);
+                    i += 1;
+                }
+            });
+        }
+        for number in 1..=BLOCKS {
+            let mut batch = store.write_batch();
+            for i in 0..ACCOUNTS_PER_BLOCK {
+                let address =
+                    Address::from_low_u64_be((number * ACCOUNTS_PER_BLOCK + i) % ACCOUNTS);
+                batch.increment_balance(address, U256::one()).unwrap();
+            }
+            batch.add_block(Block {
+                header: BlockHeader {
+                    number,
+                    ..Default::default()
+                },
+                body: BlockBody::empty(),
+            });
+            batch.commit().unwrap();
+        }
+        writer_done.store(true, Ordering::Relaxed);
+    });
+    (reads.into_inner(), start.elapsed())
+}
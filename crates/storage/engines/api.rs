@@ -1,14 +1,19 @@
 use bytes::Bytes;
 use ethereum_rust_core::types::{
-    Account, AccountInfo, BlockBody, BlockHash, BlockHeader, BlockNumber, ChainConfig, Index,
-    Receipt, Transaction,
+    Account, AccountInfo, Block, BlockBody, BlockHash, BlockHeader, BlockNumber, ChainConfig,
+    Index, Receipt, Transaction,
 };
 use ethereum_types::{Address, H256, U256};
 use std::fmt::Debug;
 
-use crate::error::StoreError;
+use crate::{
+    error::StoreError,
+    state_diff::StateDiff,
+    trie::{db::TrieDB, Trie},
+    write_batch::WriteOp,
+};
 
-pub trait StoreEngine: Debug + Send {
+pub trait StoreEngine: Debug + Send + Sync {
     /// Add account info
     fn add_account_info(
         &mut self,
@@ -60,6 +65,66 @@ pub trait StoreEngine: Debug + Send {
     /// Obtain block number
     fn get_block_number(&self, block_hash: BlockHash) -> Result<Option<BlockNumber>, StoreError>;
 
+    /// Remove block number
+    fn remove_block_number(&mut self, block_hash: BlockHash) -> Result<(), StoreError>;
+
+    /// Remove block header and body
+    fn remove_block(&mut self, block_number: BlockNumber) -> Result<(), StoreError>;
+
+    /// Add a block that is not part of the canonical chain along with its receipts
+    fn add_side_block(
+        &mut self,
+        block_hash: BlockHash,
+        block: Block,
+        receipts: Vec<Receipt>,
+    ) -> Result<(), StoreError>;
+
+    /// Obtain a block that is not part of the canonical chain along with its receipts
+    fn get_side_block(
+        &self,
+        block_hash: BlockHash,
+    ) -> Result<Option<(Block, Vec<Receipt>)>, StoreError>;
+
+    /// Remove a block that is not part of the canonical chain
+    fn remove_side_block(&mut self, block_hash: BlockHash) -> Result<(), StoreError>;
+
+    /// Obtain the headers of every block that is not part of the canonical chain
+    fn side_block_headers(&self) -> Result<Vec<BlockHeader>, StoreError>;
+
+    /// Add the state changes produced by executing a block
+    fn add_state_diff(
+        &mut self,
+        block_hash: BlockHash,
+        state_diff: StateDiff,
+    ) -> Result<(), StoreError>;
+
+    /// Obtain the state changes produced by executing a block
+    fn get_state_diff(&self, block_hash: BlockHash) -> Result<Option<StateDiff>, StoreError>;
+
+    /// Mark a block as invalid, storing the hash of its latest valid ancestor
+    fn add_invalid_block(
+        &mut self,
+        block_hash: BlockHash,
+        latest_valid_hash: BlockHash,
+    ) -> Result<(), StoreError>;
+
+    /// Obtain the latest valid ancestor of a block previously marked as invalid
+    fn get_latest_valid_ancestor(
+        &self,
+        block_hash: BlockHash,
+    ) -> Result<Option<BlockHash>, StoreError>;
+
+    /// Add a payload being built under the given payload id, along with its value for the fee recipient
+    fn add_payload(
+        &mut self,
+        payload_id: u64,
+        block: Block,
+        block_value: U256,
+    ) -> Result<(), StoreError>;
+
+    /// Obtain a payload and its value by its payload id
+    fn get_payload(&self, payload_id: u64) -> Result<Option<(Block, U256)>, StoreError>;
+
     /// Store transaction location (block number and index of the transaction within the block)
     fn add_transaction_location(
         &mut self,
@@ -74,6 +139,9 @@ pub trait StoreEngine: Debug + Send {
         transaction_hash: H256,
     ) -> Result<Option<(BlockNumber, Index)>, StoreError>;
 
+    /// Remove transaction location
+    fn remove_transaction_location(&mut self, transaction_hash: H256) -> Result<(), StoreError>;
+
     /// Add receipt
     fn add_receipt(
         &mut self,
@@ -150,10 +218,37 @@ pub trait StoreEngine: Debug + Send {
 
     // Get full account storage
     fn account_storage_iter(
-        &mut self,
+        &self,
         address: Address,
     ) -> Result<Box<dyn Iterator<Item = (H256, U256)>>, StoreError>;
 
+    /// Obtain a TrieDB over the stored trie nodes
+    /// The world state trie and the accounts' storage tries all share the same nodes
+    fn trie_db(&self) -> Box<dyn TrieDB>;
+
+    /// Obtain a trie over the stored trie nodes, rooted at the given hash
+    fn open_trie(&self, root: H256) -> Trie<Box<dyn TrieDB>> {
+        Trie::open(self.trie_db(), root)
+    }
+
+    /// Remove the stored trie nodes that are not to be kept, walking at most `limit` nodes in key order starting
+    /// from `start`. Returns the key to continue from, or None once the last node was walked
+    fn retain_trie_nodes(
+        &mut self,
+        keep: &dyn Fn(&[u8]) -> bool,
+        start: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Option<Vec<u8>>, StoreError>;
+
+    /// Update the root of the world state trie
+    fn update_state_root(&mut self, state_root: H256) -> Result<(), StoreError>;
+
+    /// Obtain the root of the world state trie
+    fn get_state_root(&self) -> Result<Option<H256>, StoreError>;
+
+    /// Apply the writes of a batch in order, either all of them are stored or none of them
+    fn commit_write_batch(&mut self, ops: Vec<WriteOp>) -> Result<(), StoreError>;
+
     /// Stores account in db (including info, code & storage)
     fn add_account(&mut self, address: Address, account: Account) -> Result<(), StoreError> {
         self.add_account_info(address, account.info.clone())?;
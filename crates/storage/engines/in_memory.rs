@@ -1,7 +1,12 @@
-use crate::error::StoreError;
+use crate::{
+    error::StoreError,
+    state_diff::StateDiff,
+    trie::db::{in_memory::InMemoryTrieDB, TrieDB},
+    write_batch::WriteOp,
+};
 use bytes::Bytes;
 use ethereum_rust_core::types::{
-    AccountInfo, BlockBody, BlockHash, BlockHeader, BlockNumber, ChainConfig, Index, Receipt,
+    AccountInfo, Block, BlockBody, BlockHash, BlockHeader, BlockNumber, ChainConfig, Index, Receipt,
 };
 use ethereum_types::{Address, H256, U256};
 use std::{collections::HashMap, fmt::Debug};
@@ -21,6 +26,16 @@ pub struct Store {
     // Maps transaction hashes to their block number and index within the block
     transaction_locations: HashMap<H256, (BlockNumber, Index)>,
     receipts: HashMap<BlockNumber, HashMap<Index, Receipt>>,
+    // Blocks that are not part of the canonical chain, along with their receipts
+    side_blocks: HashMap<BlockHash, (Block, Vec<Receipt>)>,
+    // Maps block hashes to the state changes produced by executing them
+    state_diffs: HashMap<BlockHash, StateDiff>,
+    // Maps invalid block hashes to the hash of their latest valid ancestor
+    invalid_blocks: HashMap<BlockHash, BlockHash>,
+    // Maps payload ids to the payloads being built
+    payloads: HashMap<u64, (Block, U256)>,
+    // Nodes of the world state trie and the accounts' storage tries
+    trie_nodes: InMemoryTrieDB,
 }
 
 #[derive(Default)]
@@ -31,6 +46,7 @@ struct ChainData {
     safe_block_number: Option<BlockNumber>,
     latest_block_number: Option<BlockNumber>,
     pending_block_number: Option<BlockNumber>,
+    state_root: Option<H256>,
 }
 
 impl Store {
@@ -103,6 +119,90 @@ impl StoreEngine for Store {
         Ok(self.block_numbers.get(&block_hash).copied())
     }
 
+    fn remove_block_number(&mut self, block_hash: BlockHash) -> Result<(), StoreError> {
+        self.block_numbers.remove(&block_hash);
+        Ok(())
+    }
+
+    fn remove_block(&mut self, block_number: BlockNumber) -> Result<(), StoreError> {
+        self.headers.remove(&block_number);
+        self.bodies.remove(&block_number);
+        Ok(())
+    }
+
+    fn add_side_block(
+        &mut self,
+        block_hash: BlockHash,
+        block: Block,
+        receipts: Vec<Receipt>,
+    ) -> Result<(), StoreError> {
+        self.side_blocks.insert(block_hash, (block, receipts));
+        Ok(())
+    }
+
+    fn get_side_block(
+        &self,
+        block_hash: BlockHash,
+    ) -> Result<Option<(Block, Vec<Receipt>)>, StoreError> {
+        Ok(self.side_blocks.get(&block_hash).cloned())
+    }
+
+    fn remove_side_block(&mut self, block_hash: BlockHash) -> Result<(), StoreError> {
+        self.side_blocks.remove(&block_hash);
+        Ok(())
+    }
+
+    fn side_block_headers(&self) -> Result<Vec<BlockHeader>, StoreError> {
+        Ok(self
+            .side_blocks
+            .values()
+            .map(|(block, _)| block.header.clone())
+            .collect())
+    }
+
+    fn add_state_diff(
+        &mut self,
+        block_hash: BlockHash,
+        state_diff: StateDiff,
+    ) -> Result<(), StoreError> {
+        self.state_diffs.insert(block_hash, state_diff);
+        Ok(())
+    }
+
+    fn get_state_diff(&self, block_hash: BlockHash) -> Result<Option<StateDiff>, StoreError> {
+        Ok(self.state_diffs.get(&block_hash).cloned())
+    }
+
+    fn add_invalid_block(
+        &mut self,
+        block_hash: BlockHash,
+        latest_valid_hash: BlockHash,
+    ) -> Result<(), StoreError> {
+        self.invalid_blocks.insert(block_hash, latest_valid_hash);
+        Ok(())
+    }
+
+    fn get_latest_valid_ancestor(
+        &self,
+        block_hash: BlockHash,
+    ) -> Result<Option<BlockHash>, StoreError> {
+        Ok(self.invalid_blocks.get(&block_hash).copied())
+    }
+
+    fn add_payload(
+        &mut self,
+        payload_id: u64,
+        block: Block,
+        block_value: U256,
+    ) -> Result<(), StoreError> {
+        self.payloads.insert(payload_id, (block, block_value));
+        Ok(())
+    }
+
+    fn get_payload(&self, payload_id: u64) -> Result<Option<(Block, U256)>, StoreError> {
+        Ok(self.payloads.get(&payload_id).cloned())
+    }
+
     fn add_transaction_location(
         &mut self,
         transaction_hash: H256,
@@ -121,6 +221,11 @@ impl StoreEngine for Store {
         Ok(self.transaction_locations.get(&transaction_hash).copied())
     }
 
+    fn remove_transaction_location(&mut self, transaction_hash: H256) -> Result<(), StoreError> {
+        self.transaction_locations.remove(&transaction_hash);
+        Ok(())
+    }
+
     fn add_receipt(
         &mut self,
         block_number: BlockNumber,
@@ -181,7 +286,7 @@ impl StoreEngine for Store {
     }
 
     fn account_storage_iter(
-        &mut self,
+        &self,
         address: Address,
     ) -> Result<Box<dyn Iterator<Item = (H256, U256)>>, StoreError> {
         Ok(Box::new(
@@ -193,6 +298,66 @@ impl StoreEngine for Store {
         ))
     }
 
+    fn trie_db(&self) -> Box<dyn TrieDB> {
+        Box::new(self.trie_nodes.clone())
+    }
+
+    fn retain_trie_nodes(
+        &mut self,
+        keep: &dyn Fn(&[u8]) -> bool,
+        start: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self.trie_nodes.retain(keep, start, limit))
+    }
+
+    fn update_state_root(&mut self, state_root: H256) -> Result<(), StoreError> {
+        self.chain_data.state_root.replace(state_root);
+        Ok(())
+    }
+
+    fn get_state_root(&self) -> Result<Option<H256>, StoreError> {
+        Ok(self.chain_data.state_root)
+    }
+
+    // In-memory writes can't fail, so the store's lock is enough for the batch to be applied as a whole
+    fn commit_write_batch(&mut self, ops: Vec<WriteOp>) -> Result<(), StoreError> {
+        for op in ops {
+            match op {
+                WriteOp::AddAccountInfo(address, info) => self.add_account_info(address, info),
+                WriteOp::RemoveAccountInfo(address) => self.remove_account_info(address),
+                WriteOp::AddAccountCode(code_hash, code) => self.add_account_code(code_hash, code),
+                WriteOp::AddStorageAt(address, key, value) => {
+                    self.add_storage_at(address, key, value)
+                }
+                WriteOp::RemoveAccountStorage(address) => self.remove_account_storage(address),
+                WriteOp::UpdateStateRoot(state_root) => self.update_state_root(state_root),
+                WriteOp::AddTrieNodes(nodes) => nodes
+                    .into_iter()
+                    .try_for_each(|(key, node)| self.trie_nodes.put(key, node)),
+                WriteOp::AddBlockHeader(number, header) => self.add_block_header(number, header),
+                WriteOp::AddBlockBody(number, body) => self.add_block_body(number, body),
+                WriteOp::AddBlockNumber(hash, number) => self.add_block_number(hash, number),
+                WriteOp::RemoveBlockNumber(hash) => self.remove_block_number(hash),
+                WriteOp::RemoveBlock(number) => self.remove_block(number),
+                WriteOp::AddTransactionLocation(hash, number, index) => {
+                    self.add_transaction_location(hash, number, index)
+                }
+                WriteOp::RemoveTransactionLocation(hash) => self.remove_transaction_location(hash),
+                WriteOp::AddReceipt(number, index, receipt) => {
+                    self.add_receipt(number, index, receipt)
+                }
+                WriteOp::AddSideBlock(hash, block, receipts) => {
+                    self.add_side_block(hash, block, receipts)
+                }
+                WriteOp::RemoveSideBlock(hash) => self.remove_side_block(hash),
+                WriteOp::AddStateDiff(hash, state_diff) => self.add_state_diff(hash, state_diff),
+                WriteOp::UpdateLatestBlockNumber(number) => self.update_latest_block_number(number),
+            }?;
+        }
+        Ok(())
+    }
+
     fn set_chain_config(&mut self, chain_config: &ChainConfig) -> Result<(), StoreError> {
         // Store cancun timestamp
         self.chain_data.chain_config = Some(*chain_config);
@@ -2,14 +2,20 @@ use super::api::StoreEngine;
 use crate::error::StoreError;
 use crate::rlp::{
     AccountCodeHashRLP, AccountCodeRLP, AccountInfoRLP, AddressRLP, BlockBodyRLP, BlockHashRLP,
-    BlockHeaderRLP, ReceiptRLP, TransactionHashRLP,
+    BlockHeaderRLP, PayloadRLP, ReceiptRLP, SideBlockRLP, StateDiffRLP, TransactionHashRLP,
 };
+use crate::state_diff::StateDiff;
+use crate::trie::db::{
+    libmdbx::{Libmdbx as LibmdbxTrieDB, TrieNodes},
+    TrieDB,
+};
+use crate::write_batch::WriteOp;
 use anyhow::Result;
 use bytes::Bytes;
 use ethereum_rust_core::rlp::decode::RLPDecode;
 use ethereum_rust_core::rlp::encode::RLPEncode;
 use ethereum_rust_core::types::{
-    AccountInfo, BlockBody, BlockHash, BlockHeader, BlockNumber, ChainConfig, Index, Receipt,
+    AccountInfo, Block, BlockBody, BlockHash, BlockHeader, BlockNumber, ChainConfig, Index, Receipt,
 };
 use ethereum_types::{Address, H256, U256};
 use libmdbx::orm::{Decodable, Encodable};
@@ -21,15 +27,16 @@ use libmdbx::{
 use serde_json;
 use std::fmt::{Debug, Formatter};
 use std::path::Path;
+use std::sync::Arc;
 
 pub struct Store {
-    db: Database,
+    db: Arc<Database>,
 }
 
 impl Store {
     pub fn new(path: &str) -> Result<Self, StoreError> {
         Ok(Self {
-            db: init_db(Some(path)),
+            db: Arc::new(init_db(Some(path))),
         })
     }
 
@@ -143,6 +150,94 @@ impl StoreEngine for Store {
         self.read::<BlockNumbers>(block_hash.into())
     }
 
+    fn remove_block_number(&mut self, block_hash: BlockHash) -> Result<(), StoreError> {
+        self.remove::<BlockNumbers>(block_hash.into())
+    }
+
+    fn remove_block(&mut self, block_number: BlockNumber) -> Result<(), StoreError> {
+        self.remove::<Headers>(block_number)?;
+        self.remove::<Bodies>(block_number)
+    }
+
+    fn add_side_block(
+        &mut self,
+        block_hash: BlockHash,
+        block: Block,
+        receipts: Vec<Receipt>,
+    ) -> Result<(), StoreError> {
+        self.write::<SideBlocks>(block_hash.into(), (block, receipts).into())
+    }
+
+    fn get_side_block(
+        &self,
+        block_hash: BlockHash,
+    ) -> Result<Option<(Block, Vec<Receipt>)>, StoreError> {
+        Ok(self.read::<SideBlocks>(block_hash.into())?.map(|b| b.to()))
+    }
+
+    fn remove_side_block(&mut self, block_hash: BlockHash) -> Result<(), StoreError> {
+        self.remove::<SideBlocks>(block_hash.into())
+    }
+
+    fn side_block_headers(&self) -> Result<Vec<BlockHeader>, StoreError> {
+        let txn = self.db.begin_read().map_err(StoreError::LibmdbxError)?;
+        let cursor = txn
+            .cursor::<SideBlocks>()
+            .map_err(StoreError::LibmdbxError)?;
+        let headers = cursor
+            .walk(None)
+            .map_while(|res| {
+                res.ok().map(|(_, side_block)| {
+                    let (block, _): (Block, Vec<Receipt>) = side_block.to();
+                    block.header
+                })
+            })
+            .collect();
+        Ok(headers)
+    }
+
+    fn add_state_diff(
+        &mut self,
+        block_hash: BlockHash,
+        state_diff: StateDiff,
+    ) -> Result<(), StoreError> {
+        self.write::<StateDiffs>(block_hash.into(), state_diff.into())
+    }
+
+    fn get_state_diff(&self, block_hash: BlockHash) -> Result<Option<StateDiff>, StoreError> {
+        Ok(self.read::<StateDiffs>(block_hash.into())?.map(|d| d.to()))
+    }
+
+    fn add_invalid_block(
+        &mut self,
+        block_hash: BlockHash,
+        latest_valid_hash: BlockHash,
+    ) -> Result<(), StoreError> {
+        self.write::<InvalidBlocks>(block_hash.into(), latest_valid_hash.into())
+    }
+
+    fn get_latest_valid_ancestor(
+        &self,
+        block_hash: BlockHash,
+    ) -> Result<Option<BlockHash>, StoreError> {
+        Ok(self
+            .read::<InvalidBlocks>(block_hash.into())?
+            .map(|h| h.to()))
+    }
+
+    fn add_payload(
+        &mut self,
+        payload_id: u64,
+        block: Block,
+        block_value: U256,
+    ) -> Result<(), StoreError> {
+        self.write::<Payloads>(payload_id, (block, block_value).into())
+    }
+
+    fn get_payload(&self, payload_id: u64) -> Result<Option<(Block, U256)>, StoreError> {
+        Ok(self.read::<Payloads>(payload_id)?.map(|p| p.to()))
+    }
+
     fn add_account_code(&mut self, code_hash: H256, code: Bytes) -> Result<(), StoreError> {
         self.write::<AccountCodes>(code_hash.into(), code.into())
     }
@@ -186,6 +281,10 @@ impl StoreEngine for Store {
         self.read::<TransactionLocations>(transaction_hash.into())
     }
 
+    fn remove_transaction_location(&mut self, transaction_hash: H256) -> Result<(), StoreError> {
+        self.remove::<TransactionLocations>(transaction_hash.into())
+    }
+
     fn add_storage_at(
         &mut self,
         address: Address,
@@ -238,7 +337,7 @@ impl StoreEngine for Store {
     }
 
     fn account_storage_iter(
-        &mut self,
+        &self,
         address: Address,
     ) -> Result<Box<dyn Iterator<Item = (H256, U256)>>, StoreError> {
         let txn = self.db.begin_read().map_err(StoreError::LibmdbxError)?;
@@ -252,6 +351,101 @@ impl StoreEngine for Store {
         Ok(Box::new(iter.collect::<Vec<_>>().into_iter()))
     }
 
+    fn trie_db(&self) -> Box<dyn TrieDB> {
+        Box::new(LibmdbxTrieDB::new(self.db.clone()))
+    }
+
+    fn retain_trie_nodes(
+        &mut self,
+        keep: &dyn Fn(&[u8]) -> bool,
+        start: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Option<Vec<u8>>, StoreError> {
+        LibmdbxTrieDB::new(self.db.clone()).retain(keep, start, limit)
+    }
+
+    fn update_state_root(&mut self, state_root: H256) -> Result<(), StoreError> {
+        self.write::<ChainData>(ChainDataIndex::StateRoot, state_root.encode_to_vec())
+    }
+
+    fn get_state_root(&self) -> Result<Option<H256>, StoreError> {
+        match self.read::<ChainData>(ChainDataIndex::StateRoot)? {
+            None => Ok(None),
+            Some(ref rlp) => RLPDecode::decode(rlp)
+                .map(Some)
+                .map_err(|_| StoreError::DecodeError),
+        }
+    }
+
+    fn commit_write_batch(&mut self, ops: Vec<WriteOp>) -> Result<(), StoreError> {
+        // All writes share a single transaction, which is aborted if any of them fails
+        let txn = self
+            .db
+            .begin_readwrite()
+            .map_err(StoreError::LibmdbxError)?;
+        for op in ops {
+            match op {
+                WriteOp::AddAccountInfo(address, info) => {
+                    txn.upsert::<AccountInfos>(address.into(), info.into())
+                }
+                WriteOp::RemoveAccountInfo(address) => {
+                    txn.delete::<AccountInfos>(address.into(), None).map(|_| ())
+                }
+                WriteOp::AddAccountCode(code_hash, code) => {
+                    txn.upsert::<AccountCodes>(code_hash.into(), code.into())
+                }
+                WriteOp::AddStorageAt(address, key, value) => {
+                    txn.upsert::<AccountStorages>(address.into(), (key.into(), value.into()))
+                }
+                WriteOp::RemoveAccountStorage(address) => txn
+                    .delete::<AccountStorages>(address.into(), None)
+                    .map(|_| ()),
+                WriteOp::AddTrieNodes(nodes) => nodes
+                    .into_iter()
+                    .try_for_each(|(key, node)| txn.upsert::<TrieNodes>(key, node)),
+                WriteOp::UpdateStateRoot(state_root) => {
+                    txn.upsert::<ChainData>(ChainDataIndex::StateRoot, state_root.encode_to_vec())
+                }
+                WriteOp::AddBlockHeader(number, header) => {
+                    txn.upsert::<Headers>(number, header.into())
+                }
+                WriteOp::AddBlockBody(number, body) => txn.upsert::<Bodies>(number, body.into()),
+                WriteOp::AddBlockNumber(hash, number) => {
+                    txn.upsert::<BlockNumbers>(hash.into(), number)
+                }
+                WriteOp::RemoveBlockNumber(hash) => {
+                    txn.delete::<BlockNumbers>(hash.into(), None).map(|_| ())
+                }
+                WriteOp::RemoveBlock(number) => txn
+                    .delete::<Headers>(number, None)
+                    .and_then(|_| txn.delete::<Bodies>(number, None))
+                    .map(|_| ()),
+                WriteOp::AddTransactionLocation(hash, number, index) => {
+                    txn.upsert::<TransactionLocations>(hash.into(), (number, index))
+                }
+                WriteOp::RemoveTransactionLocation(hash) => txn
+                    .delete::<TransactionLocations>(hash.into(), None)
+                    .map(|_| ()),
+                WriteOp::AddReceipt(number, index, receipt) => {
+                    txn.upsert::<Receipts>((number, index), receipt.into())
+                }
+                WriteOp::AddSideBlock(hash, block, receipts) => {
+                    txn.upsert::<SideBlocks>(hash.into(), (block, receipts).into())
+                }
+                WriteOp::RemoveSideBlock(hash) => {
+                    txn.delete::<SideBlocks>(hash.into(), None).map(|_| ())
+                }
+                WriteOp::AddStateDiff(hash, state_diff) => {
+                    txn.upsert::<StateDiffs>(hash.into(), state_diff.into())
+                }
+                WriteOp::UpdateLatestBlockNumber(number) => txn
+                    .upsert::<ChainData>(ChainDataIndex::LatestBlockNumber, number.encode_to_vec()),
+            }
+            .map_err(StoreError::LibmdbxError)?;
+        }
+        txn.commit().map_err(StoreError::LibmdbxError)
+    }
+
     fn update_earliest_block_number(
         &mut self,
         block_number: BlockNumber,
@@ -382,6 +576,26 @@ table!(
     ( TransactionLocations ) TransactionHashRLP => (BlockNumber, Index)
 );
 
+table!(
+    /// Side blocks table, stores blocks that are not part of the canonical chain along with their receipts.
+    ( SideBlocks ) BlockHashRLP => SideBlockRLP
+);
+
+table!(
+    /// State diffs table, stores the state changes produced by executing each block.
+    ( StateDiffs ) BlockHashRLP => StateDiffRLP
+);
+
+table!(
+    /// Invalid blocks table, maps invalid block hashes to the hash of their latest valid ancestor.
+    ( InvalidBlocks ) BlockHashRLP => BlockHashRLP
+);
+
+table!(
+    /// Payloads table, stores the payloads being built by the node along with their value.
+    ( Payloads ) u64 => PayloadRLP
+);
+
 table!(
     /// Stores chain data, each value is unique and stored as its rlp encoding
     /// See [ChainDataIndex] for available chain values
@@ -456,6 +670,7 @@ pub enum ChainDataIndex {
     SafeBlockNumber = 3,
     LatestBlockNumber = 4,
     PendingBlockNumber = 5,
+    StateRoot = 6,
 }
 
 impl Encodable for ChainDataIndex {
@@ -478,7 +693,12 @@ pub fn init_db(path: Option<impl AsRef<Path>>) -> Database {
         table_info!(AccountCodes),
         table_info!(Receipts),
         table_info!(TransactionLocations),
+        table_info!(SideBlocks),
+        table_info!(StateDiffs),
+        table_info!(InvalidBlocks),
+        table_info!(Payloads),
         table_info!(ChainData),
+        table_info!(TrieNodes),
     ]
     .into_iter()
     .collect();
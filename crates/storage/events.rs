@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use ethereum_rust_core::{
+    types::{Block, Receipt},
+    H256,
+};
+
+/// Amount of events buffered for each subscriber, subscribers that fall further behind miss the oldest ones
+pub const CHAIN_EVENTS_CAPACITY: usize = 1024;
+
+/// Changes to the canonical chain and the mempool, published as they happen to the subscribers of the store
+#[derive(Debug, Clone)]
+pub enum ChainEvent {
+    /// A block was added to the canonical chain and became its head
+    NewHead {
+        block: Arc<Block>,
+        receipts: Arc<Vec<Receipt>>,
+    },
+    /// A block was removed from the canonical chain by a reorg
+    RemovedBlock {
+        block: Arc<Block>,
+        receipts: Arc<Vec<Receipt>>,
+    },
+    /// A transaction was added to the mempool
+    NewPendingTransaction(H256),
+}
+
+impl ChainEvent {
+    pub fn new_head(block: Block, receipts: Vec<Receipt>) -> Self {
+        Self::NewHead {
+            block: Arc::new(block),
+            receipts: Arc::new(receipts),
+        }
+    }
+
+    pub fn removed_block(block: Block, receipts: Vec<Receipt>) -> Self {
+        Self::RemovedBlock {
+            block: Arc::new(block),
+            receipts: Arc::new(receipts),
+        }
+    }
+}
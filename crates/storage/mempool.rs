@@ -0,0 +1,288 @@
+// Contains the transactions waiting to be included in a block
+// Transactions are not persisted, the pool only lives in memory and is shared by all clones of the `Store`
+use std::collections::{BTreeMap, HashMap};
+
+use ethereum_rust_core::types::{Blob, BlobsBundle, Proof, Transaction};
+use ethereum_types::{Address, H256};
+
+/// Transaction held by the mempool
+#[derive(Debug, Clone, PartialEq)]
+pub struct MempoolTransaction {
+    pub hash: H256,
+    pub sender: Address,
+    pub transaction: Transaction,
+}
+
+/// Transactions of each sender, indexed by nonce.
+/// A sender's transactions are pending if they can be executed right away (ie. their nonces are consecutive
+/// starting from the sender's account nonce), the rest of them are queued until the nonce gap is filled
+#[derive(Debug, Default)]
+pub struct Mempool {
+    pending: HashMap<Address, BTreeMap<u64, MempoolTransaction>>,
+    queued: HashMap<Address, BTreeMap<u64, MempoolTransaction>>,
+    by_hash: HashMap<H256, (Address, u64)>,
+    blob_pool: BlobPool,
+}
+
+/// Blob sidecars of the blob transactions held by the mempool.
+/// They are kept apart from the transactions as they are only needed to build payloads and serve them to peers
+#[derive(Debug, Default)]
+struct BlobPool {
+    bundles: HashMap<H256, BlobsBundle>,
+    /// Maps each versioned hash to the transaction that carries its blob and the blob's index in the bundle
+    versioned_hashes: HashMap<H256, (H256, usize)>,
+    blob_count: usize,
+}
+
+impl Mempool {
+    /// Adds a transaction to the pool, replacing the sender's transaction with the same nonce if any
+    pub fn insert(&mut self, transaction: MempoolTransaction, account_nonce: u64) {
+        let sender = transaction.sender;
+        let nonce = transaction.transaction.nonce();
+        if let Some(replaced) = self.remove_by_nonce(sender, nonce) {
+            self.by_hash.remove(&replaced.hash);
+            self.blob_pool.remove(replaced.hash);
+        }
+        self.by_hash.insert(transaction.hash, (sender, nonce));
+        self.queued
+            .entry(sender)
+            .or_default()
+            .insert(nonce, transaction);
+        self.reorganize(sender, account_nonce);
+    }
+
+    /// Adds a blob transaction to the pool along with its blob sidecar
+    pub fn insert_blob_transaction(
+        &mut self,
+        transaction: MempoolTransaction,
+        blobs_bundle: BlobsBundle,
+        account_nonce: u64,
+    ) {
+        let hash = transaction.hash;
+        self.insert(transaction, account_nonce);
+        self.blob_pool.insert(hash, blobs_bundle);
+    }
+
+    /// Returns the blob sidecar of a blob transaction in the pool
+    pub fn get_blobs_bundle(&self, hash: H256) -> Option<&BlobsBundle> {
+        self.blob_pool.bundles.get(&hash)
+    }
+
+    /// Returns a blob and its proof from the pool by the blob's versioned hash
+    pub fn get_blob(&self, versioned_hash: H256) -> Option<(&Blob, &Proof)> {
+        let (hash, index) = self.blob_pool.versioned_hashes.get(&versioned_hash)?;
+        let bundle = self.blob_pool.bundles.get(hash)?;
+        Some((bundle.blobs.get(*index)?, bundle.proofs.get(*index)?))
+    }
+
+    /// Returns the amount of blobs in the pool
+    pub fn blob_count(&self) -> usize {
+        self.blob_pool.blob_count
+    }
+
+    /// Returns a transaction from the pool by its hash
+    pub fn get(&self, hash: H256) -> Option<&MempoolTransaction> {
+        let (sender, nonce) = self.by_hash.get(&hash)?;
+        self.get_by_nonce(*sender, *nonce)
+    }
+
+    /// Returns the sender's transaction with the given nonce
+    pub fn get_by_nonce(&self, sender: Address, nonce: u64) -> Option<&MempoolTransaction> {
+        self.pending
+            .get(&sender)
+            .and_then(|txs| txs.get(&nonce))
+            .or_else(|| self.queued.get(&sender).and_then(|txs| txs.get(&nonce)))
+    }
+
+    /// Removes the sender's transactions that were already executed and splits the rest
+    /// between the pending and queued sub-pools according to the sender's account nonce
+    pub fn reorganize(&mut self, sender: Address, account_nonce: u64) {
+        let mut transactions = self.pending.remove(&sender).unwrap_or_default();
+        transactions.append(&mut self.queued.remove(&sender).unwrap_or_default());
+
+        let mut pending = BTreeMap::new();
+        let mut queued = BTreeMap::new();
+        let mut next_nonce = account_nonce;
+        for (nonce, transaction) in transactions {
+            if nonce < account_nonce {
+                self.by_hash.remove(&transaction.hash);
+                self.blob_pool.remove(transaction.hash);
+            } else if nonce == next_nonce {
+                pending.insert(nonce, transaction);
+                next_nonce += 1;
+            } else {
+                queued.insert(nonce, transaction);
+            }
+        }
+        if !pending.is_empty() {
+            self.pending.insert(sender, pending);
+        }
+        if !queued.is_empty() {
+            self.queued.insert(sender, queued);
+        }
+    }
+
+    /// Returns the addresses of all senders with transactions in the pool
+    pub fn senders(&self) -> Vec<Address> {
+        let mut senders: Vec<Address> = self.pending.keys().copied().collect();
+        senders.extend(
+            self.queued
+                .keys()
+                .filter(|sender| !self.pending.contains_key(sender)),
+        );
+        senders
+    }
+
+    /// Returns the pending transactions of each sender, sorted by nonce
+    pub fn pending(&self) -> Vec<Vec<MempoolTransaction>> {
+        self.pending
+            .values()
+            .map(|txs| txs.values().cloned().collect())
+            .collect()
+    }
+
+    /// Returns the amount of transactions the sender has in the pool
+    pub fn sender_len(&self, sender: Address) -> usize {
+        self.pending.get(&sender).map_or(0, BTreeMap::len)
+            + self.queued.get(&sender).map_or(0, BTreeMap::len)
+    }
+
+    /// Returns the amount of transactions in the pool
+    pub fn len(&self) -> usize {
+        self.by_hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_hash.is_empty()
+    }
+
+    fn remove_by_nonce(&mut self, sender: Address, nonce: u64) -> Option<MempoolTransaction> {
+        [&mut self.pending, &mut self.queued]
+            .into_iter()
+            .find_map(|sub_pool| sub_pool.get_mut(&sender)?.remove(&nonce))
+    }
+}
+
+impl BlobPool {
+    fn insert(&mut self, hash: H256, blobs_bundle: BlobsBundle) {
+        for (index, versioned_hash) in blobs_bundle
+            .generate_versioned_hashes()
+            .into_iter()
+            .enumerate()
+        {
+            self.versioned_hashes.insert(versioned_hash, (hash, index));
+        }
+        self.blob_count += blobs_bundle.blobs.len();
+        self.bundles.insert(hash, blobs_bundle);
+    }
+
+    fn remove(&mut self, hash: H256) {
+        if let Some(blobs_bundle) = self.bundles.remove(&hash) {
+            for versioned_hash in blobs_bundle.generate_versioned_hashes() {
+                // The same blob may be carried by another transaction
+                if self
+                    .versioned_hashes
+                    .get(&versioned_hash)
+                    .is_some_and(|(carrier, _)| *carrier == hash)
+                {
+                    self.versioned_hashes.remove(&versioned_hash);
+                }
+            }
+            self.blob_count -= blobs_bundle.blobs.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use ethereum_rust_core::types::{LegacyTransaction, TxKind, BYTES_PER_BLOB};
+    use ethereum_types::U256;
+
+    use super::*;
+
+    fn mempool_tx(sender: Address, nonce: u64) -> MempoolTransaction {
+        let transaction = Transaction::LegacyTransaction(LegacyTransaction {
+            nonce,
+            gas_price: 1,
+            gas: 21_000,
+            to: TxKind::Create,
+            value: U256::zero(),
+            data: Bytes::new(),
+            v: U256::zero(),
+            r: U256::zero(),
+            s: U256::zero(),
+        });
+        MempoolTransaction {
+            hash: H256::random(),
+            sender,
+            transaction,
+        }
+    }
+
+    #[test]
+    fn transactions_are_split_by_nonce_gap() {
+        let mut mempool = Mempool::default();
+        let sender = Address::random();
+        mempool.insert(mempool_tx(sender, 0), 0);
+        mempool.insert(mempool_tx(sender, 2), 0);
+        assert_eq!(mempool.pending().concat().len(), 1);
+        assert_eq!(mempool.sender_len(sender), 2);
+
+        // Filling the gap promotes the queued transaction
+        mempool.insert(mempool_tx(sender, 1), 0);
+        let pending = mempool.pending().concat();
+        assert_eq!(
+            pending
+                .iter()
+                .map(|tx| tx.transaction.nonce())
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+
+        // Executed transactions are removed
+        mempool.reorganize(sender, 2);
+        assert_eq!(mempool.len(), 1);
+        assert!(mempool.get_by_nonce(sender, 2).is_some());
+    }
+
+    #[test]
+    fn transaction_with_same_nonce_is_replaced() {
+        let mut mempool = Mempool::default();
+        let sender = Address::random();
+        let original = mempool_tx(sender, 0);
+        let replacement = mempool_tx(sender, 0);
+        mempool.insert(original.clone(), 0);
+        mempool.insert(replacement.clone(), 0);
+        assert_eq!(mempool.len(), 1);
+        assert!(mempool.get(original.hash).is_none());
+        assert_eq!(mempool.get(replacement.hash), Some(&replacement));
+    }
+
+    #[test]
+    fn blobs_bundle_is_removed_along_with_its_transaction() {
+        let mut mempool = Mempool::default();
+        let sender = Address::random();
+        let transaction = mempool_tx(sender, 0);
+        let blobs_bundle = BlobsBundle {
+            blobs: vec![[1; BYTES_PER_BLOB]],
+            commitments: vec![[2; 48]],
+            proofs: vec![[3; 48]],
+        };
+        let versioned_hash = blobs_bundle.generate_versioned_hashes()[0];
+        mempool.insert_blob_transaction(transaction.clone(), blobs_bundle.clone(), 0);
+        assert_eq!(mempool.blob_count(), 1);
+        assert_eq!(
+            mempool.get_blobs_bundle(transaction.hash),
+            Some(&blobs_bundle)
+        );
+        assert_eq!(
+            mempool.get_blob(versioned_hash),
+            Some((&blobs_bundle.blobs[0], &blobs_bundle.proofs[0]))
+        );
+
+        mempool.reorganize(sender, 1);
+        assert_eq!(mempool.blob_count(), 0);
+        assert!(mempool.get_blob(versioned_hash).is_none());
+    }
+}
@@ -3,9 +3,11 @@ use std::marker::PhantomData;
 use bytes::Bytes;
 use ethereum_rust_core::{
     rlp::{decode::RLPDecode, encode::RLPEncode},
-    types::{AccountInfo, BlockBody, BlockHash, BlockHeader, Receipt},
-    Address, H256,
+    types::{AccountInfo, Block, BlockBody, BlockHash, BlockHeader, Receipt},
+    Address, H256, U256,
 };
+
+use crate::state_diff::StateDiff;
 #[cfg(feature = "libmdbx")]
 use libmdbx::orm::{Decodable, Encodable};
 
@@ -19,6 +21,9 @@ pub type AccountCodeRLP = Rlp<Bytes>;
 pub type BlockHashRLP = Rlp<BlockHash>;
 pub type BlockHeaderRLP = Rlp<BlockHeader>;
 pub type BlockBodyRLP = Rlp<BlockBody>;
+pub type PayloadRLP = Rlp<(Block, U256)>;
+pub type SideBlockRLP = Rlp<(Block, Vec<Receipt>)>;
+pub type StateDiffRLP = Rlp<StateDiff>;
 
 // Receipt types
 pub type ReceiptRLP = Rlp<Receipt>;
@@ -0,0 +1,235 @@
+// Consistent read-only views of the store
+// Writers need the store's exclusive lock to commit, so everything read through a snapshot belongs to the same
+// version of the store, even if blocks are being added in the meantime
+use std::sync::RwLockReadGuard;
+
+use bytes::Bytes;
+use ethereum_rust_core::rlp::decode::RLPDecode;
+use ethereum_rust_core::types::{
+    AccountInfo, AccountState, BlockBody, BlockHash, BlockHeader, BlockNumber, Index, Receipt,
+    Transaction,
+};
+use ethereum_types::{Address, H256, U256};
+
+use crate::{
+    engines::api::StoreEngine, error::StoreError, hash_key, StateHistory, EMPTY_TRIE_HASH,
+    PRUNED_STATE_HISTORY,
+};
+
+/// Read-only view of the store at a given point in time.
+/// Writes to the store wait until every snapshot is dropped, so snapshots should be short-lived, and the store
+/// must not be accessed other than through the snapshot while holding one, as that could wait on a pending write
+pub struct StoreSnapshot<'a> {
+    engine: RwLockReadGuard<'a, dyn StoreEngine>,
+    state_history: StateHistory,
+}
+
+impl<'a> StoreSnapshot<'a> {
+    pub(crate) fn new(
+        engine: RwLockReadGuard<'a, dyn StoreEngine>,
+        state_history: StateHistory,
+    ) -> Self {
+        Self {
+            engine,
+            state_history,
+        }
+    }
+
+    pub fn get_account_info(&self, address: Address) -> Result<Option<AccountInfo>, StoreError> {
+        self.engine.get_account_info(address)
+    }
+
+    pub fn get_storage_at(
+        &self,
+        address: Address,
+        storage_key: H256,
+    ) -> Result<Option<U256>, StoreError> {
+        self.engine.get_storage_at(address, storage_key)
+    }
+
+    pub fn get_account_code(&self, code_hash: H256) -> Result<Option<Bytes>, StoreError> {
+        self.engine.get_account_code(code_hash)
+    }
+
+    pub fn get_block_header(
+        &self,
+        block_number: BlockNumber,
+    ) -> Result<Option<BlockHeader>, StoreError> {
+        self.engine.get_block_header(block_number)
+    }
+
+    pub fn get_block_body(
+        &self,
+        block_number: BlockNumber,
+    ) -> Result<Option<BlockBody>, StoreError> {
+        self.engine.get_block_body(block_number)
+    }
+
+    pub fn get_block_number(
+        &self,
+        block_hash: BlockHash,
+    ) -> Result<Option<BlockNumber>, StoreError> {
+        self.engine.get_block_number(block_hash)
+    }
+
+    pub fn get_transaction_location(
+        &self,
+        transaction_hash: H256,
+    ) -> Result<Option<(BlockNumber, Index)>, StoreError> {
+        self.engine.get_transaction_location(transaction_hash)
+    }
+
+    pub fn get_transaction_by_hash(
+        &self,
+        transaction_hash: H256,
+    ) -> Result<Option<Transaction>, StoreError> {
+        self.engine.get_transaction_by_hash(transaction_hash)
+    }
+
+    pub fn get_receipt(
+        &self,
+        block_number: BlockNumber,
+        index: Index,
+    ) -> Result<Option<Receipt>, StoreError> {
+        self.engine.get_receipt(block_number, index)
+    }
+
+    pub fn get_earliest_block_number(&self) -> Result<Option<BlockNumber>, StoreError> {
+        self.engine.get_earliest_block_number()
+    }
+
+    pub fn get_finalized_block_number(&self) -> Result<Option<BlockNumber>, StoreError> {
+        self.engine.get_finalized_block_number()
+    }
+
+    pub fn get_safe_block_number(&self) -> Result<Option<BlockNumber>, StoreError> {
+        self.engine.get_safe_block_number()
+    }
+
+    pub fn get_latest_block_number(&self) -> Result<Option<BlockNumber>, StoreError> {
+        self.engine.get_latest_block_number()
+    }
+
+    pub fn get_pending_block_number(&self) -> Result<Option<BlockNumber>, StoreError> {
+        self.engine.get_pending_block_number()
+    }
+
+    /// Returns the root hash of the world state trie
+    pub fn world_state_root(&self) -> Result<H256, StoreError> {
+        Ok(self.engine.get_state_root()?.unwrap_or(*EMPTY_TRIE_HASH))
+    }
+
+    /// Returns the state root of a block if its state can be queried, which in pruned mode is only the case for
+    /// the latest [PRUNED_STATE_HISTORY] blocks
+    pub fn get_state_root_for_block(
+        &self,
+        block_number: BlockNumber,
+    ) -> Result<Option<H256>, StoreError> {
+        let Some(header) = self.engine.get_block_header(block_number)? else {
+            return Ok(None);
+        };
+        if self.state_history == StateHistory::Pruned {
+            let latest = self.engine.get_latest_block_number()?.unwrap_or_default();
+            if block_number + PRUNED_STATE_HISTORY <= latest {
+                return Ok(None);
+            }
+        }
+        Ok(Some(header.state_root))
+    }
+
+    /// Obtain the account info of an address in the world state with the given root
+    pub fn get_account_info_by_state_root(
+        &self,
+        state_root: H256,
+        address: Address,
+    ) -> Result<Option<AccountInfo>, StoreError> {
+        Ok(self
+            .get_account_state_by_state_root(state_root, address)?
+            .map(|account_state| AccountInfo {
+                code_hash: account_state.code_hash,
+                balance: account_state.balance,
+                nonce: account_state.nonce,
+            }))
+    }
+
+    /// Obtain the account state (including its storage root) of an address in the world state with the given root
+    pub fn get_account_state_by_state_root(
+        &self,
+        state_root: H256,
+        address: Address,
+    ) -> Result<Option<AccountState>, StoreError> {
+        self.engine
+            .open_trie(state_root)
+            .get_from_root(state_root, &hash_key(address.as_bytes()))?
+            .map(|rlp| AccountState::decode(&rlp).map_err(StoreError::RLPDecode))
+            .transpose()
+    }
+
+    /// Obtain a storage value of an address in the world state with the given root
+    pub fn get_storage_by_state_root(
+        &self,
+        state_root: H256,
+        address: Address,
+        storage_key: H256,
+    ) -> Result<Option<U256>, StoreError> {
+        let Some(account_state) = self.get_account_state_by_state_root(state_root, address)? else {
+            return Ok(None);
+        };
+        let storage_root = account_state.storage_root;
+        self.engine
+            .open_trie(storage_root)
+            .get_from_root(storage_root, &hash_key(storage_key.as_bytes()))?
+            .map(|rlp| U256::decode(&rlp).map_err(StoreError::RLPDecode))
+            .transpose()
+    }
+
+    /// Obtain every storage slot of an address in the world state with the given root.
+    /// Storage tries are keyed by the hash of the slot keys, so the slots are taken from the current state, which
+    /// is only possible if the account's storage is the same in both states
+    pub fn get_account_storage_by_state_root(
+        &self,
+        state_root: H256,
+        address: Address,
+    ) -> Result<Vec<(H256, U256)>, StoreError> {
+        let storage_root = self
+            .get_account_state_by_state_root(state_root, address)?
+            .map(|account_state| account_state.storage_root)
+            .unwrap_or(*EMPTY_TRIE_HASH);
+        if storage_root == *EMPTY_TRIE_HASH {
+            return Ok(Vec::new());
+        }
+        let current_storage_root = self
+            .get_account_state_by_state_root(self.world_state_root()?, address)?
+            .map(|account_state| account_state.storage_root);
+        if current_storage_root != Some(storage_root) {
+            return Err(StoreError::Custom(format!(
+                "Storage of account {address:#x} at state root {state_root:#x} is not available"
+            )));
+        }
+        Ok(self.engine.account_storage_iter(address)?.collect())
+    }
+
+    /// Builds a Merkle proof of an address' account in the world state with the given root.
+    /// The proof's path is the keccak hash of the address, see [verify_proof](crate::verify_proof)
+    pub fn get_account_proof(
+        &self,
+        state_root: H256,
+        address: Address,
+    ) -> Result<Vec<Vec<u8>>, StoreError> {
+        self.engine
+            .open_trie(state_root)
+            .get_proof(&hash_key(address.as_bytes()))
+    }
+
+    /// Builds a Merkle proof of a storage slot in the storage trie with the given root.
+    /// The proof's path is the keccak hash of the storage key, see [verify_proof](crate::verify_proof)
+    pub fn get_storage_proof(
+        &self,
+        storage_root: H256,
+        storage_key: H256,
+    ) -> Result<Vec<Vec<u8>>, StoreError> {
+        self.engine
+            .open_trie(storage_root)
+            .get_proof(&hash_key(storage_key.as_bytes()))
+    }
+}
@@ -0,0 +1,137 @@
+// Contains the state changes produced by executing a block
+// Each change keeps the value it replaced so it can be reverted when unwinding the block during a reorg
+use bytes::Bytes;
+use ethereum_rust_core::{
+    rlp::{
+        decode::RLPDecode,
+        encode::RLPEncode,
+        error::RLPDecodeError,
+        structs::{Decoder, Encoder},
+    },
+    types::AccountInfo,
+};
+use ethereum_types::{Address, H256, U256};
+
+/// State changes produced by executing a block
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StateDiff {
+    pub accounts: Vec<AccountDiff>,
+}
+
+/// Changes made to a single account
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountDiff {
+    pub address: Address,
+    /// Account info before the block was executed (None if the account didn't exist)
+    pub previous_info: Option<AccountInfo>,
+    /// Account info after the block was executed (None if the account was removed)
+    pub new_info: Option<AccountInfo>,
+    /// Code deployed to the account during the block (code hash and bytecode)
+    pub new_code: Option<(H256, Bytes)>,
+    /// Whether the account's storage was wiped (ie. the account was destroyed)
+    pub storage_wiped: bool,
+    /// Full account storage before it was wiped, only present if `storage_wiped` is set
+    pub wiped_storage: Vec<(H256, U256)>,
+    /// Storage slots modified during the block
+    pub storage: Vec<StorageSlotDiff>,
+}
+
+/// Change made to a single storage slot
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageSlotDiff {
+    pub key: H256,
+    pub previous_value: U256,
+    pub new_value: U256,
+}
+
+impl AccountDiff {
+    /// Creates a diff for an account that hasn't been modified yet
+    pub fn new(address: Address, previous_info: Option<AccountInfo>) -> Self {
+        Self {
+            address,
+            new_info: previous_info.clone(),
+            previous_info,
+            new_code: None,
+            storage_wiped: false,
+            wiped_storage: Vec::new(),
+            storage: Vec::new(),
+        }
+    }
+}
+
+impl RLPEncode for StateDiff {
+    fn encode(&self, buf: &mut dyn bytes::BufMut) {
+        Encoder::new(buf).encode_field(&self.accounts).finish();
+    }
+}
+
+impl RLPDecode for StateDiff {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        let decoder = Decoder::new(rlp)?;
+        let (accounts, decoder) = decoder.decode_field("accounts")?;
+        Ok((Self { accounts }, decoder.finish()?))
+    }
+}
+
+impl RLPEncode for AccountDiff {
+    fn encode(&self, buf: &mut dyn bytes::BufMut) {
+        // Optional values are encoded as lists of zero or one elements as they are not the last fields
+        Encoder::new(buf)
+            .encode_field(&self.address)
+            .encode_field(&self.previous_info.iter().cloned().collect::<Vec<_>>())
+            .encode_field(&self.new_info.iter().cloned().collect::<Vec<_>>())
+            .encode_field(&self.new_code.iter().cloned().collect::<Vec<_>>())
+            .encode_field(&self.storage_wiped)
+            .encode_field(&self.wiped_storage)
+            .encode_field(&self.storage)
+            .finish();
+    }
+}
+
+impl RLPDecode for AccountDiff {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        let decoder = Decoder::new(rlp)?;
+        let (address, decoder) = decoder.decode_field("address")?;
+        let (previous_info, decoder) = decoder.decode_field::<Vec<AccountInfo>>("previous_info")?;
+        let (new_info, decoder) = decoder.decode_field::<Vec<AccountInfo>>("new_info")?;
+        let (new_code, decoder) = decoder.decode_field::<Vec<(H256, Bytes)>>("new_code")?;
+        let (storage_wiped, decoder) = decoder.decode_field("storage_wiped")?;
+        let (wiped_storage, decoder) = decoder.decode_field("wiped_storage")?;
+        let (storage, decoder) = decoder.decode_field("storage")?;
+        let account_diff = AccountDiff {
+            address,
+            previous_info: previous_info.into_iter().next(),
+            new_info: new_info.into_iter().next(),
+            new_code: new_code.into_iter().next(),
+            storage_wiped,
+            wiped_storage,
+            storage,
+        };
+        Ok((account_diff, decoder.finish()?))
+    }
+}
+
+impl RLPEncode for StorageSlotDiff {
+    fn encode(&self, buf: &mut dyn bytes::BufMut) {
+        Encoder::new(buf)
+            .encode_field(&self.key)
+            .encode_field(&self.previous_value)
+            .encode_field(&self.new_value)
+            .finish();
+    }
+}
+
+impl RLPDecode for StorageSlotDiff {
+    fn decode_unfinished(rlp: &[u8]) -> Result<(Self, &[u8]), RLPDecodeError> {
+        let decoder = Decoder::new(rlp)?;
+        let (key, decoder) = decoder.decode_field("key")?;
+        let (previous_value, decoder) = decoder.decode_field("previous_value")?;
+        let (new_value, decoder) = decoder.decode_field("new_value")?;
+        let slot_diff = StorageSlotDiff {
+            key,
+            previous_value,
+            new_value,
+        };
+        Ok((slot_diff, decoder.finish()?))
+    }
+}
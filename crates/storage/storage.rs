@@ -3,32 +3,69 @@ use self::engines::in_memory::Store as InMemoryStore;
 #[cfg(feature = "libmdbx")]
 use self::engines::libmdbx::Store as LibmdbxStore;
 use self::error::StoreError;
+pub use self::events::{ChainEvent, CHAIN_EVENTS_CAPACITY};
+pub use self::mempool::{Mempool, MempoolTransaction};
+pub use self::state_diff::{AccountDiff, StateDiff, StorageSlotDiff};
 use bytes::Bytes;
 use engines::api::StoreEngine;
-use ethereum_rust_core::rlp::encode::RLPEncode;
+use ethereum_rust_core::rlp::decode::RLPDecode;
 use ethereum_rust_core::types::{
-    Account, AccountInfo, AccountState, Block, BlockBody, BlockHash, BlockHeader, BlockNumber,
-    ChainConfig, Genesis, Index, Receipt, Transaction,
+    Account, AccountInfo, AccountState, Blob, BlobsBundle, Block, BlockBody, BlockHash,
+    BlockHeader, BlockNumber, ChainConfig, Genesis, Index, Proof, Receipt, Transaction,
 };
 use ethereum_types::{Address, H256, U256};
-use patricia_merkle_tree::PatriciaMerkleTree;
 use sha3::{Digest as _, Keccak256};
-use std::collections::HashMap;
+pub use snapshot::StoreSnapshot;
+use std::collections::HashSet;
 use std::fmt::Debug;
-use std::sync::{Arc, Mutex};
-use tracing::info;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+use trie::Trie;
+pub use trie::{verify_proof, EMPTY_TRIE_HASH};
+pub use write_batch::WriteBatch;
 
 mod engines;
 pub mod error;
+mod events;
+mod mempool;
 mod rlp;
+mod snapshot;
+mod state_diff;
 /// TODO: Remove this allow once the trie is integrated into the codebase
 #[allow(unused)]
 mod trie;
+mod write_batch;
+
+/// Number of recent blocks whose state can be queried when running in pruned mode
+pub const PRUNED_STATE_HISTORY: u64 = 128;
+
+/// Number of trie nodes walked each time the store is locked while pruning the state
+const STATE_PRUNING_STEP: usize = 10_000;
 
 #[derive(Debug, Clone)]
 pub struct Store {
-    engine: Arc<Mutex<dyn StoreEngine>>,
-    //world_state:  PatriciaMerkleTree<Vec<u8>, Vec<u8>, Keccak256>,
+    /// Reads take a shared lock and run in parallel, writes take an exclusive one.
+    /// Block execution only reads from the store, so writers just hold the lock while committing a batch
+    engine: Arc<RwLock<dyn StoreEngine>>,
+    mempool: Arc<Mutex<Mempool>>,
+    state_history: StateHistory,
+    chain_events: broadcast::Sender<ChainEvent>,
+    /// Keys of the trie nodes stored since the state pruning in course started, which must not be swept by it.
+    /// None if the state is not being pruned
+    pruning_writes: Arc<Mutex<Option<HashSet<Vec<u8>>>>>,
+    /// Held by whoever is changing the block tree, see [Store::lock_chain_writes]
+    chain_writes: Arc<Mutex<()>>,
+}
+
+/// How much of the historical state is kept
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StateHistory {
+    /// Only the state of the latest [PRUNED_STATE_HISTORY] blocks is kept
+    #[default]
+    Pruned,
+    /// The state of every block is kept
+    Archive,
 }
 
 #[allow(dead_code)]
@@ -46,46 +83,86 @@ impl Store {
         let store = match engine_type {
             #[cfg(feature = "libmdbx")]
             EngineType::Libmdbx => Self {
-                engine: Arc::new(Mutex::new(LibmdbxStore::new(path)?)),
-                // TODO: build from DB
-                //world_state: PatriciaMerkleTree::default(),
+                engine: Arc::new(RwLock::new(LibmdbxStore::new(path)?)),
+                mempool: Arc::new(Mutex::new(Mempool::default())),
+                state_history: StateHistory::default(),
+                chain_events: broadcast::channel(CHAIN_EVENTS_CAPACITY).0,
+                pruning_writes: Arc::new(Mutex::new(None)),
+                chain_writes: Arc::new(Mutex::new(())),
             },
             #[cfg(feature = "in_memory")]
             EngineType::InMemory => Self {
-                engine: Arc::new(Mutex::new(InMemoryStore::new()?)),
-                //world_state: PatriciaMerkleTree::default(),
+                engine: Arc::new(RwLock::new(InMemoryStore::new()?)),
+                mempool: Arc::new(Mutex::new(Mempool::default())),
+                state_history: StateHistory::default(),
+                chain_events: broadcast::channel(CHAIN_EVENTS_CAPACITY).0,
+                pruning_writes: Arc::new(Mutex::new(None)),
+                chain_writes: Arc::new(Mutex::new(())),
             },
         };
+        // DBs created before the world state trie was persisted need to have it built from their state
+        if store.read_engine().get_state_root()?.is_none() {
+            store.rebuild_world_state()?;
+        }
         info!("Started store engine");
         Ok(store)
     }
 
+    /// Sets how much of the historical state is kept, should be called before the store is shared
+    pub fn set_state_history(&mut self, state_history: StateHistory) {
+        info!("Keeping historical state: {state_history:?}");
+        self.state_history = state_history;
+    }
+
+    /// Waits until no other writer, such as the engine API or the syncer, is changing the block tree of this store,
+    /// and keeps the others waiting until the returned guard is dropped
+    pub fn lock_chain_writes(&self) -> MutexGuard<'_, ()> {
+        self.chain_writes
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Subscribes to the changes of the canonical chain and the mempool.
+    /// See [ChainEvent]
+    pub fn subscribe_chain_events(&self) -> broadcast::Receiver<ChainEvent> {
+        self.chain_events.subscribe()
+    }
+
+    /// Publishes a change of the canonical chain or the mempool, it is dropped if there are no subscribers
+    pub fn notify_chain_event(&self, event: ChainEvent) {
+        let _ = self.chain_events.send(event);
+    }
+
+    /// Takes a consistent read-only view of the store, for reads that must all see the same version of it.
+    /// See [StoreSnapshot]
+    pub fn snapshot(&self) -> StoreSnapshot<'_> {
+        StoreSnapshot::new(self.read_engine(), self.state_history)
+    }
+
+    /// Starts a batch of writes that are committed to the store all at once.
+    /// See [WriteBatch]
+    pub fn write_batch(&self) -> WriteBatch {
+        WriteBatch::new(self.clone())
+    }
+
     pub fn add_account_info(
         &self,
         address: Address,
         account_info: AccountInfo,
     ) -> Result<(), StoreError> {
-        self.engine
-            .clone()
-            .lock()
-            .unwrap()
-            .add_account_info(address, account_info)
+        let mut batch = self.write_batch();
+        batch.add_account_info(address, account_info);
+        batch.commit()
     }
 
     pub fn get_account_info(&self, address: Address) -> Result<Option<AccountInfo>, StoreError> {
-        self.engine
-            .clone()
-            .lock()
-            .unwrap()
-            .get_account_info(address)
+        self.read_engine().get_account_info(address)
     }
 
     pub fn remove_account_info(&self, address: Address) -> Result<(), StoreError> {
-        self.engine
-            .clone()
-            .lock()
-            .unwrap()
-            .remove_account_info(address)
+        let mut batch = self.write_batch();
+        batch.remove_account_info(address);
+        batch.commit()
     }
 
     pub fn add_block_header(
@@ -93,10 +170,7 @@ impl Store {
         block_number: BlockNumber,
         block_header: BlockHeader,
     ) -> Result<(), StoreError> {
-        self.engine
-            .clone()
-            .lock()
-            .unwrap()
+        self.write_engine()
             .add_block_header(block_number, block_header)
     }
 
@@ -104,11 +178,7 @@ impl Store {
         &self,
         block_number: BlockNumber,
     ) -> Result<Option<BlockHeader>, StoreError> {
-        self.engine
-            .clone()
-            .lock()
-            .unwrap()
-            .get_block_header(block_number)
+        self.read_engine().get_block_header(block_number)
     }
 
     pub fn add_block_body(
@@ -116,22 +186,14 @@ impl Store {
         block_number: BlockNumber,
         block_body: BlockBody,
     ) -> Result<(), StoreError> {
-        self.engine
-            .clone()
-            .lock()
-            .unwrap()
-            .add_block_body(block_number, block_body)
+        self.write_engine().add_block_body(block_number, block_body)
     }
 
     pub fn get_block_body(
         &self,
         block_number: BlockNumber,
     ) -> Result<Option<BlockBody>, StoreError> {
-        self.engine
-            .clone()
-            .lock()
-            .unwrap()
-            .get_block_body(block_number)
+        self.read_engine().get_block_body(block_number)
     }
 
     pub fn add_block_number(
@@ -139,10 +201,7 @@ impl Store {
         block_hash: BlockHash,
         block_number: BlockNumber,
     ) -> Result<(), StoreError> {
-        self.engine
-            .clone()
-            .lock()
-            .unwrap()
+        self.write_engine()
             .add_block_number(block_hash, block_number)
     }
 
@@ -150,11 +209,48 @@ impl Store {
         &self,
         block_hash: BlockHash,
     ) -> Result<Option<BlockNumber>, StoreError> {
-        self.engine
-            .clone()
-            .lock()
-            .unwrap()
-            .get_block_number(block_hash)
+        self.read_engine().get_block_number(block_hash)
+    }
+
+    pub fn remove_block_number(&self, block_hash: BlockHash) -> Result<(), StoreError> {
+        self.write_engine().remove_block_number(block_hash)
+    }
+
+    pub fn remove_block(&self, block_number: BlockNumber) -> Result<(), StoreError> {
+        self.write_engine().remove_block(block_number)
+    }
+
+    pub fn add_side_block(
+        &self,
+        block_hash: BlockHash,
+        block: Block,
+        receipts: Vec<Receipt>,
+    ) -> Result<(), StoreError> {
+        self.write_engine()
+            .add_side_block(block_hash, block, receipts)
+    }
+
+    pub fn get_side_block(
+        &self,
+        block_hash: BlockHash,
+    ) -> Result<Option<(Block, Vec<Receipt>)>, StoreError> {
+        self.read_engine().get_side_block(block_hash)
+    }
+
+    pub fn remove_side_block(&self, block_hash: BlockHash) -> Result<(), StoreError> {
+        self.write_engine().remove_side_block(block_hash)
+    }
+
+    pub fn add_state_diff(
+        &self,
+        block_hash: BlockHash,
+        state_diff: StateDiff,
+    ) -> Result<(), StoreError> {
+        self.write_engine().add_state_diff(block_hash, state_diff)
+    }
+
+    pub fn get_state_diff(&self, block_hash: BlockHash) -> Result<Option<StateDiff>, StoreError> {
+        self.read_engine().get_state_diff(block_hash)
     }
 
     pub fn add_transaction_location(
@@ -163,9 +259,7 @@ impl Store {
         block_number: BlockNumber,
         index: Index,
     ) -> Result<(), StoreError> {
-        self.engine
-            .lock()
-            .unwrap()
+        self.write_engine()
             .add_transaction_location(transaction_hash, block_number, index)
     }
 
@@ -173,51 +267,146 @@ impl Store {
         &self,
         transaction_hash: H256,
     ) -> Result<Option<(BlockNumber, Index)>, StoreError> {
-        self.engine
+        self.read_engine()
+            .get_transaction_location(transaction_hash)
+    }
+
+    pub fn add_invalid_block(
+        &self,
+        block_hash: BlockHash,
+        latest_valid_hash: BlockHash,
+    ) -> Result<(), StoreError> {
+        self.write_engine()
+            .add_invalid_block(block_hash, latest_valid_hash)
+    }
+
+    pub fn get_latest_valid_ancestor(
+        &self,
+        block_hash: BlockHash,
+    ) -> Result<Option<BlockHash>, StoreError> {
+        self.read_engine().get_latest_valid_ancestor(block_hash)
+    }
+
+    pub fn add_payload(
+        &self,
+        payload_id: u64,
+        block: Block,
+        block_value: U256,
+    ) -> Result<(), StoreError> {
+        self.write_engine()
+            .add_payload(payload_id, block, block_value)
+    }
+
+    pub fn get_payload(&self, payload_id: u64) -> Result<Option<(Block, U256)>, StoreError> {
+        self.read_engine().get_payload(payload_id)
+    }
+
+    /// Adds a transaction to the mempool if it passes the given check, replacing the sender's transaction with the
+    /// same nonce if any. The mempool stays locked from the check to the insertion, so other additions can't make
+    /// the check stale, e.g. by filling the pool in between
+    pub fn add_transaction_to_pool<E>(
+        &self,
+        transaction: MempoolTransaction,
+        account_nonce: u64,
+        check: impl FnOnce(&Mempool, &MempoolTransaction) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut mempool = self.mempool.lock().unwrap();
+        check(&mempool, &transaction)?;
+        mempool.insert(transaction, account_nonce);
+        Ok(())
+    }
+
+    /// Adds a blob transaction to the mempool along with its blob sidecar if it passes the given check.
+    /// See [Store::add_transaction_to_pool]
+    pub fn add_blob_transaction_to_pool<E>(
+        &self,
+        transaction: MempoolTransaction,
+        blobs_bundle: BlobsBundle,
+        account_nonce: u64,
+        check: impl FnOnce(&Mempool, &MempoolTransaction) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut mempool = self.mempool.lock().unwrap();
+        check(&mempool, &transaction)?;
+        mempool.insert_blob_transaction(transaction, blobs_bundle, account_nonce);
+        Ok(())
+    }
+
+    /// Obtain the blob sidecar of a blob transaction in the mempool
+    pub fn get_blobs_bundle_from_pool(&self, transaction_hash: H256) -> Option<BlobsBundle> {
+        self.mempool
             .lock()
             .unwrap()
-            .get_transaction_location(transaction_hash)
+            .get_blobs_bundle(transaction_hash)
+            .cloned()
     }
 
-    pub fn add_account_code(&self, code_hash: H256, code: Bytes) -> Result<(), StoreError> {
-        self.engine
-            .clone()
+    /// Obtain a blob and its proof from the mempool by the blob's versioned hash
+    pub fn get_blob_from_pool(&self, versioned_hash: H256) -> Option<(Blob, Proof)> {
+        self.mempool
             .lock()
             .unwrap()
-            .add_account_code(code_hash, code)
+            .get_blob(versioned_hash)
+            .map(|(blob, proof)| (*blob, *proof))
     }
 
-    pub fn get_account_code(&self, code_hash: H256) -> Result<Option<Bytes>, StoreError> {
-        self.engine
-            .clone()
+    /// Obtain a transaction from the mempool by its hash
+    pub fn get_transaction_from_pool(&self, transaction_hash: H256) -> Option<MempoolTransaction> {
+        self.mempool.lock().unwrap().get(transaction_hash).cloned()
+    }
+
+    /// Updates the sender's transactions in the mempool to its current account nonce
+    pub fn update_pool_sender(&self, sender: Address, account_nonce: u64) {
+        self.mempool
             .lock()
             .unwrap()
-            .get_account_code(code_hash)
+            .reorganize(sender, account_nonce)
+    }
+
+    /// Obtain the addresses of all senders with transactions in the mempool
+    pub fn pool_senders(&self) -> Vec<Address> {
+        self.mempool.lock().unwrap().senders()
+    }
+
+    /// Obtain the pending transactions of each sender in the mempool, sorted by nonce
+    pub fn pending_pool_transactions(&self) -> Vec<Vec<MempoolTransaction>> {
+        self.mempool.lock().unwrap().pending()
+    }
+
+    /// Obtain the amount of transactions in the mempool
+    pub fn pool_len(&self) -> usize {
+        self.mempool.lock().unwrap().len()
+    }
+
+    pub fn remove_transaction_location(&self, transaction_hash: H256) -> Result<(), StoreError> {
+        self.write_engine()
+            .remove_transaction_location(transaction_hash)
+    }
+
+    pub fn add_account_code(&self, code_hash: H256, code: Bytes) -> Result<(), StoreError> {
+        self.write_engine().add_account_code(code_hash, code)
+    }
+
+    pub fn get_account_code(&self, code_hash: H256) -> Result<Option<Bytes>, StoreError> {
+        self.read_engine().get_account_code(code_hash)
     }
 
     pub fn get_code_by_account_address(
         &self,
         address: Address,
     ) -> Result<Option<Bytes>, StoreError> {
-        self.engine
-            .clone()
-            .lock()
-            .unwrap()
-            .get_code_by_account_address(address)
+        self.read_engine().get_code_by_account_address(address)
     }
     pub fn get_nonce_by_account_address(
         &self,
         address: Address,
     ) -> Result<Option<u64>, StoreError> {
-        self.engine
-            .clone()
-            .lock()
-            .unwrap()
-            .get_nonce_by_account_address(address)
+        self.read_engine().get_nonce_by_account_address(address)
     }
 
     pub fn add_account(&self, address: Address, account: Account) -> Result<(), StoreError> {
-        self.engine.lock().unwrap().add_account(address, account)
+        let mut batch = self.write_batch();
+        batch.add_account(address, account);
+        batch.commit()
     }
 
     pub fn add_receipt(
@@ -226,10 +415,7 @@ impl Store {
         index: Index,
         receipt: Receipt,
     ) -> Result<(), StoreError> {
-        self.engine
-            .clone()
-            .lock()
-            .unwrap()
+        self.write_engine()
             .add_receipt(block_number, index, receipt)
     }
 
@@ -238,38 +424,29 @@ impl Store {
         block_number: BlockNumber,
         index: Index,
     ) -> Result<Option<Receipt>, StoreError> {
-        self.engine
-            .clone()
-            .lock()
-            .unwrap()
-            .get_receipt(block_number, index)
+        self.read_engine().get_receipt(block_number, index)
     }
 
+    /// Stores a block as part of the canonical chain and makes it the latest block
     pub fn add_block(&self, block: Block) -> Result<(), StoreError> {
-        // TODO Maybe add both in a single tx?
-        let header = block.header;
-        let number = header.number;
-        let hash = header.compute_block_hash();
-        self.add_transaction_locations(&block.body.transactions, number)?;
-        self.add_block_body(number, block.body)?;
-        self.add_block_header(number, header)?;
-        self.add_block_number(hash, number)?;
-        self.update_latest_block_number(number)
+        let mut batch = self.write_batch();
+        batch.add_block(block);
+        batch.commit()
     }
 
-    fn add_transaction_locations(
-        &self,
-        transactions: &[Transaction],
-        block_number: BlockNumber,
-    ) -> Result<(), StoreError> {
-        for (index, transaction) in transactions.iter().enumerate() {
-            self.add_transaction_location(
-                transaction.compute_hash(),
-                block_number,
-                index as Index,
-            )?;
-        }
-        Ok(())
+    /// Applies the state changes produced by executing a block
+    pub fn apply_state_diff(&self, state_diff: &StateDiff) -> Result<(), StoreError> {
+        let mut batch = self.write_batch();
+        batch.apply_state_diff(state_diff);
+        batch.commit()
+    }
+
+    /// Reverts the state changes produced by executing a block, leaving the state as it was before the block
+    /// Account code is kept, as it is only referenced through its hash
+    pub fn revert_state_diff(&self, state_diff: &StateDiff) -> Result<(), StoreError> {
+        let mut batch = self.write_batch();
+        batch.revert_state_diff(state_diff);
+        batch.commit()
     }
 
     pub fn add_initial_state(&mut self, genesis: Genesis) -> Result<(), StoreError> {
@@ -287,14 +464,14 @@ impl Store {
             }
         }
 
-        // Store genesis block
+        // Store genesis block along with each alloc account
         self.update_earliest_block_number(genesis_block.header.number)?;
-        self.add_block(genesis_block)?;
-
-        // Store each alloc account
+        let mut batch = self.write_batch();
+        batch.add_block(genesis_block);
         for (address, account) in genesis.alloc.into_iter() {
-            self.add_account(address, account.into())?;
+            batch.add_account(address, account.into());
         }
+        batch.commit()?;
 
         // Set chain config
         self.set_chain_config(&genesis.config)
@@ -304,10 +481,7 @@ impl Store {
         &self,
         transaction_hash: H256,
     ) -> Result<Option<Transaction>, StoreError> {
-        self.engine
-            .lock()
-            .unwrap()
-            .get_transaction_by_hash(transaction_hash)
+        self.read_engine().get_transaction_by_hash(transaction_hash)
     }
 
     pub fn add_storage_at(
@@ -316,10 +490,9 @@ impl Store {
         storage_key: H256,
         storage_value: U256,
     ) -> Result<(), StoreError> {
-        self.engine
-            .lock()
-            .unwrap()
-            .add_storage_at(address, storage_key, storage_value)
+        let mut batch = self.write_batch();
+        batch.add_storage_at(address, storage_key, storage_value);
+        batch.commit()
     }
 
     pub fn get_storage_at(
@@ -327,154 +500,315 @@ impl Store {
         address: Address,
         storage_key: H256,
     ) -> Result<Option<U256>, StoreError> {
-        self.engine
-            .lock()
-            .unwrap()
-            .get_storage_at(address, storage_key)
+        self.read_engine().get_storage_at(address, storage_key)
     }
 
     pub fn remove_account_storage(&self, address: Address) -> Result<(), StoreError> {
-        self.engine.lock().unwrap().remove_account_storage(address)
+        let mut batch = self.write_batch();
+        batch.remove_account_storage(address);
+        batch.commit()
     }
 
     pub fn account_storage_iter(
         &self,
         address: Address,
     ) -> Result<Box<dyn Iterator<Item = (H256, U256)>>, StoreError> {
-        self.engine.lock().unwrap().account_storage_iter(address)
+        self.read_engine().account_storage_iter(address)
     }
 
     pub fn remove_account(&self, address: Address) -> Result<(), StoreError> {
-        self.engine.lock().unwrap().remove_account(address)
+        let mut batch = self.write_batch();
+        batch.remove_account(address);
+        batch.commit()
     }
 
     pub fn account_infos_iter(
         &self,
     ) -> Result<Box<dyn Iterator<Item = (Address, AccountInfo)>>, StoreError> {
-        self.engine.lock().unwrap().account_infos_iter()
+        self.read_engine().account_infos_iter()
     }
 
     pub fn increment_balance(&self, address: Address, amount: U256) -> Result<(), StoreError> {
-        self.engine
-            .lock()
-            .unwrap()
-            .increment_balance(address, amount)
+        let mut batch = self.write_batch();
+        batch.increment_balance(address, amount)?;
+        batch.commit()
     }
 
     pub fn set_chain_config(&self, chain_config: &ChainConfig) -> Result<(), StoreError> {
-        self.engine.lock().unwrap().set_chain_config(chain_config)
+        self.write_engine().set_chain_config(chain_config)
     }
 
     pub fn get_chain_config(&self) -> Result<ChainConfig, StoreError> {
-        self.engine.lock().unwrap().get_chain_config()
+        self.read_engine().get_chain_config()
     }
 
     pub fn update_earliest_block_number(
         &self,
         block_number: BlockNumber,
     ) -> Result<(), StoreError> {
-        self.engine
-            .lock()
-            .unwrap()
+        self.write_engine()
             .update_earliest_block_number(block_number)
     }
 
     pub fn get_earliest_block_number(&self) -> Result<Option<BlockNumber>, StoreError> {
-        self.engine.lock().unwrap().get_earliest_block_number()
+        self.read_engine().get_earliest_block_number()
     }
 
     pub fn update_finalized_block_number(
         &self,
         block_number: BlockNumber,
     ) -> Result<(), StoreError> {
-        self.engine
-            .lock()
-            .unwrap()
+        self.write_engine()
             .update_finalized_block_number(block_number)
     }
 
     pub fn get_finalized_block_number(&self) -> Result<Option<BlockNumber>, StoreError> {
-        self.engine.lock().unwrap().get_finalized_block_number()
+        self.read_engine().get_finalized_block_number()
     }
 
     pub fn update_safe_block_number(&self, block_number: BlockNumber) -> Result<(), StoreError> {
-        self.engine
-            .lock()
-            .unwrap()
-            .update_safe_block_number(block_number)
+        self.write_engine().update_safe_block_number(block_number)
     }
 
     pub fn get_safe_block_number(&self) -> Result<Option<BlockNumber>, StoreError> {
-        self.engine.lock().unwrap().get_safe_block_number()
+        self.read_engine().get_safe_block_number()
     }
 
     pub fn update_latest_block_number(&self, block_number: BlockNumber) -> Result<(), StoreError> {
-        self.engine
-            .lock()
-            .unwrap()
-            .update_latest_block_number(block_number)
+        self.write_engine().update_latest_block_number(block_number)
     }
 
     pub fn get_latest_block_number(&self) -> Result<Option<BlockNumber>, StoreError> {
-        self.engine.lock().unwrap().get_latest_block_number()
+        self.read_engine().get_latest_block_number()
     }
 
     pub fn update_pending_block_number(&self, block_number: BlockNumber) -> Result<(), StoreError> {
-        self.engine
-            .lock()
-            .unwrap()
+        self.write_engine()
             .update_pending_block_number(block_number)
     }
 
     pub fn get_pending_block_number(&self) -> Result<Option<BlockNumber>, StoreError> {
-        self.engine.lock().unwrap().get_pending_block_number()
-    }
-
-    /// Returns the root hash of the merkle tree.
-    /// Version 1: computes the trie fully from scratch
-    ///   TODO:
-    ///     Version 2: Keeps trie in memory
-    ///     Version 3: Persists trie in db
-    pub fn world_state_root(&self) -> H256 {
-        // build trie from state
-        let mut trie = self.build_trie_from_state();
-
-        // compute hash from in memory world_state trie
-        //let &root = self.world_state.compute_hash();
-
-        let &root = trie.compute_hash();
-        H256(root.into())
-    }
-
-    fn build_trie_from_state(&self) -> PatriciaMerkleTree<Vec<u8>, Vec<u8>, Keccak256> {
-        let mut trie = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
-        for (address, account) in self.account_infos_iter().unwrap() {
-            // Key: Keccak(address)
-            let k = Keccak256::new_with_prefix(address.to_fixed_bytes())
-                .finalize()
-                .to_vec();
-
-            let storage: HashMap<H256, U256> = self
-                .account_storage_iter(address)
-                .unwrap_or_else(|_| panic!("Failed to retrieve storage for {address}"))
-                .collect();
-            // Value: account
-            let mut v = Vec::new();
-            AccountState::from_info_and_storage(&account, &storage).encode(&mut v);
-            trie.insert(k, v);
+        self.read_engine().get_pending_block_number()
+    }
+
+    /// Returns the root hash of the world state trie
+    /// The trie is kept up to date as the state changes, so this is a lookup of the stored root
+    pub fn world_state_root(&self) -> Result<H256, StoreError> {
+        Ok(self
+            .read_engine()
+            .get_state_root()?
+            .unwrap_or(*EMPTY_TRIE_HASH))
+    }
+
+    /// Returns the state root of a block if its state can be queried, which in pruned mode is only the case for
+    /// the latest [PRUNED_STATE_HISTORY] blocks
+    pub fn get_state_root_for_block(
+        &self,
+        block_number: BlockNumber,
+    ) -> Result<Option<H256>, StoreError> {
+        self.snapshot().get_state_root_for_block(block_number)
+    }
+
+    /// Obtain the account info of an address in the world state with the given root
+    pub fn get_account_info_by_state_root(
+        &self,
+        state_root: H256,
+        address: Address,
+    ) -> Result<Option<AccountInfo>, StoreError> {
+        self.snapshot()
+            .get_account_info_by_state_root(state_root, address)
+    }
+
+    /// Obtain the account state (including its storage root) of an address in the world state with the given root
+    pub fn get_account_state_by_state_root(
+        &self,
+        state_root: H256,
+        address: Address,
+    ) -> Result<Option<AccountState>, StoreError> {
+        self.snapshot()
+            .get_account_state_by_state_root(state_root, address)
+    }
+
+    /// Builds a Merkle proof of an address' account in the world state with the given root.
+    /// The proof's path is the keccak hash of the address, see [verify_proof]
+    pub fn get_account_proof(
+        &self,
+        state_root: H256,
+        address: Address,
+    ) -> Result<Vec<Vec<u8>>, StoreError> {
+        self.snapshot().get_account_proof(state_root, address)
+    }
+
+    /// Builds a Merkle proof of a storage slot in the storage trie with the given root.
+    /// The proof's path is the keccak hash of the storage key, see [verify_proof]
+    pub fn get_storage_proof(
+        &self,
+        storage_root: H256,
+        storage_key: H256,
+    ) -> Result<Vec<Vec<u8>>, StoreError> {
+        self.snapshot().get_storage_proof(storage_root, storage_key)
+    }
+
+    /// Obtain a storage value of an address in the world state with the given root
+    pub fn get_storage_by_state_root(
+        &self,
+        state_root: H256,
+        address: Address,
+        storage_key: H256,
+    ) -> Result<Option<U256>, StoreError> {
+        self.snapshot()
+            .get_storage_by_state_root(state_root, address, storage_key)
+    }
+
+    /// Obtain every storage slot of an address in the world state with the given root.
+    /// See [StoreSnapshot::get_account_storage_by_state_root]
+    pub fn get_account_storage_by_state_root(
+        &self,
+        state_root: H256,
+        address: Address,
+    ) -> Result<Vec<(H256, U256)>, StoreError> {
+        self.snapshot()
+            .get_account_storage_by_state_root(state_root, address)
+    }
+
+    /// Prunes the state on a separate thread, see [Store::prune_state]
+    pub fn prune_state_in_background(&self) {
+        let store = self.clone();
+        std::thread::spawn(move || {
+            if let Err(error) = store.prune_state() {
+                warn!("Failed to prune the state: {error}");
+            }
+        });
+    }
+
+    /// Removes the trie nodes that are no longer needed by the current state nor by the state of the latest
+    /// [PRUNED_STATE_HISTORY] blocks, side blocks included. Does nothing when keeping the whole state history, or if
+    /// the state is already being pruned.
+    /// The store is only locked for a few nodes at a time, so blocks can still be added while pruning
+    pub fn prune_state(&self) -> Result<(), StoreError> {
+        if self.state_history == StateHistory::Archive {
+            return Ok(());
+        }
+        {
+            let mut pruning_writes = self.pruning_writes.lock().unwrap();
+            if pruning_writes.is_some() {
+                return Ok(());
+            }
+            pruning_writes.replace(HashSet::new());
+        }
+        let result = self.mark_and_sweep_state();
+        self.pruning_writes.lock().unwrap().take();
+        result
+    }
+
+    /// Removes the trie nodes that can't be reached from the states to keep.
+    /// Nodes stored after the states to keep were picked are either reachable from them or recorded as written
+    /// during the pruning, so the states committed in the meantime are kept too
+    fn mark_and_sweep_state(&self) -> Result<(), StoreError> {
+        let mut pending = {
+            let engine = self.read_engine();
+            let latest = engine.get_latest_block_number()?.unwrap_or_default();
+            let oldest = latest.saturating_sub(PRUNED_STATE_HISTORY - 1);
+            let mut state_roots = vec![engine.get_state_root()?.unwrap_or(*EMPTY_TRIE_HASH)];
+            for block_number in oldest..=latest {
+                if let Some(header) = engine.get_block_header(block_number)? {
+                    state_roots.push(header.state_root);
+                }
+            }
+            // Recent side blocks can still be built on top of or reorged to, so their states are kept as well
+            for header in engine.side_block_headers()? {
+                if header.number >= oldest {
+                    state_roots.push(header.state_root);
+                }
+            }
+            state_roots
+                .into_iter()
+                .map(|state_root| state_root.as_bytes().to_vec())
+                .collect::<Vec<_>>()
+        };
+        // Mark the nodes of the state tries to keep along with the storage tries they reference
+        let mut nodes = HashSet::new();
+        let mut storage_roots = Vec::new();
+        while !pending.is_empty() {
+            Trie::new(self.read_engine().trie_db()).collect_nodes_from(
+                &mut pending,
+                &mut nodes,
+                STATE_PRUNING_STEP,
+                &mut |rlp| {
+                    let storage_root = AccountState::decode(rlp)?.storage_root;
+                    if storage_root != *EMPTY_TRIE_HASH {
+                        storage_roots.push(storage_root.as_bytes().to_vec());
+                    }
+                    Ok(())
+                },
+            )?;
+        }
+        while !storage_roots.is_empty() {
+            Trie::new(self.read_engine().trie_db()).collect_nodes_from(
+                &mut storage_roots,
+                &mut nodes,
+                STATE_PRUNING_STEP,
+                &mut |_| Ok(()),
+            )?;
+        }
+        // Sweep the rest, except for the nodes written since the pruning started
+        let mut next = None;
+        loop {
+            let mut engine = self.write_engine();
+            let pruning_writes = self.pruning_writes.lock().unwrap();
+            let written = pruning_writes.as_ref();
+            let keep = |key: &[u8]| {
+                nodes.contains(key) || written.is_some_and(|written| written.contains(key))
+            };
+            next = engine.retain_trie_nodes(&keep, next, STATE_PRUNING_STEP)?;
+            if next.is_none() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Records the keys of trie nodes about to be stored, so the state pruning in course doesn't sweep them.
+    /// Must be called while holding the write lock the nodes are stored with
+    fn record_trie_writes<'a>(&self, keys: impl Iterator<Item = &'a Vec<u8>>) {
+        if let Some(written) = self.pruning_writes.lock().unwrap().as_mut() {
+            written.extend(keys.cloned());
         }
-        trie
+    }
+
+    /// Builds the world state trie from scratch out of the stored state
+    fn rebuild_world_state(&self) -> Result<(), StoreError> {
+        let addresses: Vec<_> = self
+            .read_engine()
+            .account_infos_iter()?
+            .map(|(address, _)| address)
+            .collect();
+        let mut batch = self.write_batch();
+        batch.rebuild_world_state(addresses);
+        batch.commit()
+    }
+
+    fn read_engine(&self) -> RwLockReadGuard<'_, dyn StoreEngine> {
+        self.engine.read().unwrap()
+    }
+
+    fn write_engine(&self) -> RwLockWriteGuard<'_, dyn StoreEngine> {
+        self.engine.write().unwrap()
     }
 }
 
+/// Hashes a key of the world state or storage tries, which are keyed by the keccak hash of addresses and slot keys
+fn hash_key(key: &[u8]) -> Vec<u8> {
+    Keccak256::new_with_prefix(key).finalize().to_vec()
+}
+
 #[cfg(test)]
 mod tests {
-    use std::{fs, panic, str::FromStr};
+    use std::{collections::HashMap, fs, panic, str::FromStr};
 
     use bytes::Bytes;
     use ethereum_rust_core::{
         rlp::decode::RLPDecode,
-        types::{self, Transaction, TxType},
+        types::{self, GenesisAccount, Transaction, TxType},
         Bloom,
     };
     use ethereum_types::{H256, U256};
@@ -493,6 +827,32 @@ mod tests {
         test_store_suite(EngineType::Libmdbx);
     }
 
+    #[cfg(feature = "libmdbx")]
+    #[test]
+    fn test_world_state_root_persists_across_restarts() {
+        remove_test_dbs("store-test-db-restart");
+        let state_root = {
+            let store = Store::new("store-test-db-restart", EngineType::Libmdbx)
+                .expect("Failed to create test db");
+            store
+                .add_account(
+                    Address::random(),
+                    Account {
+                        storage: HashMap::from([(H256::random(), U256::from(7))]),
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+            store.world_state_root().unwrap()
+        };
+        assert_ne!(state_root, *EMPTY_TRIE_HASH);
+        let store = Store::new("store-test-db-restart", EngineType::Libmdbx)
+            .expect("Failed to reopen test db");
+        assert_eq!(store.world_state_root().unwrap(), state_root);
+        drop(store);
+        remove_test_dbs("store-test-db-restart");
+    }
+
     // Creates an empty store, runs the test and then removes the store (if needed)
     fn run_test(test_func: &dyn Fn(Store), engine_type: EngineType) {
         // Remove preexistent DBs in case of a failed previous test
@@ -522,9 +882,18 @@ mod tests {
         run_test(&test_store_block_tags, engine_type);
         run_test(&test_account_info_iter, engine_type);
         run_test(&test_world_state_root_smoke, engine_type);
+        run_test(&test_world_state_root_incremental, engine_type);
         run_test(&test_account_storage_iter, engine_type);
         run_test(&test_chain_config_storage, engine_type);
         run_test(&test_genesis_block, engine_type);
+        run_test(&test_state_diff_apply_and_revert, engine_type);
+        run_test(&test_side_blocks, engine_type);
+        run_test(&test_historical_state, engine_type);
+        run_test(&test_prune_state_keeps_concurrent_writes, engine_type);
+        run_test(&test_account_and_storage_proofs, engine_type);
+        run_test(&test_write_batch, engine_type);
+        run_test(&test_snapshot, engine_type);
+        run_test(&test_snapshots_during_writes, engine_type);
     }
 
     fn test_genesis_block(mut store: Store) {
@@ -875,7 +1244,84 @@ mod tests {
                 )
                 .unwrap();
         }
-        store.world_state_root();
+        store.world_state_root().unwrap();
+    }
+
+    fn test_world_state_root_incremental(mut store: Store) {
+        const GENESIS_KURTOSIS: &str = include_str!("../../test_data/genesis-kurtosis.json");
+        let mut genesis: Genesis =
+            serde_json::from_str(GENESIS_KURTOSIS).expect("deserialize genesis-kurtosis.json");
+        store.add_initial_state(genesis.clone()).unwrap();
+        assert_eq!(
+            store.world_state_root().unwrap(),
+            genesis.compute_state_root()
+        );
+
+        // Update an existing account along with its storage and create a new one
+        let address = *genesis
+            .alloc
+            .iter()
+            .max_by_key(|(_, account)| account.storage.len())
+            .unwrap()
+            .0;
+        let previous_info = store.get_account_info(address).unwrap().unwrap();
+        let storage_key = H256::random();
+        let mut account_diff = AccountDiff::new(address, Some(previous_info.clone()));
+        account_diff.new_info = Some(AccountInfo {
+            balance: previous_info.balance + 1,
+            nonce: previous_info.nonce + 1,
+            ..previous_info
+        });
+        account_diff.storage.push(StorageSlotDiff {
+            key: storage_key,
+            previous_value: U256::zero(),
+            new_value: U256::from(7),
+        });
+        let new_address = Address::random();
+        let mut new_account_diff = AccountDiff::new(new_address, None);
+        new_account_diff.new_info = Some(AccountInfo {
+            balance: 25.into(),
+            ..Default::default()
+        });
+        let state_diff = StateDiff {
+            accounts: vec![account_diff, new_account_diff],
+        };
+        store.apply_state_diff(&state_diff).unwrap();
+
+        let account = genesis.alloc.get_mut(&address).unwrap();
+        account.balance += U256::one();
+        account.nonce += 1;
+        account.storage.insert(storage_key, U256::from(7));
+        genesis.alloc.insert(
+            new_address,
+            GenesisAccount {
+                code: Bytes::new(),
+                storage: HashMap::new(),
+                balance: 25.into(),
+                nonce: 0,
+            },
+        );
+        assert_eq!(
+            store.world_state_root().unwrap(),
+            genesis.compute_state_root()
+        );
+
+        // Zeroed slots are removed from the storage trie
+        store
+            .add_storage_at(address, storage_key, U256::zero())
+            .unwrap();
+        store.remove_account(new_address).unwrap();
+        genesis
+            .alloc
+            .get_mut(&address)
+            .unwrap()
+            .storage
+            .remove(&storage_key);
+        genesis.alloc.remove(&new_address);
+        assert_eq!(
+            store.world_state_root().unwrap(),
+            genesis.compute_state_root()
+        );
     }
 
     fn test_account_storage_iter(store: Store) {
@@ -899,6 +1345,412 @@ mod tests {
         assert_eq!(account_storage, account_storage_from_iter)
     }
 
+    fn test_account_and_storage_proofs(store: Store) {
+        let address = Address::random();
+        let storage_key = H256::random();
+        for _ in 0..16 {
+            store
+                .add_account_info(
+                    Address::random(),
+                    AccountInfo {
+                        balance: 1.into(),
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+        }
+        store
+            .add_account_info(
+                address,
+                AccountInfo {
+                    balance: 50.into(),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        store
+            .add_storage_at(address, storage_key, U256::from(7))
+            .unwrap();
+        let state_root = store.world_state_root().unwrap();
+
+        // Account proof
+        let proof = store.get_account_proof(state_root, address).unwrap();
+        let account_rlp = verify_proof(state_root, &hash_key(address.as_bytes()), &proof)
+            .unwrap()
+            .unwrap();
+        let account_state = AccountState::decode(&account_rlp).unwrap();
+        assert_eq!(account_state.balance, 50.into());
+
+        // Storage proof
+        let proof = store
+            .get_storage_proof(account_state.storage_root, storage_key)
+            .unwrap();
+        let value_rlp = verify_proof(
+            account_state.storage_root,
+            &hash_key(storage_key.as_bytes()),
+            &proof,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(U256::decode(&value_rlp).unwrap(), U256::from(7));
+
+        // Proof of absence
+        let missing_address = Address::random();
+        let proof = store
+            .get_account_proof(state_root, missing_address)
+            .unwrap();
+        assert!(
+            verify_proof(state_root, &hash_key(missing_address.as_bytes()), &proof)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    fn test_historical_state(mut store: Store) {
+        let address = Address::random();
+        let storage_key = H256::random();
+        store
+            .add_account_info(
+                address,
+                AccountInfo {
+                    balance: 50.into(),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        store
+            .add_storage_at(address, storage_key, U256::from(7))
+            .unwrap();
+        let old_root = store.world_state_root().unwrap();
+        store
+            .add_account_info(
+                address,
+                AccountInfo {
+                    balance: 25.into(),
+                    nonce: 1,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        store
+            .add_storage_at(address, storage_key, U256::from(77))
+            .unwrap();
+        let new_root = store.world_state_root().unwrap();
+
+        // Both states can be read
+        let old_info = store
+            .get_account_info_by_state_root(old_root, address)
+            .unwrap()
+            .unwrap();
+        assert_eq!(old_info.balance, 50.into());
+        assert_eq!(
+            store
+                .get_storage_by_state_root(old_root, address, storage_key)
+                .unwrap(),
+            Some(U256::from(7))
+        );
+        assert_eq!(
+            store
+                .get_account_info_by_state_root(new_root, address)
+                .unwrap(),
+            store.get_account_info(address).unwrap()
+        );
+        assert_eq!(
+            store
+                .get_storage_by_state_root(new_root, address, storage_key)
+                .unwrap(),
+            Some(U256::from(77))
+        );
+
+        // Archive nodes keep every state
+        store.set_state_history(StateHistory::Archive);
+        store.prune_state().unwrap();
+        assert!(store
+            .get_account_info_by_state_root(old_root, address)
+            .unwrap()
+            .is_some());
+
+        // Without any block referencing it, the old state is pruned while the current one is kept
+        store.set_state_history(StateHistory::Pruned);
+        store.prune_state().unwrap();
+        assert!(store
+            .get_account_info_by_state_root(old_root, address)
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            store
+                .get_storage_by_state_root(new_root, address, storage_key)
+                .unwrap(),
+            Some(U256::from(77))
+        );
+    }
+
+    fn test_prune_state_keeps_concurrent_writes(store: Store) {
+        let address = Address::random();
+        let mut account_diff = AccountDiff::new(address, None);
+        account_diff.new_info = Some(AccountInfo {
+            balance: 25.into(),
+            ..Default::default()
+        });
+        let state_diff = StateDiff {
+            accounts: vec![account_diff],
+        };
+        let current_root = store.world_state_root().unwrap();
+
+        // A state committed while the pruning is in course is kept, even if it isn't reachable from the kept ones
+        store.pruning_writes.lock().unwrap().replace(HashSet::new());
+        let mut batch = store.write_batch();
+        let side_root = batch
+            .apply_state_diff_at(current_root, &state_diff)
+            .unwrap();
+        batch.commit().unwrap();
+        store.mark_and_sweep_state().unwrap();
+        store.pruning_writes.lock().unwrap().take();
+        assert!(store
+            .get_account_info_by_state_root(side_root, address)
+            .unwrap()
+            .is_some());
+
+        // The next pruning removes it
+        store.prune_state().unwrap();
+        assert!(store
+            .get_account_info_by_state_root(side_root, address)
+            .unwrap()
+            .is_none());
+        assert_eq!(store.world_state_root().unwrap(), current_root);
+    }
+
+    fn test_state_diff_apply_and_revert(store: Store) {
+        let address = Address::random();
+        let new_address = Address::random();
+        let storage_key = H256::random();
+        let previous_info = AccountInfo {
+            balance: 50.into(),
+            ..Default::default()
+        };
+        store
+            .add_account_info(address, previous_info.clone())
+            .unwrap();
+        store
+            .add_storage_at(address, storage_key, U256::from(7))
+            .unwrap();
+        let root_before = store.world_state_root().unwrap();
+
+        let mut account_diff = AccountDiff::new(address, Some(previous_info));
+        account_diff.new_info = Some(AccountInfo {
+            balance: 25.into(),
+            nonce: 1,
+            ..Default::default()
+        });
+        account_diff.storage.push(StorageSlotDiff {
+            key: storage_key,
+            previous_value: U256::from(7),
+            new_value: U256::from(77),
+        });
+        let mut new_account_diff = AccountDiff::new(new_address, None);
+        new_account_diff.new_info = Some(AccountInfo {
+            balance: 25.into(),
+            ..Default::default()
+        });
+        let state_diff = StateDiff {
+            accounts: vec![account_diff, new_account_diff],
+        };
+
+        store.apply_state_diff(&state_diff).unwrap();
+        assert_eq!(
+            store.get_account_info(address).unwrap().unwrap().balance,
+            25.into()
+        );
+        assert_eq!(
+            store.get_storage_at(address, storage_key).unwrap(),
+            Some(U256::from(77))
+        );
+        assert!(store.get_account_info(new_address).unwrap().is_some());
+
+        store.revert_state_diff(&state_diff).unwrap();
+        assert_eq!(
+            store.get_account_info(address).unwrap().unwrap().balance,
+            50.into()
+        );
+        assert_eq!(
+            store.get_storage_at(address, storage_key).unwrap(),
+            Some(U256::from(7))
+        );
+        assert!(store.get_account_info(new_address).unwrap().is_none());
+        assert_eq!(store.world_state_root().unwrap(), root_before);
+
+        // Check that the diff can be stored and retrieved
+        let block_hash = H256::random();
+        store
+            .add_state_diff(block_hash, state_diff.clone())
+            .unwrap();
+        assert_eq!(store.get_state_diff(block_hash).unwrap(), Some(state_diff));
+    }
+
+    fn test_side_blocks(store: Store) {
+        let (header, body) = create_block_for_testing();
+        let block = Block { header, body };
+        let block_hash = block.header.compute_block_hash();
+        let receipts = vec![Receipt {
+            tx_type: TxType::EIP1559,
+            succeeded: true,
+            cumulative_gas_used: 1747,
+            bloom: Bloom::random(),
+            logs: vec![],
+        }];
+
+        store
+            .add_side_block(block_hash, block.clone(), receipts.clone())
+            .unwrap();
+        assert_eq!(
+            store.get_side_block(block_hash).unwrap(),
+            Some((block, receipts))
+        );
+        store.remove_side_block(block_hash).unwrap();
+        assert!(store.get_side_block(block_hash).unwrap().is_none());
+    }
+
+    fn test_write_batch(store: Store) {
+        let (header, body) = create_block_for_testing();
+        let block = Block { header, body };
+        let block_number = block.header.number;
+        let block_hash = block.header.compute_block_hash();
+        let address = Address::random();
+        let storage_key = H256::random();
+        let mut account_diff = AccountDiff::new(address, None);
+        account_diff.new_info = Some(AccountInfo {
+            balance: 25.into(),
+            ..Default::default()
+        });
+        account_diff.storage.push(StorageSlotDiff {
+            key: storage_key,
+            previous_value: U256::zero(),
+            new_value: U256::from(7),
+        });
+        let state_diff = StateDiff {
+            accounts: vec![account_diff],
+        };
+        let receipt = Receipt {
+            tx_type: TxType::EIP1559,
+            succeeded: true,
+            cumulative_gas_used: 1747,
+            bloom: Bloom::random(),
+            logs: vec![],
+        };
+        let build_batch = || {
+            let mut batch = store.write_batch();
+            batch.apply_state_diff(&state_diff);
+            batch.add_state_diff(block_hash, state_diff.clone());
+            batch.add_block(block.clone());
+            batch.add_receipts(block_number, vec![receipt.clone()]);
+            batch
+        };
+        let root_before = store.world_state_root().unwrap();
+
+        // Nothing is written until the batch is committed
+        let mut batch = build_batch();
+        let state_root = batch.state_root().unwrap();
+        assert_ne!(state_root, root_before);
+        drop(batch);
+        assert_eq!(store.world_state_root().unwrap(), root_before);
+        assert!(store.get_account_info(address).unwrap().is_none());
+        assert!(store.get_block_header(block_number).unwrap().is_none());
+        assert!(store.get_block_number(block_hash).unwrap().is_none());
+        assert!(store.get_receipt(block_number, 0).unwrap().is_none());
+        assert!(store.get_state_diff(block_hash).unwrap().is_none());
+        assert_eq!(store.get_latest_block_number().unwrap(), None);
+        // Not even the trie nodes of the batch's state are stored
+        assert!(store
+            .get_account_proof(state_root, address)
+            .unwrap()
+            .is_empty());
+
+        build_batch().commit().unwrap();
+        assert_eq!(store.world_state_root().unwrap(), state_root);
+        assert!(!store
+            .get_account_proof(state_root, address)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            store.get_storage_at(address, storage_key).unwrap(),
+            Some(U256::from(7))
+        );
+        assert_eq!(
+            store.get_block_header(block_number).unwrap(),
+            Some(block.header)
+        );
+        assert_eq!(
+            store.get_block_number(block_hash).unwrap(),
+            Some(block_number)
+        );
+        assert_eq!(store.get_receipt(block_number, 0).unwrap(), Some(receipt));
+        assert_eq!(store.get_state_diff(block_hash).unwrap(), Some(state_diff));
+        assert_eq!(store.get_latest_block_number().unwrap(), Some(block_number));
+    }
+
+    fn test_snapshot(store: Store) {
+        let (header, body) = create_block_for_testing();
+        let block_number = header.number;
+        let block = Block { header, body };
+
+        // Snapshots can be taken alongside each other, while writers have to wait for all of them to be dropped
+        let snapshot = store.snapshot();
+        let other_snapshot = store.snapshot();
+        assert!(store.engine.try_write().is_err());
+        assert_eq!(snapshot.get_latest_block_number().unwrap(), None);
+        assert_eq!(other_snapshot.get_latest_block_number().unwrap(), None);
+        drop((snapshot, other_snapshot));
+        assert!(store.engine.try_write().is_ok());
+
+        store.add_block(block.clone()).unwrap();
+        let snapshot = store.snapshot();
+        assert_eq!(
+            snapshot.get_latest_block_number().unwrap(),
+            Some(block_number)
+        );
+        assert_eq!(
+            snapshot.get_block_header(block_number).unwrap(),
+            Some(block.header)
+        );
+        assert_eq!(
+            snapshot.get_block_body(block_number).unwrap(),
+            Some(block.body)
+        );
+    }
+
+    fn test_snapshots_during_writes(store: Store) {
+        let (header, body) = create_block_for_testing();
+        let writer = {
+            let store = store.clone();
+            std::thread::spawn(move || {
+                for number in 1..=50 {
+                    let header = BlockHeader {
+                        number,
+                        ..header.clone()
+                    };
+                    let body = body.clone();
+                    store.add_block(Block { header, body }).unwrap();
+                }
+            })
+        };
+        // Every read made through a snapshot sees the same version of the store
+        while !writer.is_finished() {
+            let snapshot = store.snapshot();
+            if let Some(latest) = snapshot.get_latest_block_number().unwrap() {
+                let header = snapshot.get_block_header(latest).unwrap().unwrap();
+                assert_eq!(
+                    snapshot
+                        .get_block_number(header.compute_block_hash())
+                        .unwrap(),
+                    Some(latest)
+                );
+                assert!(snapshot.get_block_header(latest + 1).unwrap().is_none());
+            }
+        }
+        writer.join().unwrap();
+        assert_eq!(store.get_latest_block_number().unwrap(), Some(50));
+    }
+
     fn test_chain_config_storage(store: Store) {
         let chain_config = example_chain_config();
         store.set_chain_config(&chain_config).unwrap();
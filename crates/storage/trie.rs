@@ -1,13 +1,16 @@
-mod db;
+pub mod db;
 mod nibble;
 mod node;
 mod node_hash;
+mod proof;
 mod rlp;
 mod state;
 
 #[cfg(test)]
 mod test_utils;
 
+use std::collections::HashSet;
+
 use ethereum_rust_core::rlp::constants::RLP_NULL;
 use ethereum_types::H256;
 use node::Node;
@@ -16,12 +19,13 @@ use sha3::{Digest, Keccak256};
 
 use self::{db::TrieDB, nibble::NibbleSlice, node::LeafNode, state::TrieState};
 use crate::error::StoreError;
+pub use proof::verify_proof;
 
 use lazy_static::lazy_static;
 
 lazy_static! {
     // Hash value for an empty trie, equal to keccak(RLP_NULL)
-    static ref EMPTY_TRIE_HASH: H256 = H256::from_slice(
+    pub static ref EMPTY_TRIE_HASH: H256 = H256::from_slice(
         Keccak256::new()
             .chain_update([RLP_NULL])
             .finalize()
@@ -145,6 +149,36 @@ impl<DB: TrieDB> Trie<DB> {
         }
     }
 
+    /// Builds a Merkle proof for the given path: the canonical encodings of the nodes traversed from the root
+    /// towards the path's value, as defined in [EIP-1186](https://eips.ethereum.org/EIPS/eip-1186).
+    /// If the path is not part of the trie the proof ends at the node that proves its absence.
+    /// Nodes small enough to be inlined into their parent are not included, as their parent already contains them
+    pub fn get_proof(&self, path: &PathRLP) -> Result<Vec<Vec<u8>>, StoreError> {
+        let mut proof = Vec::new();
+        let Some(root) = &self.root else {
+            return Ok(proof);
+        };
+        let mut path = NibbleSlice::new(path);
+        let mut node_hash = root.clone();
+        while let Some(node) = self.state.get_node(node_hash.clone())? {
+            // The root is always included, even if it is small enough to be inlined
+            if proof.is_empty() || matches!(node_hash, NodeHash::Hashed(_)) {
+                proof.push(node.encode_raw(path.offset()));
+            }
+            node_hash = match node {
+                Node::Branch(n) => match path.next() {
+                    Some(choice) if n.choices[choice as usize].is_valid() => {
+                        n.choices[choice as usize].clone()
+                    }
+                    _ => break,
+                },
+                Node::Extension(n) if path.skip_prefix(&n.prefix) => n.child,
+                _ => break,
+            };
+        }
+        Ok(proof)
+    }
+
     /// Sets the root of the trie to the one which's hash corresponds to the one received
     /// Doesn't check that the root_hash is valid within the trie
     /// Please use a root hash that has been calculated using `compute_hash`
@@ -153,6 +187,58 @@ impl<DB: TrieDB> Trie<DB> {
     pub fn set_root(&mut self, root_hash: H256) {
         self.root = (root_hash != *EMPTY_TRIE_HASH).then_some(root_hash.into());
     }
+
+    /// Adds the keys of all the nodes reachable from the trie's root to `nodes`, calling `on_value` with each value
+    /// found along the way. Subtries whose root is already in `nodes` are skipped, as are nodes missing from the DB
+    pub fn collect_nodes(
+        &self,
+        nodes: &mut HashSet<Vec<u8>>,
+        on_value: &mut dyn FnMut(&ValueRLP) -> Result<(), StoreError>,
+    ) -> Result<(), StoreError> {
+        let mut pending = self.root.iter().map(Vec::from).collect();
+        self.collect_nodes_from(&mut pending, nodes, usize::MAX, on_value)
+    }
+
+    /// Same as [Trie::collect_nodes], but starting from the nodes with the keys in `pending` instead of the trie's root
+    /// and visiting at most `limit` of them. The keys of the nodes left to visit are kept in `pending`, so the walk can
+    /// be resumed later on
+    pub fn collect_nodes_from(
+        &self,
+        pending: &mut Vec<Vec<u8>>,
+        nodes: &mut HashSet<Vec<u8>>,
+        limit: usize,
+        on_value: &mut dyn FnMut(&ValueRLP) -> Result<(), StoreError>,
+    ) -> Result<(), StoreError> {
+        let mut visited = 0;
+        while visited < limit {
+            let Some(key) = pending.pop() else {
+                break;
+            };
+            if !nodes.insert(key.clone()) {
+                continue;
+            }
+            visited += 1;
+            let Some(node) = self.state.get_node(key.into())? else {
+                continue;
+            };
+            match node {
+                Node::Branch(n) => {
+                    pending.extend(
+                        n.choices
+                            .iter()
+                            .filter(|child| child.is_valid())
+                            .map(Vec::from),
+                    );
+                    if !n.value.is_empty() {
+                        on_value(&n.value)?;
+                    }
+                }
+                Node::Extension(n) => pending.push(n.child.into()),
+                Node::Leaf(n) => on_value(&n.value)?,
+            }
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -221,6 +307,47 @@ mod test {
         assert_eq!(trie.get(&second_path).unwrap(), Some(second_value));
     }
 
+    #[test]
+    fn collect_nodes_keeps_current_trie() {
+        let db = db::in_memory::InMemoryTrieDB::default();
+        let mut trie = Trie::new(db.clone());
+        trie.insert(b"first".to_vec(), b"value_a".to_vec()).unwrap();
+        trie.insert(b"second".to_vec(), b"value_b".to_vec())
+            .unwrap();
+        trie.hash().unwrap();
+        trie.insert(b"second".to_vec(), b"value_c".to_vec())
+            .unwrap();
+        let root = trie.hash().unwrap();
+
+        let mut nodes = HashSet::new();
+        let mut values = HashSet::new();
+        Trie::open(db.clone(), root)
+            .collect_nodes(&mut nodes, &mut |value| {
+                values.insert(value.clone());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            values,
+            HashSet::from([b"value_a".to_vec(), b"value_c".to_vec()])
+        );
+
+        // Removing the rest of the nodes doesn't affect the current trie
+        assert_eq!(
+            db.retain(&|key| nodes.contains(key), None, usize::MAX),
+            None
+        );
+        let trie = Trie::open(db, root);
+        assert_eq!(
+            trie.get(&b"first".to_vec()).unwrap(),
+            Some(b"value_a".to_vec())
+        );
+        assert_eq!(
+            trie.get(&b"second".to_vec()).unwrap(),
+            Some(b"value_c".to_vec())
+        );
+    }
+
     #[test]
     fn get_insert_zero() {
         let mut trie = new_temp_trie();
@@ -759,6 +886,30 @@ mod test {
 
         }
 
+
+        #[test]
+        fn proptest_compare_proof(data in btree_set(vec(any::<u8>(), 32..100), 1..100)) {
+            let mut trie = new_temp_trie();
+            let mut cita_trie = cita_trie();
+
+            for val in data.iter() {
+                trie.insert(val.clone(), val.clone()).unwrap();
+                cita_trie.insert(val.clone(), val.clone()).unwrap();
+            }
+            let root = trie.hash().unwrap();
+            let cita_root = cita_trie.root().unwrap();
+
+            for val in data.iter() {
+                let proof = trie.get_proof(val).unwrap();
+                let cita_proof = cita_trie.get_proof(val).unwrap();
+                // Each implementation accepts the other's proof
+                prop_assert_eq!(verify_proof(root, val, &cita_proof).unwrap(), Some(val.clone()));
+                prop_assert_eq!(
+                    cita_trie.verify_proof(&cita_root, val, proof).unwrap(),
+                    Some(val.clone())
+                );
+            }
+        }
     }
 
     fn cita_trie() -> CitaTrie<CitaMemoryDB, HasherKeccak> {
@@ -1,7 +1,19 @@
+pub mod in_memory;
 pub mod libmdbx;
+pub mod overlay;
 
 use crate::error::StoreError;
 pub trait TrieDB {
     fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>, StoreError>;
     fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), StoreError>;
 }
+
+impl<DB: TrieDB + ?Sized> TrieDB for Box<DB> {
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>, StoreError> {
+        self.as_ref().get(key)
+    }
+
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), StoreError> {
+        self.as_ref().put(key, value)
+    }
+}
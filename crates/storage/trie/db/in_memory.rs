@@ -0,0 +1,79 @@
+use std::{
+    collections::BTreeMap,
+    ops::Bound,
+    sync::{Arc, RwLock},
+};
+
+use crate::error::StoreError;
+
+use super::TrieDB;
+
+/// In-memory implementation for the TrieDB trait, with get and put operations.
+/// Clones share the same underlying map, which can be read from several threads at once
+#[derive(Clone, Default)]
+pub struct InMemoryTrieDB(Arc<RwLock<BTreeMap<Vec<u8>, Vec<u8>>>>);
+
+impl InMemoryTrieDB {
+    /// Removes the nodes that are not to be kept, walking at most `limit` nodes in key order starting from `start`.
+    /// Returns the key to continue from, or None once the last node was walked
+    pub fn retain(
+        &self,
+        keep: &dyn Fn(&[u8]) -> bool,
+        start: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Option<Vec<u8>> {
+        let mut nodes = self.0.write().unwrap();
+        let range = (
+            start.map_or(Bound::Unbounded, Bound::Included),
+            Bound::Unbounded,
+        );
+        let mut keys: Vec<_> = nodes
+            .range::<Vec<u8>, _>(range)
+            .map(|(key, _)| key.clone())
+            .take(limit.saturating_add(1))
+            .collect();
+        let next = (keys.len() > limit).then(|| keys.pop()).flatten();
+        for key in keys.into_iter().filter(|key| !keep(key)) {
+            nodes.remove(&key);
+        }
+        next
+    }
+
+    /// Removes all nodes, returning them
+    pub fn take_nodes(&self) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        std::mem::take(&mut self.0.write().unwrap())
+    }
+}
+
+impl TrieDB for InMemoryTrieDB {
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self.0.read().unwrap().get(&key).cloned())
+    }
+
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), StoreError> {
+        self.0.write().unwrap().insert(key, value);
+        Ok(())
+    }
+}
+
+#[test]
+fn simple_addition() {
+    let db = InMemoryTrieDB::default();
+    assert_eq!(db.get("hello".into()).unwrap(), None);
+    db.put("hello".into(), "value".into()).unwrap();
+    assert_eq!(db.get("hello".into()).unwrap(), Some("value".into()));
+}
+
+#[test]
+fn retain_in_steps() {
+    let db = InMemoryTrieDB::default();
+    for key in ["a", "b", "c"] {
+        db.put(key.into(), "value".into()).unwrap();
+    }
+    let keep = |key: &[u8]| key != b"b";
+    assert_eq!(db.retain(&keep, None, 2), Some("c".into()));
+    assert_eq!(db.retain(&keep, Some("c".into()), 2), None);
+    assert_eq!(db.get("a".into()).unwrap(), Some("value".into()));
+    assert_eq!(db.get("b".into()).unwrap(), None);
+    assert_eq!(db.get("c".into()).unwrap(), Some("value".into()));
+}
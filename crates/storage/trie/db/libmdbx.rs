@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use crate::error::StoreError;
 use libmdbx::{
     orm::{table, Database},
@@ -5,16 +7,22 @@ use libmdbx::{
 };
 
 /// Libmdbx implementation for the TrieDB trait, with get and put operations.
-pub struct Libmdbx(Database);
+/// The database may be shared with other users as long as it includes the [TrieNodes] table
+pub struct Libmdbx(Arc<Database>);
 
 use super::TrieDB;
 
 table!(
     /// NodeHash to Node table
-    ( Nodes ) Vec<u8> => Vec<u8>
+    ( TrieNodes ) Vec<u8> => Vec<u8>
 );
 
 impl Libmdbx {
+    /// Creates a TrieDB over an already opened DB containing the [TrieNodes] table
+    pub fn new(db: Arc<Database>) -> Self {
+        Self(db)
+    }
+
     /// Opens a DB created by a previous execution or creates a new one if it doesn't exist
 
     pub fn init(trie_dir: &str) -> Result<Self, StoreError> {
@@ -23,39 +31,67 @@ impl Libmdbx {
 
     /// Creates a new clean DB
     pub fn create(trie_dir: &str) -> Result<Self, StoreError> {
-        let tables = [table_info!(Nodes)].into_iter().collect();
+        let tables = [table_info!(TrieNodes)].into_iter().collect();
         let path = Some(trie_dir.into());
-        Ok(Self(
+        Ok(Self(Arc::new(
             Database::create(path, &tables).map_err(StoreError::LibmdbxError)?,
-        ))
+        )))
     }
 
     /// Opens a DB created by a previous execution
     pub fn open(trie_dir: &str) -> Result<Self, StoreError> {
         // Open DB
-        let tables = [table_info!(Nodes)].into_iter().collect();
+        let tables = [table_info!(TrieNodes)].into_iter().collect();
         let db = Database::open(trie_dir, &tables).map_err(StoreError::LibmdbxError)?;
-        Ok(Self(db))
+        Ok(Self(Arc::new(db)))
+    }
+
+    /// Removes the nodes that are not to be kept, walking at most `limit` nodes in key order starting from `start`.
+    /// Returns the key to continue from, or None once the last node was walked
+    pub fn retain(
+        &self,
+        keep: &dyn Fn(&[u8]) -> bool,
+        start: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Option<Vec<u8>>, StoreError> {
+        let txn = self.0.begin_readwrite().map_err(StoreError::LibmdbxError)?;
+        let mut keys = txn
+            .cursor::<TrieNodes>()
+            .map_err(StoreError::LibmdbxError)?
+            .walk(start)
+            .map(|res| res.map(|(key, _)| key))
+            .take(limit.saturating_add(1))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(StoreError::LibmdbxError)?;
+        let next = (keys.len() > limit).then(|| keys.pop()).flatten();
+        for key in keys.into_iter().filter(|key| !keep(key)) {
+            txn.delete::<TrieNodes>(key, None)
+                .map_err(StoreError::LibmdbxError)?;
+        }
+        txn.commit().map_err(StoreError::LibmdbxError)?;
+        Ok(next)
     }
 
     #[cfg(test)]
     /// Creates a temporary DB, for testing purposes only
     pub fn init_temp() -> Self {
         use tempdir::TempDir;
-        let tables = [table_info!(Nodes)].into_iter().collect();
-        Self(Database::create(None, &tables).expect("Failed to create temp DB"))
+        let tables = [table_info!(TrieNodes)].into_iter().collect();
+        Self(Arc::new(
+            Database::create(None, &tables).expect("Failed to create temp DB"),
+        ))
     }
 }
 
 impl TrieDB for Libmdbx {
     fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>, StoreError> {
         let txn = self.0.begin_read().map_err(StoreError::LibmdbxError)?;
-        txn.get::<Nodes>(key).map_err(StoreError::LibmdbxError)
+        txn.get::<TrieNodes>(key).map_err(StoreError::LibmdbxError)
     }
 
     fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), StoreError> {
         let txn = self.0.begin_readwrite().map_err(StoreError::LibmdbxError)?;
-        txn.upsert::<Nodes>(key, value)
+        txn.upsert::<TrieNodes>(key, value)
             .map_err(StoreError::LibmdbxError)?;
         txn.commit().map_err(StoreError::LibmdbxError)
     }
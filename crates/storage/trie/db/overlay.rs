@@ -0,0 +1,53 @@
+use crate::error::StoreError;
+
+use super::{in_memory::InMemoryTrieDB, TrieDB};
+
+/// TrieDB that keeps the nodes written to it in memory, on top of a DB that is only read from.
+/// Written nodes can be taken out of the overlay so they are stored all at once, or discarded along with it
+pub struct OverlayTrieDB {
+    overlay: InMemoryTrieDB,
+    db: Box<dyn TrieDB>,
+}
+
+impl OverlayTrieDB {
+    /// Creates a TrieDB writing to the given overlay, which may be shared with other tries, and reading from
+    /// the underlying DB the nodes it doesn't hold
+    pub fn new(overlay: InMemoryTrieDB, db: Box<dyn TrieDB>) -> Self {
+        Self { overlay, db }
+    }
+}
+
+impl TrieDB for OverlayTrieDB {
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>, StoreError> {
+        match self.overlay.get(key.clone())? {
+            Some(value) => Ok(Some(value)),
+            None => self.db.get(key),
+        }
+    }
+
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), StoreError> {
+        self.overlay.put(key, value)
+    }
+}
+
+#[test]
+fn writes_stay_in_overlay() {
+    let db = InMemoryTrieDB::default();
+    db.put("stored".into(), "value".into()).unwrap();
+    let overlay = InMemoryTrieDB::default();
+    let overlay_db = OverlayTrieDB::new(overlay.clone(), Box::new(db.clone()));
+    overlay_db.put("hello".into(), "value".into()).unwrap();
+    assert_eq!(
+        overlay_db.get("stored".into()).unwrap(),
+        Some("value".into())
+    );
+    assert_eq!(
+        overlay_db.get("hello".into()).unwrap(),
+        Some("value".into())
+    );
+    assert_eq!(db.get("hello".into()).unwrap(), None);
+    assert_eq!(
+        overlay.take_nodes(),
+        std::collections::BTreeMap::from([(b"hello".to_vec(), b"value".to_vec())])
+    );
+}
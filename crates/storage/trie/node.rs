@@ -98,4 +98,13 @@ impl Node {
             Node::Leaf(n) => n.compute_hash(path_offset),
         }
     }
+
+    /// Returns the node's canonical encoding, the one used to compute its hash and to build proofs
+    pub fn encode_raw(&self, path_offset: usize) -> Vec<u8> {
+        match self {
+            Node::Branch(n) => n.encode_raw(),
+            Node::Extension(n) => n.encode_raw(),
+            Node::Leaf(n) => n.encode_raw(path_offset),
+        }
+    }
 }
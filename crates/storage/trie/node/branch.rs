@@ -263,6 +263,19 @@ impl BranchNode {
 
     /// Computes the node's hash given the offset in the path traversed before reaching this node
     pub fn compute_hash(&self) -> NodeHash {
+        let mut hasher = NodeHasher::new();
+        self.write_encoding(&mut hasher);
+        hasher.finalize()
+    }
+
+    /// Returns the node's canonical encoding, the one used to compute its hash
+    pub fn encode_raw(&self) -> Vec<u8> {
+        let mut hasher = NodeHasher::new_recording();
+        self.write_encoding(&mut hasher);
+        hasher.into_encoded()
+    }
+
+    fn write_encoding(&self, hasher: &mut NodeHasher) {
         let hash_choice = |node_hash: &NodeHash| -> (Vec<u8>, usize) {
             if node_hash.is_valid() {
                 match node_hash {
@@ -291,7 +304,6 @@ impl BranchNode {
             children_len += 1;
         }
 
-        let mut hasher = NodeHasher::new();
         hasher.write_list_header(children_len);
         children.iter().for_each(|(x, len)| match len {
             0 => hasher.write_bytes(&[]),
@@ -302,7 +314,6 @@ impl BranchNode {
             Some(value) => hasher.write_bytes(value),
             None => hasher.write_bytes(&[]),
         }
-        hasher.finalize()
     }
 
     /// Inserts the node into the state and returns its hash
@@ -163,6 +163,19 @@ impl ExtensionNode {
     }
 
     pub fn compute_hash(&self) -> NodeHash {
+        let mut hasher = NodeHasher::new();
+        self.write_encoding(&mut hasher);
+        hasher.finalize()
+    }
+
+    /// Returns the node's canonical encoding, the one used to compute its hash
+    pub fn encode_raw(&self) -> Vec<u8> {
+        let mut hasher = NodeHasher::new_recording();
+        self.write_encoding(&mut hasher);
+        hasher.into_encoded()
+    }
+
+    fn write_encoding(&self, hasher: &mut NodeHasher) {
         let child_hash = &self.child;
         let prefix_len = NodeHasher::path_len(self.prefix.len());
         let child_len = match child_hash {
@@ -170,14 +183,12 @@ impl ExtensionNode {
             NodeHash::Hashed(x) => NodeHasher::bytes_len(32, x[0]),
         };
 
-        let mut hasher = NodeHasher::new();
         hasher.write_list_header(prefix_len + child_len);
         hasher.write_path_vec(&self.prefix, PathKind::Extension);
         match child_hash {
             NodeHash::Inline(x) => hasher.write_raw(x),
             NodeHash::Hashed(x) => hasher.write_bytes(&x.0),
         }
-        hasher.finalize()
     }
 
     /// Inserts the node into the state and returns its hash
@@ -122,6 +122,19 @@ impl LeafNode {
     }
 
     pub fn compute_hash(&self, offset: usize) -> NodeHash {
+        let mut hasher = NodeHasher::new();
+        self.write_encoding(offset, &mut hasher);
+        hasher.finalize()
+    }
+
+    /// Returns the node's canonical encoding, the one used to compute its hash
+    pub fn encode_raw(&self, offset: usize) -> Vec<u8> {
+        let mut hasher = NodeHasher::new_recording();
+        self.write_encoding(offset, &mut hasher);
+        hasher.into_encoded()
+    }
+
+    fn write_encoding(&self, offset: usize, hasher: &mut NodeHasher) {
         let encoded_value = &self.value;
         let encoded_path = &self.path;
 
@@ -134,11 +147,9 @@ impl LeafNode {
             encoded_value.first().copied().unwrap_or_default(),
         );
 
-        let mut hasher = NodeHasher::new();
         hasher.write_list_header(path_len + value_len);
         hasher.write_path_slice(&path, PathKind::Leaf);
         hasher.write_bytes(encoded_value);
-        hasher.finalize()
     }
 
     /// Inserts the node into the state and returns its hash
@@ -15,6 +15,8 @@ pub struct NodeHasher {
     len: usize,
     hasher: Keccak256,
     no_inline: bool,
+    /// Keeps a copy of the written node encoding, if requested
+    encoded: Option<Vec<u8>>,
 }
 
 /// Struct representing a trie node hash
@@ -34,6 +36,14 @@ impl NodeHasher {
         }
     }
 
+    /// Creates a hasher that also keeps the node encoding written to it, see [NodeHasher::into_encoded]
+    pub fn new_recording() -> Self {
+        Self {
+            encoded: Some(Vec::new()),
+            ..Default::default()
+        }
+    }
+
     pub const fn path_len(value_len: usize) -> usize {
         Self::bytes_len((value_len >> 1) + 1, 0)
     }
@@ -62,6 +72,9 @@ impl NodeHasher {
     }
 
     pub fn write_raw(&mut self, value: &[u8]) {
+        if let Some(encoded) = self.encoded.as_mut() {
+            encoded.extend_from_slice(value);
+        }
         let mut length = self.len;
         let mut hash = self.hash;
 
@@ -152,6 +165,11 @@ impl NodeHasher {
             NodeHash::Inline(self.hash[..self.len].to_vec())
         }
     }
+
+    /// Returns the node encoding written to a hasher created with [NodeHasher::new_recording]
+    pub fn into_encoded(self) -> Vec<u8> {
+        self.encoded.unwrap_or_default()
+    }
 }
 
 const fn compute_byte_usage(value: usize) -> usize {
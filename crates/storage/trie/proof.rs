@@ -0,0 +1,185 @@
+// Verification of the Merkle proofs built by `Trie::get_proof`
+// Proof nodes use the canonical trie encoding, which differs from the encoding used to store the nodes in the DB
+use std::collections::HashMap;
+
+use ethereum_rust_core::rlp::{
+    decode::{decode_rlp_item, get_item_with_prefix},
+    error::RLPDecodeError,
+};
+use ethereum_types::H256;
+use sha3::{Digest, Keccak256};
+
+use crate::error::StoreError;
+
+use super::{PathRLP, ValueRLP, EMPTY_TRIE_HASH};
+
+/// Reference to a node from its parent: either the node's hash or, for small nodes, the node's encoding itself
+enum NodeRef<'a> {
+    Hashed(H256),
+    Inline(&'a [u8]),
+}
+
+/// Verifies a Merkle proof for the given path against the root of a trie.
+/// Returns the value stored under the path, or None if the proof shows that the path is not part of the trie.
+/// Fails if the proof is incomplete or any of its nodes doesn't match the hash referencing it
+pub fn verify_proof(
+    root_hash: H256,
+    path: &PathRLP,
+    proof: &[Vec<u8>],
+) -> Result<Option<ValueRLP>, StoreError> {
+    if root_hash == *EMPTY_TRIE_HASH {
+        return Ok(None);
+    }
+    let nodes: HashMap<H256, &[u8]> = proof
+        .iter()
+        .map(|node| (H256::from_slice(&Keccak256::digest(node)), node.as_slice()))
+        .collect();
+    let nibbles: Vec<u8> = path
+        .iter()
+        .flat_map(|byte| [byte >> 4, byte & 0x0f])
+        .collect();
+    let mut remaining = nibbles.as_slice();
+    let mut node_ref = NodeRef::Hashed(root_hash);
+    loop {
+        let encoded = match node_ref {
+            NodeRef::Hashed(hash) => *nodes
+                .get(&hash)
+                .ok_or_else(|| invalid_proof(format!("missing node {hash:#x}")))?,
+            NodeRef::Inline(encoded) => encoded,
+        };
+        match decode_node_items(encoded)?.as_slice() {
+            // Branch node
+            [choices @ .., value] if choices.len() == 16 => {
+                let Some((choice, rest)) = remaining.split_first() else {
+                    let value = decode_bytes(value)?;
+                    return Ok((!value.is_empty()).then(|| value.to_vec()));
+                };
+                remaining = rest;
+                match decode_child(choices[*choice as usize])? {
+                    Some(child) => node_ref = child,
+                    None => return Ok(None),
+                }
+            }
+            // Leaf or extension node
+            [encoded_path, child] => {
+                let (prefix, is_leaf) = decode_compact_path(decode_bytes(encoded_path)?)?;
+                if is_leaf {
+                    if remaining != prefix.as_slice() {
+                        return Ok(None);
+                    }
+                    return Ok(Some(decode_bytes(child)?.to_vec()));
+                }
+                let Some(rest) = remaining.strip_prefix(prefix.as_slice()) else {
+                    return Ok(None);
+                };
+                remaining = rest;
+                node_ref = decode_child(*child)?
+                    .ok_or_else(|| invalid_proof("extension node without child".to_string()))?;
+            }
+            _ => return Err(invalid_proof("unknown node type".to_string())),
+        }
+    }
+}
+
+/// Splits an encoded node into its list items (including their prefixes)
+fn decode_node_items(encoded: &[u8]) -> Result<Vec<&[u8]>, RLPDecodeError> {
+    let (is_list, mut payload, rest) = decode_rlp_item(encoded)?;
+    if !is_list || !rest.is_empty() {
+        return Err(RLPDecodeError::MalformedData);
+    }
+    let mut items = Vec::new();
+    while !payload.is_empty() {
+        let (item, rest) = get_item_with_prefix(payload)?;
+        items.push(item);
+        payload = rest;
+    }
+    Ok(items)
+}
+
+/// Returns the payload of an RLP bytes item
+fn decode_bytes(item: &[u8]) -> Result<&[u8], RLPDecodeError> {
+    match decode_rlp_item(item)? {
+        (false, payload, _) => Ok(payload),
+        (true, _, _) => Err(RLPDecodeError::UnexpectedList),
+    }
+}
+
+/// Decodes a reference to a child node, returns None if the reference is empty
+fn decode_child(item: &[u8]) -> Result<Option<NodeRef<'_>>, StoreError> {
+    match decode_rlp_item(item)? {
+        // Nodes smaller than a hash are embedded into their parent
+        (true, _, _) => Ok(Some(NodeRef::Inline(item))),
+        (false, [], _) => Ok(None),
+        (false, hash, _) if hash.len() == 32 => Ok(Some(NodeRef::Hashed(H256::from_slice(hash)))),
+        _ => Err(invalid_proof("invalid child reference".to_string())),
+    }
+}
+
+/// Decodes a hex-prefix encoded path into its nibbles, also returning whether it belongs to a leaf node
+fn decode_compact_path(encoded: &[u8]) -> Result<(Vec<u8>, bool), StoreError> {
+    let (first, rest) = encoded
+        .split_first()
+        .ok_or_else(|| invalid_proof("empty node path".to_string()))?;
+    let flag = first >> 4;
+    let is_leaf = flag & 0x02 != 0;
+    let mut nibbles = Vec::with_capacity(rest.len() * 2 + 1);
+    if flag & 0x01 != 0 {
+        // Odd paths carry their first nibble along with the flag
+        nibbles.push(first & 0x0f);
+    }
+    nibbles.extend(rest.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]));
+    Ok((nibbles, is_leaf))
+}
+
+fn invalid_proof(reason: String) -> StoreError {
+    StoreError::Custom(format!("Invalid proof: {reason}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::trie::test_utils::new_temp_trie;
+
+    #[test]
+    fn verify_proof_of_existing_and_missing_paths() {
+        let mut trie = new_temp_trie();
+        for i in 0..64_u8 {
+            trie.insert(Keccak256::digest([i]).to_vec(), vec![i; 40])
+                .unwrap();
+        }
+        let root = trie.hash().unwrap();
+
+        let path = Keccak256::digest([7]).to_vec();
+        let proof = trie.get_proof(&path).unwrap();
+        assert_eq!(
+            verify_proof(root, &path, &proof).unwrap(),
+            Some(vec![7; 40])
+        );
+
+        let missing_path = Keccak256::digest([100]).to_vec();
+        let proof = trie.get_proof(&missing_path).unwrap();
+        assert_eq!(verify_proof(root, &missing_path, &proof).unwrap(), None);
+    }
+
+    #[test]
+    fn verify_proof_fails_on_incomplete_proof() {
+        let mut trie = new_temp_trie();
+        for i in 0..64_u8 {
+            trie.insert(Keccak256::digest([i]).to_vec(), vec![i; 40])
+                .unwrap();
+        }
+        let root = trie.hash().unwrap();
+
+        let path = Keccak256::digest([7]).to_vec();
+        let mut proof = trie.get_proof(&path).unwrap();
+        proof.pop();
+        assert!(verify_proof(root, &path, &proof).is_err());
+        assert!(verify_proof(H256::random(), &path, &trie.get_proof(&path).unwrap()).is_err());
+    }
+
+    #[test]
+    fn verify_proof_of_empty_trie() {
+        let path = Keccak256::digest([7]).to_vec();
+        assert_eq!(verify_proof(*EMPTY_TRIE_HASH, &path, &[]).unwrap(), None);
+    }
+}
@@ -0,0 +1,449 @@
+// Groups writes to the store so they are committed all at once, or not at all if the batch is dropped
+// A block is committed through a single batch (state changes, trie nodes, block data, receipts and the head pointer),
+// as is a whole reorg, so the store never holds a partially stored block or chain, even if the node is interrupted
+// halfway through
+use std::collections::{BTreeSet, HashMap};
+
+use bytes::Bytes;
+use ethereum_rust_core::rlp::{decode::RLPDecode, encode::RLPEncode};
+use ethereum_rust_core::types::{
+    Account, AccountInfo, AccountState, Block, BlockBody, BlockHash, BlockHeader, BlockNumber,
+    Index, Receipt,
+};
+use ethereum_types::{Address, H256, U256};
+
+use crate::{
+    engines::api::StoreEngine,
+    error::StoreError,
+    hash_key,
+    state_diff::StateDiff,
+    trie::{
+        db::{in_memory::InMemoryTrieDB, overlay::OverlayTrieDB},
+        Trie,
+    },
+    Store, EMPTY_TRIE_HASH,
+};
+
+/// Single write to be applied by a store engine as part of a batch
+#[derive(Debug, Clone)]
+pub enum WriteOp {
+    AddAccountInfo(Address, AccountInfo),
+    RemoveAccountInfo(Address),
+    AddAccountCode(H256, Bytes),
+    AddStorageAt(Address, H256, U256),
+    RemoveAccountStorage(Address),
+    AddTrieNodes(Vec<(Vec<u8>, Vec<u8>)>),
+    UpdateStateRoot(H256),
+    AddBlockHeader(BlockNumber, BlockHeader),
+    AddBlockBody(BlockNumber, BlockBody),
+    AddBlockNumber(BlockHash, BlockNumber),
+    RemoveBlockNumber(BlockHash),
+    RemoveBlock(BlockNumber),
+    AddTransactionLocation(H256, BlockNumber, Index),
+    RemoveTransactionLocation(H256),
+    AddReceipt(BlockNumber, Index, Receipt),
+    AddSideBlock(BlockHash, Block, Vec<Receipt>),
+    RemoveSideBlock(BlockHash),
+    AddStateDiff(BlockHash, StateDiff),
+    UpdateLatestBlockNumber(BlockNumber),
+}
+
+/// Writes to the store that are committed together.
+/// Nothing is written until the batch is committed, dropping it discards all of its writes.
+/// State reads made through the batch take its pending changes into account, and the world state trie is
+/// updated to match them before computing the batch's state root or committing it.
+/// The new trie nodes are held by the batch along with the rest of its writes, and stored when it is committed
+pub struct WriteBatch {
+    store: Store,
+    ops: Vec<WriteOp>,
+    trie_nodes: InMemoryTrieDB,
+    account_infos: HashMap<Address, Option<AccountInfo>>,
+    storages: HashMap<Address, PendingStorage>,
+    /// Accounts whose leaf in the world state trie is outdated, along with the storage keys that changed for
+    /// each of them, or None if their whole storage may have changed
+    touched_accounts: HashMap<Address, Option<BTreeSet<H256>>>,
+    state_root: Option<H256>,
+}
+
+/// Storage changes of an account that are yet to be committed
+#[derive(Default)]
+struct PendingStorage {
+    /// Whether the storage stored before the batch was removed
+    wiped: bool,
+    slots: HashMap<H256, U256>,
+}
+
+impl WriteBatch {
+    pub(crate) fn new(store: Store) -> Self {
+        Self {
+            store,
+            ops: Vec::new(),
+            trie_nodes: InMemoryTrieDB::default(),
+            account_infos: HashMap::new(),
+            storages: HashMap::new(),
+            touched_accounts: HashMap::new(),
+            state_root: None,
+        }
+    }
+
+    pub fn add_account_info(&mut self, address: Address, account_info: AccountInfo) {
+        self.touch_account(address, Some(vec![]));
+        self.account_infos
+            .insert(address, Some(account_info.clone()));
+        self.ops
+            .push(WriteOp::AddAccountInfo(address, account_info));
+    }
+
+    pub fn remove_account_info(&mut self, address: Address) {
+        self.touch_account(address, Some(vec![]));
+        self.account_infos.insert(address, None);
+        self.ops.push(WriteOp::RemoveAccountInfo(address));
+    }
+
+    pub fn add_account_code(&mut self, code_hash: H256, code: Bytes) {
+        self.ops.push(WriteOp::AddAccountCode(code_hash, code));
+    }
+
+    pub fn add_storage_at(&mut self, address: Address, storage_key: H256, storage_value: U256) {
+        self.touch_account(address, Some(vec![storage_key]));
+        self.storages
+            .entry(address)
+            .or_default()
+            .slots
+            .insert(storage_key, storage_value);
+        self.ops
+            .push(WriteOp::AddStorageAt(address, storage_key, storage_value));
+    }
+
+    pub fn remove_account_storage(&mut self, address: Address) {
+        self.touch_account(address, None);
+        self.storages.insert(
+            address,
+            PendingStorage {
+                wiped: true,
+                slots: HashMap::new(),
+            },
+        );
+        self.ops.push(WriteOp::RemoveAccountStorage(address));
+    }
+
+    /// Stores account info, code and storage
+    pub fn add_account(&mut self, address: Address, account: Account) {
+        self.touch_account(address, None);
+        self.add_account_info(address, account.info.clone());
+        self.add_account_code(account.info.code_hash, account.code);
+        for (storage_key, storage_value) in account.storage {
+            self.add_storage_at(address, storage_key, storage_value);
+        }
+    }
+
+    /// Removes account info and storage
+    pub fn remove_account(&mut self, address: Address) {
+        self.remove_account_info(address);
+        self.remove_account_storage(address);
+    }
+
+    /// Increments the balance of an account by a given amount, creating the account if it doesn't exist
+    pub fn increment_balance(&mut self, address: Address, amount: U256) -> Result<(), StoreError> {
+        let store = self.store.clone();
+        let mut account_info = self
+            .get_account_info(&*store.read_engine(), address)?
+            .unwrap_or_default();
+        account_info.balance = account_info.balance.saturating_add(amount);
+        self.add_account_info(address, account_info);
+        Ok(())
+    }
+
+    /// Applies the state changes produced by executing a block
+    pub fn apply_state_diff(&mut self, state_diff: &StateDiff) {
+        for account in state_diff.accounts.iter() {
+            if account.storage_wiped {
+                self.remove_account_storage(account.address);
+            }
+            match &account.new_info {
+                Some(info) => self.add_account_info(account.address, info.clone()),
+                None => self.remove_account_info(account.address),
+            }
+            if let Some((code_hash, code)) = &account.new_code {
+                self.add_account_code(*code_hash, code.clone());
+            }
+            for slot in account.storage.iter() {
+                self.add_storage_at(account.address, slot.key, slot.new_value);
+            }
+        }
+    }
+
+    /// Reverts the state changes produced by executing a block, leaving the state as it was before the block
+    /// Account code is kept, as it is only referenced through its hash
+    pub fn revert_state_diff(&mut self, state_diff: &StateDiff) {
+        for account in state_diff.accounts.iter().rev() {
+            if account.storage_wiped {
+                self.touch_account(account.address, None);
+            }
+            for slot in account.storage.iter().rev() {
+                self.add_storage_at(account.address, slot.key, slot.previous_value);
+            }
+            for (key, value) in account.wiped_storage.iter() {
+                self.add_storage_at(account.address, *key, *value);
+            }
+            match &account.previous_info {
+                Some(info) => self.add_account_info(account.address, info.clone()),
+                None => self.remove_account_info(account.address),
+            }
+        }
+    }
+
+    /// Stores a block as part of the canonical chain and makes it the latest block
+    pub fn add_block(&mut self, block: Block) {
+        let header = block.header;
+        let number = header.number;
+        let hash = header.compute_block_hash();
+        for (index, transaction) in block.body.transactions.iter().enumerate() {
+            self.ops.push(WriteOp::AddTransactionLocation(
+                transaction.compute_hash(),
+                number,
+                index as Index,
+            ));
+        }
+        self.ops.push(WriteOp::AddBlockBody(number, block.body));
+        self.ops.push(WriteOp::AddBlockHeader(number, header));
+        self.ops.push(WriteOp::AddBlockNumber(hash, number));
+        self.update_latest_block_number(number);
+    }
+
+    /// Removes a block from the canonical chain, along with its hash and the locations of its transactions
+    pub fn remove_block(&mut self, block: &Block) {
+        for transaction in block.body.transactions.iter() {
+            self.ops.push(WriteOp::RemoveTransactionLocation(
+                transaction.compute_hash(),
+            ));
+        }
+        self.ops.push(WriteOp::RemoveBlockNumber(
+            block.header.compute_block_hash(),
+        ));
+        self.ops.push(WriteOp::RemoveBlock(block.header.number));
+    }
+
+    /// Stores the receipts of a block's transactions
+    pub fn add_receipts(&mut self, block_number: BlockNumber, receipts: Vec<Receipt>) {
+        for (index, receipt) in receipts.into_iter().enumerate() {
+            self.ops
+                .push(WriteOp::AddReceipt(block_number, index as Index, receipt));
+        }
+    }
+
+    pub fn add_side_block(&mut self, block_hash: BlockHash, block: Block, receipts: Vec<Receipt>) {
+        self.ops
+            .push(WriteOp::AddSideBlock(block_hash, block, receipts));
+    }
+
+    pub fn remove_side_block(&mut self, block_hash: BlockHash) {
+        self.ops.push(WriteOp::RemoveSideBlock(block_hash));
+    }
+
+    pub fn add_state_diff(&mut self, block_hash: BlockHash, state_diff: StateDiff) {
+        self.ops.push(WriteOp::AddStateDiff(block_hash, state_diff));
+    }
+
+    pub fn update_latest_block_number(&mut self, block_number: BlockNumber) {
+        self.ops
+            .push(WriteOp::UpdateLatestBlockNumber(block_number));
+    }
+
+    /// Applies the state changes produced by executing a block on top of the world state with the given root, returning
+    /// the resulting root. Only the trie and the deployed code are written: the current state is left untouched, so this
+    /// works for blocks executed on top of any state still held by the store, such as the ones of side branches
+    pub fn apply_state_diff_at(
+        &mut self,
+        state_root: H256,
+        state_diff: &StateDiff,
+    ) -> Result<H256, StoreError> {
+        let store = self.store.clone();
+        let engine = store.read_engine();
+        let mut state_trie = self.open_trie(&*engine, state_root);
+        for account in state_diff.accounts.iter() {
+            if let Some((code_hash, code)) = &account.new_code {
+                self.add_account_code(*code_hash, code.clone());
+            }
+            let hashed_address = hash_key(account.address.as_bytes());
+            let Some(info) = &account.new_info else {
+                state_trie.remove(hashed_address)?;
+                continue;
+            };
+            let account_state = state_trie
+                .get(&hashed_address)?
+                .map(|rlp| AccountState::decode(&rlp))
+                .transpose()?;
+            let storage_root = match account_state {
+                Some(account_state) if !account.storage_wiped => account_state.storage_root,
+                _ => *EMPTY_TRIE_HASH,
+            };
+            let mut storage_trie = self.open_trie(&*engine, storage_root);
+            for slot in account.storage.iter() {
+                let hashed_key = hash_key(slot.key.as_bytes());
+                // Zero values are removed from the trie
+                if slot.new_value.is_zero() {
+                    storage_trie.remove(hashed_key)?;
+                } else {
+                    storage_trie.insert(hashed_key, slot.new_value.encode_to_vec())?;
+                }
+            }
+            let account_state = AccountState {
+                nonce: info.nonce,
+                balance: info.balance,
+                storage_root: storage_trie.hash()?,
+                code_hash: info.code_hash,
+            };
+            state_trie.insert(hashed_address, account_state.encode_to_vec())?;
+        }
+        state_trie.hash()
+    }
+
+    /// Returns the root of the world state trie the store will have once the batch is committed
+    pub fn state_root(&mut self) -> Result<H256, StoreError> {
+        self.update_world_state()?;
+        self.get_state_root(&*self.store.read_engine())
+    }
+
+    /// Writes all the changes of the batch to the store at once
+    pub fn commit(mut self) -> Result<(), StoreError> {
+        self.update_world_state()?;
+        let trie_nodes = self.trie_nodes.take_nodes();
+        let mut engine = self.store.write_engine();
+        self.store.record_trie_writes(trie_nodes.keys());
+        self.ops
+            .push(WriteOp::AddTrieNodes(trie_nodes.into_iter().collect()));
+        engine.commit_write_batch(self.ops)
+    }
+
+    /// Builds the world state trie from scratch out of the stored state of the given accounts
+    pub(crate) fn rebuild_world_state(&mut self, addresses: impl IntoIterator<Item = Address>) {
+        self.state_root = Some(*EMPTY_TRIE_HASH);
+        for address in addresses {
+            self.touch_account(address, None);
+        }
+    }
+
+    /// Records that the account's leaf in the world state trie needs to be updated
+    fn touch_account(&mut self, address: Address, storage_keys: Option<Vec<H256>>) {
+        let touched = self
+            .touched_accounts
+            .entry(address)
+            .or_insert_with(|| Some(BTreeSet::new()));
+        match (touched.as_mut(), storage_keys) {
+            (Some(touched_keys), Some(storage_keys)) => touched_keys.extend(storage_keys),
+            _ => *touched = None,
+        }
+    }
+
+    /// Updates the world state trie so it matches the state of the accounts touched by the batch.
+    /// Accounts come along with the keys of the storage slots that changed, or None if their whole storage may
+    /// have changed, in which case their storage trie is built from scratch
+    fn update_world_state(&mut self) -> Result<(), StoreError> {
+        if self.touched_accounts.is_empty() {
+            return Ok(());
+        }
+        let store = self.store.clone();
+        let engine = store.read_engine();
+        let mut state_trie = self.open_trie(&*engine, self.get_state_root(&*engine)?);
+        for (address, storage_keys) in std::mem::take(&mut self.touched_accounts) {
+            let hashed_address = hash_key(address.as_bytes());
+            let Some(info) = self.get_account_info(&*engine, address)? else {
+                state_trie.remove(hashed_address)?;
+                continue;
+            };
+            let account_state = state_trie
+                .get(&hashed_address)?
+                .map(|rlp| AccountState::decode(&rlp))
+                .transpose()?;
+            // Accounts without a leaf in the trie have their storage trie built from scratch
+            let (storage_root, storage_keys) = match (account_state, storage_keys) {
+                (Some(account_state), Some(storage_keys)) => {
+                    (account_state.storage_root, storage_keys)
+                }
+                _ => (*EMPTY_TRIE_HASH, self.get_storage_keys(&*engine, address)?),
+            };
+            let mut storage_trie = self.open_trie(&*engine, storage_root);
+            for key in storage_keys {
+                let hashed_key = hash_key(key.as_bytes());
+                // Zero values are removed from the trie
+                match self.get_storage_at(&*engine, address, key)? {
+                    Some(value) if !value.is_zero() => {
+                        storage_trie.insert(hashed_key, value.encode_to_vec())?
+                    }
+                    _ => {
+                        storage_trie.remove(hashed_key)?;
+                    }
+                }
+            }
+            let account_state = AccountState {
+                nonce: info.nonce,
+                balance: info.balance,
+                storage_root: storage_trie.hash()?,
+                code_hash: info.code_hash,
+            };
+            state_trie.insert(hashed_address, account_state.encode_to_vec())?;
+        }
+        let state_root = state_trie.hash()?;
+        self.state_root = Some(state_root);
+        self.ops.push(WriteOp::UpdateStateRoot(state_root));
+        Ok(())
+    }
+
+    /// Opens a trie over the store's nodes and the ones written by the batch, keeping the nodes written to it in the batch
+    fn open_trie(&self, engine: &dyn StoreEngine, root: H256) -> Trie<OverlayTrieDB> {
+        Trie::open(
+            OverlayTrieDB::new(self.trie_nodes.clone(), engine.trie_db()),
+            root,
+        )
+    }
+
+    fn get_state_root(&self, engine: &dyn StoreEngine) -> Result<H256, StoreError> {
+        match self.state_root {
+            Some(state_root) => Ok(state_root),
+            None => Ok(engine.get_state_root()?.unwrap_or(*EMPTY_TRIE_HASH)),
+        }
+    }
+
+    fn get_account_info(
+        &self,
+        engine: &dyn StoreEngine,
+        address: Address,
+    ) -> Result<Option<AccountInfo>, StoreError> {
+        match self.account_infos.get(&address) {
+            Some(account_info) => Ok(account_info.clone()),
+            None => engine.get_account_info(address),
+        }
+    }
+
+    fn get_storage_at(
+        &self,
+        engine: &dyn StoreEngine,
+        address: Address,
+        storage_key: H256,
+    ) -> Result<Option<U256>, StoreError> {
+        match self.storages.get(&address) {
+            Some(storage) if storage.wiped || storage.slots.contains_key(&storage_key) => {
+                Ok(storage.slots.get(&storage_key).copied())
+            }
+            _ => engine.get_storage_at(address, storage_key),
+        }
+    }
+
+    /// Returns the keys of all the account's storage slots
+    fn get_storage_keys(
+        &self,
+        engine: &dyn StoreEngine,
+        address: Address,
+    ) -> Result<BTreeSet<H256>, StoreError> {
+        let storage = self.storages.get(&address);
+        let mut keys = BTreeSet::new();
+        if !storage.is_some_and(|storage| storage.wiped) {
+            keys.extend(engine.account_storage_iter(address)?.map(|(key, _)| key));
+        }
+        if let Some(storage) = storage {
+            keys.extend(storage.slots.keys());
+        }
+        Ok(keys)
+    }
+}